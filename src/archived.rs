@@ -0,0 +1,195 @@
+use std::marker::PhantomData;
+use std::ops;
+
+use rkyv::api::high::{HighSerializer, HighValidator};
+use rkyv::bytecheck::CheckBytes;
+use rkyv::rancor::{self, Source};
+use rkyv::ser::allocator::ArenaHandle;
+use rkyv::util::AlignedVec;
+use rkyv::{Archive, Portable, Serialize};
+
+use crate::cbuffer_raw::{channel, BufferSize, PopError, PushError, Receiver, RecvGuard, Sender};
+
+/// Bytes in front of every archive: its length, then padding up to `ALIGN` together
+/// with the ring's own length prefix.
+const HEADER: usize = 12;
+/// Every frame is padded to a multiple of this, so each archive starts this aligned in
+/// the (page aligned) mapping and can be validated right where it lies.
+const ALIGN: usize = 16;
+
+/// Sending half of an archived channel; values are serialized with rkyv into the ring.
+pub struct ArchivedSender<T> {
+    inner: Sender,
+    scratch: Vec<u8>,
+    _marker: PhantomData<fn(T)>,
+}
+
+/// Receiving half of an archived channel. Pops hand out the archived value in place,
+/// without copying or deserializing it.
+pub struct ArchivedReceiver<T> {
+    inner: Receiver,
+    _marker: PhantomData<fn() -> T>,
+}
+
+/// Creates a channel carrying rkyv archives of `T`.
+pub fn channel_archived<T: Archive>(s: BufferSize) -> (ArchivedSender<T>, ArchivedReceiver<T>) {
+    let (sender, receiver) = channel(s);
+    (ArchivedSender { inner: sender, scratch: Vec::new(), _marker: PhantomData },
+     ArchivedReceiver { inner: receiver, _marker: PhantomData })
+}
+
+impl<T> ArchivedSender<T>
+    where T: for<'a> Serialize<HighSerializer<AlignedVec, ArenaHandle<'a>, rancor::Error>>
+{
+    pub fn try_push(&mut self, value: &T) -> Result<(), ArchivedError<PushError>> {
+        self.encode(value)?;
+        Ok(self.inner.try_push(&self.scratch)?)
+    }
+
+    /// Pushes `value`, parking the calling thread until the receiver frees enough space.
+    pub fn push(&mut self, value: &T) -> Result<(), ArchivedError<PushError>> {
+        self.encode(value)?;
+        Ok(self.inner.push(&self.scratch)?)
+    }
+
+    pub fn close(&mut self) -> Result<(), PushError> {
+        self.inner.close()
+    }
+
+    fn encode(&mut self, value: &T) -> Result<(), ArchivedError<PushError>> {
+        let archive = rkyv::to_bytes::<rancor::Error>(value).map_err(ArchivedError::Codec)?;
+        let len = HEADER + archive.len();
+        self.scratch.clear();
+        self.scratch.extend_from_slice(&(archive.len() as u32).to_le_bytes());
+        self.scratch.resize(HEADER, 0);
+        self.scratch.extend_from_slice(&archive);
+        self.scratch.resize(len + (ALIGN - (len + 4) % ALIGN) % ALIGN, 0);
+        Ok(())
+    }
+}
+
+impl<T> ArchivedReceiver<T>
+    where T: Archive,
+          T::Archived: Portable + for<'a> CheckBytes<HighValidator<'a, rancor::Error>>
+{
+    pub fn try_pop(&mut self) -> Result<ArchivedGuard<'_, T>, ArchivedError<PopError>> {
+        ArchivedGuard::new(self.inner.try_recv()?)
+    }
+
+    /// Pops one value, parking the calling thread until the sender pushes one.
+    pub fn pop(&mut self) -> Result<ArchivedGuard<'_, T>, ArchivedError<PopError>> {
+        ArchivedGuard::new(self.inner.recv()?)
+    }
+}
+
+/// A validated archive still sitting in the ring; it is consumed when the guard is dropped.
+pub struct ArchivedGuard<'a, T: Archive> {
+    value: &'a T::Archived,
+    _guard: RecvGuard<'a>,
+}
+
+impl<'a, T> ArchivedGuard<'a, T>
+    where T: Archive,
+          T::Archived: Portable + for<'b> CheckBytes<HighValidator<'b, rancor::Error>>
+{
+    fn new(guard: RecvGuard<'a>) -> Result<ArchivedGuard<'a, T>, ArchivedError<PopError>> {
+        // A malformed frame is still consumed along with the guard.
+        let bytes = guard.bytes();
+        let len = bytes.get(..4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .filter(|len| HEADER + len <= bytes.len())
+            .ok_or_else(|| ArchivedError::Codec(rancor::Error::new(Truncated)))?;
+        let value = rkyv::access::<T::Archived, rancor::Error>(&bytes[HEADER..HEADER + len])
+            .map_err(ArchivedError::Codec)?;
+        Ok(ArchivedGuard { value, _guard: guard })
+    }
+}
+
+impl<'a, T: Archive> ops::Deref for ArchivedGuard<'a, T> {
+    type Target = T::Archived;
+
+    fn deref(&self) -> &T::Archived {
+        self.value
+    }
+}
+
+#[derive(Debug)]
+struct Truncated;
+
+impl std::error::Error for Truncated {}
+
+impl std::fmt::Display for Truncated {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "frame shorter than its archive")
+    }
+}
+
+/// Error of an archived channel: either the underlying channel error `E`, or a value
+/// that failed to serialize or validate.
+#[derive(Debug)]
+pub enum ArchivedError<E> {
+    Channel(E),
+    Codec(rancor::Error),
+}
+
+impl<E: std::error::Error> std::error::Error for ArchivedError<E> {}
+
+impl<E: std::fmt::Display> std::fmt::Display for ArchivedError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            ArchivedError::Channel(ref err) => write!(f, "{}", err),
+            ArchivedError::Codec(ref err) => write!(f, "codec error: {}", err),
+        }
+    }
+}
+
+impl<E> From<E> for ArchivedError<E> {
+    fn from(err: E) -> ArchivedError<E> {
+        ArchivedError::Channel(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::{channel_archived, ArchivedError};
+    use crate::cbuffer_raw::{BufferSize, PopError};
+
+    #[test]
+    fn test_round_trip() {
+        let (mut sender, mut receiver) = channel_archived::<(u32, String, Vec<u64>)>(BufferSize::Custom(4096));
+        let n = 10_000;
+        let producer = thread::spawn(move || {
+            for i in 0..n {
+                sender.push(&(i, i.to_string(), vec![i as u64; (i % 7) as usize])).unwrap();
+            }
+        });
+        for i in 0..n {
+            let value = receiver.pop().unwrap();
+            assert_eq!(i, value.0);
+            assert_eq!(i.to_string(), value.1.as_str());
+            assert_eq!(vec![i as u64; (i % 7) as usize], value.2.iter().map(|v| v.to_native()).collect::<Vec<_>>());
+        }
+        producer.join().unwrap();
+        match receiver.try_pop() {
+            Err(ArchivedError::Channel(PopError::Disconnected)) => {}
+            Err(e) => panic!("unexpected {:?}", e),
+            Ok(_) => panic!("unexpected value"),
+        };
+    }
+
+    #[test]
+    fn test_invalid_frame() {
+        let (mut sender, mut receiver) = channel_archived::<String>(BufferSize::Custom(4096));
+        sender.inner.try_push(&[0xff; 12]).unwrap();
+        sender.inner.try_push(&[0x01; 20]).unwrap();
+        for _i in 0..2 {
+            match receiver.try_pop() {
+                Err(ArchivedError::Codec(_)) => {}
+                Err(e) => panic!("unexpected {:?}", e),
+                Ok(_) => panic!("unexpected value"),
+            }
+        }
+        assert!(receiver.try_pop().is_err());
+    }
+}
@@ -0,0 +1,263 @@
+//! `futures` integration: `Sender` is a `Sink<Bytes>` and `Receiver` a `Stream` of `Bytes`.
+//! Both are built on `poll_push` and `poll_pop`, which work with any executor, as are
+//! `AsyncSender` and `AsyncReceiver`.
+
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::{Sink, Stream};
+
+use crate::cbuffer_raw::{channel, BufferSize, PopError, PushError, Receiver, Sender};
+use crate::ratelimit::wake_after;
+
+impl Sender {
+    /// Pushes `elem` if it fits, or arranges for the task behind `cx` to be woken once the
+    /// receiver frees space, or the rate limit allows. Only the most recently registered
+    /// task is woken.
+    pub fn poll_push(&mut self, cx: &mut Context<'_>, elem: &[u8]) -> Poll<Result<(), PushError>> {
+        if let Some(bucket) = &mut self.limiter {
+            if let Err(wait) = bucket.take(1, elem.len()) {
+                wake_after(wait, cx.waker().clone());
+                return Poll::Pending;
+            }
+        }
+        let r = self.poll_push_now(cx, elem);
+        if let Poll::Pending | Poll::Ready(Err(_)) = r {
+            // Taken again on the next poll.
+            if let Some(bucket) = &mut self.limiter {
+                bucket.refund(1, elem.len());
+            }
+        }
+        r
+    }
+
+    fn poll_push_now(&mut self, cx: &mut Context<'_>, elem: &[u8]) -> Poll<Result<(), PushError>> {
+        match self.inner.push(elem) {
+            Err(PushError::Full) => {}
+            r => return Poll::Ready(r),
+        }
+        self.inner.register_writable(cx.waker());
+        // Space freed before the waker was in place would not wake anybody.
+        match self.inner.push(elem) {
+            Err(PushError::Full) => Poll::Pending,
+            r => Poll::Ready(r),
+        }
+    }
+}
+
+impl Receiver {
+    /// Pops the oldest element into `consumer`, or arranges for the task behind `cx` to be
+    /// woken once the sender pushes. Only the most recently registered task is woken.
+    pub fn poll_pop<F>(&self, cx: &mut Context<'_>, mut consumer: F) -> Poll<Result<(), PopError>>
+        where F: FnMut(&[u8])
+    {
+        match self.inner.pop(&mut consumer) {
+            Err(PopError::Empty) => {}
+            r => return Poll::Ready(r),
+        }
+        self.inner.register_readable(cx.waker());
+        match self.inner.pop(&mut consumer) {
+            Err(PopError::Empty) => Poll::Pending,
+            r => Poll::Ready(r),
+        }
+    }
+}
+
+/// Sending half of a channel whose pushes are awaited instead of parking the thread.
+pub struct AsyncSender {
+    inner: Sender,
+}
+
+/// Receiving half of a channel whose pops are awaited instead of parking the thread.
+pub struct AsyncReceiver {
+    inner: Receiver,
+}
+
+/// Creates a channel for async code that does not tie it to a particular executor.
+pub fn channel_async(s: BufferSize) -> (AsyncSender, AsyncReceiver) {
+    let (sender, receiver) = channel(s);
+    (AsyncSender::new(sender), AsyncReceiver::new(receiver))
+}
+
+impl AsyncSender {
+    pub fn new(inner: Sender) -> AsyncSender {
+        AsyncSender { inner }
+    }
+
+    /// Pushes `elem`, waiting for the receiver to make room.
+    pub async fn send(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        poll_fn(|cx| self.inner.poll_push(cx, elem)).await
+    }
+
+    pub fn close(&mut self) -> Result<(), PushError> {
+        self.inner.close()
+    }
+
+    pub fn into_inner(self) -> Sender {
+        self.inner
+    }
+}
+
+impl AsyncReceiver {
+    pub fn new(inner: Receiver) -> AsyncReceiver {
+        AsyncReceiver { inner }
+    }
+
+    /// Pops the oldest element, waiting for the sender to push one.
+    pub async fn recv(&mut self) -> Result<Bytes, PopError> {
+        let mut item = Bytes::new();
+        self.recv_with(|bytes| item = Bytes::copy_from_slice(bytes)).await?;
+        Ok(item)
+    }
+
+    /// Like `recv`, handing the element to `consumer` in place instead of copying it.
+    pub async fn recv_with<F>(&mut self, mut consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        poll_fn(|cx| self.inner.poll_pop(cx, &mut consumer)).await
+    }
+
+    pub fn into_inner(self) -> Receiver {
+        self.inner
+    }
+}
+
+impl Sink<Bytes> for Sender {
+    type Error = PushError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), PushError>> {
+        self.poll_flush(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Bytes) -> Result<(), PushError> {
+        debug_assert!(self.pending.is_none(), "start_send without poll_ready");
+        self.pending = Some(item);
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), PushError>> {
+        let this = &mut *self;
+        if let Some(item) = this.pending.take() {
+            let r = this.poll_push(cx, &item);
+            if r.is_pending() {
+                this.pending = Some(item);
+            }
+            return r;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), PushError>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl Stream for Receiver {
+    type Item = Bytes;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        let mut item = None;
+        self.poll_pop(cx, |bytes| item = Some(Bytes::copy_from_slice(bytes))).map(|_| item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use bytes::Bytes;
+    use futures::executor::block_on;
+    use futures::{SinkExt, StreamExt};
+    use crate::cbuffer_raw::{channel, BufferSize, PopError};
+    use super::channel_async;
+
+    #[test]
+    fn test_sink_stream() {
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let n = 10_000u32;
+
+        let producer = thread::spawn(move || {
+            block_on(async {
+                for i in 0..n {
+                    sender.send(Bytes::from(i.to_le_bytes().to_vec())).await.unwrap();
+                }
+            })
+        });
+
+        let received: Vec<Bytes> = block_on(receiver.collect());
+        producer.join().unwrap();
+        assert_eq!(n as usize, received.len());
+        for (i, bytes) in received.iter().enumerate() {
+            assert_eq!(&(i as u32).to_le_bytes()[..], &bytes[..]);
+        }
+    }
+
+    #[test]
+    fn test_poll_push_pop() {
+        use std::future::poll_fn;
+        use crate::cbuffer_raw::PushError;
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let n = 10_000u32;
+
+        let producer = thread::spawn(move || {
+            block_on(async {
+                for i in 0..n {
+                    poll_fn(|cx| sender.poll_push(cx, &i.to_le_bytes())).await.unwrap();
+                }
+                sender.set_max_message_size(1);
+                assert_eq!(Err(PushError::MessageTooLarge), poll_fn(|cx| sender.poll_push(cx, b"xx")).await);
+            })
+        });
+
+        block_on(async {
+            for i in 0..n {
+                poll_fn(|cx| receiver.poll_pop(cx, |bytes| assert_eq!(&i.to_le_bytes()[..], bytes))).await.unwrap();
+            }
+            assert_eq!(Err(PopError::Disconnected), poll_fn(|cx| receiver.poll_pop(cx, |_| {})).await);
+        });
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn test_async_channel() {
+        let (mut sender, mut receiver) = channel_async(BufferSize::Custom(4096));
+        let n = 10_000u32;
+
+        let producer = thread::spawn(move || {
+            block_on(async {
+                for i in 0..n {
+                    sender.send(&i.to_le_bytes()).await.unwrap();
+                }
+                sender.close().unwrap();
+            })
+        });
+
+        block_on(async {
+            for i in 0..n {
+                assert_eq!(&i.to_le_bytes()[..], &receiver.recv().await.unwrap()[..]);
+            }
+            assert_eq!(Err(PopError::Closed), receiver.recv_with(|_| {}).await);
+        });
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn test_poll_push_rate_limit() {
+        use std::future::poll_fn;
+        use std::time::{Duration, Instant};
+        use crate::ratelimit::RateLimit;
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        sender.set_rate_limit(Some(RateLimit { messages_per_sec: Some(200), bytes_per_sec: None }));
+        let start = Instant::now();
+        block_on(async {
+            for i in 0..210u32 {
+                poll_fn(|cx| sender.poll_push(cx, &i.to_le_bytes())).await.unwrap();
+            }
+        });
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert_eq!(210, receiver.len());
+    }
+}
@@ -0,0 +1,240 @@
+//! The ring protocol on nothing but `core`, for targets without an operating system: one
+//! sender and one receiver over memory the caller supplies, e.g. a `static` buffer shared
+//! between an interrupt handler and the main loop. Frames are laid out as in the other
+//! channels of this crate. Nothing ever waits: pushes into a full ring fail with
+//! `PushError::Full` and pops from an empty one with `PopError::Empty`, and the caller
+//! polls.
+
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::{mem, ptr, slice};
+
+use crate::error::{PopError, PushError};
+use crate::frame::{self, Framing, LengthPrefix, MAX_PREFIX};
+
+/// Kept at the start of the memory. Cursors are `usize`, as not every target has 64-bit
+/// atomics, and wrap around; the capacity being a power of two keeps offsets in step.
+#[repr(C)]
+struct Header {
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    closed: AtomicBool,
+    sender_dropped: AtomicBool,
+    receiver_dropped: AtomicBool,
+}
+
+/// The ring both halves work on. Its bytes follow the header twice over: writes go into
+/// both copies, so that every frame reads back in one piece without a second mapping.
+struct Ring {
+    header: &'static Header,
+    data: *mut u8,
+    capacity: usize,
+    format: Framing,
+}
+
+pub struct Sender {
+    ring: Ring,
+}
+
+pub struct Receiver {
+    ring: Ring,
+    /// Pops take `&self`, as with the other receivers, but only one may run at a time.
+    _unsync: PhantomData<*const ()>,
+}
+
+// The halves only ever touch the ring through atomics and their own side of it.
+unsafe impl Send for Sender {}
+unsafe impl Send for Receiver {}
+
+/// Creates a channel in `memory`, framing elements with `prefix`. The ring gets the
+/// largest power of two that fits into `memory` twice after a small header. Panics if
+/// that leaves no room for a single element.
+pub fn channel(memory: &'static mut [u8], prefix: LengthPrefix) -> (Sender, Receiver) {
+    let align = memory.as_ptr().align_offset(mem::align_of::<Header>());
+    let start = align + mem::size_of::<Header>();
+    let capacity = match memory.len().saturating_sub(start) / 2 {
+        0 => 0,
+        half => 1 << half.ilog2(),
+    };
+    let format = Framing::from(prefix);
+    assert!(capacity > 0 && format.max_len(capacity) > 0, "memory too small for a ring");
+    let header = unsafe {
+        let header = memory.as_mut_ptr().add(align) as *mut Header;
+        header.write(Header {
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            sender_dropped: AtomicBool::new(false),
+            receiver_dropped: AtomicBool::new(false),
+        });
+        &*header
+    };
+    let data = unsafe { memory.as_mut_ptr().add(start) };
+    let ring = || Ring { header, data, capacity, format };
+    (Sender { ring: ring() }, Receiver { ring: ring(), _unsync: PhantomData })
+}
+
+impl Ring {
+    fn used(&self) -> usize {
+        self.header.tail.load(Ordering::Acquire).wrapping_sub(self.header.head.load(Ordering::Acquire))
+    }
+
+    fn offset(&self, pos: usize) -> usize {
+        frame::offset(pos as u64, self.capacity)
+    }
+
+    fn write(&self, pos: usize, bytes: &[u8]) {
+        let offset = self.offset(pos);
+        let (low, high) = bytes.split_at((self.capacity - offset).min(bytes.len()));
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), self.data.add(offset), bytes.len());
+            ptr::copy_nonoverlapping(low.as_ptr(), self.data.add(offset + self.capacity), low.len());
+            ptr::copy_nonoverlapping(high.as_ptr(), self.data, high.len());
+        }
+    }
+
+    /// `len` bytes at `pos`, which the other side leaves alone until the cursors move.
+    fn read(&self, pos: usize, len: usize) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.data.add(self.offset(pos)), len) }
+    }
+
+    fn frame_len(&self, pos: usize) -> usize {
+        self.format.prefix.decode(self.read(pos, MAX_PREFIX)) as usize
+    }
+}
+
+impl Sender {
+    pub fn try_push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        let ring = &self.ring;
+        if ring.header.receiver_dropped.load(Ordering::Acquire) {
+            return Err(PushError::Disconnected);
+        }
+        if ring.header.closed.load(Ordering::Relaxed) {
+            return Err(PushError::Closed);
+        }
+        if !ring.format.prefix.accepts(elem.len()) || elem.len() > ring.format.max_len(ring.capacity) {
+            return Err(PushError::MessageTooLarge);
+        }
+        let size = ring.format.frame_size(elem.len());
+        if ring.capacity - ring.used() <= size {
+            return Err(PushError::Full);
+        }
+        let tail = ring.header.tail.load(Ordering::Relaxed);
+        let mut prefix = [0u8; MAX_PREFIX];
+        let width = ring.format.prefix.encode(elem.len() as u32, &mut prefix);
+        ring.write(tail, &prefix[..width]);
+        ring.write(tail.wrapping_add(width), elem);
+        ring.header.tail.store(tail.wrapping_add(size), Ordering::Release);
+        Ok(())
+    }
+
+    /// Ends the stream: once the receiver has popped everything pushed before, its pops
+    /// fail with `PopError::Closed`.
+    pub fn close(&mut self) {
+        self.ring.header.closed.store(true, Ordering::Release);
+    }
+
+    /// Bytes of frames that fit right now.
+    pub fn free(&self) -> usize {
+        self.ring.capacity - self.ring.used() - 1
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.ring.header.sender_dropped.store(true, Ordering::Release);
+    }
+}
+
+impl Receiver {
+    /// Pops one element if there is one. Fails with `PopError::Closed` or
+    /// `PopError::Disconnected` once the sender has closed the ring or is gone, and the
+    /// ring is empty.
+    pub fn try_pop<F>(&self, mut consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        let ring = &self.ring;
+        // Read ahead of the tail, so that elements pushed before either are not missed.
+        let closed = ring.header.closed.load(Ordering::Acquire);
+        let sender_dropped = ring.header.sender_dropped.load(Ordering::Acquire);
+        let head = ring.header.head.load(Ordering::Relaxed);
+        if head == ring.header.tail.load(Ordering::Acquire) {
+            return Err(if closed {
+                PopError::Closed
+            } else if sender_dropped {
+                PopError::Disconnected
+            } else {
+                PopError::Empty
+            });
+        }
+        let len = ring.frame_len(head);
+        consumer(ring.read(head.wrapping_add(ring.format.width(len)), len));
+        ring.header.head.store(head.wrapping_add(ring.format.frame_size(len)), Ordering::Release);
+        Ok(())
+    }
+
+    /// Pops the oldest element into `buf` unless it does not fit, in which case it is
+    /// left in place. Returns its length.
+    pub fn read_into(&self, buf: &mut [u8]) -> Result<usize, PopError> {
+        let ring = &self.ring;
+        let head = ring.header.head.load(Ordering::Relaxed);
+        if head != ring.header.tail.load(Ordering::Acquire) && ring.frame_len(head) > buf.len() {
+            return Err(PopError::BufferTooSmall);
+        }
+        let mut n = 0;
+        self.try_pop(|bytes| {
+            buf[..bytes.len()].copy_from_slice(bytes);
+            n = bytes.len();
+        })?;
+        Ok(n)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ring.used() == 0
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.ring.header.receiver_dropped.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+    use std::vec;
+
+    use super::channel;
+    use crate::error::{PopError, PushError};
+    use crate::frame::LengthPrefix;
+
+    #[test]
+    fn test_bare() {
+        let memory = Box::leak(vec![0u8; 300].into_boxed_slice());
+        let (mut sender, receiver) = channel(memory, LengthPrefix::U8);
+        assert_eq!(127, sender.free());
+        assert_eq!(Err(PopError::Empty), receiver.try_pop(|_| {}));
+        assert_eq!(Err(PushError::MessageTooLarge), sender.try_push(&[0; 127]));
+
+        // Frames wrap around the end of the ring and still read back in one piece.
+        for i in 0..100u8 {
+            sender.try_push(&[i; 50]).unwrap();
+            assert_eq!(Err(PushError::Full), sender.try_push(&[i; 100]));
+            assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(&[i; 50][..], bytes)));
+        }
+        sender.try_push(b"last").unwrap();
+        sender.close();
+        assert_eq!(Err(PushError::Closed), sender.try_push(b""));
+        let mut buf = [0u8; 3];
+        assert_eq!(Err(PopError::BufferTooSmall), receiver.read_into(&mut buf));
+        let mut buf = [0u8; 8];
+        assert_eq!(Ok(4), receiver.read_into(&mut buf));
+        assert_eq!(b"last", &buf[..4]);
+        assert_eq!(Err(PopError::Closed), receiver.try_pop(|_| {}));
+        drop(receiver);
+        assert_eq!(Err(PushError::Disconnected), sender.try_push(b""));
+    }
+}
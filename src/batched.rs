@@ -0,0 +1,127 @@
+use std::mem;
+
+use crate::cbuffer_raw::{PushError, Sender, Transaction};
+
+/// Sending half of a channel that stages elements in a local buffer and publishes them
+/// all with one copy and one tail update, on `flush` or once the buffer is full. The
+/// receiver sees nothing until then. Dropping it flushes what is still staged.
+pub struct BatchedSender {
+    inner: Sender,
+    /// Staged elements, framed as they will be in the ring.
+    frames: Vec<u8>,
+    count: usize,
+    bytes: usize,
+    limit: usize,
+}
+
+impl BatchedSender {
+    /// Batches the elements pushed to `inner`, flushing whenever another one would take
+    /// the staged frames past `limit` bytes.
+    pub fn new(inner: Sender, limit: usize) -> BatchedSender {
+        BatchedSender { inner, frames: Vec::with_capacity(limit), count: 0, bytes: 0, limit }
+    }
+
+    /// Stages `elem`, first flushing the batch if `elem` would not fit in the staging
+    /// buffer. Parks like `Sender::push` while that flush waits for room.
+    pub fn push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        let format = self.inner.inner.format;
+        if self.count > 0 && self.frames.len() + format.frame_size(elem.len()) > self.limit {
+            self.flush()?;
+        }
+        self.staged(|tx| tx.push(elem))
+    }
+
+    /// Publishes the staged elements, parking until they all fit unless the channel's
+    /// `FullPolicy` says otherwise. On failure they stay staged.
+    pub fn flush(&mut self) -> Result<(), PushError> {
+        if self.count == 0 {
+            return Ok(());
+        }
+        self.staged(|tx| tx.publish())
+    }
+
+    /// Publishes the staged elements if they all fit right now.
+    pub fn try_flush(&mut self) -> Result<(), PushError> {
+        if self.count == 0 {
+            return Ok(());
+        }
+        self.staged(|tx| tx.try_commit())
+    }
+
+    /// Number of elements staged.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn sender(&self) -> &Sender {
+        &self.inner
+    }
+
+    /// Runs `f` on a transaction holding the staged elements, keeping whatever it leaves.
+    fn staged<R>(&mut self, f: impl FnOnce(&mut Transaction<'_>) -> R) -> R {
+        let mut tx = Transaction {
+            buffer: &self.inner.inner,
+            limiter: &mut self.inner.limiter,
+            frames: mem::take(&mut self.frames),
+            count: self.count,
+            bytes: self.bytes,
+        };
+        let r = f(&mut tx);
+        self.frames = tx.frames;
+        self.count = tx.count;
+        self.bytes = tx.bytes;
+        r
+    }
+}
+
+impl Drop for BatchedSender {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BatchedSender;
+    use crate::{channel, BufferSize, PopError, PushError};
+
+    #[test]
+    fn test_batched_sender() {
+        let (tx, rx) = channel(BufferSize::Custom(4096));
+        let mut tx = BatchedSender::new(tx, 32);
+        tx.push(b"one").unwrap();
+        tx.push(b"two").unwrap();
+        assert_eq!(tx.len(), 2);
+        assert_eq!(rx.try_pop(|_| ()), Err(PopError::Empty));
+
+        tx.flush().unwrap();
+        assert!(tx.is_empty());
+        let mut popped = Vec::new();
+        while rx.try_pop(|bytes| popped.push(bytes.to_vec())).is_ok() {}
+        assert_eq!(popped, vec![b"one".to_vec(), b"two".to_vec()]);
+
+        // Filling the staging buffer publishes what was staged before.
+        for _ in 0..4 {
+            tx.push(&[7; 10]).unwrap();
+        }
+        assert_eq!(tx.len(), 2);
+        let mut count = 0;
+        while rx.try_pop(|bytes| assert_eq!(bytes, &[7; 10])).is_ok() {
+            count += 1;
+        }
+        assert_eq!(count, 2);
+
+        assert_eq!(tx.push(&[0; 8192]), Err(PushError::MessageTooLarge));
+        drop(tx);
+        let mut count = 0;
+        while rx.try_pop(|_| ()).is_ok() {
+            count += 1;
+        }
+        assert_eq!(count, 2);
+        assert_eq!(rx.try_pop(|_| ()), Err(PopError::Disconnected));
+    }
+}
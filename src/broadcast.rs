@@ -0,0 +1,430 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::cbuffer_raw::{BufferSize, CBuffer, PopError, PopTimeoutError, PushError};
+
+/// Read position of one receiver.
+struct Cursor {
+    /// Stored with `Release` once the element before it has been read, so that the sender
+    /// only reuses the space after loading it with `Acquire`.
+    pos: AtomicU64,
+    /// Whether a follower waits on this cursor, so that moving it has to wake readers.
+    followed: AtomicBool,
+    /// Set once the receiver is dropped, after its last move.
+    gone: AtomicBool,
+}
+
+impl Cursor {
+    fn new(pos: u64) -> Arc<Cursor> {
+        Arc::new(Cursor { pos: AtomicU64::new(pos), followed: AtomicBool::new(false), gone: AtomicBool::new(false) })
+    }
+}
+
+/// Shared state of a broadcast channel. The ring's own head is only a cache of the
+/// slowest cursor, refreshed by the sender when it runs out of space. Elements between it
+/// and the tail stay readable, so new receivers can replay them.
+struct Shared {
+    ring: CBuffer,
+    cursors: Mutex<Vec<Arc<Cursor>>>,
+    /// Sequence number of the element at the ring's head, only moved along with the head
+    /// under the `cursors` lock.
+    head_seq: AtomicU64,
+    /// Number of elements ever pushed, counted once they are readable.
+    pushed: AtomicU64,
+}
+
+/// The slowest cursor and the position it was found at.
+type Slowest = (Arc<Cursor>, u64);
+
+impl Shared {
+    /// Recomputes the slowest cursor and hands everything before it back to the sender.
+    /// Returns that cursor along with the position it was found at, if there is one.
+    fn refresh_head(&self) -> Option<Slowest> {
+        let cursors = self.cursors.lock().unwrap();
+        let slowest = cursors.iter().map(|c| (c.clone(), c.pos.load(Ordering::Acquire))).min_by_key(|&(_, pos)| pos);
+        let head = slowest.as_ref().map_or_else(|| self.ring.tail(), |&(_, pos)| pos);
+        let (mut pos, mut seq) = (self.ring.head(), self.head_seq.load(Ordering::Relaxed));
+        while pos != head {
+            pos = self.ring.next(pos, self.ring.frame_len(pos));
+            seq += 1;
+        }
+        self.head_seq.store(seq, Ordering::Relaxed);
+        self.ring.set_head(head);
+        slowest
+    }
+
+    /// Sequence numbers of the elements still in the ring, to be called with the `cursors`
+    /// lock held.
+    fn retained(&self) -> Range<u64> {
+        self.head_seq.load(Ordering::Relaxed)..self.pushed.load(Ordering::Acquire)
+    }
+}
+
+pub struct BroadcastSender {
+    shared: Arc<Shared>,
+}
+
+/// A subscriber with its own read position. Clones start at the position of the
+/// receiver they were cloned from.
+pub struct BroadcastReceiver {
+    shared: Arc<Shared>,
+    cursor: Arc<Cursor>,
+    /// Cursor of the receiver this one follows, which it never overtakes.
+    upstream: Option<Arc<Cursor>>,
+    /// Sequence number of the element at the cursor.
+    seq: u64,
+}
+
+/// Creates a channel where every receiver sees every element. The sender can only reuse
+/// space once the slowest receiver has moved past it.
+pub fn broadcast(s: BufferSize) -> (BroadcastSender, BroadcastReceiver) {
+    let cursor = Cursor::new(0);
+    let shared = Arc::new(Shared {
+        ring: CBuffer::with_capacity(s).expect("fail to create cbuffer."),
+        cursors: Mutex::new(vec![cursor.clone()]),
+        head_seq: AtomicU64::new(0),
+        pushed: AtomicU64::new(0),
+    });
+    (BroadcastSender { shared: shared.clone() }, BroadcastReceiver { shared, cursor, upstream: None, seq: 0 })
+}
+
+impl BroadcastSender {
+    pub fn try_push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        self.push_once(elem).map_err(|(err, _)| err)
+    }
+
+    /// Like `try_push`, also handing back the slowest cursor if it is what kept the ring
+    /// full, along with where it was.
+    fn push_once(&mut self, elem: &[u8]) -> Result<(), (PushError, Option<Slowest>)> {
+        let r = match self.shared.ring.push(elem) {
+            Err(PushError::Full) => {
+                let slowest = self.shared.refresh_head();
+                self.shared.ring.push(elem).map_err(|err| (err, slowest))
+            }
+            r => r.map_err(|err| (err, None)),
+        };
+        if r.is_ok() {
+            self.shared.pushed.fetch_add(1, Ordering::Release);
+        }
+        r
+    }
+
+    /// Sequence numbers of the elements receivers can still replay; the sender only
+    /// overwrites them when it runs out of space.
+    pub fn retained(&self) -> Range<u64> {
+        let _cursors = self.shared.cursors.lock().unwrap();
+        self.shared.retained()
+    }
+
+    /// Pushes `elem`, parking until every receiver has made room for it.
+    pub fn push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        loop {
+            match self.push_once(elem) {
+                // Only the slowest cursor moving can make room, so that is all the wait
+                // looks at; the next attempt refreshes the head again.
+                Err((PushError::Full, slowest)) => {
+                    let ring = &self.shared.ring;
+                    ring.wait_writable(|| {
+                        let moved = |(c, pos): &Slowest| c.pos.load(Ordering::Acquire) != *pos || c.gone.load(Ordering::Acquire);
+                        slowest.as_ref().is_none_or(moved) || ring.is_receiver_dropped()
+                    }, None);
+                }
+                r => return r.map_err(|(err, _)| err),
+            }
+        }
+    }
+}
+
+impl Drop for BroadcastSender {
+    fn drop(&mut self) {
+        self.shared.ring.disconnect_sender();
+    }
+}
+
+impl BroadcastReceiver {
+    pub fn try_pop<F>(&mut self, mut consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        let ring = &self.shared.ring;
+        let sender_dropped = ring.is_sender_dropped();
+        let upstream_gone = self.is_upstream_gone();
+        let tail = ring.tail();
+        let head = self.cursor.pos.load(Ordering::Relaxed);
+        if head == self.limit(tail) {
+            let done = upstream_gone || sender_dropped && head == tail;
+            return Err(if done { PopError::Disconnected } else { PopError::Empty });
+        }
+        let len = ring.frame_len(head);
+        consumer(ring.payload(head, len));
+        self.cursor.pos.store(ring.next(head, len), Ordering::Release);
+        self.seq += 1;
+        ring.notify_writable();
+        if self.cursor.followed.load(Ordering::Acquire) {
+            ring.notify_readable();
+        }
+        Ok(())
+    }
+
+    /// Pops one element, parking until the sender pushes one.
+    pub fn pop<F>(&mut self, mut consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        loop {
+            match self.try_pop(&mut consumer) {
+                Err(PopError::Empty) => self.wait(None),
+                r => return r,
+            }
+        }
+    }
+
+    /// Like `pop`, but gives up once `timeout` has elapsed without an element arriving.
+    pub fn pop_timeout<F>(&mut self, timeout: Duration, mut consumer: F) -> Result<(), PopTimeoutError>
+        where F: FnMut(&[u8])
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.try_pop(&mut consumer) {
+                Err(PopError::Empty) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(PopTimeoutError::Timeout);
+                    }
+                    self.wait(Some(deadline - now));
+                }
+                r => return r.map_err(PopTimeoutError::from),
+            }
+        }
+    }
+
+    fn wait(&self, timeout: Option<Duration>) {
+        let ring = &self.shared.ring;
+        ring.wait_readable(|| {
+            self.cursor.pos.load(Ordering::Relaxed) != self.limit(ring.tail()) || ring.is_sender_dropped()
+                || self.is_upstream_gone()
+        }, timeout);
+    }
+
+    fn is_upstream_gone(&self) -> bool {
+        self.upstream.as_ref().is_some_and(|u| u.gone.load(Ordering::Acquire))
+    }
+
+    /// How far this receiver may read given the sender's `tail`.
+    fn limit(&self, tail: u64) -> u64 {
+        self.upstream.as_ref().map_or(tail, |u| u.pos.load(Ordering::Acquire))
+    }
+
+    /// Sequence number of the next element this receiver pops, counting from 0 for the
+    /// first element pushed.
+    pub fn sequence(&self) -> u64 {
+        self.seq
+    }
+
+    /// See `BroadcastSender::retained`.
+    pub fn retained(&self) -> Range<u64> {
+        let _cursors = self.shared.cursors.lock().unwrap();
+        self.shared.retained()
+    }
+
+    /// Creates a receiver that starts at the oldest element still in the ring instead of
+    /// this one's position, to replay what was pushed before it joined.
+    pub fn replay(&self) -> BroadcastReceiver {
+        self.attach(|retained| Some(retained.start)).expect("oldest element is retained")
+    }
+
+    /// Like `replay`, starting at the element with sequence number `seq`, or `None` if it
+    /// is no longer or not yet in the ring. `seq` may be the next one to be pushed.
+    pub fn replay_from(&self, seq: u64) -> Option<BroadcastReceiver> {
+        self.attach(|retained| Some(seq).filter(|&seq| retained.start <= seq && seq <= retained.end))
+    }
+
+    /// Adds a receiver at the sequence number `pick` chooses from the retained range.
+    fn attach<F>(&self, pick: F) -> Option<BroadcastReceiver>
+        where F: FnOnce(Range<u64>) -> Option<u64>
+    {
+        let ring = &self.shared.ring;
+        let mut cursors = self.shared.cursors.lock().unwrap();
+        let retained = self.shared.retained();
+        let start = retained.start;
+        let seq = pick(retained)?;
+        let mut pos = ring.head();
+        for _ in start..seq {
+            pos = ring.next(pos, ring.frame_len(pos));
+        }
+        let cursor = Cursor::new(pos);
+        cursors.push(cursor.clone());
+        Some(BroadcastReceiver { shared: self.shared.clone(), cursor, upstream: None, seq })
+    }
+
+    /// Whether this receiver has seen everything it may see so far.
+    pub fn is_empty(&self) -> bool {
+        self.cursor.pos.load(Ordering::Relaxed) == self.limit(self.shared.ring.tail())
+    }
+
+    /// Creates a receiver that starts at this one's position and only sees elements this
+    /// one has already popped, like a later stage of a pipeline. Once this receiver is
+    /// dropped, the follower is disconnected after catching up with it.
+    pub fn follower(&self) -> BroadcastReceiver {
+        self.cursor.followed.store(true, Ordering::Release);
+        let cursor = Cursor::new(self.cursor.pos.load(Ordering::Relaxed));
+        self.shared.cursors.lock().unwrap().push(cursor.clone());
+        BroadcastReceiver { shared: self.shared.clone(), cursor, upstream: Some(self.cursor.clone()), seq: self.seq }
+    }
+}
+
+impl Clone for BroadcastReceiver {
+    fn clone(&self) -> BroadcastReceiver {
+        let cursor = Cursor::new(self.cursor.pos.load(Ordering::Relaxed));
+        self.shared.cursors.lock().unwrap().push(cursor.clone());
+        BroadcastReceiver { shared: self.shared.clone(), cursor, upstream: self.upstream.clone(), seq: self.seq }
+    }
+}
+
+impl Drop for BroadcastReceiver {
+    fn drop(&mut self) {
+        self.cursor.gone.store(true, Ordering::Release);
+        if self.cursor.followed.load(Ordering::Acquire) {
+            self.shared.ring.notify_readable();
+        }
+        let mut cursors = self.shared.cursors.lock().unwrap();
+        cursors.retain(|c| !Arc::ptr_eq(c, &self.cursor));
+        if cursors.is_empty() {
+            self.shared.ring.disconnect_receiver();
+        } else {
+            self.shared.ring.notify_writable();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::broadcast;
+    use crate::cbuffer_raw::{BufferSize, PopError, PushError};
+
+    #[test]
+    fn test_every_receiver_sees_everything() {
+        let (mut sender, receiver) = broadcast(BufferSize::Custom(4096));
+        let n = 20_000u32;
+
+        let handles: Vec<_> = (0..3).map(|_| {
+            let mut receiver = receiver.clone();
+            thread::spawn(move || {
+                for i in 0..n {
+                    receiver.pop(|bytes| assert_eq!(&i.to_le_bytes()[..], bytes)).unwrap();
+                }
+                assert_eq!(Err(PopError::Disconnected), receiver.pop(|_| {}));
+            })
+        }).collect();
+        drop(receiver);
+
+        for i in 0..n {
+            sender.push(&i.to_le_bytes()).unwrap();
+        }
+        drop(sender);
+        for h in handles {
+            h.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_slowest_receiver_gates_sender() {
+        let (mut sender, mut fast) = broadcast(BufferSize::Custom(4096));
+        let mut slow = fast.clone();
+        let frame = vec![0u8; 1000];
+
+        while sender.try_push(&frame).is_ok() {}
+        while fast.try_pop(|_| {}).is_ok() {}
+        assert_eq!(Err(PushError::Full), sender.try_push(&frame));
+
+        assert_eq!(Ok(()), slow.try_pop(|_| {}));
+        assert_eq!(Ok(()), sender.try_push(&frame));
+
+        drop((fast, slow));
+        assert_eq!(Err(PushError::Disconnected), sender.try_push(&frame));
+    }
+
+    #[test]
+    fn test_follower() {
+        let (mut sender, mut journal) = broadcast(BufferSize::Custom(4096));
+        let mut logic = journal.follower();
+        let n = 20_000u32;
+
+        let handle = thread::spawn(move || {
+            for i in 0..n {
+                logic.pop(|bytes| assert_eq!(&i.to_le_bytes()[..], bytes)).unwrap();
+            }
+            assert_eq!(Err(PopError::Disconnected), logic.pop(|_| {}));
+        });
+        let producer = thread::spawn(move || {
+            for i in 0..n {
+                sender.push(&i.to_le_bytes()).unwrap();
+            }
+        });
+        for i in 0..n {
+            journal.pop(|bytes| assert_eq!(&i.to_le_bytes()[..], bytes)).unwrap();
+        }
+        producer.join().unwrap();
+        drop(journal);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_follower_stays_behind() {
+        let (mut sender, mut journal) = broadcast(BufferSize::Custom(4096));
+        let mut logic = journal.follower();
+        sender.try_push(b"first").unwrap();
+        sender.try_push(b"second").unwrap();
+        assert_eq!(Err(PopError::Empty), logic.try_pop(|_| {}));
+        assert!(logic.is_empty());
+
+        journal.try_pop(|_| {}).unwrap();
+        assert_eq!(Ok(()), logic.try_pop(|bytes| assert_eq!(b"first", bytes)));
+        assert_eq!(Err(PopError::Empty), logic.try_pop(|_| {}));
+
+        // The follower of a dropped receiver only gets what that one got to.
+        drop(journal);
+        assert_eq!(Err(PopError::Disconnected), logic.try_pop(|_| {}));
+        assert_eq!(Ok(()), sender.try_push(b"third"));
+    }
+
+    #[test]
+    fn test_replay() {
+        let (mut sender, mut live) = broadcast(BufferSize::Custom(4096));
+        for i in 0..3u32 {
+            sender.try_push(&i.to_le_bytes()).unwrap();
+        }
+        assert_eq!(0..3, sender.retained());
+        while live.try_pop(|_| {}).is_ok() {}
+        assert_eq!(3, live.sequence());
+
+        // Popped elements stay around until the sender needs their space.
+        let mut late = live.replay();
+        assert_eq!(0, late.sequence());
+        assert_eq!(Ok(()), late.try_pop(|bytes| assert_eq!(&0u32.to_le_bytes()[..], bytes)));
+        let mut later = live.replay_from(2).unwrap();
+        assert_eq!(Ok(()), later.try_pop(|bytes| assert_eq!(&2u32.to_le_bytes()[..], bytes)));
+        assert!(live.replay_from(4).is_none());
+        drop((late, later));
+
+        // Filling the ring makes the sender reclaim what every receiver has seen.
+        let frame = vec![0u8; 1000];
+        for _ in 0..10 {
+            sender.try_push(&frame).unwrap();
+            while live.try_pop(|_| {}).is_ok() {}
+        }
+        let retained = live.retained();
+        assert!(retained.start > 0);
+        assert_eq!(live.sequence(), retained.end);
+        assert!(live.replay_from(0).is_none());
+        let mut late = live.replay();
+        assert_eq!(retained.start, late.sequence());
+        let mut replayed = 0;
+        while late.try_pop(|bytes| assert_eq!(1000, bytes.len())).is_ok() {
+            replayed += 1;
+        }
+        assert_eq!(retained.end - retained.start, replayed);
+        assert_eq!(retained.end, late.sequence());
+    }
+}
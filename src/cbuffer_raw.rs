@@ -1,216 +1,3951 @@
 #![allow(dead_code)]
 
-use crossbeam::atomic::AtomicCell;
-use byteorder::{ByteOrder, LittleEndian};
+#[cfg(unix)]
 use libc::{
-    c_void,
-    mmap, munmap,
+    c_int, c_void,
+    close, ftruncate, mmap, munmap, off_t,
     MAP_ANONYMOUS, MAP_FAILED, MAP_FIXED, MAP_PRIVATE, MAP_SHARED,
     PROT_NONE, PROT_READ, PROT_WRITE,
 };
-use std::{ptr, slice};
-use std::cell::UnsafeCell;
-use std::sync::Arc;
-use std::time::Duration;
+use std::{alloc, mem, ops, ptr, slice};
+use std::alloc::Layout;
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::io::{self, IoSlice, Write};
+use std::fs::File;
+use std::path::Path;
+use std::ffi::CString;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{fence, AtomicBool, AtomicU8, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+/// Cursors are the only atomics whose orderings matter for the data itself, so they are
+/// the ones loom gets to model.
+#[cfg(not(loom))]
+use std::sync::atomic::AtomicU64 as Cursor;
+#[cfg(loom)]
+use loom::sync::atomic::AtomicU64 as Cursor;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+#[cfg(feature = "async")]
+use futures::task::AtomicWaker;
+#[cfg(feature = "async")]
+use std::task::Waker;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::RawFd;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, BorrowedFd, IntoRawFd, OwnedFd};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::OnceLock;
+
+use crate::frame::{self, Framing, LengthPrefix, END_OF_STREAM, MAX_PREFIX};
+pub use crate::error::{PopError, PushError};
+use crate::ratelimit::{RateLimit, TokenBucket};
+use crate::stream::{StreamReceiver, StreamSender};
+#[cfg(unix)]
+use crate::fdpass;
+#[cfg(feature = "trace")]
+use crate::trace::{Trace, TraceEvent, TraceOp};
 
 pub struct Sender {
-    inner: Arc<UnsafeCell<CBuffer>>,
+    pub(crate) inner: Arc<CBuffer>,
+    pub(crate) limiter: Option<TokenBucket>,
+    #[cfg(feature = "async")]
+    pub(crate) pending: Option<bytes::Bytes>,
+}
+
+/// Pops take `&self`, so a `Receiver` is `Send` but not `Sync`: the single-consumer
+/// fast path relies on no other thread popping through the same handle.
+pub struct Receiver {
+    pub(crate) inner: Arc<CBuffer>,
+    _unsync: PhantomData<Cell<()>>,
+}
+
+pub fn channel(s: BufferSize) -> (Sender, Receiver) {
+    let a = Arc::new(CBuffer::with_capacity(s).expect("fail to create cbuffer."));
+    (Sender::new(a.clone()), Receiver::new(a))
+}
+
+/// Like `channel`, but the ring uses the multi-producer/multi-consumer protocol from the
+/// start instead of switching to it when a handle is cloned. Meant for handles that are
+/// cloned and dropped all the time, where flipping back and forth buys nothing.
+pub fn channel_mpmc(s: BufferSize) -> (Sender, Receiver) {
+    ChannelBuilder::new().capacity(s).mpmc(true).build().expect("fail to create cbuffer.")
+}
+
+/// Like `channel`, but a push into a full ring drops the oldest elements to make room
+/// instead of failing, so the receiver always sees the most recent data.
+pub fn channel_overwrite(s: BufferSize) -> (Sender, Receiver) {
+    channel_with_policy(s, FullPolicy::OverwriteOldest)
+}
+
+/// Like `channel`, with pushes into a full ring handled according to `policy`.
+pub fn channel_with_policy(s: BufferSize, policy: FullPolicy) -> (Sender, Receiver) {
+    ChannelBuilder::new().capacity(s).policy(policy).build().expect("fail to create cbuffer.")
+}
+
+/// Like `channel`, with the length in front of every element encoded as `format`.
+pub fn channel_with_format(s: BufferSize, format: LengthPrefix) -> (Sender, Receiver) {
+    ChannelBuilder::new().capacity(s).prefix(format).build().expect("fail to create cbuffer.")
+}
+
+/// Like `channel`, with the ring kept in `backend`.
+pub fn channel_with_backend(s: BufferSize, backend: MemoryBackend) -> (Sender, Receiver) {
+    ChannelBuilder::new().capacity(s).backend(backend).build().expect("fail to create cbuffer.")
+}
+
+/// Like `channel`, with the ring kept in the caller's `memory`: the largest power of two
+/// that fits twice holds the data, followed by its mirror. Panics if `memory` is too small
+/// to hold anything.
+pub fn channel_with_memory(memory: &'static mut [u8]) -> (Sender, Receiver) {
+    let a = Arc::new(CBuffer::with_memory(memory).expect("fail to create cbuffer."));
+    (Sender::new(a.clone()), Receiver::new(a))
+}
+
+/// Creates a channel in the memory object behind `fd` (e.g. an existing shm segment or a
+/// DAX file), laid out as a page of header followed by `capacity` bytes, a power of two
+/// multiple of the page size. If the header already holds a ring, both halves join it instead, like
+/// `Sender::attach` and `Receiver::attach`. `fd` stays the caller's.
+#[cfg(unix)]
+pub fn channel_in_fd(fd: BorrowedFd<'_>, capacity: usize) -> io::Result<(Sender, Receiver)> {
+    Ok(join_both(CBuffer::from_fd(fd, capacity)?))
+}
+
+/// Like `channel_in_fd`, for the `page_size() + 2 * capacity` bytes at `ptr`, e.g. memory
+/// shared with a component that is not written in Rust. The second half mirrors the
+/// first, which is kept up to date by copying since the memory cannot be mapped twice.
+///
+/// # Safety
+///
+/// The memory has to be valid for reads and writes and aligned to a page for as long as
+/// either half exists, and only be touched through channels made from it.
+#[cfg(unix)]
+pub unsafe fn channel_from_raw_parts(ptr: *mut u8, capacity: usize) -> io::Result<(Sender, Receiver)> {
+    Ok(join_both(CBuffer::from_raw_parts(ptr, capacity)?))
+}
+
+#[cfg(unix)]
+pub(crate) fn join_both(b: CBuffer) -> (Sender, Receiver) {
+    b.join_senders();
+    b.join_receivers();
+    let a = Arc::new(b);
+    (Sender::new(a.clone()), Receiver::new(a))
+}
+
+/// Creates a channel in the shared memory object `name` (e.g. `"/my-ring"`), which other
+/// processes join with `Sender::attach` or `Receiver::attach`. The object is unlinked once
+/// both halves returned here are dropped; a half dropped before its peer attached leaves
+/// the channel disconnected, so keep both until then. Eventfd and async wakeups only see
+/// activity from within the same process.
+#[cfg(unix)]
+pub fn channel_shared(name: &str, s: BufferSize) -> io::Result<(Sender, Receiver)> {
+    ChannelBuilder::new().capacity(s).build_shared(name)
+}
+
+/// Collects the options of a channel before creating it, for combinations the `channel_*`
+/// functions do not cover. Starts out with what `channel(BufferSize::Buf64M)` creates.
+#[derive(Clone, Debug)]
+pub struct ChannelBuilder {
+    size: BufferSize,
+    backend: MemoryBackend,
+    policy: FullPolicy,
+    format: LengthPrefix,
+    align: usize,
+    mpmc: bool,
+    max_message_size: usize,
+    wait_strategy: WaitStrategy,
+    credits: Option<Credits>,
+    name: Option<String>,
+    #[cfg(target_os = "linux")]
+    numa_node: Option<usize>,
+    #[cfg(unix)]
+    advice: Vec<Advice>,
+    prefault: bool,
+}
+
+impl Default for ChannelBuilder {
+    fn default() -> ChannelBuilder {
+        ChannelBuilder::new()
+    }
+}
+
+impl ChannelBuilder {
+    pub fn new() -> ChannelBuilder {
+        ChannelBuilder {
+            size: BufferSize::Buf64M,
+            backend: default_backend(),
+            policy: FullPolicy::Block,
+            format: LengthPrefix::U32,
+            align: 1,
+            mpmc: false,
+            max_message_size: usize::MAX,
+            wait_strategy: WaitStrategy::Park,
+            credits: None,
+            name: None,
+            #[cfg(target_os = "linux")]
+            numa_node: None,
+            #[cfg(unix)]
+            advice: Vec::new(),
+            prefault: false,
+        }
+    }
+
+    pub fn capacity(mut self, s: BufferSize) -> ChannelBuilder {
+        self.size = s;
+        self
+    }
+
+    /// Like `capacity(BufferSize::Custom(bytes))`, rounding `bytes` up to a power of two.
+    pub fn capacity_bytes(self, bytes: usize) -> ChannelBuilder {
+        self.capacity(BufferSize::Custom(bytes))
+    }
+
+    /// Where the ring's bytes live; does not apply to `build_shared`.
+    pub fn backend(mut self, backend: MemoryBackend) -> ChannelBuilder {
+        self.backend = backend;
+        self
+    }
+
+    pub fn policy(mut self, policy: FullPolicy) -> ChannelBuilder {
+        self.policy = policy;
+        self
+    }
+
+    /// How the length in front of every element is encoded. Panics on
+    /// `LengthPrefix::Fixed` sizes of zero or of 2 GiB and more.
+    pub fn prefix(mut self, format: LengthPrefix) -> ChannelBuilder {
+        assert!(format.is_valid(), "fixed-size records must be between 1 byte and 2 GiB");
+        self.format = format;
+        self
+    }
+
+    /// Starts every payload at a multiple of `align` bytes in the ring, e.g. to cast it to
+    /// a `#[repr(C)]` struct or load it into SIMD registers in place. Frames are padded
+    /// after the prefix and at the end. Panics unless `align` is a power of two of at most
+    /// 4096.
+    pub fn payload_align(mut self, align: usize) -> ChannelBuilder {
+        assert!(align.is_power_of_two() && align <= MAX_ALIGN, "payload alignment must be a power of two up to 4096");
+        self.align = align;
+        self
+    }
+
+    /// Like `channel_mpmc`; shared rings always are.
+    pub fn mpmc(mut self, mpmc: bool) -> ChannelBuilder {
+        self.mpmc = mpmc;
+        self
+    }
+
+    /// Like `Sender::set_max_message_size`.
+    pub fn max_message_size(mut self, max: usize) -> ChannelBuilder {
+        self.max_message_size = max;
+        self
+    }
+
+    /// How the returned handles wait in blocking pushes and pops.
+    pub fn wait_strategy(mut self, strategy: WaitStrategy) -> ChannelBuilder {
+        self.wait_strategy = strategy;
+        self
+    }
+
+    /// Makes pushes wait for credits from `Receiver::grant`, on top of free space. Shared
+    /// rings record this in their header, so attaching senders spend credits too.
+    pub fn credits(mut self, credits: Credits) -> ChannelBuilder {
+        self.credits = Some(credits);
+        self
+    }
+
+    /// Like `Sender::set_name`.
+    pub fn name(mut self, name: &str) -> ChannelBuilder {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Binds the ring's pages to NUMA node `node`; see `pin_thread_to_node` for the
+    /// threads using it.
+    #[cfg(target_os = "linux")]
+    pub fn numa_node(mut self, node: usize) -> ChannelBuilder {
+        self.numa_node = Some(node);
+        self
+    }
+
+    /// Passes `advice` on to the kernel for the ring's pages right after mapping them.
+    /// Can be given more than once. `build` fails with `Error::UnsupportedBackend` if the
+    /// backend is `Heap` or `Static`, and `build_in` if the memory is not mirrored.
+    #[cfg(unix)]
+    pub fn advise(mut self, advice: Advice) -> ChannelBuilder {
+        self.advice.push(advice);
+        self
+    }
+
+    /// Faults in every page of the ring up front, so that the first lap around it does not
+    /// take a page fault on every new page in the middle of the traffic.
+    pub fn prefault(mut self, prefault: bool) -> ChannelBuilder {
+        self.prefault = prefault;
+        self
+    }
+
+    pub fn build(self) -> Result<(Sender, Receiver), Error> {
+        let b = CBuffer::with_backend_aligned(self.size, self.backend, self.align)?;
+        self.place(&b).map_err(Error::from)?;
+        Ok(self.finish(b))
+    }
+
+    /// Like `build`, for a byte stream without any framing. The options about elements,
+    /// i.e. the length prefix, alignment, policy, credits and MPMC, do not apply to it.
+    pub fn build_stream(self) -> Result<(StreamSender, StreamReceiver), Error> {
+        let b = CBuffer::with_backend(self.size, self.backend)?;
+        self.place(&b).map_err(Error::from)?;
+        let a = Arc::new(self.configure(b));
+        Ok((StreamSender::new(a.clone()), StreamReceiver::new(a)))
+    }
+
+    /// Like `build`, with the ring in `memory` rather than memory of the size and backend
+    /// set here. Fails with `Error::UnalignedMemory` unless `memory` is aligned to 8 bytes
+    /// and to `payload_align`.
+    pub fn build_in<M: RingMemory>(self, memory: M) -> Result<(Sender, Receiver), Error> {
+        let b = CBuffer::with_ring_memory_aligned(Box::new(memory), self.align)?;
+        self.place(&b).map_err(Error::from)?;
+        Ok(self.finish(b))
+    }
+
+    /// Like `channel_shared`. The length prefix goes into the ring's header, so attaching
+    /// handles use it too; the other options only apply to the two handles returned here.
+    #[cfg(unix)]
+    pub fn build_shared(self, name: &str) -> io::Result<(Sender, Receiver)> {
+        let b = CBuffer::create_shared(name, self.size, self.framing(), self.credits)?;
+        self.place(&b)?;
+        Ok(self.finish(b))
+    }
+
+    /// Like `build_shared`, but the ring has no name: other processes join it through its
+    /// descriptor, passed e.g. with `Sender::send_over`. It goes away with the last handle.
+    /// On Linux it is sealed against resizing, so the processes it is passed to cannot pull
+    /// the memory out from under each other.
+    #[cfg(unix)]
+    pub fn build_anonymous(self) -> io::Result<(Sender, Receiver)> {
+        let b = CBuffer::create_anonymous(self.size, self.framing(), self.credits)?;
+        self.place(&b)?;
+        Ok(self.finish(b))
+    }
+
+    /// Applies the options about the ring's pages, before anything touches them.
+    fn place(&self, _b: &CBuffer) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(node) = self.numa_node {
+            _b.bind_to_node(node)?;
+        }
+        #[cfg(unix)]
+        for &advice in &self.advice {
+            _b.advise(advice)?;
+        }
+        // After binding, so that the pages come from the right node.
+        if self.prefault {
+            _b.prefault();
+        }
+        Ok(())
+    }
+
+    /// Creates a channel holding the elements `Receiver::snapshot` saved to `path`, with
+    /// the length prefix they were saved in. The ring has to be larger than they are.
+    pub fn restore<P: AsRef<Path>>(mut self, path: P) -> io::Result<(Sender, Receiver)> {
+        let (framing, frames) = read_snapshot(path.as_ref())?;
+        self.format = framing.prefix;
+        self.align = framing.align;
+        let b = CBuffer::with_backend_aligned(self.size, self.backend, self.align)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        self.place(&b)?;
+        let (sender, receiver) = self.finish(b);
+        sender.inner.restore(&frames)?;
+        Ok((sender, receiver))
+    }
+
+    fn framing(&self) -> Framing {
+        Framing { prefix: self.format, align: self.align }
+    }
+
+    fn finish(self, b: CBuffer) -> (Sender, Receiver) {
+        let a = Arc::new(self.configure(b));
+        (Sender::new(a.clone()), Receiver::new(a))
+    }
+
+    fn configure(self, mut b: CBuffer) -> CBuffer {
+        b.mpmc |= self.mpmc;
+        b.policy = self.policy;
+        b.format = self.framing();
+        *b.max_message_size.get_mut() = self.max_message_size;
+        b.readable.strategy = self.wait_strategy;
+        b.writable.strategy = self.wait_strategy;
+        if let (Some(credits), None) = (self.credits, &b.shared) {
+            b.use_credits(credits);
+        }
+        if let Some(name) = self.name {
+            // A shared ring comes named after its memory object.
+            b.name = OnceLock::new();
+            let _ = b.name.set(name.into());
+        }
+        b
+    }
+}
+
+impl Sender {
+    /// Joins the shared channel `name` created by `channel_shared` as another sender.
+    #[cfg(unix)]
+    pub fn attach(name: &str) -> io::Result<Sender> {
+        CBuffer::attach_shared(name).map(Sender::join)
+    }
+
+    /// Joins the shared channel whose memory object `fd` refers to, as another sender.
+    #[cfg(unix)]
+    pub fn from_ring_fd(fd: OwnedFd) -> io::Result<Sender> {
+        CBuffer::attach_fd(fd.into_raw_fd(), None).map(Sender::join)
+    }
+
+    /// Waits for a shared channel sent over `socket` with `send_over`, and joins it as
+    /// another sender.
+    #[cfg(unix)]
+    pub fn receive_from(socket: &UnixStream) -> io::Result<Sender> {
+        Sender::from_ring_fd(fdpass::recv_fd(socket)?)
+    }
+
+    /// Descriptor of a shared channel's memory object, which another process can join
+    /// with `from_ring_fd`. `None` for channels within one process.
+    #[cfg(unix)]
+    pub fn ring_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.inner.shared_fd().map(|fd| unsafe { BorrowedFd::borrow_raw(fd) })
+    }
+
+    /// Sends `ring_fd` over `socket`, for `Sender::receive_from` or
+    /// `Receiver::receive_from` in another process.
+    #[cfg(unix)]
+    pub fn send_over(&self, socket: &UnixStream) -> io::Result<()> {
+        self.inner.send_over(socket)
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn join(b: CBuffer) -> Sender {
+        b.join_senders();
+        Sender::new(Arc::new(b))
+    }
+
+    fn new(inner: Arc<CBuffer>) -> Sender {
+        Sender {
+            inner,
+            limiter: None,
+            #[cfg(feature = "async")]
+            pending: None,
+        }
+    }
+
+    /// Number of elements in the channel.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes the ring has room for right now. Every element takes its length prefix on
+    /// top of its payload, so less payload than this fits.
+    pub fn remaining_bytes(&self) -> usize {
+        self.inner.free()
+    }
+
+    /// Whether an element of `len` bytes fits into the ring right now, is within the
+    /// maximum message size, has the credits it needs, and has a receiver to go to. The
+    /// rate limit is not taken into account.
+    pub fn can_push(&self, len: usize) -> bool {
+        !self.inner.too_large(len) && self.inner.fits(len) && self.inner.has_credits(1, len)
+            && !self.inner.is_receiver_dropped()
+    }
+
+    /// Number of pushes discarded so far under `FullPolicy::DropNewest`.
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped()
+    }
+
+    /// Number of elements evicted so far under `FullPolicy::OverwriteOldest`.
+    pub fn overwritten(&self) -> u64 {
+        self.inner.overwritten()
+    }
+
+    /// Totals for the channel as a whole, shared by all of its handles.
+    pub fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+
+    /// The channel's latest pushes and pops, oldest first, e.g. to print when a test
+    /// fails on elements going missing.
+    #[cfg(feature = "trace")]
+    pub fn trace(&self) -> Vec<TraceEvent> {
+        self.inner.trace()
+    }
+
+    /// Pushes `elem` if it fits right now. Under a rate limit, a push that would exceed
+    /// it fails with `PushError::Full` as well.
+    pub fn try_push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        if !self.throttle(1, elem.len(), Some(Instant::now())) {
+            return Err(PushError::Full);
+        }
+        let r = self.inner.push(elem);
+        self.settle(1, elem.len(), r)
+    }
+
+    /// Like `try_push`, with the element gathered from `bufs`, e.g. a header and a payload
+    /// kept apart, without concatenating them first.
+    pub fn try_push_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<(), PushError> {
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        if !self.throttle(1, len, Some(Instant::now())) {
+            return Err(PushError::Full);
+        }
+        let r = self.inner.push_vectored(bufs);
+        self.settle(1, len, r)
+    }
+
+    /// Like `push`, with the element gathered from `bufs`.
+    pub fn push_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<(), PushError> {
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        self.throttle(1, len, None);
+        let r = self.inner.push_vectored_blocking(bufs);
+        self.settle(1, len, r)
+    }
+
+    /// Starts a batch of elements that the receiver sees all at once when it commits, or
+    /// not at all if the transaction is dropped first.
+    pub fn transaction(&mut self) -> Transaction<'_> {
+        Transaction { buffer: &self.inner, limiter: &mut self.limiter, frames: Vec::new(), count: 0, bytes: 0 }
+    }
+
+    /// Pushes as many elements from `iter` as currently fit and returns how many that was.
+    /// The element that did not fit, if any, has been taken from `iter` but not pushed.
+    pub fn push_all<'a, I>(&mut self, iter: I) -> usize
+        where I: Iterator<Item = &'a [u8]>
+    {
+        if self.limiter.is_some() {
+            return iter.take_while(|elem| self.try_push(elem).is_ok()).count();
+        }
+        self.inner.push_all(iter)
+    }
+
+    /// Pushes `elem`, parking the calling thread until the receiver frees enough space
+    /// unless the channel's `FullPolicy` says otherwise, and until the rate limit allows.
+    pub fn push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        self.throttle(1, elem.len(), None);
+        let r = self.inner.push_blocking(elem);
+        self.settle(1, elem.len(), r)
+    }
+
+    /// Like `push`, but gives up once `timeout` has elapsed without enough space freeing up.
+    pub fn push_timeout(&mut self, elem: &[u8], timeout: Duration) -> Result<(), PushTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        if !self.throttle(1, elem.len(), Some(deadline)) {
+            return Err(PushTimeoutError::Timeout);
+        }
+        let r = self.inner.push_deadline(elem, deadline);
+        self.settle(1, elem.len(), r)
+    }
+
+    /// Pushes `elem` with an 8-byte `header` in front, for routing information that
+    /// should not have to be encoded into the element. Parks like `push`.
+    pub fn push_with_header(&mut self, header: u64, elem: &[u8]) -> Result<(), PushError> {
+        self.throttle(1, elem.len(), None);
+        let r = self.inner.push_with_header(header, elem);
+        self.settle(1, elem.len(), r)
+    }
+
+    /// Takes the rate limit's tokens for `count` elements of `bytes` bytes, sleeping for
+    /// them until `deadline` if there is one. Returns whether it got them.
+    pub(crate) fn throttle(&mut self, count: usize, bytes: usize, deadline: Option<Instant>) -> bool {
+        match &mut self.limiter {
+            Some(bucket) => bucket.wait_for(count, bytes, deadline),
+            None => true,
+        }
+    }
+
+    /// Gives back the tokens `throttle` took if the push failed after all.
+    pub(crate) fn settle<E>(&mut self, count: usize, bytes: usize, r: Result<(), E>) -> Result<(), E> {
+        if let (Err(_), Some(bucket)) = (&r, &mut self.limiter) {
+            bucket.refund(count, bytes);
+        }
+        r
+    }
+
+    /// Credits left to spend under `ChannelBuilder::credits`, or `None` without them.
+    pub fn credits(&self) -> Option<u64> {
+        self.inner.credits()
+    }
+
+    /// Whether a receiver is left. On a shared ring this also means one of the processes
+    /// holding receivers is still running; blocking pushes fail with
+    /// `PushError::Disconnected` soon after the last of them dies.
+    pub fn peer_alive(&self) -> bool {
+        self.inner.is_receiver_alive()
+    }
+
+    /// When the receivers' side of a shared ring last joined, waited, or called
+    /// `Receiver::heartbeat`. `None` for channels within one process.
+    pub fn peer_heartbeat(&self) -> Option<SystemTime> {
+        self.inner.receiver_last_seen()
+    }
+
+    /// Tells the receivers of a shared ring that this side is still alive, e.g. while it
+    /// is busy with something other than pushing.
+    pub fn heartbeat(&self) {
+        self.inner.sender_heartbeat()
+    }
+
+    /// Holds this sender to `limit`, or lifts its limit for `None`. Other senders of the
+    /// channel keep their own; clones start out without one.
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.limiter = limit.map(TokenBucket::new);
+    }
+
+    /// Ends the stream for every sender: once the receiver has drained what was pushed
+    /// before, its pops report `PopError::Closed`. Parks until the marker fits; rings of
+    /// `LengthPrefix::Fixed` records have no marker and only flag the close.
+    pub fn close(&mut self) -> Result<(), PushError> {
+        self.inner.close()
+    }
+
+    /// Calls `callback` with `Watermark::High` once the ring holds at least `high` bytes,
+    /// and with `Watermark::Low` once it is back down to `low`, on whichever thread moved
+    /// it there. A channel keeps the first watermarks it gets; returns false otherwise.
+    pub fn set_watermarks<F>(&mut self, high: usize, low: usize, callback: F) -> bool
+        where F: Fn(Watermark) + Send + Sync + 'static
+    {
+        assert!(low < high, "low watermark must be below the high one");
+        let watermarks = Watermarks { high, low, above: AtomicBool::new(false), callback: Box::new(callback) };
+        self.inner.watermarks.set(watermarks).is_ok()
+    }
+
+    /// Makes pushes of elements longer than `max` bytes fail with
+    /// `PushError::MessageTooLarge`, for every sender in this process.
+    pub fn set_max_message_size(&mut self, max: usize) {
+        self.inner.max_message_size.store(max, Ordering::Relaxed);
+    }
+
+    /// Largest element a push accepts, taking the capacity into account.
+    pub fn max_message_size(&self) -> usize {
+        self.inner.max_message_size()
+    }
+
+    /// Names the channel in its tracing events. A channel keeps the first name it gets;
+    /// returns false otherwise.
+    pub fn set_name(&mut self, name: &str) -> bool {
+        self.inner.name.set(name.into()).is_ok()
+    }
 }
 
-unsafe impl Send for Sender {}
+impl Receiver {
+    /// Joins the shared channel `name` created by `channel_shared` as another receiver.
+    /// The data is mapped read-only in this process, so a bug here cannot corrupt it.
+    /// If the receivers' process died, this one takes over where it left off, and the
+    /// elements it was in the middle of popping come out again.
+    #[cfg(unix)]
+    pub fn attach(name: &str) -> io::Result<Receiver> {
+        CBuffer::attach_shared(name).and_then(Receiver::join)
+    }
+
+    /// Like `Sender::from_ring_fd`.
+    #[cfg(unix)]
+    pub fn from_ring_fd(fd: OwnedFd) -> io::Result<Receiver> {
+        CBuffer::attach_fd(fd.into_raw_fd(), None).and_then(Receiver::join)
+    }
+
+    /// Like `Sender::receive_from`.
+    #[cfg(unix)]
+    pub fn receive_from(socket: &UnixStream) -> io::Result<Receiver> {
+        Receiver::from_ring_fd(fdpass::recv_fd(socket)?)
+    }
+
+    /// Like `Sender::ring_fd`.
+    #[cfg(unix)]
+    pub fn ring_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.inner.shared_fd().map(|fd| unsafe { BorrowedFd::borrow_raw(fd) })
+    }
+
+    /// Like `Sender::send_over`.
+    #[cfg(unix)]
+    pub fn send_over(&self, socket: &UnixStream) -> io::Result<()> {
+        self.inner.send_over(socket)
+    }
+
+    /// The ring's data is only mapped for reading here, since nothing but senders writes
+    /// to it.
+    #[cfg(unix)]
+    pub(crate) fn join(b: CBuffer) -> io::Result<Receiver> {
+        b.protect_data()?;
+        b.join_receivers();
+        Ok(Receiver::new(Arc::new(b)))
+    }
+
+    fn new(inner: Arc<CBuffer>) -> Receiver {
+        Receiver { inner, _unsync: PhantomData }
+    }
+
+    /// Pops one element if there is one. Fails with `PopError::Disconnected` once the
+    /// ring is empty and every sender has been dropped.
+    pub fn try_pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        self.inner.pop(consumer)
+    }
+
+    /// Pops one element pushed with `Sender::push_with_header`, handing its header and
+    /// the element to `consumer`. Parks like `pop`.
+    pub fn pop_with_header<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u64, &[u8])
+    {
+        self.inner.pop_with_header(consumer)
+    }
+
+    /// Like `pop_with_header`, without waiting for an element.
+    pub fn try_pop_with_header<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u64, &[u8])
+    {
+        self.inner.try_pop_with_header(consumer)
+    }
+
+    /// Pops elements pushed with `Sender::push_with_header` until one has a header
+    /// `filter` accepts, and hands that one to `consumer`. The ones before it are dealt
+    /// with as `unmatched` says, without being shown to `consumer`. Parks like `pop`.
+    pub fn pop_matching<P, F>(&self, filter: P, unmatched: Unmatched<'_>, consumer: F) -> Result<(), PopError>
+        where P: FnMut(u64) -> bool,
+              F: FnOnce(u64, &[u8])
+    {
+        self.pop_matching_with(true, filter, unmatched, consumer)
+    }
+
+    /// Like `pop_matching`, without waiting for an element. Fails with `PopError::Empty`
+    /// once it has passed over every element queued when it was called.
+    pub fn try_pop_matching<P, F>(&self, filter: P, unmatched: Unmatched<'_>, consumer: F) -> Result<(), PopError>
+        where P: FnMut(u64) -> bool,
+              F: FnOnce(u64, &[u8])
+    {
+        self.pop_matching_with(false, filter, unmatched, consumer)
+    }
+
+    fn pop_matching_with<P, F>(&self, blocking: bool, mut filter: P, mut unmatched: Unmatched<'_>, consumer: F)
+        -> Result<(), PopError>
+        where P: FnMut(u64) -> bool,
+              F: FnOnce(u64, &[u8])
+    {
+        let mut consumer = Some(consumer);
+        loop {
+            // Elements requeued into this very ring come back around, so a pass only goes
+            // through the ones queued when it starts.
+            for _ in 0..self.len().max(1) {
+                let mut requeue = None;
+                let deliver = |header, bytes: &[u8]| {
+                    if filter(header) {
+                        (consumer.take().expect("element already delivered"))(header, bytes);
+                    } else if let Unmatched::Requeue(_) = unmatched {
+                        requeue = Some((header, bytes.to_vec()));
+                    }
+                };
+                if blocking {
+                    self.inner.pop_with_header(deliver)?;
+                } else {
+                    self.inner.try_pop_with_header(deliver)?;
+                }
+                // Pushed only once the element is popped, which frees the room it needs when
+                // it goes back into this very ring.
+                if let (Some((header, bytes)), Unmatched::Requeue(sender)) = (requeue, &mut unmatched) {
+                    let _ = sender.push_with_header(header, &bytes);
+                }
+                if consumer.is_none() {
+                    return Ok(());
+                }
+            }
+            if !blocking {
+                return Err(PopError::Empty);
+            }
+            // Whatever is left got requeued; only a push from elsewhere can bring a match.
+            let tail = self.inner.tail();
+            while self.inner.tail() == tail && !self.inner.is_sender_dropped() {
+                self.inner.wait_readable(|| self.inner.tail() != tail || self.inner.is_sender_dropped(), None);
+            }
+        }
+    }
+
+    /// Shows the oldest element to `consumer` without popping it. Competing receivers
+    /// wait for the call to return before they take anything.
+    pub fn peek<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(&[u8])
+    {
+        self.inner.peek(consumer)
+    }
+
+    /// Borrows the oldest element in place and leaves it in the channel when the guard is
+    /// dropped. Competing receivers wait until then.
+    pub fn peek_ref(&mut self) -> Option<PeekGuard<'_>> {
+        let buffer = &*self.inner;
+        buffer.peek_frame().ok().map(|(head, len)| PeekGuard { buffer, head, len })
+    }
+
+    /// Borrows the oldest element in place, parking until there is one. It is only popped
+    /// once the guard commits; dropping the guard leaves it for the next pop, e.g. when
+    /// handling it failed. Competing receivers wait until then.
+    pub fn begin_pop(&mut self) -> Result<PendingPop<'_>, PopError> {
+        let buffer = &*self.inner;
+        buffer.peek_frame_blocking().map(|(head, len)| PendingPop { buffer, head, len })
+    }
+
+    /// Like `begin_pop`, without waiting for an element.
+    pub fn try_begin_pop(&mut self) -> Result<PendingPop<'_>, PopError> {
+        let buffer = &*self.inner;
+        buffer.peek_frame().map(|(head, len)| PendingPop { buffer, head, len })
+    }
+
+    /// Borrows the oldest element in place; it is consumed when the guard is dropped.
+    pub fn recv_ref(&mut self) -> Option<RecvGuard<'_>> {
+        self.try_recv().ok()
+    }
+
+    /// Like `recv_ref`, but says why there was nothing to borrow, so callers can use `?`
+    /// and `match` where `try_pop` needs a closure.
+    pub fn try_recv(&mut self) -> Result<RecvGuard<'_>, PopError> {
+        let buffer = &*self.inner;
+        buffer.take_frame().map(|(head, len)| RecvGuard { buffer, head, len })
+    }
+
+    /// Like `try_recv`, but parks until there is an element to borrow.
+    pub fn recv(&mut self) -> Result<RecvGuard<'_>, PopError> {
+        let buffer = &*self.inner;
+        buffer.take_frame_blocking().map(|(head, len)| RecvGuard { buffer, head, len })
+    }
+
+    /// Hands up to `max` elements to `consumer`, returning how many were popped.
+    pub fn pop_batch<F>(&self, max: usize, consumer: F) -> usize
+        where F: FnMut(&[u8])
+    {
+        self.inner.pop_batch(max, consumer)
+    }
+
+    /// Moves up to `max` elements onto the end of `buf`, parking until there is at least
+    /// one, and returns how many that was. Returns 0 only if `max` is 0 or nothing more
+    /// will arrive.
+    pub fn recv_many(&self, buf: &mut Vec<Vec<u8>>, max: usize) -> usize {
+        if max == 0 || self.pop(|bytes| buf.push(bytes.to_vec())).is_err() {
+            return 0;
+        }
+        1 + self.pop_batch(max - 1, |bytes| buf.push(bytes.to_vec()))
+    }
+
+    /// Whether a sender is left; see `Sender::peer_alive`. Once every process holding
+    /// senders of a shared ring has died, blocking pops fail with `PopError::Disconnected` after draining
+    /// the ring rather than waiting forever.
+    pub fn peer_alive(&self) -> bool {
+        self.inner.is_sender_alive()
+    }
+
+    /// Like `Sender::peer_heartbeat`, for the senders' side.
+    pub fn peer_heartbeat(&self) -> Option<SystemTime> {
+        self.inner.sender_last_seen()
+    }
+
+    /// Like `Sender::heartbeat`.
+    pub fn heartbeat(&self) {
+        self.inner.receiver_heartbeat()
+    }
+
+    /// Lets the senders push `n` more credits' worth under `ChannelBuilder::credits`,
+    /// waking them if they ran out. Does nothing for channels without credits.
+    pub fn grant(&self, n: u64) {
+        if self.inner.credits().is_some() {
+            self.inner.grant(n);
+        }
+    }
+
+    /// Pops elements for as long as `predicate` accepts them, leaving the first one it
+    /// rejects in the channel, and returns how many were popped. Competing receivers wait
+    /// until it returns.
+    pub fn pop_while<F>(&self, predicate: F) -> usize
+        where F: FnMut(&[u8]) -> bool
+    {
+        self.inner.pop_while(predicate)
+    }
+
+    /// Pops one element, parking the calling thread until the sender pushes one.
+    pub fn pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        self.inner.pop_blocking(consumer)
+    }
+
+    /// Like `pop`, but gives up once `timeout` has elapsed without an element arriving.
+    pub fn pop_timeout<F>(&self, timeout: Duration, consumer: F) -> Result<(), PopTimeoutError>
+        where F: FnMut(&[u8])
+    {
+        self.inner.pop_deadline(Instant::now() + timeout, consumer)
+    }
+
+    /// Number of elements waiting to be popped.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes waiting to be popped, length prefixes included.
+    pub fn available_bytes(&self) -> usize {
+        self.inner.used()
+    }
+
+    /// Reports every change of the channel's `Occupancy` from now on, counting it as
+    /// `Occupancy::High` from `high` bytes on. Pushes and pops only pay for a lock when the
+    /// level changes. A channel has one such stream; returns `None` after the first.
+    pub fn occupancy_events(&self, high: usize) -> Option<OccupancyEvents> {
+        self.inner.occupancy_events(high)
+    }
+
+    /// Like `Sender::dropped`.
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped()
+    }
+
+    /// Like `Sender::overwritten`.
+    pub fn overwritten(&self) -> u64 {
+        self.inner.overwritten()
+    }
+
+    /// Like `Sender::stats`.
+    pub fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+
+    /// Like `Sender::trace`.
+    #[cfg(feature = "trace")]
+    pub fn trace(&self) -> Vec<TraceEvent> {
+        self.inner.trace()
+    }
+
+    /// Pops one element into a fresh `Vec`, for when it has to outlive the ring's memory.
+    /// Returns `None` when there is nothing to pop right now.
+    pub fn pop_owned(&self) -> Option<Vec<u8>> {
+        let mut elem = None;
+        self.try_pop(|bytes| elem = Some(bytes.to_vec())).ok()?;
+        elem
+    }
+
+    /// Copies the oldest element into the front of `buf` and returns its length, without
+    /// waiting for one.
+    pub fn read_into(&self, buf: &mut [u8]) -> Result<usize, PopError> {
+        self.inner.read_into(buf)
+    }
+
+    /// Saves every queued element to the file at `path` without popping any, while
+    /// competing receivers wait. Returns how many elements were saved;
+    /// `ChannelBuilder::restore` loads them into a new channel.
+    pub fn snapshot<P: AsRef<Path>>(&self, path: P) -> io::Result<usize> {
+        let (frames, count) = self.inner.snapshot();
+        let mut file = File::create(path)?;
+        file.write_all(&SNAPSHOT_MAGIC)?;
+        file.write_all(&self.inner.format.prefix.code().to_le_bytes())?;
+        file.write_all(&(self.inner.format.align as u32).to_le_bytes())?;
+        file.write_all(&(frames.len() as u64).to_le_bytes())?;
+        file.write_all(&frames)?;
+        file.sync_all()?;
+        Ok(count)
+    }
+
+    /// Like `pop_owned`, as `Bytes`.
+    #[cfg(feature = "bytes")]
+    pub fn pop_bytes(&self) -> Option<bytes::Bytes> {
+        let mut elem = None;
+        self.try_pop(|bytes| elem = Some(bytes::Bytes::copy_from_slice(bytes))).ok()?;
+        elem
+    }
+
+    /// Iterates over copies of the elements already in the channel, without waiting.
+    pub fn try_iter(&self) -> TryIter<'_> {
+        TryIter { receiver: self }
+    }
+
+    /// Iterates over copies of the elements, parking between them, until the channel is
+    /// disconnected or closed.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { receiver: self }
+    }
+}
+
+/// Returned by `Receiver::try_iter`.
+pub struct TryIter<'a> {
+    receiver: &'a Receiver,
+}
+
+impl<'a> Iterator for TryIter<'a> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        self.receiver.pop_owned()
+    }
+}
+
+/// Returned by `Receiver::iter`.
+pub struct Iter<'a> {
+    receiver: &'a Receiver,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let mut elem = None;
+        self.receiver.pop(|bytes| elem = Some(bytes.to_vec())).ok()?;
+        elem
+    }
+}
+
+impl<'a> IntoIterator for &'a Receiver {
+    type Item = Vec<u8>;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// What `Receiver::pop_matching` does with the elements whose header it passes over.
+pub enum Unmatched<'a> {
+    /// Consumes them unseen.
+    Discard,
+    /// Pushes them, header and all, through this sender, e.g. a clone of the channel's
+    /// own to put them at the back of the queue. Parks while that channel is full; if it
+    /// does not take them at all, say because it is closed, they are dropped.
+    Requeue(&'a mut Sender),
+}
+
+/// An element still sitting in the ring, handed out by `Receiver::recv_ref`.
+pub struct RecvGuard<'a> {
+    buffer: &'a CBuffer,
+    head: u64,
+    len: usize,
+}
+
+impl<'a> ops::Deref for RecvGuard<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.buffer.payload(self.head, self.len)
+    }
+}
+
+impl<'a> RecvGuard<'a> {
+    /// The element, borrowed for as long as the ring itself rather than the guard.
+    pub(crate) fn bytes(&self) -> &'a [u8] {
+        self.buffer.payload(self.head, self.len)
+    }
+
+    /// The element as a `T`, if it is exactly that large and suitably aligned; see
+    /// `ChannelBuilder::payload_align`.
+    #[cfg(feature = "zerocopy")]
+    pub fn as_pod<T: bytemuck::Pod>(&self) -> Result<&T, CastError> {
+        cast(self)
+    }
+}
+
+impl<'a> Drop for RecvGuard<'a> {
+    fn drop(&mut self) {
+        self.buffer.finish(self.head, self.buffer.next(self.head, self.len), 1);
+    }
+}
+
+/// The oldest element of a channel, borrowed by `Receiver::begin_pop` until it is either
+/// committed or handed back.
+pub struct PendingPop<'a> {
+    buffer: &'a CBuffer,
+    head: u64,
+    len: usize,
+}
+
+impl<'a> ops::Deref for PendingPop<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.buffer.payload(self.head, self.len)
+    }
+}
+
+impl<'a> PendingPop<'a> {
+    /// Pops the element.
+    pub fn commit(self) {
+        self.buffer.consume_peeked(self.head, self.len);
+        mem::forget(self);
+    }
+
+    /// Leaves the element in the channel, to be delivered again. Same as dropping the
+    /// guard.
+    pub fn abort(self) {}
+
+    /// Like `RecvGuard::as_pod`.
+    #[cfg(feature = "zerocopy")]
+    pub fn as_pod<T: bytemuck::Pod>(&self) -> Result<&T, CastError> {
+        cast(self)
+    }
+}
+
+impl<'a> Drop for PendingPop<'a> {
+    fn drop(&mut self) {
+        self.buffer.unpeek(self.head);
+    }
+}
+
+/// Elements staged by `Sender::transaction`, published together by `commit`. Dropping
+/// it discards them.
+pub struct Transaction<'a> {
+    pub(crate) buffer: &'a CBuffer,
+    pub(crate) limiter: &'a mut Option<TokenBucket>,
+    /// Staged elements, framed as they will be in the ring.
+    pub(crate) frames: Vec<u8>,
+    pub(crate) count: usize,
+    pub(crate) bytes: usize,
+}
+
+impl<'a> Transaction<'a> {
+    /// Stages `elem`. Fails with `PushError::MessageTooLarge` if the transaction as a whole
+    /// would no longer fit into the empty ring, leaving what was staged before.
+    pub fn push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        if self.buffer.too_large(elem.len())
+            || self.frames.len() + self.buffer.format.frame_size(elem.len()) >= self.buffer.capacity {
+            return Err(PushError::MessageTooLarge);
+        }
+        let format = self.buffer.format;
+        let start = self.frames.len();
+        let mut buf = [0u8; MAX_PREFIX];
+        let width = format.prefix.encode(elem.len() as u32, &mut buf);
+        self.frames.extend_from_slice(&buf[..width]);
+        self.frames.resize(start + format.width(elem.len()), 0);
+        self.frames.extend_from_slice(elem);
+        self.frames.resize(start + format.frame_size(elem.len()), 0);
+        self.count += 1;
+        self.bytes += elem.len();
+        Ok(())
+    }
+
+    /// Number of elements staged.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Publishes the staged elements if they all fit right now, and the sender's rate
+    /// limit allows them. On failure they stay staged, so the commit can be retried.
+    pub fn try_commit(&mut self) -> Result<(), PushError> {
+        if let Some(bucket) = self.limiter.as_mut() {
+            if !bucket.wait_for(self.count, self.bytes, Some(Instant::now())) {
+                return Err(PushError::Full);
+            }
+        }
+        let r = self.push_staged();
+        self.settle(r)
+    }
+
+    /// Publishes the staged elements, parking until they all fit unless the channel's
+    /// `FullPolicy` says otherwise.
+    pub fn commit(mut self) -> Result<(), PushError> {
+        self.publish()
+    }
+
+    /// `commit` that leaves the elements staged if it fails.
+    pub(crate) fn publish(&mut self) -> Result<(), PushError> {
+        let buffer = self.buffer;
+        if let Some(bucket) = self.limiter.as_mut() {
+            bucket.wait_for(self.count, self.bytes, None);
+        }
+        loop {
+            match self.push_staged() {
+                Err(PushError::Full) if buffer.policy != FullPolicy::Reject => {
+                    buffer.wait_writable(|| {
+                        buffer.has_room(self.frames.len()) && buffer.has_credits(self.count, self.bytes)
+                            || buffer.receiver_dropped.load(Ordering::Acquire)
+                    }, None)
+                }
+                r => return self.settle(r),
+            }
+        }
+    }
+
+    /// Pushes the staged elements if they fit right now, without touching the rate limit.
+    fn push_staged(&mut self) -> Result<(), PushError> {
+        let r = self.buffer.counted(self.buffer.push_frames(&self.frames, self.count, self.bytes));
+        if r.is_ok() {
+            self.frames.clear();
+            self.count = 0;
+            self.bytes = 0;
+        }
+        r
+    }
+
+    /// Gives back the rate limit's tokens for the staged elements if they were not
+    /// published after all.
+    fn settle(&mut self, r: Result<(), PushError>) -> Result<(), PushError> {
+        if let (Err(_), Some(bucket)) = (&r, self.limiter.as_mut()) {
+            bucket.refund(self.count, self.bytes);
+        }
+        r
+    }
+}
+
+/// The oldest element of a channel, borrowed by `Receiver::peek_ref` without popping it.
+pub struct PeekGuard<'a> {
+    buffer: &'a CBuffer,
+    head: u64,
+    len: usize,
+}
+
+impl<'a> ops::Deref for PeekGuard<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.buffer.payload(self.head, self.len)
+    }
+}
+
+impl<'a> PeekGuard<'a> {
+    /// Like `RecvGuard::as_pod`.
+    #[cfg(feature = "zerocopy")]
+    pub fn as_pod<T: bytemuck::Pod>(&self) -> Result<&T, CastError> {
+        cast(self)
+    }
+}
+
+impl<'a> Drop for PeekGuard<'a> {
+    fn drop(&mut self) {
+        self.buffer.unpeek(self.head);
+    }
+}
+
+/// An eventfd that becomes readable whenever the receiver frees space. Reading it resets
+/// the notification; the ring itself still has to be polled with `try_push`.
+#[cfg(target_os = "linux")]
+impl AsRawFd for Sender {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.writable.event_fd()
+    }
+}
+
+/// An eventfd that becomes readable whenever the sender pushes. Reading it resets the
+/// notification; the ring itself still has to be drained with `try_pop`.
+#[cfg(target_os = "linux")]
+impl AsRawFd for Receiver {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.readable.event_fd()
+    }
+}
+
+/// Registers the eventfd behind `as_raw_fd`, so a `mio::Poll` reports the receiver
+/// readable whenever the sender pushes. Events are edge-triggered: drain the ring with
+/// `try_pop` until it is empty after each one.
+#[cfg(all(feature = "mio", target_os = "linux"))]
+impl mio::event::Source for Receiver {
+    fn register(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).deregister(registry)
+    }
+}
+
+/// Cloning a sender switches the ring to the multi-producer protocol until all but one
+/// of the senders have been dropped again.
+impl Clone for Sender {
+    fn clone(&self) -> Sender {
+        self.inner.senders.fetch_add(1, Ordering::AcqRel);
+        if self.inner.shared.is_some() {
+            self.inner.sender_peer.join();
+        }
+        Sender::new(self.inner.clone())
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        if self.inner.shared.is_some() {
+            self.inner.sender_peer.leave();
+        }
+        if self.inner.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.inner.disconnect_sender();
+        }
+    }
+}
+
+/// Cloned receivers compete for elements: each one is handed to exactly one of them.
+/// As with `Sender`, the ring only pays for the extra coordination while clones exist.
+impl Clone for Receiver {
+    fn clone(&self) -> Receiver {
+        self.inner.receivers.fetch_add(1, Ordering::AcqRel);
+        if self.inner.shared.is_some() {
+            self.inner.receiver_peer.join();
+        }
+        Receiver::new(self.inner.clone())
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        if self.inner.shared.is_some() {
+            self.inner.receiver_peer.leave();
+        }
+        if self.inner.receivers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.inner.disconnect_receiver();
+        }
+    }
+}
+
+
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    OS,
+    Overflow,
+    Underflow,
+    InvalidCapacity,
+    /// The capacity asked for exactly is not a power of two and a multiple of
+    /// `page_size()`, as the mirrored mappings and the cursor arithmetic need.
+    UnalignedCapacity,
+    /// The backend cannot do what was asked of it, e.g. set up a ring without memory from
+    /// the caller or take `Advice` for pages it does not own.
+    UnsupportedBackend,
+    /// Memory handed to `ChannelBuilder::build_in` is not aligned to 8 bytes and to the
+    /// payload alignment.
+    UnalignedMemory,
+}
+
+impl std::error::Error for Error {
+    fn description(&self) -> &str { "cbuffer error" }
+    fn cause(&self) -> Option<& dyn std::error::Error> { None }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            Error::OS => write!(f, "OS error"),
+            Error::Overflow => write!(f, "overflow"),
+            Error::Underflow => write!(f, "underflow"),
+            Error::InvalidCapacity => write!(f, "invalid capacity"),
+            Error::UnalignedCapacity => write!(f, "capacity is not a power of two multiple of the {} byte page size", page_size()),
+            Error::UnsupportedBackend => write!(f, "not supported by this backend"),
+            Error::UnalignedMemory => write!(f, "memory is not aligned for the ring"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PushTimeoutError {
+    Timeout,
+    MessageTooLarge,
+    Disconnected,
+    Closed,
+}
+
+impl std::error::Error for PushTimeoutError {}
+
+impl std::fmt::Display for PushTimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            PushTimeoutError::Timeout => write!(f, "timed out waiting for free space"),
+            PushTimeoutError::MessageTooLarge => write!(f, "message larger than buffer"),
+            PushTimeoutError::Disconnected => write!(f, "receiver disconnected"),
+            PushTimeoutError::Closed => write!(f, "channel closed"),
+        }
+    }
+}
+
+impl From<PushError> for PushTimeoutError {
+    fn from(err: PushError) -> PushTimeoutError {
+        match err {
+            PushError::Full => PushTimeoutError::Timeout,
+            PushError::MessageTooLarge => PushTimeoutError::MessageTooLarge,
+            PushError::Disconnected => PushTimeoutError::Disconnected,
+            PushError::Closed => PushTimeoutError::Closed,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PopTimeoutError {
+    Timeout,
+    Disconnected,
+    Closed,
+    BufferTooSmall,
+    MissingHeader,
+    Corrupted,
+}
+
+impl std::error::Error for PopTimeoutError {}
+
+impl std::fmt::Display for PopTimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            PopTimeoutError::Timeout => write!(f, "timed out waiting for an element"),
+            PopTimeoutError::Disconnected => write!(f, "sender disconnected"),
+            PopTimeoutError::Closed => write!(f, "channel closed"),
+            PopTimeoutError::BufferTooSmall => write!(f, "element larger than buffer"),
+            PopTimeoutError::MissingHeader => write!(f, "element has no header"),
+            PopTimeoutError::Corrupted => write!(f, "element failed its checksum"),
+        }
+    }
+}
+
+impl From<PopError> for PopTimeoutError {
+    fn from(err: PopError) -> PopTimeoutError {
+        match err {
+            PopError::Empty => PopTimeoutError::Timeout,
+            PopError::Disconnected => PopTimeoutError::Disconnected,
+            PopError::Closed => PopTimeoutError::Closed,
+            PopError::BufferTooSmall => PopTimeoutError::BufferTooSmall,
+            PopError::MissingHeader => PopTimeoutError::MissingHeader,
+            PopError::Corrupted => PopTimeoutError::Corrupted,
+        }
+    }
+}
+
+/// Why an element could not be viewed as a `T`.
+#[cfg(feature = "zerocopy")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CastError {
+    /// The element is not `size_of::<T>()` bytes long.
+    Size,
+    /// The element does not start at a multiple of `align_of::<T>()`.
+    Alignment,
+}
+
+#[cfg(feature = "zerocopy")]
+impl std::error::Error for CastError {}
+
+#[cfg(feature = "zerocopy")]
+impl std::fmt::Display for CastError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            CastError::Size => write!(f, "element size does not match the type"),
+            CastError::Alignment => write!(f, "element is not aligned for the type"),
+        }
+    }
+}
+
+#[cfg(feature = "zerocopy")]
+fn cast<T: bytemuck::Pod>(bytes: &[u8]) -> Result<&T, CastError> {
+    bytemuck::try_from_bytes(bytes).map_err(|err| match err {
+        bytemuck::PodCastError::TargetAlignmentGreaterAndInputNotAligned => CastError::Alignment,
+        _ => CastError::Size,
+    })
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        match err.kind() {
+            io::ErrorKind::Unsupported => Error::UnsupportedBackend,
+            _ => Error::OS,
+        }
+    }
+}
+
+impl From<std::num::TryFromIntError> for Error {
+    fn from(_err: std::num::TryFromIntError) -> Error {
+        Error::OS
+    }
+}
+
+pub const BUF_128M: u32 = 27;
+pub const BUF_256M: u32 = 28;
+pub const BUF_512M: u32 = 29;
+pub const BUF_1G: u32 = 30;
+pub const BUF_2G: u32 = 31;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BufferSize {
+    Buf64M,
+    Buf128M,
+    Buf256M,
+    Buf512M,
+    Buf1G,
+    Buf2G,
+    /// Any size in bytes, rounded up to a power of two of at least the page size.
+    Custom(usize),
+    /// Exactly this many bytes, which have to be a power of two and a multiple of the page
+    /// size: 4 KiB on most systems, but e.g. 16 KiB on Apple Silicon and up to 64 KiB on
+    /// ARM servers.
+    Exact(usize),
+}
+
+impl BufferSize {
+    /// Size of the ring in bytes, i.e. of one of the two mirrored mappings.
+    pub fn bytes(&self) -> Result<usize, Error> {
+        match *self {
+            BufferSize::Buf64M => Ok(64 * 1024 * 1024usize),
+            BufferSize::Buf128M => Ok(128 * 1024 * 1024usize),
+            BufferSize::Buf256M => Ok(256 * 1024 * 1024usize),
+            BufferSize::Buf512M => Ok(512 * 1024 * 1024usize),
+            BufferSize::Buf1G => check_capacity(1 << BUF_1G),
+            BufferSize::Buf2G => check_capacity(1 << BUF_2G),
+            BufferSize::Custom(0) => Err(Error::InvalidCapacity),
+            BufferSize::Custom(bytes) => {
+                // The second mapping is placed at `base + capacity` with MAP_FIXED, so the
+                // capacity has to be page aligned; the page size being a power of two, so
+                // is any larger power of two.
+                check_capacity(bytes.max(page_size()).checked_next_power_of_two().ok_or(Error::InvalidCapacity)?)
+            }
+            BufferSize::Exact(bytes) if !bytes.is_power_of_two() || !bytes.is_multiple_of(page_size()) => {
+                Err(Error::UnalignedCapacity)
+            }
+            BufferSize::Exact(bytes) => check_capacity(bytes),
+        }
+    }
+}
+
+/// Both mirrored halves have to fit in the address space, and positions are reduced to
+/// the ring with a mask.
+fn check_capacity(capacity: usize) -> Result<usize, Error> {
+    if !capacity.is_power_of_two() || capacity > isize::MAX as usize / 2 {
+        return Err(Error::InvalidCapacity);
+    }
+    Ok(capacity)
+}
+
+#[cfg(unix)]
+pub fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// Views can only be placed at multiples of the allocation granularity on Windows, so
+/// capacities are rounded to that instead of the page size.
+#[cfg(windows)]
+pub fn page_size() -> usize {
+    crate::windows::allocation_granularity()
+}
+
+/// Wait queue used to park one side of the ring until the other side makes progress.
+///
+/// Waiters sleep on a futex keyed by `seq`; notifiers only bump `seq` and issue a
+/// wake syscall when somebody is actually parked, so the uncontended path stays
+/// a single atomic load.
+struct Signal {
+    parking: ptr::NonNull<Parking>,
+    /// Whether other processes may be parked on the same futex.
+    shared: bool,
+    strategy: WaitStrategy,
+    #[cfg(feature = "async")]
+    waker: AtomicWaker,
+    /// eventfd for epoll-style readiness, created on first request.
+    #[cfg(target_os = "linux")]
+    fd: OnceLock<RawFd>,
+}
+
+/// The futex words of a `Signal`, kept in the ring's `State`.
+#[repr(C)]
+pub struct Parking {
+    seq: AtomicU32,
+    waiters: AtomicU32,
+}
+
+impl Parking {
+    fn new() -> Parking {
+        Parking { seq: AtomicU32::new(0), waiters: AtomicU32::new(0) }
+    }
+}
+
+/// Processes each side of a shared ring keeps track of.
+const PEER_SLOTS: usize = 32;
+
+/// A process holding handles on one side of a shared ring.
+#[repr(C)]
+struct PeerSlot {
+    /// 0 while the slot is free. A process keeps its slot until it dies.
+    pid: AtomicU32,
+    /// Handles the process holds, each also counted in `senders` or `receivers`.
+    handles: AtomicU32,
+}
+
+/// In `Peer::abandoned` once the last of a side's handles went with a dead process.
+const ABANDONED: u32 = 1;
+
+/// In `Peer::abandoned` while a joining receiver takes over from dead ones.
+const RECOVERING: u32 = 2;
+
+/// One side of a shared ring: the processes that joined it, and when that side last
+/// showed signs of life, in milliseconds since the Unix epoch.
+#[repr(C)]
+pub struct Peer {
+    slots: [PeerSlot; PEER_SLOTS],
+    heartbeat: AtomicU64,
+    /// `ABANDONED`, `RECOVERING` or 0.
+    abandoned: AtomicU32,
+}
+
+impl Peer {
+    fn new() -> Peer {
+        Peer {
+            slots: std::array::from_fn(|_| PeerSlot { pid: AtomicU32::new(0), handles: AtomicU32::new(0) }),
+            heartbeat: AtomicU64::new(0),
+            abandoned: AtomicU32::new(0),
+        }
+    }
+
+    /// Counts a handle for this process. With every slot taken by live processes the
+    /// handle goes untracked, and its side never counts as dead while it is open.
+    fn join(&self) {
+        let me = std::process::id();
+        let slot = self.slots.iter().find(|slot| slot.pid.load(Ordering::Acquire) == me).or_else(|| {
+            self.slots.iter().find(|slot| slot.pid.compare_exchange(0, me, Ordering::AcqRel, Ordering::Acquire).is_ok())
+        });
+        if let Some(slot) = slot {
+            slot.handles.fetch_add(1, Ordering::AcqRel);
+        }
+        self.beat();
+    }
+
+    /// Stops counting a handle `join` counted for this process.
+    fn leave(&self) {
+        let me = std::process::id();
+        if let Some(slot) = self.slots.iter().find(|slot| slot.pid.load(Ordering::Acquire) == me) {
+            let _ = slot.handles.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+        }
+    }
+
+    /// Frees the slots of processes that died and takes their handles off `count`, which
+    /// counts this side's handles, marking the side abandoned if that leaves none. Returns
+    /// whether any handle is left.
+    fn reap(&self, count: &AtomicUsize) -> bool {
+        for slot in &self.slots {
+            let pid = slot.pid.load(Ordering::Acquire);
+            if pid == 0 || is_running(pid) {
+                continue;
+            }
+            // A dead process's handles stay put, and only one reaper gets to free its slot.
+            let handles = slot.handles.load(Ordering::Acquire);
+            if slot.pid.compare_exchange(pid, 0, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+                slot.handles.fetch_sub(handles, Ordering::AcqRel);
+                if handles > 0 && count.fetch_sub(handles as usize, Ordering::AcqRel) == handles as usize {
+                    self.abandoned.store(ABANDONED, Ordering::Release);
+                }
+            }
+        }
+        count.load(Ordering::Acquire) > 0
+    }
+
+    /// Forgets every process, for a ring nobody has open.
+    fn reset(&self) {
+        for slot in &self.slots {
+            slot.pid.store(0, Ordering::Release);
+            slot.handles.store(0, Ordering::Release);
+        }
+        self.heartbeat.store(0, Ordering::Release);
+        self.abandoned.store(0, Ordering::Release);
+    }
+
+    fn beat(&self) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_millis() as u64);
+        self.heartbeat.store(now, Ordering::Release);
+    }
+
+    fn last_seen(&self) -> Option<SystemTime> {
+        match self.heartbeat.load(Ordering::Acquire) {
+            0 => None,
+            millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+        }
+    }
+}
+
+/// Whether process `pid` is still around; `kill` with no signal only checks that.
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    pid == std::process::id() || unsafe { libc::kill(pid as libc::pid_t, 0) } == 0
+        || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
+
+impl Signal {
+    fn new(parking: &Parking, shared: bool) -> Signal {
+        Signal {
+            parking: ptr::NonNull::from(parking),
+            shared,
+            strategy: WaitStrategy::Park,
+            #[cfg(feature = "async")]
+            waker: AtomicWaker::new(),
+            #[cfg(target_os = "linux")]
+            fd: OnceLock::new(),
+        }
+    }
+
+    fn parking(&self) -> &Parking {
+        // The owning `CBuffer` keeps its `State` alive for as long as its signals.
+        unsafe { self.parking.as_ref() }
+    }
+
+    /// The eventfd is created readable, so a caller that registers it late still gets
+    /// one wakeup for anything that happened before.
+    #[cfg(target_os = "linux")]
+    fn event_fd(&self) -> RawFd {
+        *self.fd.get_or_init(|| {
+            let fd = unsafe { libc::eventfd(1, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+            if fd < 0 {
+                panic!("fail to create eventfd.");
+            }
+            fd
+        })
+    }
+
+    /// Waits as `strategy` says unless `ready` already holds, for at most `timeout` if
+    /// one is given. May return spuriously.
+    fn wait<F>(&self, ready: F, timeout: Option<Duration>)
+        where F: Fn() -> bool
+    {
+        let start = timeout.map(|_| Instant::now());
+        let mut n = 0u32;
+        while !ready() {
+            if let (Some(start), Some(timeout)) = (start, timeout) {
+                if start.elapsed() >= timeout {
+                    return;
+                }
+            }
+            match self.strategy {
+                WaitStrategy::Spin => std::hint::spin_loop(),
+                WaitStrategy::Yield if n < 64 => std::hint::spin_loop(),
+                WaitStrategy::Yield => std::thread::yield_now(),
+                WaitStrategy::Hybrid { spins, .. } if n < spins => std::hint::spin_loop(),
+                WaitStrategy::Hybrid { spins, yields } if n - spins < yields => std::thread::yield_now(),
+                WaitStrategy::Park | WaitStrategy::Hybrid { .. } => {
+                    let timeout = start.zip(timeout).map(|(start, t)| t.saturating_sub(start.elapsed()));
+                    return self.park(ready, timeout);
+                }
+            }
+            n = n.saturating_add(1);
+        }
+    }
+
+    fn park<F>(&self, ready: F, timeout: Option<Duration>)
+        where F: Fn() -> bool
+    {
+        let parking = self.parking();
+        let seq = parking.seq.load(Ordering::Acquire);
+        parking.waiters.fetch_add(1, Ordering::SeqCst);
+        fence(Ordering::SeqCst);
+        if !ready() {
+            futex_wait(&parking.seq, seq, timeout, self.shared);
+        }
+        parking.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn notify(&self) {
+        let parking = self.parking();
+        fence(Ordering::SeqCst);
+        if parking.waiters.load(Ordering::Relaxed) > 0 {
+            parking.seq.fetch_add(1, Ordering::Release);
+            futex_wake(&parking.seq, self.shared);
+        }
+        #[cfg(feature = "async")]
+        self.waker.wake();
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(fd) = self.fd.get() {
+                let one = 1u64;
+                unsafe { libc::write(*fd, &one as *const u64 as *const c_void, 8); }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Signal {
+    fn drop(&mut self) {
+        if let Some(fd) = self.fd.get() {
+            unsafe { close(*fd); }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn futex_wait(word: &AtomicU32, expected: u32, timeout: Option<Duration>, shared: bool) {
+    let ts = timeout.map(|t| libc::timespec {
+        tv_sec: t.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+        tv_nsec: t.subsec_nanos() as libc::c_long,
+    });
+    unsafe {
+        libc::syscall(libc::SYS_futex,
+                      word as *const AtomicU32,
+                      libc::FUTEX_WAIT | futex_flags(shared),
+                      expected,
+                      ts.as_ref().map_or(ptr::null(), |ts| ts as *const libc::timespec));
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn futex_wake(word: &AtomicU32, shared: bool) {
+    unsafe {
+        libc::syscall(libc::SYS_futex,
+                      word as *const AtomicU32,
+                      libc::FUTEX_WAKE | futex_flags(shared),
+                      i32::MAX);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn futex_flags(shared: bool) -> c_int {
+    if shared { 0 } else { libc::FUTEX_PRIVATE_FLAG }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn futex_wait(word: &AtomicU32, expected: u32, timeout: Option<Duration>, _shared: bool) {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    while word.load(Ordering::Acquire) == expected {
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            return;
+        }
+        std::thread::sleep(Duration::from_micros(5));
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn futex_wake(_word: &AtomicU32, _shared: bool) {}
+
+/// Set in `taken` while the element there is being peeked at, which keeps consumers from
+/// taking it and so producers from overwriting it. Cursors never get near this bit.
+const PEEKING: u64 = 1 << 63;
+
+/// Set in `claim` once the channel is closed: pushes that claimed room before it end up
+/// ahead of the end-of-stream marker, and no push can claim any after it.
+const CLOSING: u64 = 1 << 63;
+
+/// What a push does when the element does not fit. `try_push` never waits, so it treats
+/// `Block` like `Reject`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FullPolicy {
+    /// Fail with `PushError::Full`, from `push` as well as `try_push`.
+    Reject,
+    /// Park `push` until the receiver makes room. What `channel` uses.
+    Block,
+    /// Drop the oldest elements until the new one fits.
+    OverwriteOldest,
+    /// Discard the new element and report success.
+    DropNewest,
+}
+
+/// How a blocking push or pop waits for the other side, trading CPU for latency.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WaitStrategy {
+    /// Sleep in the kernel until woken. What `channel` uses.
+    Park,
+    /// Busy-spin, keeping a core to itself but reacting the soonest.
+    Spin,
+    /// Spin briefly, then keep yielding the CPU to other threads.
+    Yield,
+    /// Spin `spins` times, then yield `yields` times, then park.
+    Hybrid { spins: u32, yields: u32 },
+}
+
+/// Credit-based flow control, set up with `ChannelBuilder::credits`: pushes spend
+/// credits that only `Receiver::grant` hands out, and wait for more once they run out.
+/// Carries the credits the channel starts out with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Credits {
+    /// One credit per element.
+    Messages(u64),
+    /// One credit per payload byte.
+    Bytes(u64),
+}
+
+impl Credits {
+    fn flag(self) -> u32 {
+        match self {
+            Credits::Messages(_) => FLAG_CREDIT_MESSAGES,
+            Credits::Bytes(_) => FLAG_CREDIT_BYTES,
+        }
+    }
+
+    fn initial(self) -> u64 {
+        match self {
+            Credits::Messages(n) | Credits::Bytes(n) => n,
+        }
+    }
+}
+
+/// Which occupancy threshold a `Sender::set_watermarks` callback reports.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Watermark {
+    High,
+    Low,
+}
+
+/// Counters kept by a channel since its creation, as returned by `Sender::stats`. Each
+/// is read on its own, so a snapshot taken under load need not add up exactly.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Elements pushed.
+    pub messages: u64,
+    /// Payload bytes pushed.
+    pub bytes: u64,
+    /// Elements popped, including the ones `FullPolicy::OverwriteOldest` evicted.
+    pub popped: u64,
+    /// Pushes that returned an error.
+    pub failed_pushes: u64,
+    /// Most elements queued at once.
+    pub max_occupancy: u64,
+}
+
+/// How full a ring is, as reported by `OccupancyEvents`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Occupancy {
+    Empty,
+    /// Holds something, but less than the stream's `high` bytes.
+    NonEmpty,
+    /// Holds at least `high` bytes.
+    High,
+    /// A push found no room, and nothing has been popped since.
+    Full,
+}
+
+impl Occupancy {
+    fn from_code(code: u8) -> Occupancy {
+        match code {
+            0 => Occupancy::Empty,
+            1 => Occupancy::NonEmpty,
+            2 => Occupancy::High,
+            _ => Occupancy::Full,
+        }
+    }
+}
+
+/// Most transitions an `OccupancyEvents` holds on to; older ones make way for newer ones.
+const OCCUPANCY_EVENTS: usize = 64;
+
+/// What the handles of a ring share with its `OccupancyEvents`.
+struct OccupancyQueue {
+    high: usize,
+    /// The current `Occupancy`, so that pushes and pops only take the lock when it changes.
+    level: AtomicU8,
+    events: Mutex<VecDeque<Occupancy>>,
+    changed: Condvar,
+}
+
+impl OccupancyQueue {
+    fn update(&self, level: Occupancy) {
+        if self.level.load(Ordering::Relaxed) == level as u8 {
+            return;
+        }
+        let mut events = self.events.lock().unwrap();
+        if self.level.swap(level as u8, Ordering::Relaxed) == level as u8 {
+            return;
+        }
+        if events.len() == OCCUPANCY_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(level);
+        self.changed.notify_all();
+    }
+}
+
+/// Transitions between `Occupancy` levels of a channel, for a monitoring thread to wait on
+/// instead of polling. Only pushes and pops in this process are seen, and a stream that
+/// falls behind loses the oldest of its last 64 transitions first.
+pub struct OccupancyEvents {
+    inner: Arc<CBuffer>,
+}
+
+impl OccupancyEvents {
+    fn queue(&self) -> &OccupancyQueue {
+        self.inner.occupancy.get().expect("occupancy events without a queue.")
+    }
+
+    /// The latest level, whether or not its transition was taken from the stream yet.
+    pub fn current(&self) -> Occupancy {
+        Occupancy::from_code(self.queue().level.load(Ordering::Relaxed))
+    }
+
+    /// Takes the oldest transition, if there is one.
+    pub fn try_next(&self) -> Option<Occupancy> {
+        self.queue().events.lock().unwrap().pop_front()
+    }
+
+    /// Like `next`, waiting at most `timeout`.
+    pub fn next_timeout(&self, timeout: Duration) -> Option<Occupancy> {
+        let queue = self.queue();
+        let events = queue.events.lock().unwrap();
+        let (mut events, _) = queue.changed
+            .wait_timeout_while(events, timeout, |events| events.is_empty() && !self.is_disconnected())
+            .unwrap();
+        events.pop_front()
+    }
+
+    fn is_disconnected(&self) -> bool {
+        self.inner.is_sender_dropped() || self.inner.is_receiver_dropped()
+    }
+}
+
+impl Iterator for OccupancyEvents {
+    type Item = Occupancy;
+
+    /// Waits for the next transition. Ends once either side of the channel is gone and
+    /// every transition before that was taken.
+    fn next(&mut self) -> Option<Occupancy> {
+        let queue = self.queue();
+        let events = queue.events.lock().unwrap();
+        let mut events = queue.changed.wait_while(events, |events| events.is_empty() && !self.is_disconnected()).unwrap();
+        events.pop_front()
+    }
+}
+
+struct Watermarks {
+    high: usize,
+    low: usize,
+    /// Whether `high` was reached more recently than `low`, so that each crossing is
+    /// reported once.
+    above: AtomicBool,
+    callback: Box<dyn Fn(Watermark) + Send + Sync>,
+}
+
+/// Where the ring's bytes live: which of the built-in `RingMemory` implementations a
+/// channel gets. Others go through `ChannelBuilder::build_in`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryBackend {
+    /// Two adjacent views of one memory object, so frames wrap around the end for free.
+    Mmap,
+    /// Like `Mmap`, on huge pages where the system has them to spare: hugetlbfs pages if
+    /// the capacity is a multiple of their size, else transparent huge pages, else plain
+    /// pages. Only makes a difference on Linux.
+    HugePages,
+    /// A plain heap allocation of twice the capacity, with every write copied into both
+    /// halves. Slower, but needs no virtual memory tricks; handy under Miri or sanitizers.
+    Heap,
+    /// Like `Heap`, in memory the caller hands to `channel_with_memory`, e.g. a static
+    /// buffer on a target without an allocator for it. `channel_with_backend` rejects it.
+    Static,
+}
+
+/// Memory of the caller's for a ring to live in, e.g. persistent memory or pinned buffers a
+/// device reads from, for `ChannelBuilder::build_in`. It holds `2 * capacity()` bytes: the
+/// ring's data, then a mirror of it. `build_in` rejects it unless it is aligned to 8 bytes
+/// and to the builder's `payload_align`.
+///
+/// # Safety
+///
+/// `as_mut_ptr` has to return memory that is valid for reads and writes of
+/// `2 * capacity()` bytes and stays put for as long as the value lives, moved or not, and
+/// is only touched through the ring. If `is_mirrored`, its upper half has to map the same
+/// memory as the lower one.
+pub unsafe trait RingMemory: Send + Sync + 'static {
+    fn as_mut_ptr(&mut self) -> *mut u8;
+
+    /// Bytes of data in the ring, a power of two.
+    fn capacity(&self) -> usize;
+
+    /// Whether the upper half is a second view of the lower one, as with
+    /// `MemoryBackend::Mmap`, rather than memory of its own that every write is copied
+    /// into as well.
+    fn is_mirrored(&self) -> bool {
+        false
+    }
+}
+
+/// Like `channel_with_memory`.
+unsafe impl RingMemory for &'static mut [u8] {
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        <[u8]>::as_mut_ptr(self)
+    }
+
+    fn capacity(&self) -> usize {
+        fitting_capacity(self.len())
+    }
+}
+
+/// A heap allocation of the caller's, e.g. one made with a `Layout` of the alignment needed.
+unsafe impl RingMemory for Box<[u8]> {
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        <[u8]>::as_mut_ptr(self)
+    }
+
+    fn capacity(&self) -> usize {
+        fitting_capacity(self.len())
+    }
+}
+
+/// Zeroed heap memory for `MemoryBackend::Heap`, aligned for the cursors and payloads in it
+/// rather than to whatever the allocator hands out for bytes.
+struct HeapMemory {
+    pointer: ptr::NonNull<u8>,
+    layout: Layout,
+}
+
+// Only ever touched through the ring it backs.
+unsafe impl Send for HeapMemory {}
+
+unsafe impl Sync for HeapMemory {}
+
+impl HeapMemory {
+    fn new(capacity: usize, align: usize) -> Result<HeapMemory, Error> {
+        let layout = Layout::from_size_align(2 * capacity, align).map_err(|_| Error::InvalidCapacity)?;
+        if layout.size() == 0 {
+            return Err(Error::InvalidCapacity);
+        }
+        let pointer = ptr::NonNull::new(unsafe { alloc::alloc_zeroed(layout) })
+            .unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        Ok(HeapMemory { pointer, layout })
+    }
+}
+
+unsafe impl RingMemory for HeapMemory {
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.pointer.as_ptr()
+    }
+
+    fn capacity(&self) -> usize {
+        self.layout.size() / 2
+    }
+}
+
+impl Drop for HeapMemory {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.pointer.as_ptr(), self.layout) }
+    }
+}
+
+/// A memory object mapped twice back to back, so that frames wrap around the end for free.
+/// Backs `MemoryBackend::Mmap` and `HugePages` and the data of shared rings; `from_fd`
+/// gives other memory objects, e.g. a file on persistent memory, the same layout.
+pub struct MirroredMapping {
+    pointer: ptr::NonNull<u8>,
+    capacity: usize,
+}
+
+// Only ever touched through the ring it backs.
+unsafe impl Send for MirroredMapping {}
+
+unsafe impl Sync for MirroredMapping {}
+
+impl MirroredMapping {
+    /// Fresh memory of `capacity` bytes.
+    fn anonymous(capacity: usize) -> Result<MirroredMapping, Error> {
+        map_ring(capacity).map(|pointer| MirroredMapping { pointer, capacity })
+    }
+
+    /// Like `anonymous`, on huge pages if possible.
+    fn huge(capacity: usize) -> Result<MirroredMapping, Error> {
+        map_ring_huge(capacity).map(|pointer| MirroredMapping { pointer, capacity })
+    }
+
+    /// Maps the `capacity` bytes of the memory object behind `fd` from `offset` on twice.
+    /// `capacity` has to be a power of two multiple of the page size and `offset` a
+    /// multiple of it, and the object has to be large enough. `fd` may be closed afterwards.
+    #[cfg(unix)]
+    pub fn from_fd(fd: BorrowedFd<'_>, offset: usize, capacity: usize) -> io::Result<MirroredMapping> {
+        let page = page_size();
+        if !capacity.is_power_of_two() || !capacity.is_multiple_of(page) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, Error::UnalignedCapacity));
+        }
+        if !offset.is_multiple_of(page) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "offset is not aligned to a page"));
+        }
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if (stat.st_size as usize) < offset + capacity {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "memory object too small for the ring"));
+        }
+        let pointer = map_mirror(fd.as_raw_fd(), offset, capacity).map_err(|_| io::Error::last_os_error())?;
+        Ok(MirroredMapping { pointer, capacity })
+    }
+}
+
+unsafe impl RingMemory for MirroredMapping {
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.pointer.as_ptr()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn is_mirrored(&self) -> bool {
+        true
+    }
+}
+
+impl Drop for MirroredMapping {
+    fn drop(&mut self) {
+        unsafe { unmap_ring(self.pointer, self.capacity) }
+    }
+}
+
+/// The largest power of two that fits into `len` bytes twice, or 0 if none does.
+fn fitting_capacity(len: usize) -> usize {
+    match len / 2 {
+        0 => 0,
+        half => 1 << half.ilog2(),
+    }
+}
+
+fn default_backend() -> MemoryBackend {
+    // Loom runs a model thousands of times over; a mapping per run buys it nothing.
+    if cfg!(loom) { MemoryBackend::Heap } else { MemoryBackend::Mmap }
+}
+
+/// Hints about the ring's pages for `ChannelBuilder::advise`, passed on via `madvise`.
+#[cfg(unix)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Advice {
+    /// Leave the ring out of core dumps. Linux only; ignored elsewhere.
+    DontDump,
+    /// The ring is about to be used, so the kernel may read its pages in ahead of time.
+    WillNeed,
+    /// Back the ring with transparent huge pages where it can. Linux only; ignored
+    /// elsewhere.
+    HugePage,
+}
+
+/// Marks the header page of a shared ring as initialized. Its low byte used to carry
+/// the layout version, so builds from before `SHARED_VERSION` reject it outright.
+const SHARED_MAGIC: u64 = 0x6362_7566_6665_7200;
+
+/// In `State::magic` while `CBuffer::from_fd` or `from_raw_parts` sets up a ring.
+const SHARED_INIT: u64 = SHARED_MAGIC | 1;
+
+/// Layout of `State` and of the frames behind it; bumped whenever either changes.
+const SHARED_VERSION: u32 = 8;
+
+/// How often waits on a shared ring look for the other side's process.
+const PEER_CHECK: Duration = Duration::from_millis(100);
+
+/// Bits of `State::flags` this build understands. A flag marks an option that changes
+/// how the ring has to be read, so attaching to a ring with any other bit set fails.
+const SHARED_FLAGS: u32 = FLAG_CREDIT_MESSAGES | FLAG_CREDIT_BYTES | FLAG_ALIGN;
+
+/// Pushes spend `State::credits`, per element or per payload byte.
+const FLAG_CREDIT_MESSAGES: u32 = 1;
+const FLAG_CREDIT_BYTES: u32 = 1 << 1;
+/// Base-2 logarithm of the payload alignment; zero for frames packed back to back.
+const FLAG_ALIGN: u32 = 0xf << ALIGN_SHIFT;
+const ALIGN_SHIFT: u32 = 8;
+
+/// Leads a file written by `Receiver::snapshot`; the last byte is the file's version. The
+/// magic is followed by the `LengthPrefix::code`, the payload alignment and the byte
+/// length of the frames, and then the frames themselves, exactly as they were in the ring.
+/// Version 1 files lack the alignment.
+const SNAPSHOT_MAGIC: [u8; 8] = *b"cbufsnp\x02";
+const SNAPSHOT_HEADER: usize = 24;
+
+/// Largest `ChannelBuilder::payload_align`.
+const MAX_ALIGN: usize = 4096;
+
+/// Everything both ends of a ring update. Local rings keep it on the heap, shared ones in
+/// a header page in front of the ring so that every attached process sees the same one.
+#[repr(C)]
+pub struct State {
+    /// The fields up to `flags` describe the ring to processes attaching to it and keep
+    /// their place in every version.
+    magic: AtomicU64,
+    version: AtomicU32,
+    /// `size_of::<State>()` in the creating build, which catches e.g. a 32-bit process
+    /// attaching to a 64-bit one's ring.
+    state_size: AtomicU32,
+    capacity: AtomicU64,
+    /// `LengthPrefix::code` of the ring's frames.
+    prefix: AtomicU32,
+    flags: AtomicU32,
+    /// Cursors count bytes since creation and are only reduced modulo the capacity when
+    /// touching memory, so a compare-exchange on them cannot be fooled by a lap of the ring.
+    /// Stored with `Release` once a consumer is done with the bytes before it, and loaded
+    /// with `Acquire` before a producer reuses them.
+    head: Cursor,
+    /// Stored with `Release` once the frames before it are written, and loaded with
+    /// `Acquire` before a consumer reads them.
+    tail: Cursor,
+    /// End of the space handed out to producers. Runs ahead of `tail` while a producer
+    /// is still copying its frame in; equal to it otherwise.
+    claim: Cursor,
+    /// End of the elements handed out to consumers; runs ahead of `head` the same way.
+    /// Carries `PEEKING` while a receiver looks at the element there.
+    taken: Cursor,
+    /// Elements published and not yet released; `Sender::close`'s marker does not count.
+    messages: AtomicU64,
+    /// Elements thrown away by `FullPolicy::DropNewest` and `FullPolicy::OverwriteOldest`.
+    dropped: AtomicU64,
+    overwritten: AtomicU64,
+    /// Running totals behind `Stats`.
+    pushed: AtomicU64,
+    pushed_bytes: AtomicU64,
+    popped: AtomicU64,
+    failed_pushes: AtomicU64,
+    max_occupancy: AtomicU64,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    receiver_dropped: AtomicBool,
+    sender_dropped: AtomicBool,
+    closed: AtomicBool,
+    /// What pushes may still spend, if `flags` says they spend anything.
+    credits: AtomicU64,
+    sender_peer: Peer,
+    receiver_peer: Peer,
+    readable_parking: Parking,
+    writable_parking: Parking,
+}
+
+impl State {
+    fn new(capacity: usize) -> State {
+        State {
+            magic: AtomicU64::new(0),
+            version: AtomicU32::new(SHARED_VERSION),
+            state_size: AtomicU32::new(std::mem::size_of::<State>() as u32),
+            capacity: AtomicU64::new(capacity as u64),
+            prefix: AtomicU32::new(LengthPrefix::U32.code()),
+            flags: AtomicU32::new(0),
+            head: Cursor::new(0),
+            tail: Cursor::new(0),
+            claim: Cursor::new(0),
+            taken: Cursor::new(0),
+            messages: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            overwritten: AtomicU64::new(0),
+            pushed: AtomicU64::new(0),
+            pushed_bytes: AtomicU64::new(0),
+            popped: AtomicU64::new(0),
+            failed_pushes: AtomicU64::new(0),
+            max_occupancy: AtomicU64::new(0),
+            senders: AtomicUsize::new(1),
+            receivers: AtomicUsize::new(1),
+            receiver_dropped: AtomicBool::new(false),
+            sender_dropped: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            credits: AtomicU64::new(0),
+            sender_peer: Peer::new(),
+            receiver_peer: Peer::new(),
+            readable_parking: Parking::new(),
+            writable_parking: Parking::new(),
+        }
+    }
+}
+
+pub struct CBuffer {
+    capacity: usize,
+    /// Start of `memory`, looked up once.
+    pointer: ptr::NonNull<u8>,
+    /// Whether writes go into both halves themselves, the upper one not being a view of
+    /// the lower.
+    copy_mirror: bool,
+    /// Holds the ring's bytes, whichever backend they come from.
+    memory: Box<dyn RingMemory>,
+    state: ptr::NonNull<State>,
+    /// Set for rings in a named shared memory object.
+    shared: Option<SharedName>,
+    mpmc: bool,
+    policy: FullPolicy,
+    /// Recorded in the header of a shared ring, where the attaching ends pick it up.
+    pub(crate) format: Framing,
+    /// The `State::flags` bit saying what pushes spend credits on, if any; read once so
+    /// that pushes do not have to load it.
+    credit_flag: u32,
+    watermarks: OnceLock<Watermarks>,
+    occupancy: OnceLock<OccupancyQueue>,
+    /// Reported with tracing events; a shared ring starts out with its object's name.
+    name: OnceLock<Box<str>>,
+    /// Largest element pushes accept, below what the capacity allows.
+    max_message_size: AtomicUsize,
+    /// The producer's last look at `head` and the consumer's at `tail`, so that the
+    /// single-handle paths only touch the other side's cursor when these run out. Both
+    /// only ever lag the real cursor, which understates what is available.
+    cached_head: AtomicU64,
+    cached_tail: AtomicU64,
+    readable: Signal,
+    writable: Signal,
+    #[cfg(feature = "trace")]
+    trace: Trace,
+}
+
+struct PeekMark<'a> {
+    buffer: &'a CBuffer,
+    head: u64,
+}
+
+impl<'a> Drop for PeekMark<'a> {
+    fn drop(&mut self) {
+        self.buffer.unpeek(self.head);
+    }
+}
+
+/// A shared ring's memory object: its name unless it is anonymous, whether this end
+/// created it and so has to unlink it again, and the descriptor `Sender::send_over` passes
+/// on, unless the ring lives in memory the caller mapped itself.
+struct SharedName {
+    name: Option<CString>,
+    owner: bool,
+    #[cfg(unix)]
+    fd: Option<c_int>,
+}
+
+unsafe impl Send for CBuffer {}
+
+unsafe impl Sync for CBuffer {}
+
+impl ops::Deref for CBuffer {
+    type Target = State;
+
+    fn deref(&self) -> &State {
+        // Either boxed by `with_capacity` or the mapped header page, both freed on drop.
+        unsafe { self.state.as_ref() }
+    }
+}
+
+impl CBuffer {
+    pub fn with_capacity(s: BufferSize) -> Result<Self, Error> {
+        CBuffer::with_backend(s, default_backend())
+    }
+
+    pub fn with_backend(s: BufferSize, backend: MemoryBackend) -> Result<Self, Error> {
+        CBuffer::with_backend_aligned(s, backend, 1)
+    }
+
+    /// Like `with_backend`, for payloads aligned to `align` bytes.
+    pub(crate) fn with_backend_aligned(s: BufferSize, backend: MemoryBackend, align: usize) -> Result<Self, Error> {
+        let capacity = s.bytes()?;
+        let memory: Box<dyn RingMemory> = match backend {
+            MemoryBackend::Mmap => Box::new(MirroredMapping::anonymous(capacity)?),
+            MemoryBackend::HugePages => Box::new(MirroredMapping::huge(capacity)?),
+            MemoryBackend::Heap => Box::new(HeapMemory::new(capacity, align.max(8))?),
+            MemoryBackend::Static => return Err(Error::UnsupportedBackend),
+        };
+        CBuffer::with_ring_memory_aligned(memory, align)
+    }
+
+    /// A ring in the start of `memory`, which stays borrowed for as long as the ring
+    /// exists: the largest power of two that fits twice holds the data and its mirror.
+    pub fn with_memory(memory: &'static mut [u8]) -> Result<Self, Error> {
+        CBuffer::with_ring_memory(Box::new(memory))
+    }
+
+    /// A ring in `memory`, which it owns from then on. Fails with `Error::UnalignedMemory`
+    /// unless the memory is aligned to 8 bytes.
+    pub fn with_ring_memory(memory: Box<dyn RingMemory>) -> Result<Self, Error> {
+        CBuffer::with_ring_memory_aligned(memory, 1)
+    }
+
+    /// Like `with_ring_memory`, for payloads aligned to `align` bytes.
+    pub(crate) fn with_ring_memory_aligned(mut memory: Box<dyn RingMemory>, align: usize) -> Result<Self, Error> {
+        let capacity = check_capacity(memory.capacity())?;
+        if !(memory.as_mut_ptr() as usize).is_multiple_of(align.max(8)) {
+            return Err(Error::UnalignedMemory);
+        }
+        let state = ptr::NonNull::from(Box::leak(Box::new(State::new(capacity))));
+        Ok(CBuffer::from_parts(memory, state, None))
+    }
+
+    /// Creates a ring in the shared memory object `name`, which must not exist yet, with
+    /// frames in `format` and pushes spending `credits`, if given.
+    #[cfg(unix)]
+    pub(crate) fn create_shared(name: &str, s: BufferSize, format: Framing, credits: Option<Credits>) -> io::Result<Self> {
+        let capacity = s.bytes().map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let name = shared_name(name)?;
+        let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDWR | libc::O_CREAT | libc::O_EXCL, 0o600) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        CBuffer::init_shared(fd, capacity, format, credits, Some(name.clone())).inspect_err(|_| {
+            unsafe { libc::shm_unlink(name.as_ptr()); }
+        })
+    }
+
+    /// Like `create_shared`, but the memory object has no name, so other processes can
+    /// only join through its descriptor, e.g. one passed with `Sender::send_over`.
+    #[cfg(unix)]
+    pub(crate) fn create_anonymous(s: BufferSize, format: Framing, credits: Option<Credits>) -> io::Result<Self> {
+        let capacity = s.bytes().map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        #[cfg(target_os = "linux")]
+        let fd = unsafe {
+            libc::memfd_create(b"cbuffer\0".as_ptr() as *const libc::c_char, libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING)
+        };
+        #[cfg(not(target_os = "linux"))]
+        let fd = {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let name = shared_name(&format!("/cbuffer-anon-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)))?;
+            let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDWR | libc::O_CREAT | libc::O_EXCL, 0o600) };
+            if fd >= 0 {
+                unsafe { libc::shm_unlink(name.as_ptr()); }
+            }
+            fd
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let b = CBuffer::init_shared(fd, capacity, format, credits, None)?;
+        // Whoever the descriptor is passed to cannot shrink the object under the other
+        // processes' mappings, which would crash them on their next access.
+        #[cfg(target_os = "linux")]
+        if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_SEAL) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(b)
+    }
+
+    /// Sets up a ring in the fresh memory object behind `fd`, which it takes over.
+    #[cfg(unix)]
+    fn init_shared(fd: c_int, capacity: usize, format: Framing, credits: Option<Credits>,
+                   name: Option<CString>) -> io::Result<Self> {
+        let page = page_size();
+        let mapped = if unsafe { ftruncate(fd, (page + capacity) as off_t) } < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            map_shared(fd, capacity)
+        };
+        let (memory, state) = match mapped {
+            Ok(views) => views,
+            Err(err) => {
+                unsafe { close(fd); }
+                return Err(err);
+            }
+        };
+        unsafe {
+            ptr::write(state.as_ptr(), State::new(capacity));
+            state.as_ref().prefix.store(format.prefix.code(), Ordering::Relaxed);
+            state.as_ref().flags.store(format.align.trailing_zeros() << ALIGN_SHIFT, Ordering::Relaxed);
+        }
+        let mut b = CBuffer::from_parts(Box::new(memory), state, Some(SharedName { name, owner: true, fd: Some(fd) }));
+        b.format = format;
+        if let Some(credits) = credits {
+            b.use_credits(credits);
+        }
+        b.sender_peer.join();
+        b.receiver_peer.join();
+        b.magic.store(SHARED_MAGIC, Ordering::Release);
+        Ok(b)
+    }
+
+    /// Maps the ring another process created with `create_shared`. The caller accounts
+    /// for the new handle in `senders` or `receivers`.
+    #[cfg(unix)]
+    pub fn attach_shared(name: &str) -> io::Result<Self> {
+        let name = shared_name(name)?;
+        let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDWR, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        CBuffer::attach_fd(fd, Some(name))
+    }
+
+    /// Like `attach_shared`, for the memory object behind `fd`, which it takes over.
+    #[cfg(unix)]
+    pub fn attach_fd(fd: c_int, name: Option<CString>) -> io::Result<Self> {
+        let mapped = shared_capacity(fd).and_then(|capacity| {
+            map_shared(fd, capacity).map(|(memory, state)| (capacity, memory, state))
+        });
+        let (capacity, memory, state) = match mapped {
+            Ok(mapped) => mapped,
+            Err(err) => {
+                unsafe { close(fd); }
+                return Err(err);
+            }
+        };
+        let mut b = CBuffer::from_parts(Box::new(memory), state, Some(SharedName { name, owner: false, fd: Some(fd) }));
+        b.read_header(capacity)?;
+        Ok(b)
+    }
+
+    /// A ring in the memory object behind `fd`, laid out like a shared ring's: a page of
+    /// header followed by `capacity` bytes of data, which has to be a power of two multiple
+    /// of the page size. Sets up a fresh ring if the header is all zeroes and joins the one there
+    /// otherwise. The caller accounts for its handles in `senders` and `receivers`.
+    #[cfg(unix)]
+    pub fn from_fd(fd: BorrowedFd<'_>, capacity: usize) -> io::Result<Self> {
+        if !capacity.is_power_of_two() || !capacity.is_multiple_of(page_size()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, Error::UnalignedCapacity));
+        }
+        let fd = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mapped = shared_capacity(fd).and_then(|available| match available {
+            available if available < capacity => {
+                Err(io::Error::new(io::ErrorKind::InvalidInput, "memory object too small for the ring"))
+            }
+            _ => map_shared(fd, capacity),
+        });
+        let (memory, state) = match mapped {
+            Ok(mapped) => mapped,
+            Err(err) => {
+                unsafe { close(fd); }
+                return Err(err);
+            }
+        };
+        CBuffer::from_parts(Box::new(memory), state, Some(SharedName { name: None, owner: false, fd: Some(fd) }))
+            .adopt(capacity)
+    }
+
+    /// Like `from_fd`, for a log file that may have been left behind by a crash. With
+    /// `recover`, which only whoever has the file to themselves may pass, the ring is
+    /// repaired first: elements from the oldest unreleased one up to the first that is
+    /// torn or fails `recover` are kept, and the processes that had joined are forgotten.
+    #[cfg(unix)]
+    pub(crate) fn open_log<F>(fd: BorrowedFd<'_>, capacity: usize, recover: Option<F>) -> io::Result<Self>
+        where F: Fn(&[u8]) -> bool
+    {
+        if recover.is_some() {
+            let mut magic = [0u8; 8];
+            let read = unsafe { libc::pread(fd.as_raw_fd(), magic.as_mut_ptr() as *mut c_void, 8, 0) };
+            if read == 8 && u64::from_ne_bytes(magic) != SHARED_MAGIC {
+                // Set up or torn before it was: start over rather than wait in `adopt`.
+                let zeroes = vec![0u8; page_size()];
+                if unsafe { libc::pwrite(fd.as_raw_fd(), zeroes.as_ptr() as *const c_void, zeroes.len(), 0) } < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        let b = CBuffer::from_fd(fd, capacity)?;
+        if let Some(valid) = recover {
+            b.recover_log(valid);
+        }
+        Ok(b)
+    }
+
+    /// A ring in the `page_size() + 2 * capacity` bytes at `ptr`: a page of header, then
+    /// the data and a copy of it kept up to date as with `with_memory`, for memory that
+    /// cannot be mapped twice. Sets up or joins a ring like `from_fd`.
+    ///
+    /// # Safety
+    ///
+    /// The memory has to be valid for reads and writes and aligned to a page for as long as
+    /// the ring exists, and only be touched through rings made from it.
+    #[cfg(unix)]
+    pub unsafe fn from_raw_parts(ptr: *mut u8, capacity: usize) -> io::Result<Self> {
+        let capacity = check_capacity(capacity).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        if !(ptr as usize).is_multiple_of(page_size()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "memory is not aligned to a page"));
+        }
+        let state = ptr::NonNull::new(ptr as *mut State)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "null pointer"))?;
+        let memory: &'static mut [u8] = slice::from_raw_parts_mut(ptr.add(page_size()), 2 * capacity);
+        CBuffer::from_parts(Box::new(memory), state, Some(SharedName { name: None, owner: false, fd: None }))
+            .adopt(capacity)
+    }
+
+    /// Sets up a fresh ring behind a zeroed header, or waits for whoever is doing so and
+    /// checks the ring that is there.
+    #[cfg(unix)]
+    fn adopt(mut self, capacity: usize) -> io::Result<Self> {
+        if self.magic.compare_exchange(0, SHARED_INIT, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            let mut state = State::new(capacity);
+            *state.magic.get_mut() = SHARED_INIT;
+            // Every handle joins, the first ones included.
+            *state.senders.get_mut() = 0;
+            *state.receivers.get_mut() = 0;
+            unsafe { ptr::write(self.state.as_ptr(), state); }
+            self.magic.store(SHARED_MAGIC, Ordering::Release);
+        } else {
+            spin_until(|| self.magic.load(Ordering::Acquire) != SHARED_INIT);
+        }
+        self.read_header(capacity)?;
+        Ok(self)
+    }
+
+    #[cfg(unix)]
+    fn read_header(&mut self, capacity: usize) -> io::Result<()> {
+        self.format = check_header(self, capacity).map_err(|msg| io::Error::new(io::ErrorKind::InvalidData, msg))?;
+        self.credit_flag = self.flags.load(Ordering::Relaxed) & (FLAG_CREDIT_MESSAGES | FLAG_CREDIT_BYTES);
+        Ok(())
+    }
+
+    /// Descriptor of a shared ring's memory object.
+    #[cfg(unix)]
+    pub fn shared_fd(&self) -> Option<c_int> {
+        self.shared.as_ref().and_then(|shared| shared.fd)
+    }
+
+    #[cfg(unix)]
+    pub fn send_over(&self, socket: &UnixStream) -> io::Result<()> {
+        let fd = self.shared_fd().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a shared cbuffer"))?;
+        fdpass::send_fd(socket, fd)
+    }
+
+    /// Unlinks the shared memory object `name` if it holds a ring whose senders' and
+    /// receivers' processes have both died. Returns whether it did.
+    #[cfg(unix)]
+    pub(crate) fn remove_abandoned(name: &str) -> io::Result<bool> {
+        match CBuffer::attach_shared(name) {
+            Ok(b) if !b.sender_peer.reap(&b.senders) && !b.receiver_peer.reap(&b.receivers) => {
+                CBuffer::unlink_shared(name)?;
+                Ok(true)
+            }
+            // Missing, in use, or unreadable, which may just mean its creator is still
+            // setting it up.
+            _ => Ok(false),
+        }
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn unlink_shared(name: &str) -> io::Result<()> {
+        let name = shared_name(name)?;
+        if unsafe { libc::shm_unlink(name.as_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Makes pushes spend credits, starting out with those in `credits`.
+    fn use_credits(&mut self, credits: Credits) {
+        self.credit_flag = credits.flag();
+        self.flags.fetch_or(credits.flag(), Ordering::Relaxed);
+        self.credits.store(credits.initial(), Ordering::Relaxed);
+    }
+
+    /// Credits that pushing `count` elements of `bytes` bytes costs, if pushes cost any.
+    fn credit_cost(&self, count: usize, bytes: usize) -> Option<u64> {
+        match self.credit_flag {
+            FLAG_CREDIT_MESSAGES => Some(count as u64),
+            FLAG_CREDIT_BYTES => Some(bytes as u64),
+            _ => None,
+        }
+    }
+
+    /// Spends the credits for `count` elements of `bytes` bytes, unless there are too few.
+    fn spend_credits(&self, count: usize, bytes: usize) -> bool {
+        match self.credit_cost(count, bytes) {
+            Some(cost) => self.credits.fetch_update(Ordering::AcqRel, Ordering::Acquire, |c| c.checked_sub(cost)).is_ok(),
+            None => true,
+        }
+    }
+
+    /// Gives back what `spend_credits` took for a push that did not go through.
+    fn refund_credits(&self, count: usize, bytes: usize) {
+        if let Some(cost) = self.credit_cost(count, bytes) {
+            self.credits.fetch_add(cost, Ordering::AcqRel);
+        }
+    }
+
+    fn has_credits(&self, count: usize, bytes: usize) -> bool {
+        match self.credit_cost(count, bytes) {
+            Some(cost) => self.credits.load(Ordering::Acquire) >= cost,
+            None => true,
+        }
+    }
+
+    /// Lets pushes spend `n` more credits.
+    pub fn grant(&self, n: u64) {
+        self.credits.fetch_add(n, Ordering::AcqRel);
+        self.writable.notify();
+    }
+
+    /// Credits left to spend, or `None` if pushes do not spend any.
+    pub fn credits(&self) -> Option<u64> {
+        self.credit_cost(0, 0).map(|_| self.credits.load(Ordering::Acquire))
+    }
+
+    fn from_parts(mut memory: Box<dyn RingMemory>, state: ptr::NonNull<State>, shared: Option<SharedName>) -> CBuffer {
+        let parking = unsafe { state.as_ref() };
+        let is_shared = shared.is_some();
+        let name = OnceLock::new();
+        if let Some(shared_name) = shared.as_ref().and_then(|shared| shared.name.as_ref()) {
+            let _ = name.set(shared_name.to_string_lossy().into());
+        }
+        // Valid memory is never at null.
+        let pointer = unsafe { ptr::NonNull::new_unchecked(memory.as_mut_ptr()) };
+        let b = CBuffer {
+            capacity: memory.capacity(),
+            pointer,
+            copy_mirror: !memory.is_mirrored(),
+            memory,
+            state,
+            // Another process may attach a handle at any time, so the single-handle fast
+            // paths are never safe on a shared ring.
+            mpmc: is_shared,
+            policy: FullPolicy::Block,
+            format: Framing::from(LengthPrefix::U32),
+            credit_flag: 0,
+            watermarks: OnceLock::new(),
+            occupancy: OnceLock::new(),
+            name,
+            max_message_size: AtomicUsize::new(usize::MAX),
+            cached_head: AtomicU64::new(0),
+            cached_tail: AtomicU64::new(0),
+            readable: Signal::new(&parking.readable_parking, is_shared),
+            writable: Signal::new(&parking.writable_parking, is_shared),
+            #[cfg(feature = "trace")]
+            trace: Trace::new(),
+            shared,
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(channel = b.name(), capacity = b.capacity, shared = is_shared, "channel mapped");
+        b
+    }
+
+    pub fn push(&self, data: &[u8]) -> Result<(), PushError> {
+        self.push_parts(&[], data)
+    }
+
+    /// Pushes `prefix` followed by `data` as one element.
+    pub(crate) fn push_parts(&self, prefix: &[u8], data: &[u8]) -> Result<(), PushError> {
+        self.counted(self.push_once(&[prefix, data]))
+    }
+
+    /// Pushes the concatenation of `bufs` as one element.
+    pub fn push_vectored(&self, bufs: &[IoSlice<'_>]) -> Result<(), PushError> {
+        self.counted(self.push_once(bufs))
+    }
+
+    /// Like `push_parts`, without counting a failure towards `Stats::failed_pushes`,
+    /// for callers that may still retry.
+    fn push_once<B>(&self, parts: &[B]) -> Result<(), PushError>
+        where B: ops::Deref<Target = [u8]>
+    {
+        let size = parts.iter().map(|part| part.len()).sum();
+        if self.receiver_dropped.load(Ordering::Acquire) {
+            return Err(PushError::Disconnected);
+        }
+        if self.closed.load(Ordering::Acquire) {
+            return Err(PushError::Closed);
+        }
+        if self.too_large(size) {
+            return Err(PushError::MessageTooLarge);
+        }
+        if !self.spend_credits(1, size) {
+            return Err(PushError::Full);
+        }
+        let start = loop {
+            let frame_size = self.format.frame_size(size);
+            match self.claim(|free| if free > frame_size { frame_size } else { 0 }) {
+                Some(start) => break start,
+                None if self.is_closing() => {
+                    self.refund_credits(1, size);
+                    return Err(PushError::Closed);
+                }
+                // The consumers may have emptied the ring before there was anything to evict.
+                None if self.policy == FullPolicy::OverwriteOldest
+                    && (self.evict() || self.fits(size)) => {}
+                None if self.policy == FullPolicy::DropNewest => {
+                    self.refund_credits(1, size);
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    self.note_full();
+                    return Ok(());
+                }
+                None => {
+                    self.refund_credits(1, size);
+                    self.note_full();
+                    return Err(PushError::Full);
+                }
+            }
+        };
+        let mut tail = self.write_prefix(start, size);
+        for part in parts {
+            self.write(tail, part);
+            tail += part.len() as u64;
+        }
+        self.commit(start, start + self.format.frame_size(size) as u64, 1, size);
+        Ok(())
+    }
+
+    /// Pushes `frames`, holding `count` elements of `bytes` bytes in total already framed
+    /// back to back, as one reservation, so that they become visible together.
+    fn push_frames(&self, frames: &[u8], count: usize, bytes: usize) -> Result<(), PushError> {
+        if self.receiver_dropped.load(Ordering::Acquire) {
+            return Err(PushError::Disconnected);
+        }
+        if self.closed.load(Ordering::Acquire) {
+            return Err(PushError::Closed);
+        }
+        if count == 0 {
+            return Ok(());
+        }
+        if !self.spend_credits(count, bytes) {
+            return Err(PushError::Full);
+        }
+        let start = loop {
+            match self.claim(|free| if free > frames.len() { frames.len() } else { 0 }) {
+                Some(start) => break start,
+                None if self.is_closing() => {
+                    self.refund_credits(count, bytes);
+                    return Err(PushError::Closed);
+                }
+                None if self.policy == FullPolicy::OverwriteOldest
+                    && (self.evict() || self.has_room(frames.len())) => {}
+                None if self.policy == FullPolicy::DropNewest => {
+                    self.refund_credits(count, bytes);
+                    self.dropped.fetch_add(count as u64, Ordering::Relaxed);
+                    self.note_full();
+                    return Ok(());
+                }
+                None => {
+                    self.refund_credits(count, bytes);
+                    self.note_full();
+                    return Err(PushError::Full);
+                }
+            }
+        };
+        self.write(start, frames);
+        self.commit(start, start + frames.len() as u64, count, bytes);
+        Ok(())
+    }
+
+    /// Pushes elements from `iter` until one does not fit, publishing the new tail once
+    /// at the end. Returns how many elements were pushed.
+    pub fn push_all<'a, I>(&self, iter: I) -> usize
+        where I: Iterator<Item = &'a [u8]>
+    {
+        if self.receiver_dropped.load(Ordering::Acquire) || self.closed.load(Ordering::Acquire) {
+            return 0;
+        }
+        if self.policy == FullPolicy::OverwriteOldest || self.credit_flag != 0 {
+            return iter.take_while(|data| self.push(data).is_ok()).count();
+        }
+        if !self.is_multi_producer() {
+            let start = self.claim.load(Ordering::Relaxed);
+            if start & CLOSING != 0 {
+                return 0;
+            }
+            let head = self.head.load(Ordering::Acquire);
+            self.cached_head.store(head, Ordering::Relaxed);
+            let mut unused = self.capacity - self.distance(head, start);
+            let mut tail = start;
+            let mut count = 0;
+            let mut bytes = 0;
+            for data in iter {
+                if self.too_large(data.len()) || unused <= self.format.frame_size(data.len()) {
+                    break;
+                }
+                tail = self.write_frame(tail, data);
+                unused -= self.format.frame_size(data.len());
+                bytes += data.len();
+                count += 1;
+            }
+            if count > 0 {
+                self.claim.store(tail, Ordering::Relaxed);
+                self.commit(start, tail, count, bytes);
+            }
+            return count;
+        }
+
+        // Other producers may claim space concurrently, so the batch has to be sized up
+        // front and claimed in one go.
+        let frames: Vec<&[u8]> = iter.collect();
+        let mut count = 0;
+        let start = match self.claim(|free| {
+            let mut total = 0;
+            count = 0;
+            for data in frames.iter() {
+                if self.too_large(data.len()) || free <= total + self.format.frame_size(data.len()) {
+                    break;
+                }
+                total += self.format.frame_size(data.len());
+                count += 1;
+            }
+            total
+        }) {
+            Some(start) => start,
+            None => return 0,
+        };
+        let mut tail = start;
+        let mut bytes = 0;
+        for data in frames.iter().take(count) {
+            tail = self.write_frame(tail, data);
+            bytes += data.len();
+        }
+        self.commit(start, tail, count, bytes);
+        count
+    }
+
+    /// Appends the end-of-stream marker, waiting for room if need be. Closing twice is
+    /// a no-op.
+    pub fn close(&self) -> Result<(), PushError> {
+        let start = self.claim.fetch_or(CLOSING, Ordering::AcqRel);
+        if start & CLOSING != 0 {
+            return Ok(());
+        }
+        // Pushes parked for room fail now instead.
+        self.writable.notify();
+        if let LengthPrefix::Fixed(_) = self.format.prefix {
+            self.closed.store(true, Ordering::Release);
+            self.readable.notify();
+            return Ok(());
+        }
+        // Nobody else claims room any more, so the marker goes right at `start`.
+        let frame_size = self.format.width(END_OF_STREAM as usize);
+        while !self.has_room(frame_size) {
+            if self.receiver_dropped.load(Ordering::Acquire) {
+                self.closed.store(true, Ordering::Release);
+                return Err(PushError::Disconnected);
+            }
+            self.wait_writable(|| self.has_room(frame_size) || self.receiver_dropped.load(Ordering::Acquire), None);
+        }
+        let end = self.write_prefix(start, END_OF_STREAM as usize);
+        self.claim.store(end | CLOSING, Ordering::Relaxed);
+        self.commit(start, end, 0, 0);
+        // Only set once the marker is out, so that consumers never see a close without it.
+        self.closed.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Whether `close` has ended the channel for pushes.
+    fn is_closing(&self) -> bool {
+        self.claim.load(Ordering::Acquire) & CLOSING != 0
+    }
+
+    fn is_multi_producer(&self) -> bool {
+        self.mpmc || self.senders.load(Ordering::Acquire) > 1
+    }
+
+    /// Reserves `size(free)` bytes for the calling producer, where `free` is the space
+    /// currently available. Returns the start of the reservation, or `None` if `size`
+    /// asked for nothing or the channel is closed.
+    fn claim<F>(&self, mut size: F) -> Option<u64>
+        where F: FnMut(usize) -> usize
+    {
+        // Decided before the cursors are read: a clone dropped after we load `start`
+        // could otherwise let a stale snapshot take the unchecked store.
+        if !self.is_multi_producer() {
+            let start = self.claim.load(Ordering::Relaxed);
+            if start & CLOSING != 0 {
+                return None;
+            }
+            // A cached head left over from a multi-producer spell can be more than a lap
+            // behind; it then just counts as no room.
+            let cached = self.cached_head.load(Ordering::Relaxed);
+            let mut n = self.capacity.checked_sub(self.distance(cached, start)).map_or(0, &mut size);
+            if n == 0 {
+                let head = self.head.load(Ordering::Acquire);
+                self.cached_head.store(head, Ordering::Relaxed);
+                n = size(self.capacity - self.distance(head, start));
+            }
+            if n == 0 {
+                return None;
+            }
+            self.claim.store(start + n as u64, Ordering::Relaxed);
+            return Some(start);
+        }
+        loop {
+            // Loading `head` first keeps it at or behind `start`; if other producers and
+            // the consumer lapped it in between, the snapshot is stale and we start over.
+            let head = self.head.load(Ordering::Acquire);
+            let start = self.claim.load(Ordering::Relaxed);
+            // Fails the exchange below if `close` gets in first.
+            if start & CLOSING != 0 {
+                return None;
+            }
+            let free = match self.capacity.checked_sub(self.distance(head, start)) {
+                Some(free) => free,
+                None => continue,
+            };
+            let n = size(free);
+            if n == 0 {
+                return None;
+            }
+            let end = start + n as u64;
+            if self.claim.compare_exchange(start, end, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+                return Some(start);
+            }
+        }
+    }
+
+    /// Publishes the reservation `start..end`, holding `count` elements of `bytes` bytes
+    /// in total, once every earlier reservation has been published, keeping frames in
+    /// claim order.
+    fn commit(&self, start: u64, end: u64, count: usize, bytes: usize) {
+        // Counted ahead of the tail store, so a consumer never uncounts it first.
+        let queued = self.messages.fetch_add(count as u64, Ordering::Relaxed) + count as u64;
+        self.max_occupancy.fetch_max(queued, Ordering::Relaxed);
+        self.pushed.fetch_add(count as u64, Ordering::Relaxed);
+        self.pushed_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        spin_until(|| self.tail.load(Ordering::Acquire) == start);
+        self.publish(end);
+        #[cfg(feature = "tracing")]
+        if end / self.capacity as u64 > start / self.capacity as u64 {
+            tracing::trace!(channel = self.name(), lap = end / self.capacity as u64, "ring wrapped around");
+        }
+    }
+
+    /// Writes `data` with its length prefix at `tail`, returning the offset right after it.
+    fn write_frame(&self, tail: u64, data: &[u8]) -> u64 {
+        let body = self.write_prefix(tail, data.len());
+        self.write(body, data);
+        tail + self.format.frame_size(data.len()) as u64
+    }
+
+    /// Writes the length prefix for `len` bytes at `tail`, returning where they go.
+    fn write_prefix(&self, tail: u64, len: usize) -> u64 {
+        let mut buf = [0u8; MAX_PREFIX];
+        let width = self.format.prefix.encode(len as u32, &mut buf);
+        self.write(tail, &buf[..width]);
+        tail + self.format.width(len) as u64
+    }
+
+    /// Makes everything before `tail` visible to the consumer.
+    fn publish(&self, tail: u64) {
+        self.tail.store(tail, Ordering::Release);
+        #[cfg(feature = "trace")]
+        self.trace.record(TraceOp::Push, self.head.load(Ordering::Acquire), tail);
+        self.readable.notify();
+        if let Some(w) = self.watermarks.get() {
+            if self.used() >= w.high && !w.above.load(Ordering::Relaxed) && !w.above.swap(true, Ordering::AcqRel) {
+                (w.callback)(Watermark::High);
+            }
+        }
+        if let Some(queue) = self.occupancy.get() {
+            // Only a pop gets the ring out of `Full`.
+            if queue.level.load(Ordering::Relaxed) != Occupancy::Full as u8 {
+                queue.update(self.occupancy_level(queue));
+            }
+        }
+    }
+
+    fn occupancy_level(&self, queue: &OccupancyQueue) -> Occupancy {
+        match self.used() {
+            0 => Occupancy::Empty,
+            used if used >= queue.high => Occupancy::High,
+            _ => Occupancy::NonEmpty,
+        }
+    }
+
+    /// Reports a push that found no room.
+    fn note_full(&self) {
+        if let Some(queue) = self.occupancy.get() {
+            queue.update(Occupancy::Full);
+        }
+    }
+
+    /// Starts reporting transitions between `Occupancy` levels, with `High` from `high`
+    /// bytes on. A channel has at most one stream of them; returns `None` after the first.
+    pub(crate) fn occupancy_events(self: &Arc<Self>, high: usize) -> Option<OccupancyEvents> {
+        let queue = OccupancyQueue {
+            high,
+            level: AtomicU8::new(Occupancy::Empty as u8),
+            events: Mutex::new(VecDeque::new()),
+            changed: Condvar::new(),
+        };
+        self.occupancy.set(queue).ok()?;
+        let queue = self.occupancy.get().unwrap();
+        queue.update(self.occupancy_level(queue));
+        Some(OccupancyEvents { inner: self.clone() })
+    }
+
+    pub fn push_blocking(&self, data: &[u8]) -> Result<(), PushError> {
+        self.push_parts_blocking(&[], data)
+    }
+
+    pub(crate) fn push_parts_blocking(&self, prefix: &[u8], data: &[u8]) -> Result<(), PushError> {
+        self.push_vectored_blocking(&[prefix, data])
+    }
+
+    /// Like `push_vectored`, parking until the element fits.
+    pub fn push_vectored_blocking<B>(&self, parts: &[B]) -> Result<(), PushError>
+        where B: ops::Deref<Target = [u8]>
+    {
+        let size = parts.iter().map(|part| part.len()).sum();
+        loop {
+            match self.push_once(parts) {
+                Err(PushError::Full) if self.policy != FullPolicy::Reject => self.wait_writable(|| self.can_retry_push(size), None),
+                r => return self.counted(r),
+            }
+        }
+    }
+
+    /// Pushes `data` behind the 8-byte `header`, parking until it fits.
+    pub fn push_with_header(&self, header: u64, data: &[u8]) -> Result<(), PushError> {
+        self.push_parts_blocking(&header.to_le_bytes(), data)
+    }
+
+    pub fn push_deadline(&self, data: &[u8], deadline: Instant) -> Result<(), PushTimeoutError> {
+        loop {
+            match self.push_once(&[data]) {
+                Err(PushError::Full) if self.policy != FullPolicy::Reject => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return self.counted(Err(PushTimeoutError::Timeout));
+                    }
+                    self.wait_writable(|| self.can_retry_push(data.len()), Some(deadline - now));
+                }
+                r => return self.counted(r).map_err(PushTimeoutError::from),
+            }
+        }
+    }
+
+    pub fn pop<F>(&self, mut consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        let (head, len) = self.take_frame()?;
+        consumer(self.payload(head, len));
+        self.finish(head, self.next(head, len), 1);
+        Ok(())
+    }
+
+    /// Like `take`, but says why there was nothing to take.
+    fn take_frame(&self) -> Result<(u64, usize), PopError> {
+        // Read before looking at the ring: anything pushed before the last sender went
+        // away is then guaranteed to be visible.
+        let sender_dropped = self.is_sender_dropped();
+        match self.take() {
+            Some(frame) => Ok(frame),
+            None if self.is_closed() => Err(PopError::Closed),
+            None if sender_dropped => Err(PopError::Disconnected),
+            None => Err(PopError::Empty),
+        }
+    }
+
+    fn take_frame_blocking(&self) -> Result<(u64, usize), PopError> {
+        loop {
+            match self.take_frame() {
+                Err(PopError::Empty) => self.wait_readable(|| self.can_retry_pop(), None),
+                r => return r,
+            }
+        }
+    }
+
+    /// Pops up to `max` elements, publishing the new head once at the end.
+    pub fn pop_batch<F>(&self, max: usize, mut consumer: F) -> usize
+        where F: FnMut(&[u8])
+    {
+        let (start, end, count) = match self.take_batch(max) {
+            Some(batch) => batch,
+            None => return 0,
+        };
+        let mut head = start;
+        for _i in 0..count {
+            let len = self.frame_len(head);
+            consumer(self.payload(head, len));
+            head = self.next(head, len);
+        }
+        self.finish(start, end, count);
+        count
+    }
+
+    pub fn pop_while<F>(&self, mut predicate: F) -> usize
+        where F: FnMut(&[u8]) -> bool
+    {
+        let start = self.mark_peeking();
+        let mark = self.peek_mark(start);
+        let tail = self.tail.load(Ordering::Acquire);
+        let mut end = start;
+        let mut count = 0;
+        while end != tail {
+            let len = self.frame_len(end);
+            if len == END_OF_STREAM as usize || !predicate(self.payload(end, len)) {
+                break;
+            }
+            end = self.next(end, len);
+            count += 1;
+        }
+        if count == 0 {
+            return 0;
+        }
+        mem::forget(mark);
+        // As in `consume_peeked`, nobody else moves `taken` while it is marked.
+        self.taken.store(end, Ordering::Relaxed);
+        self.finish(start, end, count);
+        count
+    }
+
+    fn is_multi_consumer(&self) -> bool {
+        // An overwriting producer takes elements just like another consumer would.
+        self.mpmc || self.policy == FullPolicy::OverwriteOldest || self.receivers.load(Ordering::Acquire) > 1
+    }
+
+    /// Drops the oldest element to make room for a push. Fails if there is nothing left
+    /// to drop, i.e. the consumers have taken everything and still hold on to it.
+    fn evict(&self) -> bool {
+        match self.take_batch(1) {
+            Some((start, end, _)) => {
+                self.finish(start, end, 1);
+                self.overwritten.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Hands the oldest element to the calling consumer as its offset and payload length.
+    fn take(&self) -> Option<(u64, usize)> {
+        self.take_batch(1).map(|(head, _, _)| (head, self.frame_len(head)))
+    }
+
+    /// Hands up to `max` of the oldest elements to the calling consumer, returning where
+    /// they start and end and how many there are.
+    fn take_batch(&self, max: usize) -> Option<(u64, u64, usize)> {
+        // As in `claim`, decide on the fast path before any cursor is read.
+        let multi = self.is_multi_consumer();
+        'retry: loop {
+            let start = self.taken.load(Ordering::Relaxed);
+            if start & PEEKING != 0 {
+                spin_until(|| self.taken.load(Ordering::Relaxed) & PEEKING == 0);
+                continue;
+            }
+            // A batch wants everything there is, so only single pops settle for the
+            // cached tail, and only while it has something past `start`.
+            let mut tail = self.cached_tail.load(Ordering::Relaxed);
+            if multi || max > 1 || tail <= start {
+                tail = self.tail.load(Ordering::Acquire);
+                if !multi {
+                    self.cached_tail.store(tail, Ordering::Relaxed);
+                }
+            }
+            let available = self.distance(start, tail);
+            let mut end = start;
+            let mut walked = 0;
+            let mut count = 0;
+            // Another consumer may take and release these frames under our feet, so the
+            // lengths read here are only trusted once the compare-exchange succeeds.
+            while count < max && end != tail {
+                if self.frame_len(end) == END_OF_STREAM as usize {
+                    // The marker is never taken, so it stays put for every later pop.
+                    if self.taken.load(Ordering::Relaxed) != start {
+                        continue 'retry;
+                    }
+                    break;
+                }
+                let len = self.format.frame_size(self.frame_len(end));
+                walked += len;
+                if walked > available {
+                    continue 'retry;
+                }
+                end += len as u64;
+                count += 1;
+            }
+            if count == 0 {
+                return None;
+            }
+            if !multi {
+                self.taken.store(end, Ordering::Relaxed);
+                return Some((start, end, count));
+            }
+            // Acquire pairs with `unpeek`, so a peek is done reading before this element
+            // can be released and overwritten.
+            if self.taken.compare_exchange(start, end, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                return Some((start, end, count));
+            }
+        }
+    }
+
+    /// Releases the `count` taken elements `start..end` once every earlier one has been
+    /// released.
+    fn finish(&self, start: u64, end: u64, count: usize) {
+        self.messages.fetch_sub(count as u64, Ordering::Relaxed);
+        self.popped.fetch_add(count as u64, Ordering::Relaxed);
+        spin_until(|| self.head.load(Ordering::Acquire) == start);
+        self.release(end);
+    }
+
+    pub(crate) fn frame_len(&self, head: u64) -> usize {
+        self.format.prefix.decode(self.readable_slice(head, MAX_PREFIX)) as usize
+    }
+
+    /// The `len` bytes of the element at `head`.
+    pub(crate) fn payload(&self, head: u64, len: usize) -> &[u8] {
+        self.readable_slice(head + self.format.width(len) as u64, len)
+    }
+
+    /// Offset of the element following the one at `head`.
+    pub(crate) fn next(&self, head: u64, len: usize) -> u64 {
+        head + self.format.frame_size(len) as u64
+    }
+
+    /// Hands everything before `head` back to the producer.
+    fn release(&self, head: u64) {
+        self.head.store(head, Ordering::Release);
+        #[cfg(feature = "trace")]
+        self.trace.record(TraceOp::Pop, head, self.tail.load(Ordering::Acquire));
+        self.writable.notify();
+        if let Some(w) = self.watermarks.get() {
+            if self.used() <= w.low && w.above.load(Ordering::Relaxed) && w.above.swap(false, Ordering::AcqRel) {
+                (w.callback)(Watermark::Low);
+            }
+        }
+        if let Some(queue) = self.occupancy.get() {
+            queue.update(self.occupancy_level(queue));
+        }
+    }
+
+    pub fn pop_blocking<F>(&self, mut consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        let (head, len) = self.take_frame_blocking()?;
+        consumer(self.payload(head, len));
+        self.finish(head, self.next(head, len), 1);
+        Ok(())
+    }
+
+    pub fn pop_deadline<F>(&self, deadline: Instant, mut consumer: F) -> Result<(), PopTimeoutError>
+        where F: FnMut(&[u8])
+    {
+        loop {
+            match self.pop(&mut consumer) {
+                Err(PopError::Empty) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(PopTimeoutError::Timeout);
+                    }
+                    self.wait_readable(|| self.can_retry_pop(), Some(deadline - now));
+                }
+                r => return r.map_err(PopTimeoutError::from),
+            }
+        }
+    }
+
+    /// Copies as much of `data` as fits into the ring without any framing, parking until
+    /// at least one byte fits. Backs the stream channel.
+    pub fn write_stream(&self, data: &[u8]) -> Result<usize, PushError> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if self.receiver_dropped.load(Ordering::Acquire) {
+                return Err(PushError::Disconnected);
+            }
+            let n = data.len().min(self.unused() - 1);
+            if n > 0 {
+                let tail = self.tail.load(Ordering::Relaxed);
+                self.write(tail, &data[..n]);
+                self.claim.store(tail + n as u64, Ordering::Relaxed);
+                self.publish(tail + n as u64);
+                return Ok(n);
+            }
+            self.wait_writable(|| self.unused() > 1 || self.receiver_dropped.load(Ordering::Acquire), None);
+        }
+    }
+
+    /// Copies up to `buf.len()` unframed bytes out of the ring, parking until at least one
+    /// byte is available. Returns 0 once the writer is gone and everything has been read.
+    pub fn read_stream(&self, buf: &mut [u8]) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+        loop {
+            let n = buf.len().min(self.used());
+            if n > 0 {
+                let head = self.head.load(Ordering::Relaxed);
+                buf[..n].copy_from_slice(self.readable_slice(head, n));
+                self.taken.store(head + n as u64, Ordering::Relaxed);
+                self.release(head + n as u64);
+                return n;
+            }
+            if self.sender_dropped.load(Ordering::Acquire) {
+                if self.is_empty() {
+                    return 0;
+                }
+                continue;
+            }
+            self.wait_readable(|| !self.is_empty() || self.sender_dropped.load(Ordering::Acquire), None);
+        }
+    }
+
+    #[cfg(feature = "async")]
+    pub fn register_readable(&self, waker: &Waker) {
+        self.readable.waker.register(waker);
+    }
+
+    #[cfg(feature = "async")]
+    pub fn register_writable(&self, waker: &Waker) {
+        self.writable.waker.register(waker);
+    }
+
+    pub fn is_sender_dropped(&self) -> bool {
+        self.sender_dropped.load(Ordering::Acquire)
+    }
+
+    pub fn disconnect_sender(&self) {
+        self.sender_dropped.store(true, Ordering::Release);
+        self.readable.notify();
+        self.wake_occupancy_events();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(channel = self.name(), "sender disconnected");
+    }
+
+    pub fn disconnect_receiver(&self) {
+        self.receiver_dropped.store(true, Ordering::Release);
+        self.writable.notify();
+        self.wake_occupancy_events();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(channel = self.name(), "receiver disconnected");
+    }
+
+    /// Lets a waiting `OccupancyEvents` see the disconnect.
+    fn wake_occupancy_events(&self) {
+        if let Some(queue) = self.occupancy.get() {
+            let _events = queue.events.lock().unwrap();
+            queue.changed.notify_all();
+        }
+    }
+
+    /// Maps the data of a shared ring read-only, for a process that only receives from it,
+    /// so that stray writes fault instead of corrupting what the senders wrote. The header
+    /// is a page of its own and stays writable.
+    #[cfg(unix)]
+    fn protect_data(&self) -> io::Result<()> {
+        if unsafe { libc::mprotect(self.pointer.as_ptr() as *mut c_void, 2 * self.capacity, PROT_READ) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Accounts for a sender attaching to a shared ring.
+    fn join_senders(&self) {
+        self.senders.fetch_add(1, Ordering::AcqRel);
+        self.sender_peer.join();
+    }
+
+    /// Accounts for a receiver attaching to a shared ring, taking over if the last ones
+    /// went with processes that died. Only then can nobody be in the middle of a pop.
+    fn join_receivers(&self) {
+        let peer = &self.receiver_peer;
+        if !peer.reap(&self.receivers)
+            && peer.abandoned.compare_exchange(ABANDONED, RECOVERING, Ordering::AcqRel, Ordering::Acquire).is_ok()
+        {
+            self.recover_receivers();
+            self.receivers.fetch_add(1, Ordering::AcqRel);
+            peer.abandoned.store(0, Ordering::Release);
+        } else {
+            // Whoever takes over goes first.
+            spin_until(|| peer.abandoned.load(Ordering::Acquire) != RECOVERING);
+            self.receivers.fetch_add(1, Ordering::AcqRel);
+        }
+        peer.join();
+    }
+
+    /// Rolls the header of a ring nobody has open back to the last intact element before
+    /// `tail`. Whatever was claimed, taken or parked on belonged to processes that are gone.
+    #[cfg(unix)]
+    fn recover_log<F>(&self, valid: F)
+        where F: Fn(&[u8]) -> bool
+    {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        let max_len = self.format.max_len(self.capacity);
+        let (mut end, mut messages, mut closed) = (head, 0, false);
+        while end < tail {
+            let len = self.frame_len(end);
+            if len == END_OF_STREAM as usize {
+                let marker = end + self.format.width(len) as u64;
+                if marker <= tail {
+                    end = marker;
+                    closed = true;
+                }
+                break;
+            }
+            if len > max_len || self.next(end, len) > tail || !valid(self.payload(end, len)) {
+                break;
+            }
+            end = self.next(end, len);
+            messages += 1;
+        }
+        self.tail.store(end, Ordering::Release);
+        self.claim.store(if closed { end | CLOSING } else { end }, Ordering::Release);
+        self.taken.store(head, Ordering::Release);
+        self.messages.store(messages, Ordering::Release);
+        self.closed.store(closed, Ordering::Release);
+        self.senders.store(0, Ordering::Release);
+        self.receivers.store(0, Ordering::Release);
+        self.sender_dropped.store(false, Ordering::Release);
+        self.receiver_dropped.store(false, Ordering::Release);
+        self.sender_peer.reset();
+        self.receiver_peer.reset();
+        for parking in [&self.readable_parking, &self.writable_parking] {
+            parking.seq.store(0, Ordering::Release);
+            parking.waiters.store(0, Ordering::Release);
+        }
+    }
+
+    /// Takes over a shared ring whose receivers all went with processes that died, rolling
+    /// back whatever they had taken and not released yet: `head` only moves once a pop is
+    /// done with the bytes before it.
+    fn recover_receivers(&self) {
+        self.taken.store(self.head.load(Ordering::Acquire), Ordering::Release);
+        self.receiver_dropped.store(false, Ordering::Release);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(channel = self.name(), "recovered from dead receiver");
+    }
+
+    /// End of the published elements.
+    pub(crate) fn tail(&self) -> u64 {
+        self.tail.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn head(&self) -> u64 {
+        self.head.load(Ordering::Acquire)
+    }
+
+    /// Moves the producer's view of the oldest unconsumed byte, for layers that track
+    /// consumption themselves.
+    pub(crate) fn set_head(&self, head: u64) {
+        self.head.store(head, Ordering::Release);
+        #[cfg(feature = "trace")]
+        self.trace.record(TraceOp::Pop, head, self.tail.load(Ordering::Acquire));
+    }
+
+    /// Waits on `readable` like `Signal::wait`. On a shared ring it wakes up every
+    /// `PEER_CHECK` to look for the senders' processes, and counts them as dropped once
+    /// all of them are gone, since a crashed process never says so itself.
+    pub(crate) fn wait_readable<F>(&self, ready: F, timeout: Option<Duration>)
+        where F: Fn() -> bool
+    {
+        if self.shared.is_none() {
+            return self.readable.wait(ready, timeout);
+        }
+        self.receiver_peer.beat();
+        self.readable.wait(&ready, Some(timeout.map_or(PEER_CHECK, |t| t.min(PEER_CHECK))));
+        if !ready() && !self.sender_peer.reap(&self.senders) {
+            self.sender_dropped.store(true, Ordering::Release);
+        }
+    }
+
+    /// Like `wait_readable`, for `writable` and the receivers' processes.
+    pub(crate) fn wait_writable<F>(&self, ready: F, timeout: Option<Duration>)
+        where F: Fn() -> bool
+    {
+        if self.shared.is_none() {
+            return self.writable.wait(ready, timeout);
+        }
+        self.sender_peer.beat();
+        self.writable.wait(&ready, Some(timeout.map_or(PEER_CHECK, |t| t.min(PEER_CHECK))));
+        if !ready() && !self.receiver_peer.reap(&self.receivers) {
+            self.receiver_dropped.store(true, Ordering::Release);
+        }
+    }
+
+    /// Whether a sender may still push: one is left, and on a shared ring one of the
+    /// processes holding them is still running.
+    pub fn is_sender_alive(&self) -> bool {
+        !self.sender_dropped.load(Ordering::Acquire) && (self.shared.is_none() || self.sender_peer.reap(&self.senders))
+    }
+
+    pub fn is_receiver_alive(&self) -> bool {
+        !self.receiver_dropped.load(Ordering::Acquire) && (self.shared.is_none() || self.receiver_peer.reap(&self.receivers))
+    }
+
+    /// Marks the senders' side of a shared ring as alive, for `Receiver::peer_heartbeat`.
+    pub fn sender_heartbeat(&self) {
+        self.sender_peer.beat();
+    }
+
+    pub fn receiver_heartbeat(&self) {
+        self.receiver_peer.beat();
+    }
+
+    /// When the senders' side of a shared ring last showed signs of life.
+    pub fn sender_last_seen(&self) -> Option<SystemTime> {
+        self.shared.as_ref().and_then(|_| self.sender_peer.last_seen())
+    }
+
+    pub fn receiver_last_seen(&self) -> Option<SystemTime> {
+        self.shared.as_ref().and_then(|_| self.receiver_peer.last_seen())
+    }
+
+    pub(crate) fn notify_writable(&self) {
+        self.writable.notify()
+    }
+
+    pub(crate) fn notify_readable(&self) {
+        self.readable.notify()
+    }
+
+    pub(crate) fn is_receiver_dropped(&self) -> bool {
+        self.receiver_dropped.load(Ordering::Acquire)
+    }
+
+    /// Whether the next element is the end-of-stream marker, or for fixed-size records
+    /// whether the ring has drained after a close.
+    fn is_closed(&self) -> bool {
+        if let LengthPrefix::Fixed(_) = self.format.prefix {
+            return self.closed.load(Ordering::Acquire)
+                && self.taken.load(Ordering::Acquire) & !PEEKING == self.tail.load(Ordering::Acquire);
+        }
+        let start = self.taken.load(Ordering::Acquire) & !PEEKING;
+        // Until `taken` moves past `start` nobody can release and overwrite that frame.
+        start != self.tail.load(Ordering::Acquire)
+            && self.frame_len(start) == END_OF_STREAM as usize
+            && self.taken.load(Ordering::Acquire) & !PEEKING == start
+    }
+
+    fn can_retry_pop(&self) -> bool {
+        self.taken.load(Ordering::Acquire) & !PEEKING != self.tail.load(Ordering::Acquire) || self.is_sender_dropped()
+            || self.closed.load(Ordering::Acquire)
+    }
+
+    /// Marks the oldest element as peeked at and returns where it is and how long.
+    pub(crate) fn peek_frame(&self) -> Result<(u64, usize), PopError> {
+        let sender_dropped = self.is_sender_dropped();
+        loop {
+            let start = self.taken.load(Ordering::Relaxed);
+            if start & PEEKING != 0 {
+                spin_until(|| self.taken.load(Ordering::Relaxed) & PEEKING == 0);
+                continue;
+            }
+            if start == self.tail.load(Ordering::Acquire) {
+                return Err(if self.is_closed() {
+                    PopError::Closed
+                } else if sender_dropped {
+                    PopError::Disconnected
+                } else {
+                    PopError::Empty
+                });
+            }
+            if self.taken.compare_exchange(start, start | PEEKING, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+                let len = self.frame_len(start);
+                if len == END_OF_STREAM as usize {
+                    self.unpeek(start);
+                    return Err(PopError::Closed);
+                }
+                return Ok((start, len));
+            }
+        }
+    }
+
+    /// Like `peek_frame`, but parks until there is an element.
+    pub(crate) fn peek_frame_blocking(&self) -> Result<(u64, usize), PopError> {
+        loop {
+            match self.peek_frame() {
+                Err(PopError::Empty) => self.wait_readable(|| self.can_retry_pop(), None),
+                r => return r,
+            }
+        }
+    }
+
+    /// Marks `taken` as peeked at, whether or not there is anything there, and returns it.
+    fn mark_peeking(&self) -> u64 {
+        loop {
+            let start = self.taken.load(Ordering::Relaxed);
+            if start & PEEKING != 0 {
+                spin_until(|| self.taken.load(Ordering::Relaxed) & PEEKING == 0);
+                continue;
+            }
+            if self.taken.compare_exchange(start, start | PEEKING, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+                return start;
+            }
+        }
+    }
 
-pub struct Receiver {
-    inner: Arc<UnsafeCell<CBuffer>>,
-}
+    /// Hands the element at `head` back after `peek_frame`.
+    pub(crate) fn unpeek(&self, head: u64) {
+        self.taken.store(head, Ordering::Release);
+    }
 
-unsafe impl Send for Receiver {}
+    /// Unpeeks `head` when dropped, so that a consumer panicking on a peeked element
+    /// does not leave every other consumer waiting for the mark to go.
+    fn peek_mark(&self, head: u64) -> PeekMark<'_> {
+        PeekMark { buffer: self, head }
+    }
 
-pub fn channel(s: BufferSize) -> (Sender, Receiver) {
-    let a = Arc::new(UnsafeCell::new(CBuffer::with_capacity(s).expect("fail to create cbuffer.")));
-    (Sender::new(a.clone()), Receiver::new(a))
-}
+    /// Copies every queued frame, an end-of-stream marker included, while competing
+    /// receivers wait. Returns the frames and how many elements they hold.
+    pub(crate) fn snapshot(&self) -> (Vec<u8>, usize) {
+        let start = self.mark_peeking();
+        let tail = self.tail.load(Ordering::Acquire);
+        let frames = self.readable_slice(start, self.distance(start, tail)).to_vec();
+        self.unpeek(start);
+        // Published frames always add up.
+        let (count, _closed) = self.format.walk(&frames).unwrap();
+        (frames, count)
+    }
 
-impl Sender {
-    fn new(inner: Arc<UnsafeCell<CBuffer>>) -> Sender {
-        Sender { inner }
+    /// Copies out every queued element, oldest first, while competing receivers wait.
+    pub(crate) fn dump(&self) -> Vec<Vec<u8>> {
+        let start = self.mark_peeking();
+        let tail = self.tail.load(Ordering::Acquire);
+        let mut elems = Vec::new();
+        let mut head = start;
+        while head != tail {
+            let len = self.frame_len(head);
+            if len == END_OF_STREAM as usize {
+                break;
+            }
+            elems.push(self.payload(head, len).to_vec());
+            head = self.next(head, len);
+        }
+        self.unpeek(start);
+        elems
     }
 
-    pub fn try_push(&mut self, elem: &[u8]) -> bool {
-        unsafe { (*self.inner.get()).push(elem) }
+    /// Fills this fresh ring with `frames` saved by `snapshot`.
+    fn restore(&self, frames: &[u8]) -> io::Result<()> {
+        let (count, closed) = self.format.walk(frames)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "corrupted snapshot"))?;
+        if frames.len() >= self.capacity {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "snapshot does not fit the ring"));
+        }
+        self.write(0, frames);
+        let end = frames.len() as u64;
+        self.claim.store(if closed { end | CLOSING } else { end }, Ordering::Relaxed);
+        self.messages.store(count as u64, Ordering::Relaxed);
+        self.closed.store(closed, Ordering::Release);
+        self.publish(frames.len() as u64);
+        Ok(())
     }
 
-    pub fn push(&mut self, elem: &[u8]) {
-        if !unsafe { (*self.inner.get()).push(elem) } {
-            std::thread::sleep(Duration::from_micros(5));
+    /// Pops the oldest element into `buf` unless it does not fit, in which case it is
+    /// left in place.
+    pub fn read_into(&self, buf: &mut [u8]) -> Result<usize, PopError> {
+        let (head, len) = self.peek_frame()?;
+        if len > buf.len() {
+            self.unpeek(head);
+            return Err(PopError::BufferTooSmall);
         }
+        buf[..len].copy_from_slice(self.payload(head, len));
+        self.consume_peeked(head, len);
+        Ok(len)
     }
-}
 
-impl Receiver {
-    fn new(inner: Arc<UnsafeCell<CBuffer>>) -> Receiver {
-        Receiver { inner }
+    /// Pops the element marked by `peek_frame`.
+    fn consume_peeked(&self, head: u64, len: usize) {
+        // Nobody else moves `taken` while it is marked, so the peek becomes a take.
+        let end = self.next(head, len);
+        self.taken.store(end, Ordering::Relaxed);
+        self.finish(head, end, 1);
     }
 
-    pub fn try_pop<F>(&self, consumer: F) -> bool
-        where F: FnMut(&[u8]) -> ()
+    /// Pops one element pushed by `push_with_header`, parking until there is one. An
+    /// element too short to carry a header is left in place.
+    pub fn pop_with_header<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u64, &[u8])
     {
-        unsafe { (*self.inner.get()).pop(consumer) }
+        let (head, len) = self.peek_frame_blocking()?;
+        self.pop_peeked_with_header(head, len, consumer)
     }
 
-    pub fn pop<F>(&self, consumer: F)
-        where F: FnMut(&[u8]) -> ()
+    pub fn try_pop_with_header<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u64, &[u8])
     {
-        if !unsafe { (*self.inner.get()).pop(consumer) } {
-            std::thread::sleep(Duration::from_micros(5));
-        }
+        let (head, len) = self.peek_frame()?;
+        self.pop_peeked_with_header(head, len, consumer)
     }
-}
-
-
-#[allow(missing_docs)]
-#[derive(Clone, Copy, Debug, PartialEq)]
-pub enum Error {
-    OS,
-    Overflow,
-    Underflow,
-}
-
-impl std::error::Error for Error {
-    fn description(&self) -> &str { "cbuffer error" }
-    fn cause(&self) -> Option<& dyn std::error::Error> { None }
-}
 
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-        match *self {
-            Error::OS => write!(f, "OS error"),
-            Error::Overflow => write!(f, "overflow"),
-            Error::Underflow => write!(f, "underflow"),
+    fn pop_peeked_with_header<F>(&self, head: u64, len: usize, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u64, &[u8])
+    {
+        if len < 8 {
+            self.unpeek(head);
+            return Err(PopError::MissingHeader);
         }
+        let (bytes, data) = self.payload(head, len).split_at(8);
+        let mut header = [0u8; 8];
+        header.copy_from_slice(bytes);
+        let mark = self.peek_mark(head);
+        consumer(u64::from_le_bytes(header), data);
+        mem::forget(mark);
+        self.consume_peeked(head, len);
+        Ok(())
     }
-}
 
-impl From<std::num::TryFromIntError> for Error {
-    fn from(_err: std::num::TryFromIntError) -> Error {
-        Error::OS
+    pub fn peek<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(&[u8])
+    {
+        let (head, len) = self.peek_frame()?;
+        let _mark = self.peek_mark(head);
+        consumer(self.payload(head, len));
+        Ok(())
     }
-}
 
-pub const BUF_128M: u32 = 27;
-pub const BUF_256M: u32 = 28;
-pub const BUF_512M: u32 = 29;
+    pub fn is_empty(&self) -> bool {
+        self.tail.load(Ordering::Acquire) == self.head.load(Ordering::Acquire)
+    }
 
-#[allow(dead_code)]
-pub enum BufferSize {
-    Buf64M,
-    Buf128M,
-    Buf256M,
-    Buf512M,
-}
+    /// Number of queued elements. Runs ahead of what can be popped while a push is
+    /// still being published.
+    pub fn len(&self) -> usize {
+        self.messages.load(Ordering::Relaxed) as usize
+    }
 
-pub fn page_size() -> usize {
-    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
-}
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
 
-pub struct CBuffer {
-    capacity: usize,
-    pointer: ptr::NonNull<u8>,
-    head: AtomicCell<u32>,
-    tail: AtomicCell<u32>,
-}
+    pub fn overwritten(&self) -> u64 {
+        self.overwritten.load(Ordering::Relaxed)
+    }
 
-unsafe impl Send for CBuffer {}
+    pub fn stats(&self) -> Stats {
+        Stats {
+            messages: self.pushed.load(Ordering::Relaxed),
+            bytes: self.pushed_bytes.load(Ordering::Relaxed),
+            popped: self.popped.load(Ordering::Relaxed),
+            failed_pushes: self.failed_pushes.load(Ordering::Relaxed),
+            max_occupancy: self.max_occupancy.load(Ordering::Relaxed),
+        }
+    }
 
-unsafe impl Sync for CBuffer {}
+    /// Counts `result` towards `Stats::failed_pushes` if it is an error.
+    fn counted<E: std::fmt::Debug>(&self, result: Result<(), E>) -> Result<(), E> {
+        if let Err(_err) = &result {
+            self.failed_pushes.fetch_add(1, Ordering::Relaxed);
+            #[cfg(feature = "tracing")]
+            tracing::debug!(channel = self.name(), error = ?_err, "push failed");
+        }
+        result
+    }
 
-impl CBuffer {
-    pub fn with_capacity(s: BufferSize) -> Result<Self, Error> {
-        let capacity = match s {
-            BufferSize::Buf64M => {
-                64 * 1024 * 1024usize
-            }
-            BufferSize::Buf128M => {
-                128 * 1024 * 1024usize
-            }
-            BufferSize::Buf256M => {
-                256 * 1024 * 1024usize
-            }
-            BufferSize::Buf512M => {
-                512 * 1024 * 1024usize
-            }
-        };
+    /// Whether an element of `size` bytes can never be pushed: its length does not fit the
+    /// prefix, it would not fit even into the empty ring, or it exceeds the configured
+    /// maximum.
+    pub(crate) fn too_large(&self, size: usize) -> bool {
+        size >= self.format.prefix.end_of_stream() as usize || !self.format.prefix.accepts(size)
+            || self.format.frame_size(size) >= self.capacity
+            || size > self.max_message_size.load(Ordering::Relaxed)
+    }
 
-        unsafe {
-            let checked_mmap = |ptr, size, prot, flags| {
-                let p = mmap(ptr, size, prot, flags, -1, 0);
-                if p == MAP_FAILED { return Err(Error::OS); }
-                Ok(p)
-            };
+    /// Largest element a push accepts.
+    pub fn max_message_size(&self) -> usize {
+        let max = self.max_message_size.load(Ordering::Relaxed);
+        max.min(self.format.max_len(self.capacity))
+    }
 
-            let base_pointer = checked_mmap(ptr::null_mut(),
-                                            2 * capacity,
-                                            PROT_NONE,
-                                            MAP_ANONYMOUS | MAP_PRIVATE)?;
-            let primary = checked_mmap(base_pointer,
-                                       capacity,
-                                       PROT_READ | PROT_WRITE,
-                                       MAP_FIXED | MAP_SHARED | MAP_ANONYMOUS)?;
-            checked_mmap(base_pointer.offset(capacity as isize),
-                         capacity,
-                         PROT_READ | PROT_WRITE,
-                         MAP_FIXED | MAP_SHARED | MAP_ANONYMOUS)?;
-
-            Ok(CBuffer {
-                capacity,
-                pointer: ptr::NonNull::new(primary as *mut u8).ok_or(Error::OS).unwrap(),
-                head: AtomicCell::new(0u32),
-                tail: AtomicCell::new(0u32),
-            })
-        }
-    }
-
-    pub fn push(&mut self, data: &[u8]) -> bool {
-        let size = data.len();
-        let tail = self.tail.load() as usize;
-        let head = self.head.load() as usize;
-        let used = if head <= tail {
-            (tail - head) as usize
-        } else {
-            self.capacity - (head as usize - tail as usize)
-        };
-        let unused = self.capacity - used;
+    /// Binds the ring's pages to NUMA node `node`, moving any already in use.
+    #[cfg(target_os = "linux")]
+    pub fn bind_to_node(&self, node: usize) -> io::Result<()> {
+        crate::numa::bind(self.pointer.as_ptr(), 2 * self.capacity, node)
+    }
 
-        if unused <= size + 4 {
-            return false;
+    /// Passes `advice` on to the kernel for the ring's pages. Fails with
+    /// `io::ErrorKind::Unsupported` for memory that is not mirrored, as with the `Heap`
+    /// and `Static` backends, whose pages the ring may share with other allocations.
+    #[cfg(unix)]
+    pub fn advise(&self, advice: Advice) -> io::Result<()> {
+        if self.copy_mirror {
+            return Err(io::ErrorKind::Unsupported.into());
         }
-        self.writable_slice(tail as isize, 4).copy_from_slice(&transform_u32_to_array_of_u8(size as u32));
-        self.writable_slice((tail + 4) as isize, size).copy_from_slice(data);
-        if self.capacity < (tail + size + 4) as usize {
-            self.tail.store(((tail + size + 4) as usize % self.capacity) as u32);
-        } else {
-            // self.head_tail.store((head, tail + size));
-            self.tail.store((tail + size + 4) as u32);
+        let flag = match advice {
+            #[cfg(target_os = "linux")]
+            Advice::DontDump => libc::MADV_DONTDUMP,
+            #[cfg(target_os = "linux")]
+            Advice::HugePage => libc::MADV_HUGEPAGE,
+            Advice::WillNeed => libc::MADV_WILLNEED,
+            #[cfg(not(target_os = "linux"))]
+            Advice::DontDump | Advice::HugePage => return Ok(()),
+        };
+        if unsafe { libc::madvise(self.pointer.as_ptr() as *mut c_void, 2 * self.capacity, flag) } < 0 {
+            return Err(io::Error::last_os_error());
         }
-        true
+        Ok(())
     }
 
-    pub fn pop<F>(&self, mut consumer: F) -> bool
-        where F: FnMut(&[u8]) -> ()
-    {
-        let tail = self.tail.load() as usize;
-        let head = self.head.load() as usize;
-        if head == tail {
-            return false;
+    /// Faults in the pages of both views. Another process may already be using a shared
+    /// ring, so each page is touched with an atomic add of zero rather than a write.
+    pub fn prefault(&self) {
+        let page = page_size();
+        let start = self.pointer.as_ptr() as usize / page * page;
+        let end = (self.pointer.as_ptr() as usize + 2 * self.capacity).div_ceil(page) * page;
+        // Linux 5.14 and later do it in one call.
+        #[cfg(target_os = "linux")]
+        if unsafe { libc::madvise(start as *mut c_void, end - start, libc::MADV_POPULATE_WRITE) } == 0 {
+            return;
+        }
+        // Heap rings need not start on a page boundary, so touch the first byte of each
+        // page that lies within the ring.
+        let first = self.pointer.as_ptr() as usize;
+        for addr in (start..end).step_by(page).map(|addr| addr.max(first)) {
+            unsafe { (*(addr as *const AtomicU8)).fetch_add(0, Ordering::Relaxed); }
         }
-        let len = transform_array_of_u8_to_u32(self.readable_slice(head as isize, 4).to_vec().as_slice());
-        let rt = self.readable_slice((head + 4) as isize, len as usize);
-        consumer(rt);
-        self.head.store(len + 4 + head as u32);
-        true
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.tail.load() == self.head.load()
+    pub fn name(&self) -> &str {
+        self.name.get().map_or("", |name| name)
+    }
+
+    #[cfg(feature = "trace")]
+    pub fn trace(&self) -> Vec<TraceEvent> {
+        self.trace.events()
     }
 
     pub fn size(&self) -> usize {
@@ -218,58 +3953,367 @@ impl CBuffer {
     }
 
     pub fn used(&self) -> usize {
-        let (head, tail) = {
-            (self.head.load(),
-             self.tail.load())
-        };
-        if head <= tail {
-            (tail - head) as usize
-        } else {
-            self.capacity - (head as usize - tail as usize)
-        }
+        // A stale `head` can put `tail` more than a lap ahead of it.
+        self.distance(self.head.load(Ordering::Acquire), self.tail.load(Ordering::Acquire)).min(self.capacity)
     }
 
     pub fn unused(&self) -> usize {
         self.capacity - self.used()
     }
 
-    fn readable_slice(&self, head: isize, len: usize) -> &[u8] {
+    /// Bytes between `head` and `tail`.
+    fn distance(&self, head: u64, tail: u64) -> usize {
+        frame::distance(head, tail)
+    }
+
+    pub(crate) fn fits(&self, size: usize) -> bool {
+        self.has_room(self.format.frame_size(size))
+    }
+
+    /// Whether `frame_size` bytes of frames can be claimed right now.
+    fn has_room(&self, frame_size: usize) -> bool {
+        self.free() >= frame_size
+    }
+
+    /// Bytes of frames that can be claimed right now; claims leave at least one byte of
+    /// the ring free.
+    pub fn free(&self) -> usize {
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            let used = self.distance(head, self.claim.load(Ordering::Acquire) & !CLOSING);
+            if used <= self.capacity {
+                return (self.capacity - used).saturating_sub(1);
+            }
+        }
+    }
+
+    fn can_retry_push(&self, size: usize) -> bool {
+        self.fits(size) && self.has_credits(1, size) || self.receiver_dropped.load(Ordering::Acquire) || self.is_closing()
+    }
+
+    /// Offset of cursor position `pos` in the primary mapping.
+    fn offset(&self, pos: u64) -> usize {
+        frame::offset(pos, self.capacity)
+    }
+
+    /// Start of the ring's memory, for layers that lay it out themselves instead of
+    /// framing elements into it. Only the first `size()` bytes are theirs.
+    pub(crate) fn data(&self) -> *mut u8 {
+        self.pointer.as_ptr()
+    }
+
+    pub(crate) fn readable_slice(&self, head: u64, len: usize) -> &[u8] {
         unsafe {
-            slice::from_raw_parts(self.pointer.as_ptr().offset(head), len)
+            slice::from_raw_parts(self.pointer.as_ptr().add(self.offset(head)), len)
         }
     }
 
-    fn writable_slice(&self, tail: isize, len: usize) -> &mut [u8] {
+    fn write(&self, tail: u64, data: &[u8]) {
+        let offset = self.offset(tail);
         unsafe {
-            slice::from_raw_parts_mut(self.pointer.as_ptr().offset(tail), len)
+            ptr::copy_nonoverlapping(data.as_ptr(), self.pointer.as_ptr().add(offset), data.len());
+            if self.copy_mirror {
+                // Keep both halves identical, as the second view of a mapping would.
+                let (low, high) = data.split_at((self.capacity - offset).min(data.len()));
+                ptr::copy_nonoverlapping(low.as_ptr(), self.pointer.as_ptr().add(offset + self.capacity), low.len());
+                ptr::copy_nonoverlapping(high.as_ptr(), self.pointer.as_ptr(), high.len());
+            }
         }
     }
 }
 
 impl Drop for CBuffer {
     fn drop(&mut self) {
+        // `memory` goes with the other fields.
+        unsafe {
+            match self.shared {
+                #[cfg(unix)]
+                Some(ref shared) => {
+                    if let Some(fd) = shared.fd {
+                        munmap(self.state.as_ptr() as *mut c_void, page_size());
+                        close(fd);
+                    }
+                    if let (true, Some(name)) = (shared.owner, &shared.name) {
+                        libc::shm_unlink(name.as_ptr());
+                    }
+                }
+                _ => drop(Box::from_raw(self.state.as_ptr())),
+            }
+        }
+    }
+}
+
+/// Maps a fresh mirrored ring of `capacity` bytes on huge pages if possible, falling back
+/// to transparent huge pages and then to whatever the kernel hands out.
+#[cfg(target_os = "linux")]
+fn map_ring_huge(capacity: usize) -> Result<ptr::NonNull<u8>, Error> {
+    if let Some(huge) = huge_page_size().filter(|huge| capacity.is_multiple_of(*huge)) {
         unsafe {
-            // It's not clear what makes the most sense for handling
-            // errors in `drop`, but the consensus seems to be either
-            // ignore the error, or panic.
-            if munmap(self.pointer.as_ptr().offset(0) as *mut c_void, 2*self.capacity) < 0 {
-                panic!("munmap({:p}, {}) failed", self.pointer, 2*self.capacity)
+            let fd = libc::memfd_create(b"cbuffer\0".as_ptr() as *const libc::c_char,
+                                        libc::MFD_CLOEXEC | libc::MFD_HUGETLB);
+            if fd >= 0 {
+                let pointer = if ftruncate(fd, capacity as off_t) < 0 {
+                    Err(Error::OS)
+                } else {
+                    map_mirror_aligned(fd, 0, capacity, huge)
+                };
+                close(fd);
+                if pointer.is_ok() {
+                    return pointer;
+                }
             }
         }
     }
+    let pointer = map_ring(capacity)?;
+    unsafe { libc::madvise(pointer.as_ptr() as *mut c_void, 2 * capacity, libc::MADV_HUGEPAGE); }
+    Ok(pointer)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn map_ring_huge(capacity: usize) -> Result<ptr::NonNull<u8>, Error> {
+    map_ring(capacity)
+}
+
+/// Default huge page size, as reported by `/proc/meminfo`.
+#[cfg(target_os = "linux")]
+fn huge_page_size() -> Option<usize> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|line| line.starts_with("Hugepagesize:"))?;
+    let kb: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// Maps a fresh mirrored ring of `capacity` bytes.
+fn map_ring(capacity: usize) -> Result<ptr::NonNull<u8>, Error> {
+    // Both halves have to map the same pages for the mirror to work, so they are
+    // views of one shared memory object rather than two anonymous mappings.
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        let fd = backing_fd(capacity)?;
+        let pointer = map_mirror(fd, 0, capacity);
+        unsafe { close(fd); }
+        pointer
+    }
+    #[cfg(target_os = "macos")]
+    {
+        crate::macos::map_mirror(capacity)
+    }
+    #[cfg(windows)]
+    {
+        crate::windows::map_mirror(capacity)
+    }
+}
+
+#[cfg(unix)]
+unsafe fn unmap_ring(pointer: ptr::NonNull<u8>, capacity: usize) {
+    // It's not clear what makes the most sense for handling
+    // errors in `drop`, but the consensus seems to be either
+    // ignore the error, or panic.
+    if munmap(pointer.as_ptr() as *mut c_void, 2 * capacity) < 0 {
+        panic!("munmap({:p}, {}) failed", pointer, 2 * capacity)
+    }
+}
+
+#[cfg(windows)]
+unsafe fn unmap_ring(pointer: ptr::NonNull<u8>, capacity: usize) {
+    crate::windows::unmap_mirror(pointer, capacity);
+}
+
+/// Maps `capacity` bytes of `fd`, starting at `offset`, twice back to back.
+#[cfg(unix)]
+fn map_mirror(fd: c_int, offset: usize, capacity: usize) -> Result<ptr::NonNull<u8>, Error> {
+    map_mirror_aligned(fd, offset, capacity, page_size())
+}
+
+/// Like `map_mirror`, with the views placed at a multiple of `align`, as hugetlbfs needs.
+#[cfg(unix)]
+fn map_mirror_aligned(fd: c_int, offset: usize, capacity: usize, align: usize) -> Result<ptr::NonNull<u8>, Error> {
+    unsafe {
+        let checked_mmap = |ptr, size, prot, flags, fd, offset| {
+            let p = mmap(ptr, size, prot, flags, fd, offset as off_t);
+            if p == MAP_FAILED { return Err(Error::OS); }
+            Ok(p)
+        };
+
+        let slack = align - page_size();
+        let reservation = checked_mmap(ptr::null_mut(),
+                                       2 * capacity + slack,
+                                       PROT_NONE,
+                                       MAP_ANONYMOUS | MAP_PRIVATE,
+                                       -1,
+                                       0)? as *mut u8;
+        // Trim the reservation down to an aligned `2 * capacity` bytes.
+        let lead = (align - reservation as usize % align) % align;
+        if lead > 0 {
+            munmap(reservation as *mut c_void, lead);
+        }
+        if slack > lead {
+            munmap(reservation.add(lead + 2 * capacity) as *mut c_void, slack - lead);
+        }
+        let base_pointer = reservation.add(lead) as *mut c_void;
+        let views = checked_mmap(base_pointer,
+                                 capacity,
+                                 PROT_READ | PROT_WRITE,
+                                 MAP_FIXED | MAP_SHARED,
+                                 fd,
+                                 offset)
+            .and_then(|_| checked_mmap(base_pointer.add(capacity),
+                                       capacity,
+                                       PROT_READ | PROT_WRITE,
+                                       MAP_FIXED | MAP_SHARED,
+                                       fd,
+                                       offset));
+        if let Err(e) = views {
+            munmap(base_pointer, 2 * capacity);
+            return Err(e);
+        }
+        Ok(ptr::NonNull::new_unchecked(base_pointer as *mut u8))
+    }
+}
+
+/// Maps the header page and the mirrored ring behind it of a shared memory object.
+#[cfg(unix)]
+fn map_shared(fd: c_int, capacity: usize) -> io::Result<(MirroredMapping, ptr::NonNull<State>)> {
+    let page = page_size();
+    let pointer = map_mirror(fd, page, capacity).map_err(|_| io::Error::last_os_error())?;
+    let data = MirroredMapping { pointer, capacity };
+    let header = unsafe { mmap(ptr::null_mut(), page, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0) };
+    if header == MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((data, unsafe { ptr::NonNull::new_unchecked(header as *mut State) }))
+}
+
+/// Reads back a file written by `Receiver::snapshot`.
+fn read_snapshot(path: &Path) -> io::Result<(Framing, Vec<u8>)> {
+    let mut bytes = std::fs::read(path)?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a cbuffer snapshot");
+    let header = match bytes.get(..8) {
+        Some(magic) if magic[..7] == SNAPSHOT_MAGIC[..7] && magic[7] == 1 => SNAPSHOT_HEADER - 4,
+        Some(magic) if magic == SNAPSHOT_MAGIC => SNAPSHOT_HEADER,
+        _ => return Err(invalid()),
+    };
+    if bytes.len() < header {
+        return Err(invalid());
+    }
+    let word = |at: usize| u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+    let align = if header == SNAPSHOT_HEADER { word(12) as usize } else { 1 };
+    let prefix = LengthPrefix::from_code(word(8)).ok_or_else(invalid)?;
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&bytes[header - 8..header]);
+    if u64::from_le_bytes(len) != (bytes.len() - header) as u64 || !align.is_power_of_two() || align > MAX_ALIGN {
+        return Err(invalid());
+    }
+    bytes.drain(..header);
+    Ok((Framing { prefix, align }, bytes))
+}
+
+/// Makes sure a shared ring's `header` was written by a compatible build, returning the
+/// ring's frame format.
+#[cfg(unix)]
+fn check_header(header: &State, capacity: usize) -> Result<Framing, &'static str> {
+    if header.magic.load(Ordering::Acquire) != SHARED_MAGIC || header.capacity.load(Ordering::Relaxed) != capacity as u64 {
+        return Err("not an initialized cbuffer");
+    }
+    if header.version.load(Ordering::Relaxed) != SHARED_VERSION {
+        return Err("cbuffer layout version mismatch");
+    }
+    if !capacity.is_power_of_two() {
+        return Err("cbuffer capacity is not a power of two");
+    }
+    if header.state_size.load(Ordering::Relaxed) != std::mem::size_of::<State>() as u32 {
+        return Err("cbuffer header size mismatch");
+    }
+    if header.flags.load(Ordering::Relaxed) & !SHARED_FLAGS != 0 {
+        return Err("cbuffer uses features this build does not support");
+    }
+    let prefix = LengthPrefix::from_code(header.prefix.load(Ordering::Relaxed)).ok_or("cbuffer uses an unknown length prefix")?;
+    let align = 1 << ((header.flags.load(Ordering::Relaxed) & FLAG_ALIGN) >> ALIGN_SHIFT);
+    Ok(Framing { prefix, align })
+}
+
+/// Capacity of the shared ring behind `fd`, going by the object's size.
+#[cfg(unix)]
+fn shared_capacity(fd: c_int) -> io::Result<usize> {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut stat) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let page = page_size();
+    let size = stat.st_size as usize;
+    if size <= page || !(size - page).is_multiple_of(page) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an initialized cbuffer"));
+    }
+    Ok(size - page)
+}
+
+#[cfg(unix)]
+fn shared_name(name: &str) -> io::Result<CString> {
+    CString::new(name).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+
+/// Spins, then yields, until `ready` holds.
+#[cfg(not(loom))]
+fn spin_until<F>(ready: F)
+    where F: Fn() -> bool
+{
+    let mut spins = 0u32;
+    while !ready() {
+        if spins < 64 {
+            std::hint::spin_loop();
+            spins += 1;
+        } else {
+            std::thread::yield_now();
+        }
+    }
 }
 
+/// Loom only switches threads where it is told to, so waiting has to go through it.
+#[cfg(loom)]
+fn spin_until<F>(ready: F)
+    where F: Fn() -> bool
+{
+    while !ready() {
+        loom::thread::yield_now();
+    }
+}
 
-#[inline]
-fn transform_u32_to_array_of_u8(x: u32) -> [u8; 4] {
-    let mut bytes: [u8; 4] = [0; 4];
-    LittleEndian::write_u32(&mut bytes, x);
-    bytes
+/// Creates an unnamed shared memory object of `size` bytes to back both ring views.
+#[cfg(target_os = "linux")]
+fn backing_fd(size: usize) -> Result<c_int, Error> {
+    unsafe {
+        let fd = libc::memfd_create(b"cbuffer\0".as_ptr() as *const libc::c_char, libc::MFD_CLOEXEC);
+        if fd < 0 {
+            return Err(Error::OS);
+        }
+        if ftruncate(fd, size as off_t) < 0 {
+            close(fd);
+            return Err(Error::OS);
+        }
+        Ok(fd)
+    }
 }
 
-#[inline]
-fn transform_array_of_u8_to_u32(x: &[u8]) -> u32 {
-    LittleEndian::read_u32(x)
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "macos"))))]
+fn backing_fd(size: usize) -> Result<c_int, Error> {
+    use std::sync::atomic::AtomicUsize;
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let name = format!("/cbuffer-{}-{}\0", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed));
+    unsafe {
+        let name = name.as_ptr() as *const libc::c_char;
+        let fd = libc::shm_open(name, libc::O_RDWR | libc::O_CREAT | libc::O_EXCL, 0o600);
+        if fd < 0 {
+            return Err(Error::OS);
+        }
+        libc::shm_unlink(name);
+        if ftruncate(fd, size as off_t) < 0 {
+            close(fd);
+            return Err(Error::OS);
+        }
+        Ok(fd)
+    }
 }
 
 
@@ -283,4 +4327,336 @@ mod tests {
         assert_eq!(134217728usize, b.size());
         assert_eq!(0usize, b.used());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_custom_capacity() {
+        use super::{page_size, CBuffer, BufferSize, Error};
+        let page = page_size();
+        assert_eq!(Err(Error::InvalidCapacity), BufferSize::Custom(0).bytes());
+        assert_eq!(Ok(page), BufferSize::Custom(1).bytes());
+        assert_eq!(Ok(4 * 1024 * 1024), BufferSize::Custom(3 * 1024 * 1024 + 1).bytes());
+        assert_eq!(Ok(4 * page), BufferSize::Custom(3 * page).bytes());
+        assert_eq!(Ok(1 << 30), BufferSize::Buf1G.bytes());
+        assert_eq!(Err(Error::InvalidCapacity), BufferSize::Custom(usize::MAX).bytes());
+        assert_eq!(Ok(2 * page), BufferSize::Exact(2 * page).bytes());
+        assert_eq!(Err(Error::UnalignedCapacity), BufferSize::Exact(page + 1).bytes());
+        assert_eq!(Err(Error::UnalignedCapacity), BufferSize::Exact(page / 2).bytes());
+        assert_eq!(Err(Error::UnalignedCapacity), BufferSize::Exact(3 * page).bytes());
+        assert_eq!(Err(Error::UnalignedCapacity), BufferSize::Exact(0).bytes());
+        assert!(CBuffer::with_capacity(BufferSize::Exact(page + 1)).is_err());
+
+        let b = CBuffer::with_capacity(BufferSize::Custom(page)).unwrap();
+        assert_eq!(page, b.size());
+        // Frames of a size coprime to the page keep straddling the mirror boundary.
+        let frame: Vec<u8> = (0..97u8).collect();
+        for _i in 0..10 * page {
+            assert_eq!(Ok(()), b.push(&frame));
+            assert_eq!(Ok(()), b.pop(|bytes| assert_eq!(frame.as_slice(), bytes)));
+        }
+        assert!(b.is_empty());
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_large_capacity() {
+        use super::{CBuffer, BufferSize};
+        // Only the pages that are touched get backed, so a 5 GiB ring is cheap to map.
+        let b = CBuffer::with_capacity(BufferSize::Custom(5usize << 30)).unwrap();
+        assert_eq!(8usize << 30, b.size());
+        assert_eq!(Ok(()), b.push(b"beyond u32"));
+        assert_eq!(Ok(()), b.pop(|bytes| assert_eq!(b"beyond u32", bytes)));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_aligned_mirror() {
+        use super::{backing_fd, map_mirror_aligned, unmap_ring};
+        let (capacity, align) = (4 << 20, 2 << 20);
+        let fd = backing_fd(capacity).unwrap();
+        let pointer = map_mirror_aligned(fd, 0, capacity, align).unwrap();
+        unsafe {
+            libc::close(fd);
+            assert_eq!(0, pointer.as_ptr() as usize % align);
+            *pointer.as_ptr().add(capacity - 1) = 42;
+            assert_eq!(42, *pointer.as_ptr().add(2 * capacity - 1));
+            unmap_ring(pointer, capacity);
+        }
+    }
+
+    #[cfg(all(unix, not(loom)))]
+    #[test]
+    fn test_shared_header() {
+        use super::{BufferSize, CBuffer, Framing, LengthPrefix, SHARED_VERSION};
+        use std::io::ErrorKind;
+        use std::sync::atomic::Ordering;
+
+        let name = format!("/cbuffer-header-{}", std::process::id());
+        let framing = Framing { prefix: LengthPrefix::U32, align: 64 };
+        let b = CBuffer::create_shared(&name, BufferSize::Custom(4096), framing, None).unwrap();
+        let flags = b.flags.load(Ordering::Relaxed);
+        let rejected = || CBuffer::attach_shared(&name).err().map(|err| (err.kind(), err.to_string()));
+
+        b.version.store(SHARED_VERSION + 1, Ordering::Relaxed);
+        assert_eq!(Some((ErrorKind::InvalidData, "cbuffer layout version mismatch".into())), rejected());
+        b.version.store(SHARED_VERSION, Ordering::Relaxed);
+        b.flags.store(1 << 31, Ordering::Relaxed);
+        assert_eq!(Some((ErrorKind::InvalidData, "cbuffer uses features this build does not support".into())), rejected());
+        b.flags.store(flags, Ordering::Relaxed);
+        b.prefix.store(3, Ordering::Relaxed);
+        assert_eq!(Some((ErrorKind::InvalidData, "cbuffer uses an unknown length prefix".into())), rejected());
+
+        b.prefix.store(LengthPrefix::Varint.code(), Ordering::Relaxed);
+        let attached = CBuffer::attach_shared(&name).unwrap();
+        assert_eq!(Framing { prefix: LengthPrefix::Varint, align: 64 }, attached.format);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dead_peer() {
+        use super::{PopError, PushError, Receiver, Sender};
+        use crate::ChannelBuilder;
+        use std::time::{Duration, Instant};
+
+        let name = format!("/cbuffer-peer-{}", std::process::id());
+        let (sender, receiver) = ChannelBuilder::new().capacity_bytes(4096).build_shared(&name).unwrap();
+        let mut attached = Sender::attach(&name).unwrap();
+        assert!(receiver.peer_alive() && attached.peer_alive());
+        assert!(receiver.peer_heartbeat().is_some());
+        attached.push(b"x").unwrap();
+
+        // Pretend both senders' processes crashed without dropping them.
+        die_holding(&sender.inner.sender_peer);
+        die_holding(&sender.inner.sender_peer);
+        std::mem::forget(sender);
+        assert!(!receiver.peer_alive());
+        assert_eq!(Some(b"x".to_vec()), receiver.pop_owned());
+        let start = Instant::now();
+        assert_eq!(Err(PopError::Disconnected), receiver.pop(|_| {}));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let other = Receiver::attach(&name).unwrap();
+        die_holding(&other.inner.receiver_peer);
+        std::mem::forget(other);
+        drop(receiver);
+        assert!(!attached.peer_alive());
+        while attached.try_push(&[0u8; 100]).is_ok() {}
+        assert_eq!(Err(PushError::Disconnected), attached.push(&[0u8; 100]));
+        std::mem::forget(attached);
+    }
+
+    /// Moves one of this process's handles on `peer` to a process that has exited, as if
+    /// that one had attached it and crashed.
+    #[cfg(unix)]
+    fn die_holding(peer: &super::Peer) {
+        use std::sync::atomic::Ordering;
+
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let dead = child.id();
+        child.wait().unwrap();
+        peer.leave();
+        let slot = peer.slots.iter().find(|slot| slot.pid.compare_exchange(0, dead, Ordering::AcqRel, Ordering::Acquire).is_ok());
+        slot.unwrap().handles.fetch_add(1, Ordering::AcqRel);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_one_sender_process_dies() {
+        use super::{PopError, PopTimeoutError, Sender, PEER_CHECK};
+        use crate::ChannelBuilder;
+
+        let name = format!("/cbuffer-senders-{}", std::process::id());
+        let (mut sender, receiver) = ChannelBuilder::new().capacity_bytes(4096).build_shared(&name).unwrap();
+        // A second process attaches a sender, pushes and exits without dropping it.
+        let child = unsafe { libc::fork() };
+        if child == 0 {
+            let pushed = std::panic::catch_unwind(|| {
+                let mut attached = Sender::attach(&name).unwrap();
+                attached.push(b"child").unwrap();
+                std::mem::forget(attached);
+            });
+            unsafe { libc::_exit(pushed.is_err() as i32) }
+        }
+        let mut status = 0;
+        assert_eq!(child, unsafe { libc::waitpid(child, &mut status, 0) });
+        assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+
+        assert!(receiver.peer_alive());
+        assert_eq!(Some(b"child".to_vec()), receiver.pop_owned());
+        assert_eq!(Err(PopTimeoutError::Timeout), receiver.pop_timeout(3 * PEER_CHECK, |_| {}));
+        assert!(receiver.peer_alive());
+        sender.push(b"parent").unwrap();
+        assert_eq!(Some(b"parent".to_vec()), receiver.pop_owned());
+        drop(sender);
+        assert_eq!(Err(PopError::Disconnected), receiver.pop(|_| {}));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_recover_dead_receiver() {
+        use super::{PopError, Receiver};
+        use crate::ChannelBuilder;
+        use std::sync::atomic::Ordering;
+
+        let name = format!("/cbuffer-recover-{}", std::process::id());
+        let (mut sender, receiver) = ChannelBuilder::new().capacity_bytes(4096).build_shared(&name).unwrap();
+        let mut attached = Receiver::attach(&name).unwrap();
+        drop(receiver);
+        for elem in &[&b"first"[..], b"second", b"third"] {
+            sender.push(elem).unwrap();
+        }
+        attached.try_pop(|_| {}).unwrap();
+
+        // The receivers' process dies halfway through popping "second".
+        std::mem::forget(attached.try_begin_pop().unwrap());
+        die_holding(&attached.inner.receiver_peer);
+        std::mem::forget(attached);
+        assert!(!sender.peer_alive());
+
+        let recovered = Receiver::attach(&name).unwrap();
+        assert!(sender.peer_alive());
+        assert_eq!(1, recovered.inner.receivers.load(Ordering::Acquire));
+        assert_eq!(Some(b"second".to_vec()), recovered.pop_owned());
+        assert_eq!(Some(b"third".to_vec()), recovered.pop_owned());
+        assert_eq!(Err(PopError::Empty), recovered.try_pop(|_| {}));
+        sender.push(b"fourth").unwrap();
+        assert_eq!(Some(b"fourth".to_vec()), recovered.pop_owned());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_no_recovery_while_a_receiver_lives() {
+        use super::Receiver;
+        use crate::ChannelBuilder;
+        use std::sync::atomic::Ordering;
+
+        let name = format!("/cbuffer-no-recover-{}", std::process::id());
+        let (mut sender, receiver) = ChannelBuilder::new().capacity_bytes(4096).build_shared(&name).unwrap();
+        let attached = Receiver::attach(&name).unwrap();
+        sender.push(b"first").unwrap();
+        sender.push(b"second").unwrap();
+
+        // The receiver that joined last dies, and another one joins while the first one is
+        // halfway through a pop.
+        die_holding(&attached.inner.receiver_peer);
+        std::mem::forget(attached);
+        let mut joined = None;
+        receiver.try_pop(|bytes| {
+            assert_eq!(b"first", bytes);
+            joined = Some(Receiver::attach(&name).unwrap());
+        }).unwrap();
+        let joined = joined.unwrap();
+        assert_eq!(2, joined.inner.receivers.load(Ordering::Acquire));
+        assert_eq!(Some(b"second".to_vec()), joined.pop_owned());
+        assert_eq!(None, receiver.pop_owned());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_registry_cleanup() {
+        use crate::{registry, ChannelBuilder};
+
+        let name = format!("stale-{}", std::process::id());
+        let (sender, receiver) = registry::create(&name, ChannelBuilder::new().capacity_bytes(4096)).unwrap();
+        die_holding(&sender.inner.sender_peer);
+        die_holding(&sender.inner.receiver_peer);
+        // The creator crashes, so nothing unlinks the object.
+        std::mem::forget((sender, receiver));
+
+        let (mut sender, _receiver) = registry::create(&name, ChannelBuilder::new().capacity_bytes(4096)).unwrap();
+        sender.push(b"x").unwrap();
+        let receiver = registry::connect(&name).unwrap();
+        assert_eq!(Some(b"x".to_vec()), receiver.pop_owned());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_read_only_receiver() {
+        use super::Receiver;
+        use crate::ChannelBuilder;
+        use std::os::unix::io::AsRawFd;
+        use std::os::unix::net::UnixStream;
+
+        let (mut sender, _receiver) = ChannelBuilder::new().capacity_bytes(4096).build_anonymous().unwrap();
+        let fd = sender.ring_fd().unwrap().as_raw_fd();
+        let seals = unsafe { libc::fcntl(fd, libc::F_GET_SEALS) };
+        assert_eq!(libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_SEAL, seals);
+        assert!(unsafe { libc::ftruncate(fd, 0) } < 0);
+
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        sender.send_over(&ours).unwrap();
+        let receiver = Receiver::receive_from(&theirs).unwrap();
+        let start = receiver.inner.pointer.as_ptr() as usize;
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        let perms = maps.lines().find_map(|line| {
+            let mut fields = line.split_whitespace();
+            let range = fields.next()?;
+            (usize::from_str_radix(range.split('-').next()?, 16).ok()? == start).then(|| fields.next().unwrap().to_owned())
+        });
+        assert_eq!(Some("r--s"), perms.as_deref());
+        sender.push(b"read only").unwrap();
+        assert_eq!(Some(b"read only".to_vec()), receiver.pop_owned());
+    }
+
+    /// Run with `RUSTFLAGS="--cfg loom" cargo test --release --lib loom`.
+    #[cfg(loom)]
+    #[test]
+    fn test_loom_spsc() {
+        use super::{page_size, CBuffer, BufferSize, PopError, PushError};
+        use std::sync::Arc;
+        loom::model(|| {
+            let b = Arc::new(CBuffer::with_capacity(BufferSize::Custom(page_size())).unwrap());
+            // Three of these do not fit at once, so the producer has to see the consumer's
+            // head move, and the last frame straddles the mirror boundary.
+            let frame = vec![7u8; page_size() / 3];
+            let producer = {
+                let (b, frame) = (b.clone(), frame.clone());
+                loom::thread::spawn(move || {
+                    for _i in 0..3 {
+                        while b.push(&frame) == Err(PushError::Full) {
+                            loom::thread::yield_now();
+                        }
+                    }
+                })
+            };
+            let mut popped = 0;
+            while popped < 3 {
+                match b.pop(|bytes| assert_eq!(frame.as_slice(), bytes)) {
+                    Ok(()) => popped += 1,
+                    Err(PopError::Empty) => loom::thread::yield_now(),
+                    Err(err) => panic!("{}", err),
+                }
+            }
+            producer.join().unwrap();
+            assert!(b.is_empty());
+        });
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn test_loom_close() {
+        use super::{page_size, CBuffer, BufferSize, PopError};
+        use std::sync::Arc;
+        loom::model(|| {
+            let b = Arc::new(CBuffer::with_capacity(BufferSize::Custom(page_size())).unwrap());
+            let producer = {
+                let b = b.clone();
+                loom::thread::spawn(move || {
+                    b.push(b"last").unwrap();
+                    b.close().unwrap();
+                })
+            };
+            // The element has to come out before the end-of-stream marker does.
+            let mut popped = false;
+            loop {
+                match b.pop(|bytes| assert_eq!(b"last", bytes)) {
+                    Ok(()) => popped = true,
+                    Err(PopError::Empty) => loom::thread::yield_now(),
+                    Err(PopError::Closed) => break,
+                    Err(err) => panic!("{}", err),
+                }
+            }
+            assert!(popped);
+            producer.join().unwrap();
+        });
+    }
+}
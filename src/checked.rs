@@ -0,0 +1,124 @@
+use std::io::IoSlice;
+
+use crate::cbuffer_raw::{channel, BufferSize, PopError, PushError, Receiver, Sender};
+
+/// Sending half of a checked channel; every element goes into the ring prefixed with
+/// its CRC32.
+pub struct CheckedSender {
+    inner: Sender,
+}
+
+/// Receiving half of a checked channel, which verifies each element before handing it
+/// out.
+pub struct CheckedReceiver {
+    inner: Receiver,
+}
+
+/// Creates a channel whose elements carry a CRC32 of their payload, so that a peer
+/// scribbling on the ring shows up as `PopError::Corrupted`.
+pub fn channel_checked(s: BufferSize) -> (CheckedSender, CheckedReceiver) {
+    let (sender, receiver) = channel(s);
+    (CheckedSender::new(sender), CheckedReceiver::new(receiver))
+}
+
+impl CheckedSender {
+    /// Checksums the elements pushed through `inner`, e.g. one from `channel_shared`. The
+    /// other end has to be a `CheckedReceiver` as well.
+    pub fn new(inner: Sender) -> CheckedSender {
+        CheckedSender { inner }
+    }
+
+    /// Like `Sender::try_push`, under the same rate limit.
+    pub fn try_push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        let sum = crc32fast::hash(elem).to_le_bytes();
+        self.inner.try_push_vectored(&[IoSlice::new(&sum), IoSlice::new(elem)])
+    }
+
+    /// Pushes `elem`, parking the calling thread until the receiver frees enough space
+    /// and the rate limit allows.
+    pub fn push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        let sum = crc32fast::hash(elem).to_le_bytes();
+        self.inner.push_vectored(&[IoSlice::new(&sum), IoSlice::new(elem)])
+    }
+}
+
+impl CheckedReceiver {
+    /// Verifies the elements popped from `inner`, which a `CheckedSender` pushes into.
+    pub fn new(inner: Receiver) -> CheckedReceiver {
+        CheckedReceiver { inner }
+    }
+
+    /// Pops the oldest element and hands it to `consumer` if its checksum matches. A
+    /// corrupted element is discarded all the same.
+    pub fn try_pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(&[u8])
+    {
+        let mut result = Ok(());
+        let mut consumer = Some(consumer);
+        self.inner.try_pop(|bytes| result = verify(bytes, &mut consumer))?;
+        result
+    }
+
+    /// Like `try_pop`, parking the calling thread until an element arrives.
+    pub fn pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(&[u8])
+    {
+        let mut result = Ok(());
+        let mut consumer = Some(consumer);
+        self.inner.pop(|bytes| result = verify(bytes, &mut consumer))?;
+        result
+    }
+}
+
+fn verify<F>(bytes: &[u8], consumer: &mut Option<F>) -> Result<(), PopError>
+    where F: FnOnce(&[u8])
+{
+    if bytes.len() < 4 {
+        return Err(PopError::Corrupted);
+    }
+    let (sum, payload) = bytes.split_at(4);
+    if u32::from_le_bytes([sum[0], sum[1], sum[2], sum[3]]) != crc32fast::hash(payload) {
+        return Err(PopError::Corrupted);
+    }
+    (consumer.take().expect("element already delivered"))(payload);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel_checked, CheckedReceiver, CheckedSender};
+    use crate::cbuffer_raw::{channel, BufferSize, PopError, PushError};
+    use crate::ratelimit::RateLimit;
+
+    #[test]
+    fn test_checksum() {
+        let (mut sender, receiver) = channel_checked(BufferSize::Custom(4096));
+        sender.try_push(b"intact").unwrap();
+        sender.push(b"").unwrap();
+        assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(b"intact", bytes)));
+        assert_eq!(Ok(()), receiver.pop(|bytes| assert!(bytes.is_empty())));
+        assert_eq!(Err(PopError::Empty), receiver.try_pop(|_| {}));
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let receiver = CheckedReceiver::new(receiver);
+        sender.try_push(&[0, 0, 0, 0, 1, 2, 3]).unwrap();
+        sender.try_push(&[1]).unwrap();
+        assert_eq!(Err(PopError::Corrupted), receiver.try_pop(|_| panic!("corrupted element delivered")));
+        assert_eq!(Err(PopError::Corrupted), receiver.try_pop(|_| panic!("corrupted element delivered")));
+        assert_eq!(Err(PopError::Empty), receiver.try_pop(|_| {}));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        sender.set_rate_limit(Some(RateLimit { messages_per_sec: Some(2), bytes_per_sec: None }));
+        let mut sender = CheckedSender::new(sender);
+        sender.try_push(b"one").unwrap();
+        sender.push(b"two").unwrap();
+        assert_eq!(Err(PushError::Full), sender.try_push(b"three"));
+        assert_eq!(2, receiver.len());
+    }
+}
@@ -0,0 +1,169 @@
+use std::convert::TryFrom;
+
+use crate::cbuffer_raw::{channel, BufferSize, PopError, PushError, Receiver, Sender};
+
+/// Set in an element's length prefix when its payload is LZ4 compressed.
+const COMPRESSED: u32 = 1 << 31;
+
+/// Most an LZ4 block expands by: each byte of a match's length extension stands for 255
+/// more bytes, plus a few for the literals at the end.
+const MAX_EXPANSION: usize = 255;
+
+/// Sending half of a compressing channel. Elements of at least `threshold` bytes go into
+/// the ring LZ4 compressed, unless that would not make them smaller.
+pub struct CompressedSender {
+    inner: Sender,
+    threshold: usize,
+    scratch: Vec<u8>,
+}
+
+/// Receiving half of a compressing channel, which hands out elements decompressed.
+pub struct CompressedReceiver {
+    inner: Receiver,
+    scratch: Vec<u8>,
+}
+
+/// Creates a channel that compresses elements of `threshold` bytes and more. Each element
+/// is prefixed with its uncompressed length, the top bit of which flags compression.
+pub fn channel_compressed(s: BufferSize, threshold: usize) -> (CompressedSender, CompressedReceiver) {
+    let (sender, receiver) = channel(s);
+    (CompressedSender::new(sender, threshold), CompressedReceiver::new(receiver))
+}
+
+impl CompressedSender {
+    /// Compresses the elements pushed through `inner`. The other end has to be a
+    /// `CompressedReceiver` as well.
+    pub fn new(inner: Sender, threshold: usize) -> CompressedSender {
+        CompressedSender { inner, threshold, scratch: Vec::new() }
+    }
+
+    pub fn try_push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        let compress = elem.len() >= self.threshold;
+        self.frame(elem, compress)?;
+        self.inner.try_push(&self.scratch)
+    }
+
+    /// Pushes `elem`, parking the calling thread until the receiver frees enough space.
+    pub fn push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        let compress = elem.len() >= self.threshold;
+        self.frame(elem, compress)?;
+        self.inner.push(&self.scratch)
+    }
+
+    /// Like `push`, compressing `elem` whatever its size.
+    pub fn push_compressed(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        self.frame(elem, true)?;
+        self.inner.push(&self.scratch)
+    }
+
+    fn frame(&mut self, elem: &[u8], compress: bool) -> Result<(), PushError> {
+        let len = u32::try_from(elem.len()).ok().filter(|len| len & COMPRESSED == 0).ok_or(PushError::MessageTooLarge)?;
+        self.scratch.clear();
+        if compress {
+            self.scratch.resize(4 + lz4_flex::block::get_maximum_output_size(elem.len()), 0);
+            let size = lz4_flex::block::compress_into(elem, &mut self.scratch[4..]).expect("output sized for the worst case");
+            if size < elem.len() {
+                self.scratch.truncate(4 + size);
+                self.scratch[..4].copy_from_slice(&(len | COMPRESSED).to_le_bytes());
+                return Ok(());
+            }
+            self.scratch.clear();
+        }
+        self.scratch.extend_from_slice(&len.to_le_bytes());
+        self.scratch.extend_from_slice(elem);
+        Ok(())
+    }
+}
+
+impl CompressedReceiver {
+    /// Decompresses the elements popped from `inner`, which a `CompressedSender` pushes
+    /// into.
+    pub fn new(inner: Receiver) -> CompressedReceiver {
+        CompressedReceiver { inner, scratch: Vec::new() }
+    }
+
+    /// Pops the oldest element and hands it to `consumer` decompressed. An element that
+    /// does not decompress is discarded with `PopError::Corrupted`.
+    pub fn try_pop<F>(&mut self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(&[u8])
+    {
+        let mut result = Ok(());
+        let mut consumer = Some(consumer);
+        let scratch = &mut self.scratch;
+        self.inner.try_pop(|bytes| result = unframe(bytes, scratch, &mut consumer))?;
+        result
+    }
+
+    /// Like `try_pop`, parking the calling thread until an element arrives.
+    pub fn pop<F>(&mut self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(&[u8])
+    {
+        let mut result = Ok(());
+        let mut consumer = Some(consumer);
+        let scratch = &mut self.scratch;
+        self.inner.pop(|bytes| result = unframe(bytes, scratch, &mut consumer))?;
+        result
+    }
+}
+
+fn unframe<F>(bytes: &[u8], scratch: &mut Vec<u8>, consumer: &mut Option<F>) -> Result<(), PopError>
+    where F: FnOnce(&[u8])
+{
+    if bytes.len() < 4 {
+        return Err(PopError::Corrupted);
+    }
+    let (prefix, payload) = bytes.split_at(4);
+    let prefix = u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]);
+    let len = (prefix & !COMPRESSED) as usize;
+    let consumer = consumer.take().expect("element already delivered");
+    if prefix & COMPRESSED == 0 {
+        if payload.len() != len {
+            return Err(PopError::Corrupted);
+        }
+        consumer(payload);
+        return Ok(());
+    }
+    // A corrupted length must not make the receiver allocate gigabytes first.
+    if len > payload.len() * MAX_EXPANSION + 16 {
+        return Err(PopError::Corrupted);
+    }
+    scratch.resize(len, 0);
+    match lz4_flex::block::decompress_into(payload, scratch) {
+        Ok(size) if size == len => {
+            consumer(scratch);
+            Ok(())
+        }
+        _ => Err(PopError::Corrupted),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel_compressed, CompressedReceiver, COMPRESSED};
+    use crate::cbuffer_raw::{channel, BufferSize, PopError};
+
+    #[test]
+    fn test_compress() {
+        let (mut sender, mut receiver) = channel_compressed(BufferSize::Custom(4096), 64);
+        // Would never fit the ring uncompressed.
+        let json = br#"{"id":1,"tags":["a","b"]}"#.repeat(1000);
+        sender.try_push(&json).unwrap();
+        sender.push(b"tiny").unwrap();
+        sender.push_compressed(b"tiny").unwrap();
+        assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(&json[..], bytes)));
+        assert_eq!(Ok(()), receiver.pop(|bytes| assert_eq!(b"tiny", bytes)));
+        assert_eq!(Ok(()), receiver.pop(|bytes| assert_eq!(b"tiny", bytes)));
+        assert_eq!(Err(PopError::Empty), receiver.try_pop(|_| {}));
+    }
+
+    #[test]
+    fn test_corrupted_length() {
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let mut receiver = CompressedReceiver::new(receiver);
+        let mut frame = (COMPRESSED | (1 << 30)).to_le_bytes().to_vec();
+        frame.extend_from_slice(&[0x10, b'x']);
+        sender.try_push(&frame).unwrap();
+        assert_eq!(Err(PopError::Corrupted), receiver.try_pop(|_| panic!("corrupted element delivered")));
+        assert_eq!(0, receiver.scratch.capacity());
+    }
+}
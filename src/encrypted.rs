@@ -0,0 +1,132 @@
+use chacha20poly1305::aead::{AeadCore, AeadInPlace, KeyInit, OsRng};
+use chacha20poly1305::{Tag, XChaCha20Poly1305, XNonce};
+
+use crate::cbuffer_raw::{channel, BufferSize, PopError, PushError, Receiver, Sender};
+
+const NONCE: usize = 24;
+const TAG: usize = 16;
+
+/// Sending half of an encrypted channel; every element goes into the ring sealed with
+/// XChaCha20-Poly1305 under a fresh random nonce.
+pub struct EncryptedSender {
+    inner: Sender,
+    cipher: XChaCha20Poly1305,
+    scratch: Vec<u8>,
+}
+
+/// Receiving half of an encrypted channel, which authenticates and decrypts each element
+/// before handing it out.
+pub struct EncryptedReceiver {
+    inner: Receiver,
+    cipher: XChaCha20Poly1305,
+    scratch: Vec<u8>,
+}
+
+/// Creates a channel whose elements only sit in the ring encrypted under `key`. Each
+/// element is laid out as nonce, ciphertext, tag.
+pub fn channel_encrypted(s: BufferSize, key: &[u8; 32]) -> (EncryptedSender, EncryptedReceiver) {
+    let (sender, receiver) = channel(s);
+    (EncryptedSender::new(sender, key), EncryptedReceiver::new(receiver, key))
+}
+
+impl EncryptedSender {
+    /// Encrypts the elements pushed through `inner`, e.g. one from `channel_shared`. The
+    /// other end has to be an `EncryptedReceiver` with the same key.
+    pub fn new(inner: Sender, key: &[u8; 32]) -> EncryptedSender {
+        EncryptedSender { inner, cipher: XChaCha20Poly1305::new(key.into()), scratch: Vec::new() }
+    }
+
+    pub fn try_push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        self.seal(elem);
+        self.inner.try_push(&self.scratch)
+    }
+
+    /// Pushes `elem`, parking the calling thread until the receiver frees enough space.
+    pub fn push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        self.seal(elem);
+        self.inner.push(&self.scratch)
+    }
+
+    fn seal(&mut self, elem: &[u8]) {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        self.scratch.clear();
+        self.scratch.extend_from_slice(&nonce);
+        self.scratch.extend_from_slice(elem);
+        let tag = self.cipher.encrypt_in_place_detached(&nonce, b"", &mut self.scratch[NONCE..])
+            .expect("element too large to encrypt");
+        self.scratch.extend_from_slice(&tag);
+    }
+}
+
+impl EncryptedReceiver {
+    /// Decrypts the elements popped from `inner`, which an `EncryptedSender` with the same
+    /// key pushes into.
+    pub fn new(inner: Receiver, key: &[u8; 32]) -> EncryptedReceiver {
+        EncryptedReceiver { inner, cipher: XChaCha20Poly1305::new(key.into()), scratch: Vec::new() }
+    }
+
+    /// Pops the oldest element and hands it to `consumer` decrypted. An element that
+    /// fails authentication is discarded with `PopError::Corrupted`.
+    pub fn try_pop<F>(&mut self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(&[u8])
+    {
+        let mut result = Ok(());
+        let mut consumer = Some(consumer);
+        let (cipher, scratch) = (&self.cipher, &mut self.scratch);
+        self.inner.try_pop(|bytes| result = open(cipher, bytes, scratch, &mut consumer))?;
+        result
+    }
+
+    /// Like `try_pop`, parking the calling thread until an element arrives.
+    pub fn pop<F>(&mut self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(&[u8])
+    {
+        let mut result = Ok(());
+        let mut consumer = Some(consumer);
+        let (cipher, scratch) = (&self.cipher, &mut self.scratch);
+        self.inner.pop(|bytes| result = open(cipher, bytes, scratch, &mut consumer))?;
+        result
+    }
+}
+
+fn open<F>(cipher: &XChaCha20Poly1305, bytes: &[u8], scratch: &mut Vec<u8>, consumer: &mut Option<F>) -> Result<(), PopError>
+    where F: FnOnce(&[u8])
+{
+    if bytes.len() < NONCE + TAG {
+        return Err(PopError::Corrupted);
+    }
+    let (nonce, rest) = bytes.split_at(NONCE);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG);
+    scratch.clear();
+    scratch.extend_from_slice(ciphertext);
+    cipher.decrypt_in_place_detached(XNonce::from_slice(nonce), b"", scratch, Tag::from_slice(tag))
+        .map_err(|_| PopError::Corrupted)?;
+    (consumer.take().expect("element already delivered"))(scratch);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel_encrypted, EncryptedReceiver};
+    use crate::cbuffer_raw::{BufferSize, PopError};
+
+    #[test]
+    fn test_encrypt() {
+        let (mut sender, mut receiver) = channel_encrypted(BufferSize::Custom(4096), &[7; 32]);
+        sender.try_push(b"secret").unwrap();
+        sender.push(b"").unwrap();
+        assert_eq!(Ok(()), receiver.inner.peek(|bytes| assert!(!bytes.windows(6).any(|w| w == b"secret"))));
+        assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(b"secret", bytes)));
+        assert_eq!(Ok(()), receiver.pop(|bytes| assert!(bytes.is_empty())));
+        assert_eq!(Err(PopError::Empty), receiver.try_pop(|_| {}));
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let (mut sender, receiver) = channel_encrypted(BufferSize::Custom(4096), &[7; 32]);
+        let mut receiver = EncryptedReceiver::new(receiver.inner, &[8; 32]);
+        sender.try_push(b"secret").unwrap();
+        assert_eq!(Err(PopError::Corrupted), receiver.try_pop(|_| panic!("forged element delivered")));
+        assert_eq!(Err(PopError::Empty), receiver.try_pop(|_| {}));
+    }
+}
@@ -0,0 +1,261 @@
+//! The methods `Sender` and `Receiver` share with their stand-ins in `mock`, as traits, so
+//! that code taking `impl Push` or `impl Pop` runs against a real channel in production and
+//! a mock one in unit tests.
+
+use std::io::IoSlice;
+use std::ops::Deref;
+use std::time::Duration;
+
+use crate::cbuffer_raw::{Iter, PeekGuard, PopError, PopTimeoutError, PushError, PushTimeoutError, Receiver,
+                         RecvGuard, Sender, Stats, TryIter};
+
+/// The sending half of a channel. See `Sender` for what each method does.
+pub trait Push {
+    fn try_push(&mut self, elem: &[u8]) -> Result<(), PushError>;
+    fn try_push_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<(), PushError>;
+    fn push(&mut self, elem: &[u8]) -> Result<(), PushError>;
+    fn push_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<(), PushError>;
+    fn push_timeout(&mut self, elem: &[u8], timeout: Duration) -> Result<(), PushTimeoutError>;
+    fn push_with_header(&mut self, header: u64, elem: &[u8]) -> Result<(), PushError>;
+    fn push_all<'a, I>(&mut self, iter: I) -> usize
+        where I: Iterator<Item = &'a [u8]>;
+    fn close(&mut self) -> Result<(), PushError>;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool;
+    fn remaining_bytes(&self) -> usize;
+    fn can_push(&self, len: usize) -> bool;
+    fn peer_alive(&self) -> bool;
+    fn dropped(&self) -> u64;
+    fn overwritten(&self) -> u64;
+    fn stats(&self) -> Stats;
+}
+
+/// The receiving half of a channel. See `Receiver` for what each method does.
+pub trait Pop {
+    /// Element borrowed by `recv_ref`, `try_recv` and `recv`, popped once dropped.
+    type RecvGuard<'a>: Deref<Target = [u8]> where Self: 'a;
+    /// Element borrowed by `peek_ref`, left in the channel.
+    type PeekGuard<'a>: Deref<Target = [u8]> where Self: 'a;
+    type TryIter<'a>: Iterator<Item = Vec<u8>> where Self: 'a;
+    type Iter<'a>: Iterator<Item = Vec<u8>> where Self: 'a;
+
+    fn try_pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8]);
+    fn pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8]);
+    fn pop_timeout<F>(&self, timeout: Duration, consumer: F) -> Result<(), PopTimeoutError>
+        where F: FnMut(&[u8]);
+    fn try_pop_with_header<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u64, &[u8]);
+    fn pop_with_header<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u64, &[u8]);
+    fn peek<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(&[u8]);
+    fn peek_ref(&mut self) -> Option<Self::PeekGuard<'_>>;
+    fn recv_ref(&mut self) -> Option<Self::RecvGuard<'_>>;
+    fn try_recv(&mut self) -> Result<Self::RecvGuard<'_>, PopError>;
+    fn recv(&mut self) -> Result<Self::RecvGuard<'_>, PopError>;
+    fn pop_batch<F>(&self, max: usize, consumer: F) -> usize
+        where F: FnMut(&[u8]);
+    fn recv_many(&self, buf: &mut Vec<Vec<u8>>, max: usize) -> usize;
+    fn pop_while<F>(&self, predicate: F) -> usize
+        where F: FnMut(&[u8]) -> bool;
+    fn pop_owned(&self) -> Option<Vec<u8>>;
+    fn read_into(&self, buf: &mut [u8]) -> Result<usize, PopError>;
+    fn try_iter(&self) -> Self::TryIter<'_>;
+    fn iter(&self) -> Self::Iter<'_>;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool;
+    fn available_bytes(&self) -> usize;
+    fn peer_alive(&self) -> bool;
+    fn dropped(&self) -> u64;
+    fn overwritten(&self) -> u64;
+    fn stats(&self) -> Stats;
+}
+
+impl Push for Sender {
+    fn try_push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        Sender::try_push(self, elem)
+    }
+
+    fn try_push_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<(), PushError> {
+        Sender::try_push_vectored(self, bufs)
+    }
+
+    fn push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        Sender::push(self, elem)
+    }
+
+    fn push_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<(), PushError> {
+        Sender::push_vectored(self, bufs)
+    }
+
+    fn push_timeout(&mut self, elem: &[u8], timeout: Duration) -> Result<(), PushTimeoutError> {
+        Sender::push_timeout(self, elem, timeout)
+    }
+
+    fn push_with_header(&mut self, header: u64, elem: &[u8]) -> Result<(), PushError> {
+        Sender::push_with_header(self, header, elem)
+    }
+
+    fn push_all<'a, I>(&mut self, iter: I) -> usize
+        where I: Iterator<Item = &'a [u8]>
+    {
+        Sender::push_all(self, iter)
+    }
+
+    fn close(&mut self) -> Result<(), PushError> {
+        Sender::close(self)
+    }
+
+    fn len(&self) -> usize {
+        Sender::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        Sender::is_empty(self)
+    }
+
+    fn remaining_bytes(&self) -> usize {
+        Sender::remaining_bytes(self)
+    }
+
+    fn can_push(&self, len: usize) -> bool {
+        Sender::can_push(self, len)
+    }
+
+    fn peer_alive(&self) -> bool {
+        Sender::peer_alive(self)
+    }
+
+    fn dropped(&self) -> u64 {
+        Sender::dropped(self)
+    }
+
+    fn overwritten(&self) -> u64 {
+        Sender::overwritten(self)
+    }
+
+    fn stats(&self) -> Stats {
+        Sender::stats(self)
+    }
+}
+
+impl Pop for Receiver {
+    type RecvGuard<'a> = RecvGuard<'a>;
+    type PeekGuard<'a> = PeekGuard<'a>;
+    type TryIter<'a> = TryIter<'a>;
+    type Iter<'a> = Iter<'a>;
+
+    fn try_pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        Receiver::try_pop(self, consumer)
+    }
+
+    fn pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        Receiver::pop(self, consumer)
+    }
+
+    fn pop_timeout<F>(&self, timeout: Duration, consumer: F) -> Result<(), PopTimeoutError>
+        where F: FnMut(&[u8])
+    {
+        Receiver::pop_timeout(self, timeout, consumer)
+    }
+
+    fn try_pop_with_header<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u64, &[u8])
+    {
+        Receiver::try_pop_with_header(self, consumer)
+    }
+
+    fn pop_with_header<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u64, &[u8])
+    {
+        Receiver::pop_with_header(self, consumer)
+    }
+
+    fn peek<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(&[u8])
+    {
+        Receiver::peek(self, consumer)
+    }
+
+    fn peek_ref(&mut self) -> Option<PeekGuard<'_>> {
+        Receiver::peek_ref(self)
+    }
+
+    fn recv_ref(&mut self) -> Option<RecvGuard<'_>> {
+        Receiver::recv_ref(self)
+    }
+
+    fn try_recv(&mut self) -> Result<RecvGuard<'_>, PopError> {
+        Receiver::try_recv(self)
+    }
+
+    fn recv(&mut self) -> Result<RecvGuard<'_>, PopError> {
+        Receiver::recv(self)
+    }
+
+    fn pop_batch<F>(&self, max: usize, consumer: F) -> usize
+        where F: FnMut(&[u8])
+    {
+        Receiver::pop_batch(self, max, consumer)
+    }
+
+    fn recv_many(&self, buf: &mut Vec<Vec<u8>>, max: usize) -> usize {
+        Receiver::recv_many(self, buf, max)
+    }
+
+    fn pop_while<F>(&self, predicate: F) -> usize
+        where F: FnMut(&[u8]) -> bool
+    {
+        Receiver::pop_while(self, predicate)
+    }
+
+    fn pop_owned(&self) -> Option<Vec<u8>> {
+        Receiver::pop_owned(self)
+    }
+
+    fn read_into(&self, buf: &mut [u8]) -> Result<usize, PopError> {
+        Receiver::read_into(self, buf)
+    }
+
+    fn try_iter(&self) -> TryIter<'_> {
+        Receiver::try_iter(self)
+    }
+
+    fn iter(&self) -> Iter<'_> {
+        Receiver::iter(self)
+    }
+
+    fn len(&self) -> usize {
+        Receiver::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        Receiver::is_empty(self)
+    }
+
+    fn available_bytes(&self) -> usize {
+        Receiver::available_bytes(self)
+    }
+
+    fn peer_alive(&self) -> bool {
+        Receiver::peer_alive(self)
+    }
+
+    fn dropped(&self) -> u64 {
+        Receiver::dropped(self)
+    }
+
+    fn overwritten(&self) -> u64 {
+        Receiver::overwritten(self)
+    }
+
+    fn stats(&self) -> Stats {
+        Receiver::stats(self)
+    }
+}
@@ -0,0 +1,63 @@
+use core::fmt;
+
+/// Why a push failed. Only `Full` is worth retrying; the others fail the same way
+/// every time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PushError {
+    /// Not enough free space right now; retrying later may succeed.
+    Full,
+    /// The element can never fit, not even into an empty ring, or is not the size of the
+    /// ring's `LengthPrefix::Fixed` records.
+    MessageTooLarge,
+    /// The receiver has been dropped.
+    Disconnected,
+    /// The channel has been closed with `Sender::close`.
+    Closed,
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PushError {}
+
+impl fmt::Display for PushError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            PushError::Full => write!(f, "buffer full"),
+            PushError::MessageTooLarge => write!(f, "message larger than buffer"),
+            PushError::Disconnected => write!(f, "receiver disconnected"),
+            PushError::Closed => write!(f, "channel closed"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PopError {
+    /// Nothing to pop right now; the sender may still push more.
+    Empty,
+    /// The ring is empty and every sender has been dropped.
+    Disconnected,
+    /// Everything pushed before `Sender::close` has been popped.
+    Closed,
+    /// The element is larger than the buffer passed to `read_into`; it stays queued.
+    BufferTooSmall,
+    /// The element is too short to have been pushed with `push_with_header`; it stays
+    /// queued.
+    MissingHeader,
+    /// The element failed its checksum and was discarded.
+    Corrupted,
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PopError {}
+
+impl fmt::Display for PopError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            PopError::Empty => write!(f, "buffer empty"),
+            PopError::Disconnected => write!(f, "sender disconnected"),
+            PopError::Closed => write!(f, "channel closed"),
+            PopError::BufferTooSmall => write!(f, "element larger than buffer"),
+            PopError::MissingHeader => write!(f, "element has no header"),
+            PopError::Corrupted => write!(f, "element failed its checksum"),
+        }
+    }
+}
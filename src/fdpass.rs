@@ -0,0 +1,72 @@
+//! Passing the descriptor of a shared ring's memory object to another process over a Unix
+//! domain socket, as `SCM_RIGHTS` ancillary data.
+
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::ptr;
+
+use libc::{c_int, c_void};
+
+/// Sends a duplicate of `fd` along with a single byte of data, since some systems drop
+/// ancillary data sent on its own.
+pub(crate) fn send_fd(socket: &UnixStream, fd: RawFd) -> io::Result<()> {
+    let mut byte = 0u8;
+    let mut iov = libc::iovec { iov_base: &mut byte as *mut u8 as *mut c_void, iov_len: 1 };
+    // u64s to keep the `cmsghdr` in it aligned.
+    let mut control = [0u64; 8];
+    let space = unsafe { libc::CMSG_SPACE(mem::size_of::<c_int>() as u32) } as usize;
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = space as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<c_int>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut c_int, fd);
+    }
+    loop {
+        match unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) } {
+            n if n >= 0 => return Ok(()),
+            _ if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+            _ => return Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+/// Receives a descriptor sent with `send_fd`.
+pub(crate) fn recv_fd(socket: &UnixStream) -> io::Result<OwnedFd> {
+    let mut byte = 0u8;
+    let mut iov = libc::iovec { iov_base: &mut byte as *mut u8 as *mut c_void, iov_len: 1 };
+    let mut control = [0u64; 8];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+    #[cfg(target_os = "linux")]
+    let flags = libc::MSG_CMSG_CLOEXEC;
+    #[cfg(not(target_os = "linux"))]
+    let flags = 0;
+    let n = loop {
+        match unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, flags) } {
+            n if n >= 0 => break n,
+            _ if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+            _ => return Err(io::Error::last_os_error()),
+        }
+    };
+    if n == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "socket closed before a cbuffer arrived"));
+    }
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if cmsg.is_null() || (*cmsg).cmsg_level != libc::SOL_SOCKET || (*cmsg).cmsg_type != libc::SCM_RIGHTS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no cbuffer descriptor received"));
+        }
+        Ok(OwnedFd::from_raw_fd(ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const c_int)))
+    }
+}
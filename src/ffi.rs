@@ -0,0 +1,149 @@
+//! C interface to a channel within one process. Both halves come back as the same opaque
+//! `cbuffer_t`, and every call reports one of the `CBUFFER_*` codes. The crate only builds
+//! as an rlib by default; build the shared library for C with
+//! `cargo rustc --release --lib --features ffi --crate-type cdylib`.
+
+use std::os::raw::{c_int, c_void};
+use std::panic;
+use std::slice;
+
+use crate::cbuffer_raw::{channel, BufferSize, PopError, PushError, Receiver, Sender};
+
+pub const CBUFFER_OK: c_int = 0;
+pub const CBUFFER_FULL: c_int = 1;
+pub const CBUFFER_EMPTY: c_int = 2;
+pub const CBUFFER_MESSAGE_TOO_LARGE: c_int = 3;
+pub const CBUFFER_DISCONNECTED: c_int = 4;
+pub const CBUFFER_CLOSED: c_int = 5;
+/// A null pointer, the wrong half, or a capacity the ring cannot have.
+pub const CBUFFER_INVALID: c_int = -1;
+
+/// One half of a channel, opaque to C.
+#[allow(non_camel_case_types)]
+pub enum cbuffer_t {
+    Sender(Sender),
+    Receiver(Receiver),
+}
+
+/// Creates a channel of `capacity` bytes, rounded up to a power of two, and stores its
+/// halves in `*sender` and `*receiver`. Each has to be released with `cbuffer_free`.
+///
+/// # Safety
+/// `sender` and `receiver` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn cbuffer_channel_new(capacity: usize, sender: *mut *mut cbuffer_t,
+                                             receiver: *mut *mut cbuffer_t) -> c_int {
+    if sender.is_null() || receiver.is_null() {
+        return CBUFFER_INVALID;
+    }
+    // `channel` panics on a capacity it cannot map, which must not unwind into C.
+    let (tx, rx) = match panic::catch_unwind(|| channel(BufferSize::Custom(capacity))) {
+        Ok(halves) => halves,
+        Err(_) => return CBUFFER_INVALID,
+    };
+    *sender = Box::into_raw(Box::new(cbuffer_t::Sender(tx)));
+    *receiver = Box::into_raw(Box::new(cbuffer_t::Receiver(rx)));
+    CBUFFER_OK
+}
+
+/// Pushes the `len` bytes at `data` without waiting; `CBUFFER_FULL` means try again later.
+///
+/// # Safety
+/// `sender` must be null or a live handle, not used from another thread at the same time,
+/// and `data` must be valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn cbuffer_push(sender: *mut cbuffer_t, data: *const u8, len: usize) -> c_int {
+    let sender = match sender.as_mut() {
+        Some(cbuffer_t::Sender(sender)) => sender,
+        _ => return CBUFFER_INVALID,
+    };
+    if data.is_null() && len > 0 {
+        return CBUFFER_INVALID;
+    }
+    let elem = if len == 0 { &[][..] } else { slice::from_raw_parts(data, len) };
+    match sender.try_push(elem) {
+        Ok(()) => CBUFFER_OK,
+        Err(PushError::Full) => CBUFFER_FULL,
+        Err(PushError::MessageTooLarge) => CBUFFER_MESSAGE_TOO_LARGE,
+        Err(PushError::Disconnected) => CBUFFER_DISCONNECTED,
+        Err(PushError::Closed) => CBUFFER_CLOSED,
+    }
+}
+
+/// Pops one element without waiting and calls `callback(user, data, len)` with it. The
+/// bytes are only valid until the callback returns.
+///
+/// # Safety
+/// `receiver` must be null or a live handle, not used from another thread at the same
+/// time.
+#[no_mangle]
+pub unsafe extern "C" fn cbuffer_pop_cb(receiver: *mut cbuffer_t,
+                                        callback: Option<unsafe extern "C" fn(*mut c_void, *const u8, usize)>,
+                                        user: *mut c_void) -> c_int {
+    let (receiver, callback) = match (receiver.as_ref(), callback) {
+        (Some(cbuffer_t::Receiver(receiver)), Some(callback)) => (receiver, callback),
+        _ => return CBUFFER_INVALID,
+    };
+    match receiver.try_pop(|bytes| callback(user, bytes.as_ptr(), bytes.len())) {
+        Ok(()) => CBUFFER_OK,
+        Err(PopError::Empty) => CBUFFER_EMPTY,
+        Err(PopError::Disconnected) => CBUFFER_DISCONNECTED,
+        Err(PopError::Closed) => CBUFFER_CLOSED,
+        Err(_) => CBUFFER_INVALID,
+    }
+}
+
+/// Releases a half returned by `cbuffer_channel_new`, disconnecting it from its peer.
+/// Null is ignored.
+///
+/// # Safety
+/// `handle` must be null or a live handle, which is not used again afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn cbuffer_free(handle: *mut cbuffer_t) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::raw::c_void;
+    use std::ptr;
+    use super::*;
+
+    unsafe extern "C" fn collect(user: *mut c_void, data: *const u8, len: usize) {
+        (*(user as *mut Vec<u8>)).extend_from_slice(std::slice::from_raw_parts(data, len));
+    }
+
+    #[test]
+    fn test_ffi() {
+        unsafe {
+            let (mut sender, mut receiver) = (ptr::null_mut(), ptr::null_mut());
+            assert_eq!(CBUFFER_OK, cbuffer_channel_new(4096, &mut sender, &mut receiver));
+            assert_eq!(CBUFFER_OK, cbuffer_push(sender, b"hello".as_ptr(), 5));
+            assert_eq!(CBUFFER_INVALID, cbuffer_push(receiver, b"hello".as_ptr(), 5));
+            assert_eq!(CBUFFER_MESSAGE_TOO_LARGE, cbuffer_push(sender, [0u8; 8192].as_ptr(), 8192));
+
+            let mut out = Vec::new();
+            let user = &mut out as *mut Vec<u8> as *mut c_void;
+            assert_eq!(CBUFFER_OK, cbuffer_pop_cb(receiver, Some(collect), user));
+            assert_eq!(b"hello", &out[..]);
+            assert_eq!(CBUFFER_EMPTY, cbuffer_pop_cb(receiver, Some(collect), user));
+
+            cbuffer_free(sender);
+            assert_eq!(CBUFFER_DISCONNECTED, cbuffer_pop_cb(receiver, Some(collect), user));
+            cbuffer_free(receiver);
+            cbuffer_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_ffi_invalid() {
+        unsafe {
+            let (mut sender, mut receiver) = (ptr::null_mut(), ptr::null_mut());
+            assert_eq!(CBUFFER_INVALID, cbuffer_channel_new(0, &mut sender, &mut receiver));
+            assert!(sender.is_null() && receiver.is_null());
+            assert_eq!(CBUFFER_INVALID, cbuffer_channel_new(4096, ptr::null_mut(), &mut receiver));
+        }
+    }
+}
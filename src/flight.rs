@@ -0,0 +1,72 @@
+use crate::cbuffer_raw::{channel_overwrite, BufferSize, Receiver, Sender};
+
+/// Keeps the newest elements recorded into it and forgets the older ones, as an always-on
+/// trace to dump e.g. into a crash report. Both ends of the ring live inside it.
+pub struct FlightRecorder {
+    sender: Sender,
+    receiver: Receiver,
+    limit: usize,
+}
+
+impl FlightRecorder {
+    /// Keeps at most the last `limit` elements, fewer when they do not all fit into a ring
+    /// of size `s`.
+    pub fn new(s: BufferSize, limit: usize) -> FlightRecorder {
+        assert!(limit > 0, "a flight recorder has to keep at least one element");
+        let (sender, receiver) = channel_overwrite(s);
+        FlightRecorder { sender, receiver, limit }
+    }
+
+    /// Records `elem`, forgetting the oldest elements to make room. Elements larger than
+    /// the ring are not recorded.
+    pub fn record(&mut self, elem: &[u8]) {
+        while self.receiver.len() >= self.limit && self.receiver.try_pop(|_| {}).is_ok() {}
+        let _ = self.sender.push(elem);
+    }
+
+    /// Copies out the elements kept, oldest first, leaving them in place.
+    pub fn dump(&self) -> Vec<Vec<u8>> {
+        self.receiver.inner.dump()
+    }
+
+    /// Number of elements kept.
+    pub fn len(&self) -> usize {
+        self.receiver.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.receiver.is_empty()
+    }
+
+    /// Forgets everything recorded so far.
+    pub fn clear(&mut self) {
+        while self.receiver.try_pop(|_| {}).is_ok() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FlightRecorder;
+    use crate::cbuffer_raw::BufferSize;
+
+    #[test]
+    fn test_flight_recorder() {
+        let mut recorder = FlightRecorder::new(BufferSize::Custom(4096), 3);
+        assert!(recorder.dump().is_empty());
+        for i in 0..10u8 {
+            recorder.record(&[i; 4]);
+        }
+        assert_eq!(vec![vec![7; 4], vec![8; 4], vec![9; 4]], recorder.dump());
+        assert_eq!(3, recorder.len());
+
+        // Large elements push out more of the old ones than the limit asks for.
+        recorder.record(&[10; 3000]);
+        recorder.record(&[11; 3000]);
+        assert_eq!(vec![vec![11; 3000]], recorder.dump());
+        recorder.record(&[0; 8192]);
+        assert_eq!(1, recorder.len());
+
+        recorder.clear();
+        assert!(recorder.is_empty());
+    }
+}
@@ -0,0 +1,287 @@
+//! Frame layout and cursor arithmetic of the ring, independent of where its memory comes
+//! from or how the two sides wait for each other. Only uses `core`, so it carries over to
+//! targets without `std`.
+
+// The `bare` channels only need part of it.
+#![cfg_attr(not(feature = "std"), allow(dead_code))]
+
+/// Most bytes a length prefix takes in any format.
+pub(crate) const MAX_PREFIX: usize = 5;
+
+/// Length prefix of the frame `Sender::close` appends. Pushes reject anything this long,
+/// so a real frame never carries it even in rings above 4 GiB.
+pub(crate) const END_OF_STREAM: u32 = u32::MAX;
+
+/// Bit marking `LengthPrefix::Fixed` in a format code, with the record size below it.
+const FIXED_CODE: u32 = 1 << 31;
+
+/// How the length in front of every frame is encoded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LengthPrefix {
+    /// One byte, for elements of at most 254 bytes.
+    U8,
+    /// Two little-endian bytes, for elements of at most 65534 bytes.
+    U16,
+    /// Four little-endian bytes.
+    U32,
+    /// LEB128, seven bits to the byte: one byte of framing below 128 bytes of payload,
+    /// two below 16 KiB. Saves space when most elements are small.
+    Varint,
+    /// No prefix at all: every element is exactly this many bytes, which has to be more
+    /// than zero and below 2 GiB. Pushes of any other length fail with `PushError::MessageTooLarge`.
+    Fixed(u32),
+}
+
+impl LengthPrefix {
+    /// Bytes the prefix of a frame carrying `len` bytes takes.
+    pub(crate) fn width(self, len: usize) -> usize {
+        match self {
+            LengthPrefix::U8 => 1,
+            LengthPrefix::U16 => 2,
+            LengthPrefix::U32 => 4,
+            LengthPrefix::Varint => {
+                let bits = (usize::BITS - (len | 1).leading_zeros()) as usize;
+                bits.div_ceil(7)
+            }
+            LengthPrefix::Fixed(_) => 0,
+        }
+    }
+
+    /// Whether a channel can be set up with this format. `Fixed` sizes have to fit below
+    /// the bit that marks them in `code`.
+    pub(crate) fn is_valid(self) -> bool {
+        match self {
+            LengthPrefix::Fixed(size) => size != 0 && size < FIXED_CODE,
+            _ => true,
+        }
+    }
+
+    /// Identifies the format in a shared ring's header.
+    pub(crate) fn code(self) -> u32 {
+        match self {
+            LengthPrefix::U8 => 1,
+            LengthPrefix::U16 => 2,
+            LengthPrefix::U32 => 4,
+            LengthPrefix::Varint => 0x80,
+            LengthPrefix::Fixed(size) => FIXED_CODE | size,
+        }
+    }
+
+    /// Inverse of `code`, for rings set up from elsewhere.
+    pub(crate) fn from_code(code: u32) -> Option<LengthPrefix> {
+        if code & FIXED_CODE != 0 {
+            return Some(LengthPrefix::Fixed(code & !FIXED_CODE)).filter(|format| format.is_valid());
+        }
+        [LengthPrefix::U8, LengthPrefix::U16, LengthPrefix::U32, LengthPrefix::Varint]
+            .iter().copied().find(|format| format.code() == code)
+    }
+
+    /// Whether elements of `len` bytes can be framed in this format at all.
+    pub(crate) fn accepts(self, len: usize) -> bool {
+        match self {
+            LengthPrefix::Fixed(size) => len == size as usize,
+            _ => true,
+        }
+    }
+
+    /// Prefix value that marks the end of the stream, which element lengths have to stay
+    /// below.
+    pub(crate) fn end_of_stream(self) -> u32 {
+        match self {
+            LengthPrefix::U8 => u8::MAX as u32,
+            LengthPrefix::U16 => u16::MAX as u32,
+            LengthPrefix::U32 | LengthPrefix::Varint | LengthPrefix::Fixed(_) => END_OF_STREAM,
+        }
+    }
+
+    /// Writes the prefix for `len` into the front of `buf`, returning how many bytes of it
+    /// make up the prefix. `END_OF_STREAM` becomes the format's own marker.
+    pub(crate) fn encode(self, len: u32, buf: &mut [u8; MAX_PREFIX]) -> usize {
+        match self {
+            LengthPrefix::U8 => buf[0] = len.min(u8::MAX as u32) as u8,
+            LengthPrefix::U16 => buf[..2].copy_from_slice(&(len.min(u16::MAX as u32) as u16).to_le_bytes()),
+            LengthPrefix::U32 => buf[..4].copy_from_slice(&len.to_le_bytes()),
+            LengthPrefix::Varint => {
+                let mut rest = len;
+                let mut i = 0;
+                while rest >= 0x80 {
+                    buf[i] = rest as u8 | 0x80;
+                    rest >>= 7;
+                    i += 1;
+                }
+                buf[i] = rest as u8;
+            }
+            LengthPrefix::Fixed(_) => {}
+        }
+        self.width(len as usize)
+    }
+
+    /// Reads the prefix at the start of `bytes`, which has to hold at least `MAX_PREFIX`
+    /// bytes. Only the bytes that belong to the prefix are looked at. The format's marker
+    /// comes back as `END_OF_STREAM`.
+    pub(crate) fn decode(self, bytes: &[u8]) -> u32 {
+        let len = match self {
+            LengthPrefix::U8 => u32::from(bytes[0]),
+            LengthPrefix::U16 => u32::from(u16::from_le_bytes([bytes[0], bytes[1]])),
+            LengthPrefix::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            LengthPrefix::Varint => {
+                let mut len = 0u32;
+                for (i, b) in bytes[..MAX_PREFIX].iter().enumerate() {
+                    len |= u32::from(b & 0x7f) << (7 * i);
+                    if b & 0x80 == 0 {
+                        break;
+                    }
+                }
+                len
+            }
+            LengthPrefix::Fixed(size) => size,
+        };
+        if len == self.end_of_stream() { END_OF_STREAM } else { len }
+    }
+}
+
+/// How frames are laid out in a ring: their length prefix, and the boundary every frame
+/// starts on and every payload is padded out to after its prefix.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Framing {
+    pub(crate) prefix: LengthPrefix,
+    /// A power of two; 1 packs the frames back to back.
+    pub(crate) align: usize,
+}
+
+impl From<LengthPrefix> for Framing {
+    fn from(prefix: LengthPrefix) -> Framing {
+        Framing { prefix, align: 1 }
+    }
+}
+
+impl Framing {
+    /// Bytes in front of the payload of a frame carrying `len` bytes: the prefix and the
+    /// padding after it.
+    pub(crate) fn width(self, len: usize) -> usize {
+        align_up(self.prefix.width(len), self.align)
+    }
+
+    /// Bytes a frame carrying `len` bytes takes in the ring.
+    pub(crate) fn frame_size(self, len: usize) -> usize {
+        align_up(self.width(len) + len, self.align)
+    }
+
+    /// Walks the frames making up `bytes`, returning how many elements they hold and
+    /// whether the end-of-stream marker follows them. `None` if they do not add up.
+    pub(crate) fn walk(self, bytes: &[u8]) -> Option<(usize, bool)> {
+        let mut pos = 0;
+        let mut count = 0;
+        while pos < bytes.len() {
+            let mut prefix = [0u8; MAX_PREFIX];
+            let available = (bytes.len() - pos).min(MAX_PREFIX);
+            prefix[..available].copy_from_slice(&bytes[pos..pos + available]);
+            let len = self.prefix.decode(&prefix);
+            if len == END_OF_STREAM {
+                let end = pos + self.width(len as usize);
+                return if end == bytes.len() { Some((count, true)) } else { None };
+            }
+            pos += self.frame_size(len as usize);
+            count += 1;
+        }
+        if pos == bytes.len() { Some((count, false)) } else { None }
+    }
+
+    /// Largest payload a frame can carry in a ring of `capacity` bytes. A full frame
+    /// leaves one byte free, which keeps a full ring apart from an empty one.
+    pub(crate) fn max_len(self, capacity: usize) -> usize {
+        if let LengthPrefix::Fixed(size) = self.prefix {
+            return if self.frame_size(size as usize) < capacity { size as usize } else { 0 };
+        }
+        let mut len = capacity.saturating_sub(2);
+        while len > 0 && self.frame_size(len) >= capacity {
+            len -= 1;
+        }
+        len.min(self.prefix.end_of_stream() as usize - 1)
+    }
+}
+
+fn align_up(size: usize, align: usize) -> usize {
+    (size + align - 1) & !(align - 1)
+}
+
+/// Cursors only ever grow; the ring position is their low bits, the capacity being a
+/// power of two.
+pub(crate) fn offset(pos: u64, capacity: usize) -> usize {
+    (pos & (capacity as u64 - 1)) as usize
+}
+
+/// Bytes between two cursors, `head` being the one behind.
+pub(crate) fn distance(head: u64, tail: u64) -> usize {
+    (tail - head) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec;
+
+    use super::{Framing, LengthPrefix, END_OF_STREAM, FIXED_CODE, MAX_PREFIX};
+
+    #[test]
+    fn test_varint() {
+        let mut buf = [0u8; MAX_PREFIX];
+        for &(len, width) in &[(0, 1), (127, 1), (128, 2), (16383, 2), (16384, 3), (END_OF_STREAM, 5)] {
+            assert_eq!(width, LengthPrefix::Varint.encode(len, &mut buf));
+            assert_eq!(width, LengthPrefix::Varint.width(len as usize));
+            assert_eq!(len, LengthPrefix::Varint.decode(&buf));
+        }
+        assert_eq!(4091, Framing::from(LengthPrefix::U32).max_len(4096));
+        assert_eq!(4093, Framing::from(LengthPrefix::Varint).max_len(4096));
+    }
+
+    #[test]
+    fn test_fixed_width() {
+        let mut buf = [0u8; MAX_PREFIX];
+        for &(format, width, max) in &[(LengthPrefix::U8, 1, 254), (LengthPrefix::U16, 2, 65534)] {
+            assert_eq!(width, format.encode(max, &mut buf));
+            assert_eq!(max, format.decode(&buf));
+            format.encode(END_OF_STREAM, &mut buf);
+            assert_eq!(END_OF_STREAM, format.decode(&buf));
+            assert_eq!(max as usize, Framing::from(format).max_len(1 << 20));
+        }
+        assert_eq!(4094, Framing::from(LengthPrefix::U8).frame_size(4093));
+        assert_eq!(4093, Framing::from(LengthPrefix::U16).max_len(4096));
+    }
+
+    #[test]
+    fn test_fixed() {
+        let format = LengthPrefix::Fixed(64);
+        assert_eq!(0, format.encode(64, &mut [0u8; MAX_PREFIX]));
+        assert_eq!(64, format.decode(&[0u8; MAX_PREFIX]));
+        assert_eq!(64, Framing::from(format).frame_size(64));
+        assert!(format.accepts(64) && !format.accepts(63));
+        assert_eq!(Some(format), LengthPrefix::from_code(format.code()));
+        assert_eq!(None, LengthPrefix::from_code(LengthPrefix::Fixed(0).code()));
+        assert!(!LengthPrefix::Fixed(0).is_valid() && !LengthPrefix::Fixed(FIXED_CODE).is_valid());
+        assert!(LengthPrefix::Fixed(FIXED_CODE - 1).is_valid());
+        assert_eq!(Some((3, false)), Framing::from(format).walk(&[0u8; 192]));
+        assert_eq!(None, Framing::from(format).walk(&[0u8; 100]));
+        assert_eq!(64, Framing::from(format).max_len(4096));
+        assert_eq!(0, Framing::from(LengthPrefix::Fixed(4096)).max_len(4096));
+    }
+
+    #[test]
+    fn test_aligned() {
+        let framing = Framing { prefix: LengthPrefix::U32, align: 16 };
+        assert_eq!(16, framing.width(5));
+        assert_eq!(32, framing.frame_size(5));
+        assert_eq!(32, framing.frame_size(16));
+        assert_eq!(16, framing.width(END_OF_STREAM as usize));
+        let mut frames = vec![0u8; 64];
+        frames[..4].copy_from_slice(&5u32.to_le_bytes());
+        frames[32..36].copy_from_slice(&16u32.to_le_bytes());
+        assert_eq!(Some((2, false)), framing.walk(&frames));
+        assert_eq!(4095 - 16 - 15, framing.max_len(4096));
+
+        let framing = Framing { prefix: LengthPrefix::Fixed(24), align: 16 };
+        assert_eq!((0, 32), (framing.width(24), framing.frame_size(24)));
+        assert_eq!(24, framing.max_len(4096));
+    }
+}
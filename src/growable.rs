@@ -0,0 +1,197 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::cbuffer_raw::{channel, BufferSize, PopError, PushError, Receiver, Sender};
+
+/// Sending half of a growable channel. Whenever an element does not fit, it moves on to
+/// a ring twice the size, up to the limit the channel was created with.
+pub struct GrowableSender {
+    inner: Sender,
+    max: usize,
+    next: Arc<Mutex<Next>>,
+}
+
+/// Receivers of the rings the sender moved on to, oldest first, until the
+/// `GrowableReceiver` is dropped.
+struct Next {
+    rings: VecDeque<Receiver>,
+    receiver_dropped: bool,
+}
+
+/// Receiving half of a growable channel. Drains each ring before moving on to the next,
+/// so elements come out in the order they were pushed.
+pub struct GrowableReceiver {
+    inner: Receiver,
+    next: Arc<Mutex<Next>>,
+}
+
+/// Creates a channel that starts out with a ring of size `initial` and grows as far as
+/// `max` instead of reporting it full. Both sizes are rounded up like any other.
+pub fn channel_growable(initial: BufferSize, max: BufferSize) -> (GrowableSender, GrowableReceiver) {
+    let max = max.bytes().expect("fail to create cbuffer.");
+    let (sender, receiver) = channel(initial);
+    let next = Arc::new(Mutex::new(Next { rings: VecDeque::new(), receiver_dropped: false }));
+    (GrowableSender { inner: sender, max, next: next.clone() },
+     GrowableReceiver { inner: receiver, next })
+}
+
+impl GrowableSender {
+    /// Size of the ring pushes currently go to.
+    pub fn capacity(&self) -> usize {
+        self.inner.inner.size()
+    }
+
+    /// Pushes `elem`, growing the ring if it does not fit. Fails with `PushError::Full`
+    /// only once the ring has reached its maximum size.
+    pub fn try_push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        match self.inner.try_push(elem) {
+            Err(PushError::Full) | Err(PushError::MessageTooLarge) if self.grow(elem.len())? => {
+                self.inner.try_push(elem)
+            }
+            r => r,
+        }
+    }
+
+    /// Like `try_push`, but parks once the ring cannot grow any further.
+    pub fn push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        match self.try_push(elem) {
+            Err(PushError::Full) => self.inner.push(elem),
+            r => r,
+        }
+    }
+
+    pub fn close(&mut self) -> Result<(), PushError> {
+        self.inner.close()
+    }
+
+    /// Moves on to a ring big enough for an element of `len` bytes, unless that would
+    /// exceed the maximum size. Fails with `PushError::Disconnected` once the receiver is
+    /// gone, rather than growing rings nobody reads.
+    fn grow(&mut self, len: usize) -> Result<bool, PushError> {
+        let capacity = (2 * self.capacity()).max(len + 8).min(self.max);
+        if capacity <= self.capacity() {
+            return Ok(false);
+        }
+        let mut next = self.next.lock().unwrap();
+        if next.receiver_dropped {
+            return Err(PushError::Disconnected);
+        }
+        let (sender, receiver) = channel(BufferSize::Custom(capacity));
+        // Queued before the old ring disconnects, so the receiver finds it there.
+        next.rings.push_back(receiver);
+        self.inner = sender;
+        Ok(true)
+    }
+}
+
+impl GrowableReceiver {
+    /// Size of the ring pops currently come from.
+    pub fn capacity(&self) -> usize {
+        self.inner.inner.size()
+    }
+
+    pub fn try_pop<F>(&mut self, mut consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        loop {
+            match self.inner.try_pop(&mut consumer) {
+                Err(PopError::Disconnected) if self.advance() => {}
+                r => return r,
+            }
+        }
+    }
+
+    /// Pops one element, parking the calling thread until the sender pushes one.
+    pub fn pop<F>(&mut self, mut consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        loop {
+            match self.inner.pop(&mut consumer) {
+                Err(PopError::Disconnected) if self.advance() => {}
+                r => return r,
+            }
+        }
+    }
+
+    /// Moves on to the next ring once the current one is drained and left behind.
+    fn advance(&mut self) -> bool {
+        match self.next.lock().unwrap().rings.pop_front() {
+            Some(receiver) => {
+                self.inner = receiver;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Also drops the receivers of the rings the sender moved on to, so that it sees the
+/// disconnect whichever ring it is on.
+impl Drop for GrowableReceiver {
+    fn drop(&mut self) {
+        let mut next = self.next.lock().unwrap();
+        next.receiver_dropped = true;
+        next.rings.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cbuffer_raw::{BufferSize, PopError, PushError};
+    use super::channel_growable;
+
+    #[test]
+    fn test_grow() {
+        let page = crate::cbuffer_raw::page_size();
+        let (mut sender, mut receiver) = channel_growable(BufferSize::Custom(page), BufferSize::Custom(4 * page));
+        for i in 0..page / 25 {
+            assert_eq!(Ok(()), sender.try_push(&[i as u8; 100]));
+        }
+        assert_eq!(4 * page, sender.capacity());
+        assert_eq!(page, receiver.capacity());
+        assert_eq!(Err(PushError::Full), sender.try_push(&vec![0u8; 4 * page - 8]));
+
+        for i in 0..page / 25 {
+            assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(&[i as u8; 100][..], bytes)));
+        }
+        assert_eq!(4 * page, receiver.capacity());
+        assert_eq!(Err(PopError::Empty), receiver.try_pop(|_| {}));
+        drop(sender);
+        assert_eq!(Err(PopError::Disconnected), receiver.try_pop(|_| {}));
+    }
+
+    #[test]
+    fn test_grow_blocking() {
+        use std::thread;
+
+        let page = crate::cbuffer_raw::page_size();
+        let (mut sender, mut receiver) = channel_growable(BufferSize::Custom(page), BufferSize::Custom(8 * page));
+        let n = 20_000u32;
+        let producer = thread::spawn(move || {
+            for i in 0..n {
+                sender.push(&i.to_le_bytes()).unwrap();
+            }
+        });
+        for i in 0..n {
+            assert_eq!(Ok(()), receiver.pop(|bytes| assert_eq!(&i.to_le_bytes()[..], bytes)));
+        }
+        producer.join().unwrap();
+        assert_eq!(Err(PopError::Disconnected), receiver.pop(|_| {}));
+    }
+
+    #[test]
+    fn test_receiver_dropped() {
+        let page = crate::cbuffer_raw::page_size();
+        let (mut sender, receiver) = channel_growable(BufferSize::Custom(page), BufferSize::Custom(4 * page));
+        assert_eq!(Ok(()), sender.try_push(&vec![0u8; 2 * page]));
+        assert!(sender.capacity() > receiver.capacity());
+        drop(receiver);
+        assert_eq!(Err(PushError::Disconnected), sender.try_push(b"x"));
+        assert_eq!(Err(PushError::Disconnected), sender.push(&vec![0u8; 3 * page]));
+
+        // Dropped before the sender outgrows its first ring.
+        let (mut sender, receiver) = channel_growable(BufferSize::Custom(page), BufferSize::Custom(4 * page));
+        drop(receiver);
+        assert_eq!(Err(PushError::Disconnected), sender.try_push(&vec![0u8; 2 * page]));
+    }
+}
@@ -1,10 +1,142 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
 extern crate libc;
 
+#[cfg(feature = "std")]
 mod cbuffer_raw;
+mod frame;
+mod error;
+pub mod bare;
+#[cfg(feature = "std")]
+mod stream;
+#[cfg(feature = "std")]
+mod broadcast;
+#[cfg(feature = "std")]
+mod mux;
+#[cfg(feature = "std")]
+mod priority;
+#[cfg(feature = "std")]
+mod timed;
+#[cfg(feature = "std")]
+mod sequenced;
+#[cfg(feature = "std")]
+mod growable;
+#[cfg(feature = "std")]
+mod recording;
+#[cfg(feature = "std")]
+mod spill;
+#[cfg(feature = "std")]
+mod ratelimit;
+#[cfg(feature = "std")]
+mod batched;
+#[cfg(feature = "std")]
+mod sharded;
+#[cfg(feature = "std")]
+mod pubsub;
+#[cfg(feature = "std")]
+mod watch;
+#[cfg(feature = "std")]
+mod flight;
+#[cfg(feature = "std")]
+pub mod mock;
+#[cfg(feature = "std")]
+mod endpoint;
+#[cfg(all(feature = "std", unix))]
+mod fdpass;
+#[cfg(feature = "async")]
+mod asynchronous;
+#[cfg(all(feature = "std", windows))]
+mod windows;
+#[cfg(all(feature = "std", target_os = "macos"))]
+mod macos;
+#[cfg(all(feature = "std", target_os = "linux"))]
+mod numa;
+#[cfg(feature = "typed")]
+mod typed;
+#[cfg(all(feature = "std", feature = "rkyv"))]
+mod archived;
+#[cfg(all(feature = "std", feature = "bytemuck"))]
+mod pod;
+#[cfg(feature = "checksum")]
+mod checked;
+#[cfg(all(feature = "std", unix, feature = "wal"))]
+mod wal;
+#[cfg(feature = "trace")]
+mod trace;
+#[cfg(feature = "compress")]
+mod compressed;
+#[cfg(feature = "encrypt")]
+mod encrypted;
+#[cfg(all(feature = "std", unix))]
+pub mod registry;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "python")]
+mod python;
 
-pub use cbuffer_raw::{channel, BufferSize, Sender, Receiver};
+#[cfg(feature = "std")]
+pub use endpoint::{Push, Pop};
+#[cfg(feature = "std")]
+pub use cbuffer_raw::{channel, channel_mpmc, channel_overwrite, channel_with_backend, channel_with_format, channel_with_memory, channel_with_policy, BufferSize, FullPolicy, MemoryBackend, MirroredMapping, RingMemory, WaitStrategy, Credits, ChannelBuilder, Sender, Receiver, RecvGuard, PeekGuard, PendingPop, Transaction, PushTimeoutError, PopTimeoutError, Watermark, Occupancy, OccupancyEvents, Unmatched, Stats, Iter, TryIter};
+#[cfg(all(feature = "std", unix))]
+pub use cbuffer_raw::{channel_shared, channel_in_fd, channel_from_raw_parts, Advice};
+#[cfg(feature = "zerocopy")]
+pub use cbuffer_raw::CastError;
+pub use frame::LengthPrefix;
+pub use error::{PushError, PopError};
+#[cfg(feature = "std")]
+pub use ratelimit::RateLimit;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub use numa::{node_cpus, pin_thread_to_node};
+#[cfg(feature = "std")]
+pub use stream::{stream_channel, StreamSender, StreamReceiver};
+#[cfg(feature = "std")]
+pub use broadcast::{broadcast, BroadcastSender, BroadcastReceiver};
+#[cfg(feature = "std")]
+pub use mux::{channel_mux, MuxSender, MuxStream, MuxReceiver};
+#[cfg(feature = "std")]
+pub use priority::{channel_priority, PrioritySender, PriorityReceiver};
+#[cfg(feature = "std")]
+pub use timed::{channel_timed, TimedSender, TimedReceiver};
+#[cfg(feature = "std")]
+pub use sequenced::{channel_sequenced, SequencedSender, SequencedReceiver};
+#[cfg(feature = "std")]
+pub use growable::{channel_growable, GrowableSender, GrowableReceiver};
+#[cfg(feature = "std")]
+pub use recording::{RecordingReceiver, Recording, Record};
+#[cfg(feature = "std")]
+pub use spill::{channel_spill, SpillSender, SpillReceiver};
+#[cfg(feature = "std")]
+pub use batched::BatchedSender;
+#[cfg(feature = "std")]
+pub use sharded::{channel_sharded, ShardedSender};
+#[cfg(feature = "std")]
+pub use pubsub::{channel_pubsub, Publisher, Subscriber};
+#[cfg(feature = "std")]
+pub use watch::{channel_watch, WatchSender, WatchReceiver};
+#[cfg(feature = "std")]
+pub use flight::FlightRecorder;
+#[cfg(feature = "async")]
+pub use asynchronous::{channel_async, AsyncSender, AsyncReceiver};
+#[cfg(feature = "typed")]
+pub use typed::{channel_typed, TypedSender, TypedReceiver, TypedError};
+#[cfg(all(feature = "std", feature = "rkyv"))]
+pub use archived::{channel_archived, ArchivedSender, ArchivedReceiver, ArchivedGuard, ArchivedError};
+#[cfg(all(feature = "std", feature = "bytemuck"))]
+pub use pod::{channel_of, PodSender, PodReceiver, PodGuard};
+#[cfg(feature = "checksum")]
+pub use checked::{channel_checked, CheckedSender, CheckedReceiver};
+#[cfg(all(feature = "std", unix, feature = "wal"))]
+pub use wal::{channel_wal, WalSender, WalReceiver, SyncPolicy, WalError};
+#[cfg(feature = "trace")]
+pub use trace::{TraceEvent, TraceOp};
+#[cfg(feature = "compress")]
+pub use compressed::{channel_compressed, CompressedSender, CompressedReceiver};
+#[cfg(feature = "encrypt")]
+pub use encrypted::{channel_encrypted, EncryptedSender, EncryptedReceiver};
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use chrono::Local;
 
@@ -14,7 +146,7 @@ mod tests {
         use super::{channel, BufferSize};
         use std::thread;
 
-        let (mut sender, mut receiver) = channel(BufferSize::Buf128M);
+        let (mut sender, receiver) = channel(BufferSize::Buf128M);
 
         let n = 5_000_000;
         let v = b"123abc";
@@ -23,7 +155,7 @@ mod tests {
             let begin = Local::now();
             for _i in 0..n {
                 loop {
-                    if sender.try_push(v) { break; }
+                    if sender.try_push(v).is_ok() { break; }
                 }
             }
             let end = Local::now();
@@ -35,17 +167,1686 @@ mod tests {
 
         let mut count = 0;
         let begin = Local::now();
-        loop {
-            if !receiver.try_pop(|bytes| {
+        while count < n {
+            let _ = receiver.try_pop(|bytes| {
                 assert_eq!(v, bytes);
                 count += 1;
-            }){
-                break;
-            }
+            });
         }
         let end = Local::now();
         let b = end - begin;
         println!("receiving speed: {}", (n as f32/b.num_microseconds().unwrap()as f32)*1000000f32);
         assert_eq!(count, n);
+        assert!(receiver.try_pop(|_| {}).is_err());
+    }
+
+    #[test]
+    fn test_blocking() {
+        use super::{channel, BufferSize};
+        use std::thread;
+
+        let (mut sender, receiver) = channel(BufferSize::Buf64M);
+
+        // 256 MiB through a 64 MiB ring: both sides have to park and the indices wrap.
+        let n = 256usize;
+        let frame_len = 1024 * 1024;
+
+        let producer = thread::spawn(move || {
+            for i in 0..n {
+                sender.push(&vec![i as u8; frame_len]).unwrap();
+            }
+        });
+
+        let mut count = 0;
+        for i in 0..n {
+            receiver.pop(|bytes| {
+                assert_eq!(frame_len, bytes.len());
+                assert!(bytes.iter().all(|b| *b == i as u8));
+                count += 1;
+            }).unwrap();
+        }
+        producer.join().unwrap();
+        assert_eq!(count, n);
+    }
+
+    #[test]
+    fn test_timeout() {
+        use super::{channel, BufferSize, PushTimeoutError, PopTimeoutError};
+        use std::time::{Duration, Instant};
+
+        let (mut sender, receiver) = channel(BufferSize::Buf64M);
+        let timeout = Duration::from_millis(20);
+
+        let begin = Instant::now();
+        assert_eq!(Err(PopTimeoutError::Timeout), receiver.pop_timeout(timeout, |_| {}));
+        assert!(begin.elapsed() >= timeout);
+
+        let frame = vec![0u8; 1024 * 1024];
+        while sender.try_push(&frame).is_ok() {}
+        assert_eq!(Err(PushTimeoutError::Timeout), sender.push_timeout(&frame, timeout));
+
+        assert_eq!(Ok(()), receiver.pop_timeout(timeout, |bytes| assert_eq!(frame.as_slice(), bytes)));
+        assert_eq!(Ok(()), sender.push_timeout(&frame, timeout));
+    }
+
+    #[test]
+    fn test_push_errors() {
+        use super::{channel, BufferSize, PushError, PushTimeoutError};
+        use std::time::Duration;
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let size = super::cbuffer_raw::page_size();
+
+        assert_eq!(Err(PushError::MessageTooLarge), sender.try_push(&vec![0u8; size]));
+        assert_eq!(Err(PushError::MessageTooLarge), sender.push(&vec![0u8; size]));
+        let frame = vec![0u8; size / 4];
+        while sender.try_push(&frame).is_ok() {}
+        assert_eq!(Err(PushError::Full), sender.try_push(&frame));
+
+        let blocked = std::thread::spawn(move || {
+            let r = sender.push(&frame);
+            (sender, r)
+        });
+        std::thread::sleep(Duration::from_millis(10));
+        drop(receiver);
+        let (mut sender, r) = blocked.join().unwrap();
+        assert_eq!(Err(PushError::Disconnected), r);
+        assert_eq!(Err(PushError::Disconnected), sender.try_push(b"abc"));
+        assert_eq!(Err(PushTimeoutError::Disconnected), sender.push_timeout(b"abc", Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn test_max_message_size() {
+        use super::{channel, BufferSize, PushError};
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let size = super::cbuffer_raw::page_size();
+        assert_eq!(size - 5, sender.max_message_size());
+        assert_eq!(Ok(()), sender.try_push(&vec![0u8; size - 5]));
+        assert_eq!(Ok(()), receiver.try_pop(|_| {}));
+
+        sender.set_max_message_size(100);
+        assert_eq!(100, sender.max_message_size());
+        assert_eq!(Ok(()), sender.try_push(&[0u8; 100]));
+        assert_eq!(Err(PushError::MessageTooLarge), sender.try_push(&[0u8; 101]));
+        // Never worth waiting for, so the blocking push fails right away too.
+        assert_eq!(Err(PushError::MessageTooLarge), sender.push(&[0u8; 101]));
+        assert_eq!(0, sender.push_all([&[0u8; 101][..]].iter().copied()));
+    }
+
+    #[test]
+    fn test_push_vectored() {
+        use super::{channel, BufferSize, PushError};
+        use std::io::IoSlice;
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let header = 7u32.to_le_bytes();
+        let bufs = [IoSlice::new(&header), IoSlice::new(b""), IoSlice::new(b"payload")];
+        assert_eq!(Ok(()), sender.try_push_vectored(&bufs));
+        assert_eq!(Ok(()), sender.push_vectored(&bufs[1..]));
+        assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(&b"\x07\0\0\0payload"[..], bytes)));
+        assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(b"payload", bytes)));
+
+        sender.set_max_message_size(10);
+        assert_eq!(Err(PushError::MessageTooLarge), sender.try_push_vectored(&bufs));
+        assert_eq!(Err(PushError::MessageTooLarge), sender.push_vectored(&bufs));
+    }
+
+    #[test]
+    fn test_transaction() {
+        use super::{channel, BufferSize, PopError, PushError};
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let size = super::cbuffer_raw::page_size();
+        let mut tx = sender.transaction();
+        tx.push(b"first").unwrap();
+        tx.push(b"second").unwrap();
+        assert_eq!(2, tx.len());
+        assert_eq!(Err(PopError::Empty), receiver.try_pop(|_| {}));
+        tx.commit().unwrap();
+        assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(b"first", bytes)));
+        assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(b"second", bytes)));
+
+        // Dropped without committing, nothing shows up.
+        sender.transaction().push(b"aborted").unwrap();
+        assert_eq!(Err(PopError::Empty), receiver.try_pop(|_| {}));
+
+        // Either everything fits or nothing is published.
+        sender.try_push(&vec![0u8; size / 2]).unwrap();
+        let mut tx = sender.transaction();
+        for _i in 0..4 {
+            tx.push(&vec![1u8; size / 8]).unwrap();
+        }
+        assert_eq!(Err(PushError::Full), tx.try_commit());
+        assert_eq!(Ok(()), receiver.try_pop(|_| {}));
+        assert_eq!(Ok(()), tx.try_commit());
+        assert!(tx.is_empty());
+        drop(tx);
+        assert_eq!(4, receiver.try_iter().count());
+
+        let mut tx = sender.transaction();
+        tx.push(&vec![0u8; size / 2]).unwrap();
+        assert_eq!(Err(PushError::MessageTooLarge), tx.push(&vec![0u8; size / 2]));
+        assert_eq!(1, tx.len());
+    }
+
+    #[test]
+    fn test_begin_pop() {
+        use super::{channel, BufferSize, PopError};
+        use std::thread;
+
+        let (mut sender, mut receiver) = channel(BufferSize::Custom(4096));
+        sender.try_push(b"first").unwrap();
+        sender.try_push(b"second").unwrap();
+
+        // Handed back, explicitly or by dropping the guard, the element comes out again.
+        let pending = receiver.try_begin_pop().unwrap();
+        assert_eq!(b"first", &*pending);
+        pending.abort();
+        drop(receiver.try_begin_pop().unwrap());
+        let pending = receiver.begin_pop().unwrap();
+        assert_eq!(b"first", &*pending);
+        pending.commit();
+        assert_eq!(1, receiver.len());
+        receiver.try_begin_pop().unwrap().commit();
+        assert_eq!(PopError::Empty, receiver.try_begin_pop().err().unwrap());
+
+        let producer = thread::spawn(move || {
+            sender.push(b"later").unwrap();
+        });
+        let pending = receiver.begin_pop().unwrap();
+        assert_eq!(b"later", &*pending);
+        pending.commit();
+        producer.join().unwrap();
+        assert_eq!(PopError::Disconnected, receiver.begin_pop().err().unwrap());
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        use super::{channel, BufferSize, PushError, PushTimeoutError, RateLimit};
+        use std::time::{Duration, Instant};
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        sender.set_rate_limit(Some(RateLimit { messages_per_sec: Some(100), bytes_per_sec: None }));
+        // A second's worth goes out at once.
+        for _i in 0..100 {
+            assert_eq!(Ok(()), sender.try_push(b"x"));
+        }
+        assert_eq!(Err(PushError::Full), sender.try_push(b"x"));
+        assert_eq!(Err(PushTimeoutError::Timeout), sender.push_timeout(b"x", Duration::from_micros(100)));
+        assert_eq!(0, sender.push_all([&b"x"[..]].iter().copied()));
+        let start = Instant::now();
+        assert_eq!(Ok(()), sender.push(b"x"));
+        assert_eq!(Ok(()), sender.push(b"x"));
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert_eq!(102, receiver.len());
+
+        // A failed push does not use up the limit.
+        let mut clone = sender.clone();
+        clone.set_rate_limit(Some(RateLimit { messages_per_sec: None, bytes_per_sec: Some(10_000) }));
+        clone.set_max_message_size(10);
+        assert_eq!(Err(PushError::MessageTooLarge), clone.try_push(&[0u8; 11]));
+        let mut tx = clone.transaction();
+        tx.push(&[0u8; 10]).unwrap();
+        assert_eq!(Ok(()), tx.try_commit());
+
+        sender.set_rate_limit(None);
+        assert_eq!(Ok(()), sender.try_push(b"x"));
+    }
+
+    #[test]
+    fn test_credits() {
+        use super::{channel, BufferSize, ChannelBuilder, Credits, PushError};
+        use std::thread;
+
+        let (mut sender, receiver) = ChannelBuilder::new().capacity_bytes(4096).credits(Credits::Messages(2)).build().unwrap();
+        assert_eq!(Some(2), sender.credits());
+        assert_eq!(Ok(()), sender.try_push(b"a"));
+        assert_eq!(Ok(()), sender.try_push(b"b"));
+        assert_eq!(Err(PushError::Full), sender.try_push(b"c"));
+        assert_eq!(0, sender.push_all([&b"c"[..]].iter().copied()));
+        // Popping frees space but hands out no credits.
+        receiver.try_pop(|_| {}).unwrap();
+        assert_eq!(Err(PushError::Full), sender.try_push(b"c"));
+        let producer = thread::spawn(move || {
+            sender.push(b"c").unwrap();
+            sender
+        });
+        receiver.grant(1);
+        let sender = producer.join().unwrap();
+        assert_eq!(Some(0), sender.credits());
+        assert_eq!(2, receiver.len());
+
+        let (mut sender, receiver) = ChannelBuilder::new().capacity_bytes(4096).credits(Credits::Bytes(10)).build().unwrap();
+        assert_eq!(Err(PushError::Full), sender.try_push(&[0u8; 11]));
+        let mut tx = sender.transaction();
+        tx.push(&[0u8; 6]).unwrap();
+        tx.push(&[0u8; 4]).unwrap();
+        assert_eq!(Ok(()), tx.try_commit());
+        assert_eq!(Some(0), sender.credits());
+        assert_eq!(Err(PushError::Full), sender.try_push(b"z"));
+        receiver.grant(5);
+        assert_eq!(Ok(()), sender.try_push(&[0u8; 5]));
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        receiver.grant(1);
+        assert_eq!(None, sender.credits());
+        assert_eq!(Ok(()), sender.try_push(b"x"));
+    }
+
+    #[test]
+    fn test_remaining_bytes() {
+        use super::{channel, BufferSize, PushError};
+
+        let capacity = super::cbuffer_raw::page_size();
+        let (mut sender, receiver) = channel(BufferSize::Custom(capacity));
+        assert_eq!(capacity - 1, sender.remaining_bytes());
+        assert_eq!(0, receiver.available_bytes());
+        assert!(sender.can_push(capacity - 5));
+        assert!(!sender.can_push(capacity - 4));
+
+        sender.push(&[0u8; 100]).unwrap();
+        assert_eq!(capacity - 105, sender.remaining_bytes());
+        assert_eq!(104, receiver.available_bytes());
+        let fill = sender.remaining_bytes() - 4;
+        assert!(sender.can_push(fill));
+        assert!(!sender.can_push(fill + 1));
+        assert_eq!(Err(PushError::Full), sender.try_push(&vec![0u8; fill + 1]));
+        assert_eq!(Ok(()), sender.try_push(&vec![0u8; fill]));
+        assert_eq!(0, sender.remaining_bytes());
+
+        sender.set_max_message_size(10);
+        receiver.pop(|_| {}).unwrap();
+        assert!(sender.can_push(10) && !sender.can_push(11));
+        drop(receiver);
+        assert!(!sender.can_push(1));
+    }
+
+    #[test]
+    fn test_occupancy_events() {
+        use super::{channel, BufferSize, Occupancy, PushError};
+        use std::thread;
+        use std::time::Duration;
+
+        let capacity = super::cbuffer_raw::page_size();
+        let (mut sender, receiver) = channel(BufferSize::Custom(capacity));
+        let events = receiver.occupancy_events(capacity * 3 / 4).unwrap();
+        assert!(receiver.occupancy_events(1).is_none());
+        assert_eq!(Occupancy::Empty, events.current());
+        assert_eq!(None, events.try_next());
+
+        let elem = vec![0u8; capacity / 8 - 4];
+        while sender.try_push(&elem).is_ok() {}
+        assert_eq!(Err(PushError::Full), sender.try_push(&elem));
+        assert_eq!(vec![Occupancy::NonEmpty, Occupancy::High, Occupancy::Full],
+                   std::iter::from_fn(|| events.try_next()).collect::<Vec<_>>());
+        while receiver.try_pop(|_| {}).is_ok() {}
+        assert_eq!(vec![Occupancy::High, Occupancy::NonEmpty, Occupancy::Empty],
+                   std::iter::from_fn(|| events.try_next()).collect::<Vec<_>>());
+        assert_eq!(None, events.next_timeout(Duration::from_millis(10)));
+
+        let monitor = thread::spawn(move || events.collect::<Vec<_>>());
+        sender.push(b"x").unwrap();
+        receiver.pop(|_| {}).unwrap();
+        drop(sender);
+        assert_eq!(vec![Occupancy::NonEmpty, Occupancy::Empty], monitor.join().unwrap());
+    }
+
+    #[test]
+    fn test_varint_prefix() {
+        use super::{channel_with_format, BufferSize, LengthPrefix, PopError, PushError};
+
+        let capacity = super::cbuffer_raw::page_size();
+        let (mut sender, receiver) = channel_with_format(BufferSize::Custom(capacity), LengthPrefix::Varint);
+        assert_eq!(capacity - 3, sender.max_message_size());
+
+        // Ten bytes of payload take eleven in the ring instead of fourteen.
+        let mut count = 0;
+        while sender.try_push(&[count as u8; 10]).is_ok() {
+            count += 1;
+        }
+        assert_eq!((capacity - 1) / 11, count);
+        for i in 0..count {
+            assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(&[i as u8; 10], bytes)));
+        }
+
+        let large = vec![7u8; 1000];
+        assert_eq!(Ok(()), sender.try_push(&large));
+        assert_eq!(Ok(()), sender.push_with_header(42, b"routed"));
+        assert_eq!(2, sender.push_all([&b""[..], &large[..]].iter().copied()));
+        assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(large.as_slice(), bytes)));
+        assert_eq!(Ok(()), receiver.pop_with_header(|header, bytes| assert_eq!((42, &b"routed"[..]), (header, bytes))));
+        assert_eq!(Some(Vec::new()), receiver.pop_owned());
+        assert_eq!(Some(large), receiver.pop_owned());
+
+        assert_eq!(Err(PushError::MessageTooLarge), sender.try_push(&vec![0u8; capacity - 2]));
+        sender.close().unwrap();
+        assert_eq!(Err(PopError::Closed), receiver.try_pop(|_| {}));
+        assert_eq!(count as u64 + 4, sender.stats().messages);
+        assert_eq!(10 * count as u64 + 2014, sender.stats().bytes);
+    }
+
+    #[test]
+    fn test_narrow_prefix() {
+        use super::{channel_with_format, BufferSize, LengthPrefix, PopError, PushError};
+
+        let (mut sender, receiver) = channel_with_format(BufferSize::Custom(4096), LengthPrefix::U8);
+        assert_eq!(254, sender.max_message_size());
+        assert_eq!(Err(PushError::MessageTooLarge), sender.try_push(&[0u8; 255]));
+        assert_eq!(Ok(()), sender.try_push(&[1u8; 254]));
+        assert_eq!(Ok(()), sender.try_push(b"abc"));
+        sender.close().unwrap();
+        assert_eq!(Some(vec![1u8; 254]), receiver.pop_owned());
+        assert_eq!(Some(b"abc".to_vec()), receiver.pop_owned());
+        assert_eq!(Err(PopError::Closed), receiver.try_pop(|_| {}));
+
+        let size = super::cbuffer_raw::page_size();
+        let (sender, _receiver) = channel_with_format(BufferSize::Custom(1 << 20), LengthPrefix::U16);
+        assert_eq!(65534, sender.max_message_size());
+        let (sender, _receiver) = channel_with_format(BufferSize::Custom(4096), LengthPrefix::U16);
+        assert_eq!(size.min(65536) - 3, sender.max_message_size());
+    }
+
+    #[test]
+    fn test_fixed_records() {
+        use super::{channel_with_format, BufferSize, LengthPrefix, PopError, PushError};
+        use std::time::Duration;
+
+        let (mut sender, receiver) = channel_with_format(BufferSize::Custom(4096), LengthPrefix::Fixed(64));
+        assert_eq!(64, sender.max_message_size());
+        assert_eq!(Err(PushError::MessageTooLarge), sender.try_push(&[0u8; 63]));
+        assert_eq!(Err(PushError::MessageTooLarge), sender.try_push(&[0u8; 65]));
+        // No prefixes: 63 records fill the ring up to the byte it keeps free.
+        let capacity = sender.remaining_bytes() + 1;
+        for i in 0..(capacity / 64 - 1) {
+            sender.try_push(&[i as u8; 64]).unwrap();
+        }
+        assert_eq!(Err(PushError::Full), sender.try_push(&[0u8; 64]));
+        assert_eq!(capacity - 64, receiver.available_bytes());
+        for i in 0..(capacity / 64 - 1) {
+            assert_eq!(Some(vec![i as u8; 64]), receiver.pop_owned());
+        }
+        // Across the end of the ring.
+        for i in 0..100u8 {
+            sender.try_push(&[i; 64]).unwrap();
+            assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(&[i; 64][..], bytes)));
+        }
+
+        sender.try_push(&[7u8; 64]).unwrap();
+        sender.close().unwrap();
+        assert_eq!(Err(PushError::Closed), sender.try_push(&[0u8; 64]));
+        assert_eq!(Some(vec![7u8; 64]), receiver.pop_owned());
+        assert_eq!(Err(PopError::Closed), receiver.try_pop(|_| {}));
+
+        let (mut sender, receiver) = channel_with_format(BufferSize::Custom(4096), LengthPrefix::Fixed(8));
+        let blocked = std::thread::spawn(move || receiver.pop(|_| {}));
+        std::thread::sleep(Duration::from_millis(10));
+        sender.close().unwrap();
+        assert_eq!(Err(PopError::Closed), blocked.join().unwrap());
+    }
+
+    #[test]
+    fn test_payload_align() {
+        use super::{BufferSize, ChannelBuilder, LengthPrefix, PopError};
+
+        let path = std::env::temp_dir().join(format!("cbuffer-aligned-{}", std::process::id()));
+        let page = super::cbuffer_raw::page_size();
+        let (mut sender, mut receiver) = ChannelBuilder::new()
+            .capacity(BufferSize::Custom(page))
+            .prefix(LengthPrefix::Varint)
+            .payload_align(64)
+            .build()
+            .unwrap();
+        assert_eq!(page - 1 - 64 - 63, sender.max_message_size());
+        // Past the end of the ring several times over, with every kind of padding.
+        for len in 0..300 {
+            sender.push(&vec![len as u8; len]).unwrap();
+            let mut tx = sender.transaction();
+            tx.push(&vec![1u8; len / 2]).unwrap();
+            tx.push(b"x").unwrap();
+            tx.commit().unwrap();
+            for expected in &[vec![len as u8; len], vec![1u8; len / 2], b"x".to_vec()] {
+                let elem = receiver.recv_ref().unwrap();
+                assert_eq!(0, elem.as_ptr() as usize % 64);
+                assert_eq!(&expected[..], &*elem);
+            }
+        }
+
+        assert_eq!(2, sender.push_all([&b"ab"[..], b"cde"].iter().copied()));
+        sender.close().unwrap();
+        assert_eq!(2, receiver.snapshot(&path).unwrap());
+        let (_restored, mut restored_receiver) = ChannelBuilder::new().capacity_bytes(page).restore(&path).unwrap();
+        for expected in &[&b"ab"[..], b"cde"] {
+            let elem = restored_receiver.recv_ref().unwrap();
+            assert_eq!(0, elem.as_ptr() as usize % 64);
+            assert_eq!(*expected, &*elem);
+        }
+        assert_eq!(Err(PopError::Closed), restored_receiver.try_pop(|_| {}));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "zerocopy")]
+    #[test]
+    fn test_as_pod() {
+        use super::{channel_with_format, BufferSize, CastError, ChannelBuilder, LengthPrefix};
+
+        let (mut sender, mut receiver) = ChannelBuilder::new().capacity_bytes(4096).payload_align(16).build().unwrap();
+        for i in 0..100u64 {
+            sender.push(bytemuck::bytes_of(&[i, !i])).unwrap();
+            sender.push(b"short").unwrap();
+            assert_eq!(Ok(&[i, !i]), receiver.peek_ref().unwrap().as_pod::<[u64; 2]>());
+            assert_eq!(Ok(&[i, !i]), receiver.recv_ref().unwrap().as_pod::<[u64; 2]>());
+            assert_eq!(Err(CastError::Size), receiver.begin_pop().unwrap().as_pod::<u64>());
+            receiver.pop(|_| {}).unwrap();
+        }
+
+        // A one-byte prefix leaves the payload unaligned.
+        let (mut sender, mut receiver) = channel_with_format(BufferSize::Custom(4096), LengthPrefix::U8);
+        sender.push(&7u32.to_le_bytes()).unwrap();
+        assert_eq!(Err(CastError::Alignment), receiver.recv_ref().unwrap().as_pod::<u32>());
+    }
+
+    #[test]
+    fn test_builder() {
+        use super::{BufferSize, ChannelBuilder, FullPolicy, LengthPrefix, MemoryBackend, PushError};
+        use super::cbuffer_raw::Error;
+
+        let (mut sender, receiver) = ChannelBuilder::new()
+            .capacity_bytes(4096)
+            .policy(FullPolicy::DropNewest)
+            .prefix(LengthPrefix::U8)
+            .max_message_size(100)
+            .name("jobs")
+            .build()
+            .unwrap();
+        assert_eq!(100, sender.max_message_size());
+        assert_eq!("jobs", sender.inner.name());
+        assert_eq!(Err(PushError::MessageTooLarge), sender.try_push(&[0u8; 101]));
+        // 101 bytes a frame, so forty fit and the last push is dropped.
+        for _i in 0..41 {
+            assert_eq!(Ok(()), sender.push(&[0u8; 100]));
+        }
+        assert_eq!((40, 1), (receiver.len(), sender.dropped()));
+
+        let built = ChannelBuilder::new().capacity(BufferSize::Custom(4096)).backend(MemoryBackend::Static).build();
+        assert_eq!(Some(Error::UnsupportedBackend), built.err());
+    }
+
+    #[test]
+    fn test_wait_strategy() {
+        use super::{ChannelBuilder, PopTimeoutError, WaitStrategy};
+        use std::thread;
+        use std::time::{Duration, Instant};
+
+        let strategies = [WaitStrategy::Park, WaitStrategy::Spin, WaitStrategy::Yield,
+                          WaitStrategy::Hybrid { spins: 100, yields: 10 }];
+        for &strategy in &strategies {
+            let (mut sender, receiver) = ChannelBuilder::new().capacity_bytes(4096).wait_strategy(strategy).build().unwrap();
+            let n = 20_000u32;
+            let producer = thread::spawn(move || {
+                for i in 0..n {
+                    sender.push(&i.to_le_bytes()).unwrap();
+                }
+            });
+            for i in 0..n {
+                assert_eq!(Ok(()), receiver.pop(|bytes| assert_eq!(&i.to_le_bytes()[..], bytes)));
+            }
+            producer.join().unwrap();
+
+            let (_sender, receiver) = ChannelBuilder::new().capacity_bytes(4096).wait_strategy(strategy).build().unwrap();
+            let start = Instant::now();
+            assert_eq!(Err(PopTimeoutError::Timeout), receiver.pop_timeout(Duration::from_millis(20), |_| {}));
+            assert!(start.elapsed() >= Duration::from_millis(20));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_numa_node() {
+        use super::{node_cpus, pin_thread_to_node, ChannelBuilder};
+        use super::cbuffer_raw::Error;
+        use std::thread;
+
+        let (mut sender, receiver) = ChannelBuilder::new().capacity_bytes(4096).numa_node(0).build().unwrap();
+        let producer = thread::spawn(move || {
+            pin_thread_to_node(0).unwrap();
+            sender.push(b"local").unwrap();
+        });
+        assert_eq!(Ok(()), receiver.pop(|bytes| assert_eq!(b"local", bytes)));
+        producer.join().unwrap();
+        assert!(!node_cpus(0).unwrap().is_empty());
+
+        let built = ChannelBuilder::new().capacity_bytes(4096).numa_node(1 << 20).build();
+        assert_eq!(Some(Error::OS), built.err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_advise() {
+        use super::cbuffer_raw::Error;
+        use super::{Advice, ChannelBuilder, MemoryBackend};
+
+        let (mut sender, receiver) = ChannelBuilder::new()
+            .capacity_bytes(4096)
+            .advise(Advice::DontDump)
+            .advise(Advice::WillNeed)
+            .advise(Advice::HugePage)
+            .build()
+            .unwrap();
+        sender.push(b"advised").unwrap();
+        assert_eq!(Some(b"advised".to_vec()), receiver.pop_owned());
+
+        // Heap rings share their first and last pages with whatever is allocated next to them.
+        let built = ChannelBuilder::new().capacity_bytes(4096).backend(MemoryBackend::Heap).advise(Advice::WillNeed).build();
+        assert_eq!(Some(Error::UnsupportedBackend), built.err());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        use super::{channel, channel_with_format, BufferSize, ChannelBuilder, LengthPrefix, PopError};
+        use std::io::ErrorKind;
+
+        let path = std::env::temp_dir().join(format!("cbuffer-snapshot-{}", std::process::id()));
+        let (mut sender, receiver) = channel_with_format(BufferSize::Custom(4096), LengthPrefix::Varint);
+        for elem in &[&b"first"[..], b"second", b"third"] {
+            sender.push(elem).unwrap();
+        }
+        assert_eq!(Some(b"first".to_vec()), receiver.pop_owned());
+        sender.close().unwrap();
+        assert_eq!(2, receiver.snapshot(&path).unwrap());
+        assert_eq!(2, receiver.len());
+
+        let (restored, restored_receiver) = ChannelBuilder::new().capacity_bytes(4096).restore(&path).unwrap();
+        assert_eq!(restored.inner.size() - 3, restored.max_message_size());
+        assert_eq!(2, restored_receiver.len());
+        assert_eq!(Some(b"second".to_vec()), restored_receiver.pop_owned());
+        assert_eq!(Some(b"third".to_vec()), restored_receiver.pop_owned());
+        assert_eq!(Err(PopError::Closed), restored_receiver.try_pop(|_| {}));
+
+        let page = super::cbuffer_raw::page_size();
+        let (mut sender, receiver) = channel(BufferSize::Custom(4 * page));
+        sender.push(&vec![0u8; 2 * page]).unwrap();
+        receiver.snapshot(&path).unwrap();
+        let restored = ChannelBuilder::new().capacity_bytes(page).restore(&path);
+        assert_eq!(Some(ErrorKind::InvalidInput), restored.err().map(|err| err.kind()));
+
+        // A corrupt alignment is not passed on to the builder.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[12..16].copy_from_slice(&(1u32 << 20).to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        let restored = ChannelBuilder::new().capacity_bytes(8 * page).restore(&path);
+        assert_eq!(Some(ErrorKind::InvalidData), restored.err().map(|err| err.kind()));
+
+        std::fs::write(&path, b"not a snapshot at all").unwrap();
+        let restored = ChannelBuilder::new().capacity_bytes(4096).restore(&path);
+        assert_eq!(Some(ErrorKind::InvalidData), restored.err().map(|err| err.kind()));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_recv_ref() {
+        use super::{channel, BufferSize};
+
+        let (mut sender, mut receiver) = channel(BufferSize::Custom(4096));
+        assert!(receiver.recv_ref().is_none());
+
+        sender.try_push(b"first").unwrap();
+        sender.try_push(b"second").unwrap();
+        {
+            let guard = receiver.recv_ref().unwrap();
+            assert_eq!(b"first", &guard[..]);
+        }
+        let guard = receiver.recv_ref().unwrap();
+        assert_eq!(6, guard.len());
+        assert!(guard.starts_with(b"sec"));
+        drop(guard);
+        assert!(receiver.recv_ref().is_none());
+    }
+
+    #[test]
+    fn test_try_recv() {
+        use super::{channel, BufferSize, PopError, Receiver};
+        use std::thread;
+
+        fn first_byte(receiver: &mut Receiver) -> Result<u8, PopError> {
+            let elem = receiver.try_recv()?;
+            Ok(elem.first().copied().unwrap_or(0))
+        }
+
+        let (mut sender, mut receiver) = channel(BufferSize::Custom(4096));
+        assert_eq!(Err(PopError::Empty), first_byte(&mut receiver));
+        sender.try_push(b"abc").unwrap();
+        sender.try_push(b"").unwrap();
+        assert_eq!(Ok(b'a'), first_byte(&mut receiver));
+        assert_eq!(Ok(0), first_byte(&mut receiver));
+
+        let producer = thread::spawn(move || {
+            sender.push(b"later").unwrap();
+        });
+        assert_eq!(b"later", &*receiver.recv().unwrap());
+        producer.join().unwrap();
+        assert_eq!(PopError::Disconnected, receiver.recv().err().unwrap());
+    }
+
+    #[test]
+    fn test_pop_while() {
+        use super::{channel, BufferSize, PopError};
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        assert_eq!(0, receiver.pop_while(|_| true));
+        for i in 0..10u8 {
+            sender.try_push(&[i]).unwrap();
+        }
+        assert_eq!(4, receiver.pop_while(|bytes| bytes[0] < 4));
+        assert_eq!(0, receiver.pop_while(|_| false));
+        assert_eq!(6, receiver.len());
+        assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(&[4], bytes)));
+
+        // A panicking predicate pops nothing and leaves the channel usable.
+        let popping = std::panic::AssertUnwindSafe(|| receiver.pop_while(|bytes| bytes[0] < 6 || panic!("predicate")));
+        assert!(std::panic::catch_unwind(popping).is_err());
+        assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(&[5], bytes)));
+
+        // Stops in front of the end-of-stream marker, which pops as usual.
+        sender.close().unwrap();
+        assert_eq!(4, receiver.pop_while(|_| true));
+        assert_eq!(Err(PopError::Closed), receiver.try_pop(|_| {}));
+    }
+
+    #[test]
+    fn test_recv_many() {
+        use super::{channel, BufferSize};
+        use std::thread;
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        for i in 0..5u8 {
+            sender.try_push(&[i]).unwrap();
+        }
+        let mut buf = vec![vec![9]];
+        assert_eq!(0, receiver.recv_many(&mut buf, 0));
+        assert_eq!(3, receiver.recv_many(&mut buf, 3));
+        assert_eq!(2, receiver.recv_many(&mut buf, 10));
+        assert_eq!(vec![vec![9], vec![0], vec![1], vec![2], vec![3], vec![4]], buf);
+
+        let producer = thread::spawn(move || {
+            sender.push(b"later").unwrap();
+        });
+        buf.clear();
+        assert_eq!(1, receiver.recv_many(&mut buf, 10));
+        assert_eq!(vec![b"later".to_vec()], buf);
+        producer.join().unwrap();
+        assert_eq!(0, receiver.recv_many(&mut buf, 10));
+    }
+
+    #[test]
+    fn test_pop_batch() {
+        use super::{channel, BufferSize};
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        for i in 0..10u8 {
+            sender.try_push(&[i; 3]).unwrap();
+        }
+
+        let mut seen = Vec::new();
+        assert_eq!(4, receiver.pop_batch(4, |bytes| seen.push(bytes[0])));
+        assert_eq!(6, receiver.pop_batch(100, |bytes| seen.push(bytes[0])));
+        assert_eq!(0, receiver.pop_batch(100, |bytes| seen.push(bytes[0])));
+        assert_eq!((0..10u8).collect::<Vec<_>>(), seen);
+    }
+
+    #[test]
+    fn test_push_all() {
+        use super::{channel, BufferSize};
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let size = super::cbuffer_raw::page_size();
+        let frames = vec![vec![1u8; size / 4]; 8];
+
+        let pushed = sender.push_all(frames.iter().map(|f| f.as_slice()));
+        assert_eq!(3, pushed);
+        assert_eq!(3, receiver.pop_batch(usize::MAX, |bytes| assert_eq!(frames[0].as_slice(), bytes)));
+        assert_eq!(2, sender.push_all(frames.iter().take(2).map(|f| f.as_slice())));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_event_fd() {
+        use super::{channel, BufferSize};
+        use std::os::unix::io::AsRawFd;
+
+        let readable = |fd| {
+            let mut pfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+            unsafe { libc::poll(&mut pfd, 1, 0) == 1 }
+        };
+        let drain = |fd| {
+            let mut counter = 0u64;
+            unsafe { libc::read(fd, &mut counter as *mut u64 as *mut libc::c_void, 8) };
+        };
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let (rx_fd, tx_fd) = (receiver.as_raw_fd(), sender.as_raw_fd());
+        assert!(readable(rx_fd));
+        drain(rx_fd);
+        drain(tx_fd);
+        assert!(!readable(rx_fd));
+
+        sender.try_push(b"abc").unwrap();
+        assert!(readable(rx_fd));
+        assert!(!readable(tx_fd));
+        assert_eq!(Ok(()), receiver.try_pop(|_| {}));
+        assert!(readable(tx_fd));
+    }
+
+    #[cfg(all(feature = "mio", target_os = "linux"))]
+    #[test]
+    fn test_mio_source() {
+        use super::{channel, BufferSize, PopError};
+        use mio::{Events, Interest, Poll, Token};
+        use std::time::Duration;
+
+        let (mut sender, mut receiver) = channel(BufferSize::Custom(4096));
+        let mut poll = Poll::new().unwrap();
+        let mut events = Events::with_capacity(4);
+        poll.registry().register(&mut receiver, Token(7), Interest::READABLE).unwrap();
+        // The eventfd starts out readable.
+        poll.poll(&mut events, Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(vec![Token(7)], events.iter().map(|e| e.token()).collect::<Vec<_>>());
+        poll.poll(&mut events, Some(Duration::from_millis(10))).unwrap();
+        assert!(events.is_empty());
+
+        sender.try_push(b"abc").unwrap();
+        poll.poll(&mut events, Some(Duration::from_secs(1))).unwrap();
+        assert!(events.iter().any(|e| e.token() == Token(7) && e.is_readable()));
+        assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(b"abc", bytes)));
+        assert_eq!(Err(PopError::Empty), receiver.try_pop(|_| {}));
+
+        poll.registry().deregister(&mut receiver).unwrap();
+        sender.try_push(b"def").unwrap();
+        poll.poll(&mut events, Some(Duration::from_millis(10))).unwrap();
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_multi_producer() {
+        use super::{channel, BufferSize};
+        use std::thread;
+
+        let (sender, receiver) = channel(BufferSize::Custom(4096));
+        let producers = 4u32;
+        let n = 20_000u32;
+
+        let handles: Vec<_> = (0..producers).map(|p| {
+            let mut sender = sender.clone();
+            thread::spawn(move || {
+                for i in 0..n {
+                    let mut frame = p.to_le_bytes().to_vec();
+                    frame.extend_from_slice(&i.to_le_bytes());
+                    if i % 2 == 0 {
+                        sender.push(&frame).unwrap();
+                    } else {
+                        while sender.push_all(std::iter::once(frame.as_slice())) == 0 {}
+                    }
+                }
+            })
+        }).collect();
+        drop(sender);
+
+        let mut next = vec![0u32; producers as usize];
+        for _i in 0..producers * n {
+            receiver.pop(|bytes| {
+                let p = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+                let i = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+                assert_eq!(next[p], i);
+                next[p] += 1;
+            }).unwrap();
+        }
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert!(next.iter().all(|i| *i == n));
+        assert_eq!(Err(super::PopError::Disconnected), receiver.try_pop(|_| {}));
+    }
+
+    #[test]
+    fn test_multi_consumer() {
+        use super::{channel, BufferSize};
+        use std::thread;
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let consumers = 4;
+        let n = 50_000u32;
+
+        let handles: Vec<_> = (0..consumers).map(|c| {
+            let mut receiver = receiver.clone();
+            thread::spawn(move || {
+                let mut seen = Vec::new();
+                loop {
+                    let popped = match c % 3 {
+                        0 => receiver.try_pop(|bytes| seen.push(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))).is_ok(),
+                        1 => receiver.pop_batch(3, |bytes| seen.push(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))) > 0,
+                        _ => match receiver.recv_ref() {
+                            Some(bytes) => {
+                                seen.push(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+                                true
+                            }
+                            None => false,
+                        },
+                    };
+                    if !popped && receiver.inner.is_sender_dropped() && receiver.inner.is_empty() {
+                        return seen;
+                    }
+                }
+            })
+        }).collect();
+        drop(receiver);
+
+        for i in 0..n {
+            sender.push(&i.to_le_bytes()).unwrap();
+        }
+        drop(sender);
+
+        let mut all = Vec::new();
+        for h in handles {
+            let seen = h.join().unwrap();
+            assert!(seen.windows(2).all(|w| w[0] < w[1]));
+            all.extend(seen);
+        }
+        all.sort();
+        assert_eq!((0..n).collect::<Vec<_>>(), all);
+    }
+
+    #[test]
+    fn test_mpmc() {
+        use super::{channel_mpmc, BufferSize};
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Arc;
+        use std::thread;
+
+        let (sender, receiver) = channel_mpmc(BufferSize::Custom(4096));
+        let n = 20_000u64;
+        let sum = Arc::new(AtomicU64::new(0));
+
+        let producers: Vec<_> = (0..3).map(|_| {
+            let mut sender = sender.clone();
+            thread::spawn(move || {
+                for i in 1..=n {
+                    sender.push(&i.to_le_bytes()).unwrap();
+                }
+            })
+        }).collect();
+        let consumers: Vec<_> = (0..3).map(|_| {
+            let receiver = receiver.clone();
+            let sum = sum.clone();
+            thread::spawn(move || {
+                let mut count = 0;
+                while receiver.pop_timeout(std::time::Duration::from_millis(200), |bytes| {
+                    let mut v = [0u8; 8];
+                    v.copy_from_slice(bytes);
+                    sum.fetch_add(u64::from_le_bytes(v), Ordering::Relaxed);
+                }).is_ok() {
+                    count += 1;
+                }
+                count
+            })
+        }).collect();
+        drop((sender, receiver));
+
+        for p in producers {
+            p.join().unwrap();
+        }
+        let count: u64 = consumers.into_iter().map(|c| c.join().unwrap()).sum();
+        assert_eq!(3 * n, count);
+        assert_eq!(3 * n * (n + 1) / 2, sum.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_disconnect() {
+        use super::{channel, BufferSize, PopError, PopTimeoutError};
+        use std::time::Duration;
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        sender.try_push(b"last words").unwrap();
+        let second = sender.clone();
+        drop(sender);
+        assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(b"last words", bytes)));
+        assert_eq!(Err(PopError::Empty), receiver.try_pop(|_| {}));
+
+        let blocked = std::thread::spawn(move || receiver.pop(|_| {}));
+        std::thread::sleep(Duration::from_millis(10));
+        drop(second);
+        assert_eq!(Err(PopError::Disconnected), blocked.join().unwrap());
+
+        let (sender, receiver) = channel(BufferSize::Custom(4096));
+        drop(sender);
+        assert_eq!(Err(PopTimeoutError::Disconnected), receiver.pop_timeout(Duration::from_secs(10), |_| {}));
+    }
+
+    #[test]
+    fn test_close() {
+        use super::{channel, BufferSize, PopError, PushError};
+        use std::time::Duration;
+
+        let (mut sender, mut receiver) = channel(BufferSize::Custom(4096));
+        sender.try_push(b"one").unwrap();
+        sender.try_push(b"two").unwrap();
+        let mut other = sender.clone();
+        sender.close().unwrap();
+        assert_eq!(Ok(()), sender.close());
+        assert_eq!(Err(PushError::Closed), other.try_push(b"three"));
+
+        assert_eq!(1, receiver.pop_batch(1, |bytes| assert_eq!(b"one", bytes)));
+        assert_eq!(Ok(()), receiver.pop(|bytes| assert_eq!(b"two", bytes)));
+        assert_eq!(Err(PopError::Closed), receiver.try_pop(|_| {}));
+        assert!(receiver.recv_ref().is_none());
+        assert_eq!(0, receiver.pop_batch(10, |_| {}));
+        drop((sender, other));
+        assert_eq!(Err(PopError::Closed), receiver.pop(|_| {}));
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let blocked = std::thread::spawn(move || receiver.pop(|_| {}));
+        std::thread::sleep(Duration::from_millis(10));
+        sender.close().unwrap();
+        assert_eq!(Err(PopError::Closed), blocked.join().unwrap());
+    }
+
+    #[test]
+    fn test_close_racing_pushes() {
+        use super::{channel, BufferSize, PopError, PushError};
+        use std::thread;
+
+        for _ in 0..200 {
+            let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+            let producers: Vec<_> = (0..8).map(|_| {
+                let mut sender = sender.clone();
+                thread::spawn(move || {
+                    let mut pushed = 0;
+                    loop {
+                        match sender.push(b"element") {
+                            Ok(()) => pushed += 1,
+                            Err(err) => {
+                                assert_eq!(PushError::Closed, err);
+                                return pushed;
+                            }
+                        }
+                    }
+                })
+            }).collect();
+            let consumer = thread::spawn(move || {
+                let mut popped = 0;
+                loop {
+                    match receiver.pop(|_| {}) {
+                        Ok(()) => popped += 1,
+                        Err(err) => {
+                            assert_eq!(PopError::Closed, err);
+                            // Kept alive, so that the producers still see the close.
+                            return (popped, receiver);
+                        }
+                    }
+                }
+            });
+            thread::sleep(std::time::Duration::from_micros(200));
+            sender.close().unwrap();
+            // Every push that succeeded lands ahead of the marker.
+            let pushed: usize = producers.into_iter().map(|p| p.join().unwrap()).sum();
+            assert_eq!(pushed, consumer.join().unwrap().0);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shared() {
+        use super::{channel_shared, BufferSize, PopError, Receiver, Sender};
+        use std::thread;
+
+        let name = format!("/cbuffer-test-{}", std::process::id());
+        let (sender, receiver) = channel_shared(&name, BufferSize::Custom(4096)).unwrap();
+        assert!(channel_shared(&name, BufferSize::Custom(4096)).is_err());
+
+        // Every attached handle maps the object anew, just like another process would.
+        let n = 100_000u32;
+        let producers: Vec<_> = (0..2u32).map(|p| {
+            let mut attached = Sender::attach(&name).unwrap();
+            thread::spawn(move || {
+                for i in 0..n {
+                    attached.push(&(p * n + i).to_le_bytes()).unwrap();
+                }
+            })
+        }).collect();
+        drop(sender);
+
+        let attached = Receiver::attach(&name).unwrap();
+        let mut next = [0u32, n];
+        for _i in 0..2 * n {
+            attached.pop(|bytes| {
+                let v = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                assert_eq!(next[(v / n) as usize], v);
+                next[(v / n) as usize] += 1;
+            }).unwrap();
+        }
+        for p in producers {
+            p.join().unwrap();
+        }
+        assert_eq!(Err(PopError::Disconnected), attached.try_pop(|_| {}));
+        assert_eq!(Err(PopError::Disconnected), receiver.try_pop(|_| {}));
+
+        drop((receiver, attached));
+        assert!(Sender::attach(&name).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shared_builder() {
+        use super::{ChannelBuilder, LengthPrefix, Receiver};
+
+        let name = format!("/cbuffer-builder-{}", std::process::id());
+        let (mut sender, _receiver) = ChannelBuilder::new()
+            .capacity_bytes(4096)
+            .prefix(LengthPrefix::Varint)
+            .build_shared(&name)
+            .unwrap();
+        let attached = Receiver::attach(&name).unwrap();
+        sender.push(&[5u8; 300]).unwrap();
+        sender.push(b"x").unwrap();
+        assert_eq!(Some(vec![5u8; 300]), attached.pop_owned());
+        assert_eq!(Some(b"x".to_vec()), attached.pop_owned());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_send_over() {
+        use super::{channel, BufferSize, ChannelBuilder, PopError, Receiver, Sender};
+        use std::io::ErrorKind;
+        use std::os::unix::net::UnixStream;
+
+        let (sender, receiver) = ChannelBuilder::new().capacity_bytes(4096).build_anonymous().unwrap();
+        assert!(sender.ring_fd().is_some());
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        sender.send_over(&ours).unwrap();
+        let mut joined = Sender::receive_from(&theirs).unwrap();
+        drop(sender);
+        joined.push(b"over the socket").unwrap();
+        assert_eq!(Some(b"over the socket".to_vec()), receiver.pop_owned());
+
+        receiver.send_over(&theirs).unwrap();
+        let other = Receiver::receive_from(&ours).unwrap();
+        joined.push(b"again").unwrap();
+        assert_eq!(Some(b"again".to_vec()), other.pop_owned());
+        drop(joined);
+        assert_eq!(Err(PopError::Disconnected), receiver.try_pop(|_| {}));
+
+        let (sender, _receiver) = channel(BufferSize::Custom(4096));
+        assert!(sender.ring_fd().is_none());
+        assert_eq!(ErrorKind::InvalidInput, sender.send_over(&ours).unwrap_err().kind());
+        drop(ours);
+        assert_eq!(ErrorKind::UnexpectedEof, Sender::receive_from(&theirs).err().unwrap().kind());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_channel_in_fd() {
+        use super::{channel_in_fd, PopError};
+        use std::io::ErrorKind;
+        use std::os::unix::io::AsFd;
+
+        let page = super::cbuffer_raw::page_size();
+        let path = std::env::temp_dir().join(format!("cbuffer-fd-{}", std::process::id()));
+        let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // Room to spare past the ring is left alone.
+        file.set_len(4 * page as u64).unwrap();
+
+        let (mut sender, receiver) = channel_in_fd(file.as_fd(), 2 * page).unwrap();
+        sender.push(b"in my file").unwrap();
+        let (_other_sender, other_receiver) = channel_in_fd(file.as_fd(), 2 * page).unwrap();
+        assert_eq!(Some(b"in my file".to_vec()), other_receiver.pop_owned());
+        drop(receiver);
+        sender.push(b"still there").unwrap();
+        assert_eq!(Some(b"still there".to_vec()), other_receiver.pop_owned());
+        assert_eq!(Err(PopError::Empty), other_receiver.try_pop(|_| {}));
+
+        assert_eq!(ErrorKind::InvalidInput, channel_in_fd(file.as_fd(), page + 1).err().unwrap().kind());
+        assert_eq!(ErrorKind::InvalidInput, channel_in_fd(file.as_fd(), 4 * page).err().unwrap().kind());
+        assert_eq!(ErrorKind::InvalidData, channel_in_fd(file.as_fd(), page).err().unwrap().kind());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_channel_from_raw_parts() {
+        use super::channel_from_raw_parts;
+        use std::alloc::{alloc_zeroed, dealloc, Layout};
+
+        let page = super::cbuffer_raw::page_size();
+        let layout = Layout::from_size_align(3 * page, page).unwrap();
+        let memory = unsafe { alloc_zeroed(layout) };
+        {
+            let (mut sender, _receiver) = unsafe { channel_from_raw_parts(memory, page) }.unwrap();
+            let (_sender, receiver) = unsafe { channel_from_raw_parts(memory, page) }.unwrap();
+            for i in 0..page as u32 {
+                sender.push(&i.to_le_bytes()).unwrap();
+                assert_eq!(Some(i.to_le_bytes().to_vec()), receiver.pop_owned());
+            }
+        }
+        unsafe { dealloc(memory, layout) };
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shared_credits() {
+        use super::{ChannelBuilder, Credits, PushError, Receiver, Sender};
+
+        let name = format!("/cbuffer-credits-{}", std::process::id());
+        let (_sender, receiver) = ChannelBuilder::new()
+            .capacity_bytes(4096)
+            .credits(Credits::Messages(1))
+            .build_shared(&name)
+            .unwrap();
+        let mut attached = Sender::attach(&name).unwrap();
+        assert_eq!(Some(1), attached.credits());
+        assert_eq!(Ok(()), attached.try_push(b"x"));
+        assert_eq!(Err(PushError::Full), attached.try_push(b"y"));
+        Receiver::attach(&name).unwrap().grant(1);
+        assert_eq!(Ok(()), attached.try_push(b"y"));
+        assert_eq!(2, receiver.len());
+    }
+
+    #[test]
+    fn test_prefault() {
+        use super::{ChannelBuilder, MemoryBackend};
+
+        let page = super::cbuffer_raw::page_size();
+        for &backend in &[MemoryBackend::Mmap, MemoryBackend::Heap] {
+            let (mut sender, receiver) = ChannelBuilder::new()
+                .capacity_bytes(64 * page)
+                .backend(backend)
+                .prefault(true)
+                .build()
+                .unwrap();
+            #[cfg(target_os = "linux")]
+            let faults = || {
+                let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+                unsafe { libc::getrusage(libc::RUSAGE_THREAD, &mut usage) };
+                usage.ru_minflt + usage.ru_majflt
+            };
+            #[cfg(target_os = "linux")]
+            let before = faults();
+            let elem = vec![7u8; page - 8];
+            for _i in 0..2 * 64 {
+                sender.push(&elem).unwrap();
+                assert_eq!(Some(elem.clone()), receiver.pop_owned());
+            }
+            // A lap writes every page of the ring once.
+            #[cfg(target_os = "linux")]
+            assert!(faults() - before < 16);
+        }
+    }
+
+    #[test]
+    fn test_heap_backend() {
+        use super::{channel_with_backend, BufferSize, ChannelBuilder, MemoryBackend, PopError};
+        use std::thread;
+
+        let (mut sender, receiver) = channel_with_backend(BufferSize::Custom(4096), MemoryBackend::Heap);
+        let n = 50_000u32;
+        let frame = |i: u32| -> Vec<u8> { (0..(i % 300)).map(|b| (b ^ i) as u8).collect() };
+        let producer = thread::spawn(move || {
+            for i in 0..n {
+                sender.push(&frame(i)).unwrap();
+            }
+        });
+        for i in 0..n {
+            receiver.pop(|bytes| assert_eq!(frame(i).as_slice(), bytes)).unwrap();
+        }
+        producer.join().unwrap();
+        assert_eq!(Err(PopError::Disconnected), receiver.try_pop(|_| {}));
+
+        // The allocation is aligned for the payloads, not just for bytes.
+        let (mut sender, receiver) = ChannelBuilder::new().capacity_bytes(1 << 16).backend(MemoryBackend::Heap)
+            .payload_align(4096).build().unwrap();
+        sender.push(b"aligned").unwrap();
+        receiver.pop(|bytes| assert!((bytes.as_ptr() as usize).is_multiple_of(4096))).unwrap();
+    }
+
+    #[test]
+    fn test_static_memory() {
+        use super::{channel_with_backend, channel_with_memory, BufferSize, MemoryBackend};
+        use std::panic;
+
+        // Any length will do, page aligned or not; frames wrap at the largest power of two
+        // that fits twice.
+        let memory = Box::leak(vec![0u8; 2 * 1000].into_boxed_slice());
+        let (mut sender, receiver) = channel_with_memory(memory);
+        for i in 0..1000u32 {
+            sender.try_push(&[i as u8; 37]).unwrap();
+            assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(&[i as u8; 37][..], bytes)));
+        }
+        assert!(panic::catch_unwind(|| channel_with_backend(BufferSize::Custom(4096), MemoryBackend::Static)).is_err());
+    }
+
+    #[test]
+    fn test_ring_memory() {
+        use super::{ChannelBuilder, LengthPrefix, RingMemory};
+        use super::cbuffer_raw::Error;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        // Word-aligned memory that reports being released.
+        struct Words(Vec<u64>, Arc<AtomicBool>);
+
+        unsafe impl RingMemory for Words {
+            fn as_mut_ptr(&mut self) -> *mut u8 {
+                self.0.as_mut_ptr() as *mut u8
+            }
+
+            fn capacity(&self) -> usize {
+                self.0.len() * 4
+            }
+        }
+
+        impl Drop for Words {
+            fn drop(&mut self) {
+                self.1.store(true, Ordering::Release);
+            }
+        }
+
+        let released = Arc::new(AtomicBool::new(false));
+        let memory = Words(vec![0; 256], released.clone());
+        let (mut sender, receiver) = ChannelBuilder::new().prefix(LengthPrefix::U16).build_in(memory).unwrap();
+        assert_eq!(1024, sender.inner.size());
+        for i in 0..1000u32 {
+            sender.try_push(&[i as u8; 37]).unwrap();
+            assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(&[i as u8; 37][..], bytes)));
+        }
+        drop(sender);
+        assert!(!released.load(Ordering::Acquire));
+        drop(receiver);
+        assert!(released.load(Ordering::Acquire));
+
+        let odd = Words(vec![0; 96], Arc::new(AtomicBool::new(false)));
+        assert!(ChannelBuilder::new().build_in(odd).is_err());
+
+        // Memory is checked against the payload alignment rather than trusted.
+        let words = Words(vec![0; 256], Arc::new(AtomicBool::new(false)));
+        let aligned = (words.0.as_ptr() as usize).is_multiple_of(4096);
+        let built = ChannelBuilder::new().payload_align(4096).build_in(words);
+        assert_eq!(aligned, built.is_ok());
+        let bytes = Box::leak(vec![0u64; 129].into_boxed_slice());
+        let odd: &'static mut [u8] = unsafe { std::slice::from_raw_parts_mut((bytes.as_mut_ptr() as *mut u8).add(1), 1024) };
+        assert_eq!(Some(Error::UnalignedMemory), ChannelBuilder::new().build_in(odd).err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mirrored_mapping() {
+        use super::{ChannelBuilder, MirroredMapping};
+        use std::os::unix::io::AsFd;
+
+        // A file stands in for e.g. persistent memory: the ring lives at an offset into it.
+        let page = super::cbuffer_raw::page_size();
+        let path = std::env::temp_dir().join(format!("cbuffer-mirrored-{}", std::process::id()));
+        let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        file.set_len(3 * page as u64).unwrap();
+        assert!(MirroredMapping::from_fd(file.as_fd(), page, 4 * page).is_err());
+        assert!(MirroredMapping::from_fd(file.as_fd(), 1, page).is_err());
+        let memory = MirroredMapping::from_fd(file.as_fd(), page, 2 * page).unwrap();
+        let (mut sender, receiver) = ChannelBuilder::new().build_in(memory).unwrap();
+        drop(file);
+        assert_eq!(2 * page, sender.inner.size());
+        for i in 0..1000u32 {
+            sender.try_push(&[i as u8; 37]).unwrap();
+            assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(&[i as u8; 37][..], bytes)));
+        }
+    }
+
+    #[test]
+    fn test_huge_pages() {
+        use super::{channel_with_backend, BufferSize, MemoryBackend};
+
+        let capacity = 4 << 20;
+        let (mut sender, receiver) = channel_with_backend(BufferSize::Custom(capacity), MemoryBackend::HugePages);
+        let frame = vec![7u8; 100_000];
+        for _i in 0..3 * capacity / frame.len() {
+            sender.try_push(&frame).unwrap();
+            receiver.try_pop(|bytes| assert_eq!(frame.as_slice(), bytes)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_overwrite() {
+        use super::{channel_overwrite, BufferSize, PopError};
+        use std::thread;
+
+        let (mut sender, receiver) = channel_overwrite(BufferSize::Custom(4096));
+        for i in 0..1000u32 {
+            assert_eq!(Ok(()), sender.try_push(&i.to_le_bytes()));
+        }
+        let mut seen = Vec::new();
+        while receiver.try_pop(|bytes| seen.push(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))).is_ok() {}
+        // Only the newest elements are left, and nothing in between went missing.
+        assert_eq!(Some(&999), seen.last());
+        assert!(seen.len() > 500);
+        assert!(seen.windows(2).all(|w| w[0] + 1 == w[1]));
+
+        let n = 100_000u32;
+        let producer = thread::spawn(move || {
+            for i in 0..n {
+                sender.try_push(&i.to_le_bytes()).unwrap();
+            }
+        });
+        let mut last = None;
+        loop {
+            match receiver.pop(|bytes| {
+                let v = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                assert!(last.is_none_or(|last| last < v));
+                last = Some(v);
+            }) {
+                Ok(()) => {}
+                Err(PopError::Disconnected) => break,
+                Err(err) => panic!("{}", err),
+            }
+        }
+        producer.join().unwrap();
+        assert_eq!(Some(n - 1), last);
+    }
+
+    #[test]
+    fn test_full_policy() {
+        use super::{channel_with_policy, BufferSize, FullPolicy, PushError, PushTimeoutError};
+        use std::time::Duration;
+
+        let fill = |policy| {
+            let (mut sender, receiver) = channel_with_policy(BufferSize::Custom(4096), policy);
+            while sender.try_push(&[1u8; 4]).is_ok() && receiver.inner.unused() > 8 {}
+            (sender, receiver)
+        };
+
+        let (mut sender, _receiver) = fill(FullPolicy::Reject);
+        assert_eq!(Err(PushError::Full), sender.push(&[2u8; 4]));
+        assert_eq!(Err(PushTimeoutError::Timeout), sender.push_timeout(&[2u8; 4], Duration::from_secs(60)));
+
+        let (mut sender, _receiver) = fill(FullPolicy::Block);
+        assert_eq!(Err(PushTimeoutError::Timeout), sender.push_timeout(&[2u8; 4], Duration::from_millis(10)));
+
+        let (mut sender, receiver) = fill(FullPolicy::DropNewest);
+        assert_eq!(Ok(()), sender.push(&[2u8; 4]));
+        assert_eq!(Ok(()), sender.try_push(&[2u8; 4]));
+        while receiver.try_pop(|bytes| assert_eq!(&[1u8; 4], bytes)).is_ok() {}
+
+        let (mut sender, receiver) = fill(FullPolicy::OverwriteOldest);
+        assert_eq!(Ok(()), sender.push(&[2u8; 4]));
+        let mut last = Vec::new();
+        while receiver.try_pop(|bytes| last = bytes.to_vec()).is_ok() {}
+        assert_eq!(vec![2u8; 4], last);
+    }
+
+    #[test]
+    fn test_watermarks() {
+        use super::{channel, BufferSize, Watermark};
+        use std::sync::{Arc, Mutex};
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        assert!(sender.set_watermarks(2048, 512, move |w| seen.lock().unwrap().push(w)));
+        assert!(!sender.set_watermarks(1024, 0, |_| {}));
+
+        // 2048 bytes are 256 frames of 4 + 4.
+        for _i in 0..255 {
+            sender.try_push(&[0u8; 4]).unwrap();
+        }
+        assert!(events.lock().unwrap().is_empty());
+        for _i in 0..100 {
+            sender.try_push(&[0u8; 4]).unwrap();
+        }
+        assert_eq!(vec![Watermark::High], *events.lock().unwrap());
+        for _i in 0..290 {
+            receiver.try_pop(|_| {}).unwrap();
+        }
+        assert_eq!(vec![Watermark::High], *events.lock().unwrap());
+        for _i in 0..10 {
+            receiver.try_pop(|_| {}).unwrap();
+        }
+        assert_eq!(vec![Watermark::High, Watermark::Low], *events.lock().unwrap());
+    }
+
+    #[test]
+    fn test_len() {
+        use super::{channel, BufferSize};
+
+        let (mut sender, mut receiver) = channel(BufferSize::Custom(4096));
+        assert!(sender.is_empty() && receiver.is_empty());
+        assert_eq!(3, sender.push_all([&b"a"[..], b"b", b"c"].iter().copied()));
+        assert_eq!((3, 3), (sender.len(), receiver.len()));
+        assert_eq!(2, receiver.pop_batch(2, |_| {}));
+        assert_eq!(1, receiver.len());
+        let guard = receiver.recv_ref().unwrap();
+        assert_eq!(1, sender.len());
+        drop(guard);
+        assert_eq!(0, sender.len());
+        // The end-of-stream marker is not an element.
+        sender.close().unwrap();
+        assert!(receiver.is_empty());
+    }
+
+    #[test]
+    fn test_stats() {
+        use super::{channel, BufferSize, Stats};
+        use std::time::Duration;
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        assert_eq!(Stats::default(), receiver.stats());
+        for elem in [&b"a"[..], b"bb", b"ccc"].iter() {
+            sender.try_push(elem).unwrap();
+        }
+        assert_eq!(Ok(()), receiver.try_pop(|_| {}));
+        let stats = sender.stats();
+        assert_eq!((3, 6, 1, 0, 3), (stats.messages, stats.bytes, stats.popped, stats.failed_pushes, stats.max_occupancy));
+
+        while sender.try_push(&[0; 100]).is_ok() {}
+        assert!(sender.push_timeout(&[0; 100], Duration::from_millis(10)).is_err());
+        assert_eq!(2, receiver.stats().failed_pushes);
+        assert_eq!(sender.len() as u64, receiver.stats().max_occupancy);
+    }
+
+    #[test]
+    fn test_name() {
+        use super::{channel, BufferSize};
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        assert_eq!("", receiver.inner.name());
+        assert!(sender.set_name("ingest"));
+        assert!(!sender.set_name("other"));
+        assert_eq!("ingest", receiver.inner.name());
+    }
+
+    #[test]
+    fn test_iter() {
+        use super::{channel, BufferSize};
+        use std::thread;
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        assert_eq!(None, receiver.try_iter().next());
+        for i in 0..3u8 {
+            sender.push(&[i]).unwrap();
+        }
+        assert_eq!(vec![vec![0], vec![1], vec![2]], receiver.try_iter().collect::<Vec<_>>());
+
+        let producer = thread::spawn(move || {
+            for i in 0..1000u32 {
+                sender.push(&i.to_le_bytes()).unwrap();
+            }
+        });
+        let mut n = 0u32;
+        for elem in &receiver {
+            assert_eq!(n.to_le_bytes().to_vec(), elem);
+            n += 1;
+        }
+        assert_eq!(1000, n);
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn test_peek_panic() {
+        use super::{channel, BufferSize};
+        use std::panic::{self, AssertUnwindSafe};
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        sender.push_with_header(7, b"a").unwrap();
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| receiver.peek(|_| panic!("peek")))).is_err());
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| receiver.pop_with_header(|_, _| panic!("pop")))).is_err());
+        // Neither panic left the element marked as peeked at, nor popped it.
+        assert_eq!(Ok(()), receiver.pop_with_header(|header, bytes| assert_eq!((7, &b"a"[..]), (header, bytes))));
+        assert!(receiver.is_empty());
+    }
+
+    #[test]
+    fn test_peek() {
+        use super::{channel, BufferSize, PopError};
+        use std::thread;
+
+        let (mut sender, mut receiver) = channel(BufferSize::Custom(4096));
+        assert_eq!(Err(PopError::Empty), receiver.peek(|_| {}));
+        sender.push_all([&b"a"[..], b"b"].iter().copied());
+        assert_eq!(Ok(()), receiver.peek(|bytes| assert_eq!(b"a", bytes)));
+        assert_eq!(Ok(()), receiver.peek(|bytes| assert_eq!(b"a", bytes)));
+        assert_eq!(Ok(()), receiver.pop(|bytes| assert_eq!(b"a", bytes)));
+        assert_eq!(Some(&b"b"[..]), receiver.peek_ref().as_deref());
+        assert_eq!(1, receiver.len());
+        assert_eq!(Ok(()), receiver.pop(|bytes| assert_eq!(b"b", bytes)));
+        sender.close().unwrap();
+        assert_eq!(Err(PopError::Closed), receiver.peek(|_| {}));
+
+        // A competing receiver never sees an element change under a peek.
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let popper = receiver.clone();
+        let n = 20_000u32;
+        let consumer = thread::spawn(move || {
+            (0..n).all(|i| popper.pop(|bytes| assert_eq!(&i.to_le_bytes(), bytes)).is_ok())
+        });
+        let producer = thread::spawn(move || {
+            for i in 0..n {
+                sender.push(&i.to_le_bytes()).unwrap();
+            }
+        });
+        let mut last = 0;
+        while receiver.peek(|bytes| {
+            let v = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            assert!(last <= v);
+            last = v;
+        }) != Err(PopError::Disconnected) {}
+        producer.join().unwrap();
+        assert!(consumer.join().unwrap());
+    }
+
+    #[test]
+    fn test_pop_owned() {
+        use super::{channel, BufferSize};
+        use std::thread;
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        assert_eq!(None, receiver.pop_owned());
+        sender.push(b"owned").unwrap();
+        let elem = receiver.pop_owned().unwrap();
+        assert_eq!(b"owned", thread::spawn(move || elem).join().unwrap().as_slice());
+        #[cfg(feature = "bytes")]
+        {
+            sender.push(b"bytes").unwrap();
+            assert_eq!(Some(bytes::Bytes::from_static(b"bytes")), receiver.pop_bytes());
+            assert_eq!(None, receiver.pop_bytes());
+        }
+    }
+
+    #[test]
+    fn test_read_into() {
+        use super::{channel, BufferSize, PopError};
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let mut buf = [0u8; 4];
+        assert_eq!(Err(PopError::Empty), receiver.read_into(&mut buf));
+        sender.push_all([&b"abc"[..], b"toolong", b"d"].iter().copied());
+        assert_eq!(Ok(3), receiver.read_into(&mut buf));
+        assert_eq!(b"abc", &buf[..3]);
+        assert_eq!(Err(PopError::BufferTooSmall), receiver.read_into(&mut buf));
+        let mut big = [0u8; 16];
+        assert_eq!(Ok(7), receiver.read_into(&mut big));
+        assert_eq!(b"toolong", &big[..7]);
+        assert_eq!(Ok(1), receiver.read_into(&mut buf));
+        assert_eq!(0, receiver.len());
+        drop(sender);
+        assert_eq!(Err(PopError::Disconnected), receiver.read_into(&mut buf));
+    }
+
+    #[test]
+    fn test_header() {
+        use super::{channel, BufferSize, PopError};
+        use std::thread;
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        sender.push(b"short").unwrap();
+        assert_eq!(Err(PopError::MissingHeader), receiver.pop_with_header(|_, _| {}));
+        assert_eq!(Ok(()), receiver.pop(|bytes| assert_eq!(b"short", bytes)));
+
+        let n = 10_000u64;
+        let producer = thread::spawn(move || {
+            for i in 0..n {
+                sender.push_with_header(i << 32 | 7, &i.to_le_bytes()[..(i % 8) as usize]).unwrap();
+            }
+        });
+        for i in 0..n {
+            assert_eq!(Ok(()), receiver.pop_with_header(|header, bytes| {
+                assert_eq!(i << 32 | 7, header);
+                assert_eq!(&i.to_le_bytes()[..(i % 8) as usize], bytes);
+            }));
+        }
+        producer.join().unwrap();
+        assert_eq!(Err(PopError::Disconnected), receiver.pop_with_header(|_, _| {}));
+    }
+
+    #[test]
+    fn test_pop_matching() {
+        use super::{channel, BufferSize, PopError, Unmatched};
+
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        for header in 0..6u64 {
+            sender.push_with_header(header, &[header as u8]).unwrap();
+        }
+        let even = |header: u64| header.is_multiple_of(2);
+        let mut seen = Vec::new();
+        while receiver.try_pop_matching(even, Unmatched::Discard, |header, bytes| seen.push((header, bytes[0]))).is_ok() {}
+        assert_eq!(vec![(0, 0), (2, 2), (4, 4)], seen);
+        assert!(receiver.is_empty());
+
+        // Requeued elements go to the back, behind what was pushed in the meantime.
+        let mut requeue = sender.clone();
+        for header in 0..4u64 {
+            sender.push_with_header(header, &[header as u8]).unwrap();
+        }
+        assert_eq!(Ok(()), receiver.pop_matching(|header| header == 2, Unmatched::Requeue(&mut requeue), |header, _| {
+            assert_eq!(2, header);
+        }));
+        let mut rest = Vec::new();
+        while receiver.try_pop_with_header(|header, _| rest.push(header)).is_ok() {}
+        assert_eq!(vec![3, 0, 1], rest);
+        assert_eq!(Err(PopError::Empty), receiver.try_pop_matching(|_| true, Unmatched::Discard, |_, _| {}));
+
+        // Requeueing into the same channel stops after one pass when nothing matches.
+        for header in 0..3u64 {
+            sender.push_with_header(header, &[header as u8]).unwrap();
+        }
+        assert_eq!(Err(PopError::Empty), receiver.try_pop_matching(|header| header == 9, Unmatched::Requeue(&mut requeue), |_, _| {}));
+        assert_eq!(3, receiver.len());
+
+        // ...and parks until a matching element comes in from elsewhere.
+        let producer = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            sender.push_with_header(9, b"late").unwrap();
+        });
+        assert_eq!(Ok(()), receiver.pop_matching(|header| header == 9, Unmatched::Requeue(&mut requeue), |header, bytes| {
+            assert_eq!((9, &b"late"[..]), (header, bytes));
+        }));
+        producer.join().unwrap();
+        let mut rest = Vec::new();
+        while receiver.try_pop_with_header(|header, _| rest.push(header)).is_ok() {}
+        assert_eq!(vec![0, 1, 2], rest);
     }
-}
\ No newline at end of file
+}
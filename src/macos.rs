@@ -0,0 +1,44 @@
+use std::ptr;
+
+use mach2::kern_return::KERN_SUCCESS;
+use mach2::traps::mach_task_self;
+use mach2::vm::{mach_vm_allocate, mach_vm_deallocate, mach_vm_remap};
+use mach2::vm_inherit::VM_INHERIT_NONE;
+use mach2::vm_prot::vm_prot_t;
+use mach2::vm_statistics::{VM_FLAGS_ANYWHERE, VM_FLAGS_FIXED, VM_FLAGS_OVERWRITE};
+use mach2::vm_types::mach_vm_address_t;
+
+use crate::cbuffer_raw::Error;
+
+/// Allocates `2 * capacity` bytes and remaps the lower half over the upper one, so both
+/// halves share their pages. Unlike `MAP_FIXED` over a reservation this replaces the upper
+/// half atomically, and it works with the 16 KiB pages of Apple Silicon as long as
+/// `capacity` is a multiple of the page size. `munmap` releases the result again.
+pub fn map_mirror(capacity: usize) -> Result<ptr::NonNull<u8>, Error> {
+    unsafe {
+        let task = mach_task_self();
+        let mut base: mach_vm_address_t = 0;
+        if mach_vm_allocate(task, &mut base, 2 * capacity as u64, VM_FLAGS_ANYWHERE) != KERN_SUCCESS {
+            return Err(Error::OS);
+        }
+        let mut mirror = base + capacity as u64;
+        let mut cur_protection: vm_prot_t = 0;
+        let mut max_protection: vm_prot_t = 0;
+        let r = mach_vm_remap(task,
+                              &mut mirror,
+                              capacity as u64,
+                              0,
+                              VM_FLAGS_FIXED | VM_FLAGS_OVERWRITE,
+                              task,
+                              base,
+                              0,
+                              &mut cur_protection,
+                              &mut max_protection,
+                              VM_INHERIT_NONE);
+        if r != KERN_SUCCESS || mirror != base + capacity as u64 {
+            mach_vm_deallocate(task, base, 2 * capacity as u64);
+            return Err(Error::OS);
+        }
+        Ok(ptr::NonNull::new_unchecked(base as *mut u8))
+    }
+}
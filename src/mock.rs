@@ -0,0 +1,682 @@
+//! Stand-ins for `Sender` and `Receiver` kept in a `VecDeque`, for unit tests of code built
+//! on channels: nothing is mapped, and nothing ever waits. They have the methods of the
+//! `Push` and `Pop` traits, which the real halves implement as well, so code written
+//! against those runs on either. Where a real channel would park, e.g. `pop` on an empty
+//! channel or `push` into a full one, these fail the way the non-blocking call would, and
+//! `iter` ends where `try_iter` does.
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io::IoSlice;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::cbuffer_raw::{PopError, PopTimeoutError, PushError, PushTimeoutError, Stats};
+use crate::endpoint::{Pop, Push};
+
+struct Queue {
+    elems: VecDeque<Vec<u8>>,
+    /// Payload bytes queued, held against `capacity`.
+    bytes: usize,
+    capacity: usize,
+    senders: usize,
+    receivers: usize,
+    closed: bool,
+    stats: Stats,
+}
+
+pub struct Sender {
+    queue: Arc<Mutex<Queue>>,
+}
+
+pub struct Receiver {
+    queue: Arc<Mutex<Queue>>,
+}
+
+/// An element popped by `Receiver::recv_ref`.
+pub struct RecvGuard {
+    elem: Vec<u8>,
+}
+
+/// A copy of the oldest element, handed out by `Receiver::peek_ref`.
+pub struct PeekGuard {
+    elem: Vec<u8>,
+}
+
+/// Returned by `Receiver::try_iter` and `Receiver::iter`.
+pub struct TryIter<'a> {
+    receiver: &'a Receiver,
+}
+
+/// Creates a mock channel that never runs full.
+pub fn channel() -> (Sender, Receiver) {
+    channel_with_capacity(usize::MAX)
+}
+
+/// Creates a mock channel holding up to `capacity` bytes of elements, to test how code
+/// copes with `PushError::Full`. Unlike a ring, it spends nothing on framing.
+pub fn channel_with_capacity(capacity: usize) -> (Sender, Receiver) {
+    let queue = Arc::new(Mutex::new(Queue {
+        elems: VecDeque::new(),
+        bytes: 0,
+        capacity,
+        senders: 1,
+        receivers: 1,
+        closed: false,
+        stats: Stats::default(),
+    }));
+    (Sender { queue: queue.clone() }, Receiver { queue })
+}
+
+fn lock(queue: &Mutex<Queue>) -> MutexGuard<'_, Queue> {
+    // A test that panicked while holding the lock has failed already.
+    queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Sender {
+    pub fn try_push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        let mut queue = lock(&self.queue);
+        let result = queue.push(elem);
+        if result.is_err() {
+            queue.stats.failed_pushes += 1;
+        }
+        result
+    }
+
+    /// Like `try_push`, with the element gathered from `bufs`.
+    pub fn try_push_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<(), PushError> {
+        self.try_push(&bufs.iter().flat_map(|buf| buf.iter().copied()).collect::<Vec<u8>>())
+    }
+
+    /// Like `try_push`: a full mock channel has nobody to make room in it.
+    pub fn push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        self.try_push(elem)
+    }
+
+    pub fn push_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<(), PushError> {
+        self.try_push_vectored(bufs)
+    }
+
+    /// Like `try_push`, failing with `PushTimeoutError::Timeout` at once rather than after
+    /// `timeout`.
+    pub fn push_timeout(&mut self, elem: &[u8], _timeout: Duration) -> Result<(), PushTimeoutError> {
+        self.try_push(elem).map_err(PushTimeoutError::from)
+    }
+
+    /// Like `Sender::push_with_header`.
+    pub fn push_with_header(&mut self, header: u64, elem: &[u8]) -> Result<(), PushError> {
+        let mut framed = header.to_le_bytes().to_vec();
+        framed.extend_from_slice(elem);
+        self.try_push(&framed)
+    }
+
+    /// Pushes elements from `iter` until one does not fit, returning how many were pushed.
+    pub fn push_all<'a, I>(&mut self, iter: I) -> usize
+        where I: Iterator<Item = &'a [u8]>
+    {
+        iter.take_while(|elem| self.try_push(elem).is_ok()).count()
+    }
+
+    /// Like `Sender::close`.
+    pub fn close(&mut self) -> Result<(), PushError> {
+        let mut queue = lock(&self.queue);
+        if queue.receivers == 0 {
+            return Err(PushError::Disconnected);
+        }
+        queue.closed = true;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        lock(&self.queue).elems.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes of elements the channel has room for right now.
+    pub fn remaining_bytes(&self) -> usize {
+        let queue = lock(&self.queue);
+        queue.capacity - queue.bytes
+    }
+
+    /// Whether an element of `len` bytes fits right now and has a receiver to go to.
+    pub fn can_push(&self, len: usize) -> bool {
+        let queue = lock(&self.queue);
+        queue.receivers > 0 && !queue.closed && len <= queue.capacity - queue.bytes
+    }
+
+    /// Whether the receiver is still around.
+    pub fn peer_alive(&self) -> bool {
+        lock(&self.queue).receivers > 0
+    }
+
+    /// Always 0: mock channels only ever reject pushes that do not fit.
+    pub fn dropped(&self) -> u64 {
+        0
+    }
+
+    /// Always 0, as `dropped`.
+    pub fn overwritten(&self) -> u64 {
+        0
+    }
+
+    pub fn stats(&self) -> Stats {
+        lock(&self.queue).stats
+    }
+}
+
+impl Clone for Sender {
+    fn clone(&self) -> Sender {
+        lock(&self.queue).senders += 1;
+        Sender { queue: self.queue.clone() }
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        lock(&self.queue).senders -= 1;
+    }
+}
+
+impl Receiver {
+    /// Pops one element if there is one, failing like `Receiver::try_pop` otherwise.
+    pub fn try_pop<F>(&self, mut consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        let elem = self.take(|_| Ok(()))?;
+        consumer(&elem);
+        Ok(())
+    }
+
+    /// Like `try_pop`: an empty mock channel has nobody to fill it.
+    pub fn pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        self.try_pop(consumer)
+    }
+
+    /// Like `try_pop`, failing with `PopTimeoutError::Timeout` at once rather than after
+    /// `timeout`.
+    pub fn pop_timeout<F>(&self, _timeout: Duration, consumer: F) -> Result<(), PopTimeoutError>
+        where F: FnMut(&[u8])
+    {
+        match self.try_pop(consumer) {
+            Err(PopError::Empty) => Err(PopTimeoutError::Timeout),
+            r => r.map_err(PopTimeoutError::from),
+        }
+    }
+
+    /// Like `Receiver::try_pop_with_header`.
+    pub fn try_pop_with_header<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u64, &[u8])
+    {
+        let elem = self.take(|elem| if elem.len() < 8 { Err(PopError::MissingHeader) } else { Ok(()) })?;
+        let (header, elem) = elem.split_at(8);
+        consumer(u64::from_le_bytes(<[u8; 8]>::try_from(header).unwrap()), elem);
+        Ok(())
+    }
+
+    /// Like `try_pop_with_header`.
+    pub fn pop_with_header<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u64, &[u8])
+    {
+        self.try_pop_with_header(consumer)
+    }
+
+    /// Shows the oldest element to `consumer` without popping it.
+    pub fn peek<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(&[u8])
+    {
+        let elem = self.peek_ref_or_error()?;
+        consumer(&elem);
+        Ok(())
+    }
+
+    /// Copies out the oldest element, leaving it in the channel.
+    pub fn peek_ref(&mut self) -> Option<PeekGuard> {
+        self.peek_ref_or_error().ok().map(|elem| PeekGuard { elem })
+    }
+
+    fn peek_ref_or_error(&self) -> Result<Vec<u8>, PopError> {
+        let queue = lock(&self.queue);
+        queue.elems.front().cloned().ok_or_else(|| queue.empty_error())
+    }
+
+    pub fn recv_ref(&mut self) -> Option<RecvGuard> {
+        self.try_recv().ok()
+    }
+
+    /// Pops the oldest element into a guard, as `Receiver::try_recv` borrows it.
+    pub fn try_recv(&mut self) -> Result<RecvGuard, PopError> {
+        self.take(|_| Ok(())).map(|elem| RecvGuard { elem })
+    }
+
+    /// Like `try_recv`.
+    pub fn recv(&mut self) -> Result<RecvGuard, PopError> {
+        self.try_recv()
+    }
+
+    /// Hands up to `max` elements to `consumer`, returning how many were popped.
+    pub fn pop_batch<F>(&self, max: usize, mut consumer: F) -> usize
+        where F: FnMut(&[u8])
+    {
+        (0..max).take_while(|_| self.try_pop(&mut consumer).is_ok()).count()
+    }
+
+    /// Moves up to `max` elements onto the end of `buf`, returning how many that was.
+    pub fn recv_many(&self, buf: &mut Vec<Vec<u8>>, max: usize) -> usize {
+        (0..max).map_while(|_| self.pop_owned()).map(|elem| buf.push(elem)).count()
+    }
+
+    /// Pops elements for as long as `predicate` accepts them, returning how many.
+    pub fn pop_while<F>(&self, mut predicate: F) -> usize
+        where F: FnMut(&[u8]) -> bool
+    {
+        std::iter::from_fn(|| self.take(|elem| if predicate(elem) { Ok(()) } else { Err(PopError::Empty) }).ok())
+            .count()
+    }
+
+    pub fn pop_owned(&self) -> Option<Vec<u8>> {
+        self.take(|_| Ok(())).ok()
+    }
+
+    /// Like `Receiver::read_into`.
+    pub fn read_into(&self, buf: &mut [u8]) -> Result<usize, PopError> {
+        let elem = self.take(|elem| if elem.len() > buf.len() { Err(PopError::BufferTooSmall) } else { Ok(()) })?;
+        buf[..elem.len()].copy_from_slice(&elem);
+        Ok(elem.len())
+    }
+
+    pub fn try_iter(&self) -> TryIter<'_> {
+        TryIter { receiver: self }
+    }
+
+    /// Like `try_iter`.
+    pub fn iter(&self) -> TryIter<'_> {
+        self.try_iter()
+    }
+
+    pub fn len(&self) -> usize {
+        lock(&self.queue).elems.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes of elements waiting to be popped.
+    pub fn available_bytes(&self) -> usize {
+        lock(&self.queue).bytes
+    }
+
+    /// Whether a sender is left.
+    pub fn peer_alive(&self) -> bool {
+        lock(&self.queue).senders > 0
+    }
+
+    /// Like `Sender::dropped`.
+    pub fn dropped(&self) -> u64 {
+        0
+    }
+
+    /// Like `Sender::overwritten`.
+    pub fn overwritten(&self) -> u64 {
+        0
+    }
+
+    pub fn stats(&self) -> Stats {
+        lock(&self.queue).stats
+    }
+
+    /// Removes the oldest element if `check` accepts it, leaving it queued otherwise.
+    fn take<C>(&self, check: C) -> Result<Vec<u8>, PopError>
+        where C: FnOnce(&[u8]) -> Result<(), PopError>
+    {
+        let mut queue = lock(&self.queue);
+        let elem = queue.elems.front().ok_or_else(|| queue.empty_error())?;
+        check(elem)?;
+        let elem = queue.elems.pop_front().unwrap();
+        queue.bytes -= elem.len();
+        queue.stats.popped += 1;
+        Ok(elem)
+    }
+}
+
+impl Clone for Receiver {
+    fn clone(&self) -> Receiver {
+        lock(&self.queue).receivers += 1;
+        Receiver { queue: self.queue.clone() }
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        lock(&self.queue).receivers -= 1;
+    }
+}
+
+impl<'a> IntoIterator for &'a Receiver {
+    type Item = Vec<u8>;
+    type IntoIter = TryIter<'a>;
+
+    fn into_iter(self) -> TryIter<'a> {
+        self.iter()
+    }
+}
+
+impl Deref for RecvGuard {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.elem
+    }
+}
+
+impl Deref for PeekGuard {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.elem
+    }
+}
+
+impl<'a> Iterator for TryIter<'a> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        self.receiver.pop_owned()
+    }
+}
+
+impl Queue {
+    fn push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        if elem.len() > self.capacity {
+            return Err(PushError::MessageTooLarge);
+        }
+        if self.receivers == 0 {
+            return Err(PushError::Disconnected);
+        }
+        if self.closed {
+            return Err(PushError::Closed);
+        }
+        if elem.len() > self.capacity - self.bytes {
+            return Err(PushError::Full);
+        }
+        self.bytes += elem.len();
+        self.elems.push_back(elem.to_vec());
+        self.stats.messages += 1;
+        self.stats.bytes += elem.len() as u64;
+        self.stats.max_occupancy = self.stats.max_occupancy.max(self.elems.len() as u64);
+        Ok(())
+    }
+
+    /// Why popping from the queue, found empty, fails.
+    fn empty_error(&self) -> PopError {
+        if self.closed {
+            PopError::Closed
+        } else if self.senders == 0 {
+            PopError::Disconnected
+        } else {
+            PopError::Empty
+        }
+    }
+}
+
+impl Push for Sender {
+    fn try_push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        Sender::try_push(self, elem)
+    }
+
+    fn try_push_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<(), PushError> {
+        Sender::try_push_vectored(self, bufs)
+    }
+
+    fn push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        Sender::push(self, elem)
+    }
+
+    fn push_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<(), PushError> {
+        Sender::push_vectored(self, bufs)
+    }
+
+    fn push_timeout(&mut self, elem: &[u8], timeout: Duration) -> Result<(), PushTimeoutError> {
+        Sender::push_timeout(self, elem, timeout)
+    }
+
+    fn push_with_header(&mut self, header: u64, elem: &[u8]) -> Result<(), PushError> {
+        Sender::push_with_header(self, header, elem)
+    }
+
+    fn push_all<'a, I>(&mut self, iter: I) -> usize
+        where I: Iterator<Item = &'a [u8]>
+    {
+        Sender::push_all(self, iter)
+    }
+
+    fn close(&mut self) -> Result<(), PushError> {
+        Sender::close(self)
+    }
+
+    fn len(&self) -> usize {
+        Sender::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        Sender::is_empty(self)
+    }
+
+    fn remaining_bytes(&self) -> usize {
+        Sender::remaining_bytes(self)
+    }
+
+    fn can_push(&self, len: usize) -> bool {
+        Sender::can_push(self, len)
+    }
+
+    fn peer_alive(&self) -> bool {
+        Sender::peer_alive(self)
+    }
+
+    fn dropped(&self) -> u64 {
+        Sender::dropped(self)
+    }
+
+    fn overwritten(&self) -> u64 {
+        Sender::overwritten(self)
+    }
+
+    fn stats(&self) -> Stats {
+        Sender::stats(self)
+    }
+}
+
+impl Pop for Receiver {
+    type RecvGuard<'a> = RecvGuard;
+    type PeekGuard<'a> = PeekGuard;
+    type TryIter<'a> = TryIter<'a>;
+    type Iter<'a> = TryIter<'a>;
+
+    fn try_pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        Receiver::try_pop(self, consumer)
+    }
+
+    fn pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        Receiver::pop(self, consumer)
+    }
+
+    fn pop_timeout<F>(&self, timeout: Duration, consumer: F) -> Result<(), PopTimeoutError>
+        where F: FnMut(&[u8])
+    {
+        Receiver::pop_timeout(self, timeout, consumer)
+    }
+
+    fn try_pop_with_header<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u64, &[u8])
+    {
+        Receiver::try_pop_with_header(self, consumer)
+    }
+
+    fn pop_with_header<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u64, &[u8])
+    {
+        Receiver::pop_with_header(self, consumer)
+    }
+
+    fn peek<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(&[u8])
+    {
+        Receiver::peek(self, consumer)
+    }
+
+    fn peek_ref(&mut self) -> Option<PeekGuard> {
+        Receiver::peek_ref(self)
+    }
+
+    fn recv_ref(&mut self) -> Option<RecvGuard> {
+        Receiver::recv_ref(self)
+    }
+
+    fn try_recv(&mut self) -> Result<RecvGuard, PopError> {
+        Receiver::try_recv(self)
+    }
+
+    fn recv(&mut self) -> Result<RecvGuard, PopError> {
+        Receiver::recv(self)
+    }
+
+    fn pop_batch<F>(&self, max: usize, consumer: F) -> usize
+        where F: FnMut(&[u8])
+    {
+        Receiver::pop_batch(self, max, consumer)
+    }
+
+    fn recv_many(&self, buf: &mut Vec<Vec<u8>>, max: usize) -> usize {
+        Receiver::recv_many(self, buf, max)
+    }
+
+    fn pop_while<F>(&self, predicate: F) -> usize
+        where F: FnMut(&[u8]) -> bool
+    {
+        Receiver::pop_while(self, predicate)
+    }
+
+    fn pop_owned(&self) -> Option<Vec<u8>> {
+        Receiver::pop_owned(self)
+    }
+
+    fn read_into(&self, buf: &mut [u8]) -> Result<usize, PopError> {
+        Receiver::read_into(self, buf)
+    }
+
+    fn try_iter(&self) -> TryIter<'_> {
+        Receiver::try_iter(self)
+    }
+
+    fn iter(&self) -> TryIter<'_> {
+        Receiver::iter(self)
+    }
+
+    fn len(&self) -> usize {
+        Receiver::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        Receiver::is_empty(self)
+    }
+
+    fn available_bytes(&self) -> usize {
+        Receiver::available_bytes(self)
+    }
+
+    fn peer_alive(&self) -> bool {
+        Receiver::peer_alive(self)
+    }
+
+    fn dropped(&self) -> u64 {
+        Receiver::dropped(self)
+    }
+
+    fn overwritten(&self) -> u64 {
+        Receiver::overwritten(self)
+    }
+
+    fn stats(&self) -> Stats {
+        Receiver::stats(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{channel, channel_with_capacity};
+    use crate::cbuffer_raw::{self, BufferSize, PopError, PopTimeoutError, PushError};
+    use crate::endpoint::{Pop, Push};
+
+    #[test]
+    fn test_mock() {
+        let (mut sender, mut receiver) = channel_with_capacity(8);
+        assert_eq!(Err(PopError::Empty), receiver.pop(|_| {}));
+        assert_eq!(Err(PopTimeoutError::Timeout), receiver.pop_timeout(Duration::from_secs(60), |_| {}));
+        assert_eq!(Err(PushError::MessageTooLarge), sender.push(&[0; 9]));
+        sender.push(b"first").unwrap();
+        assert_eq!(Err(PushError::Full), sender.push(b"second"));
+        assert_eq!(Ok(()), receiver.peek(|bytes| assert_eq!(b"first", bytes)));
+        assert_eq!(Some(&b"first"[..]), receiver.peek_ref().as_deref());
+        let mut buf = [0u8; 4];
+        assert_eq!(Err(PopError::BufferTooSmall), receiver.read_into(&mut buf));
+        assert_eq!(Some(b"first".to_vec()), receiver.pop_owned());
+
+        sender.push_with_header(7, b"").unwrap();
+        assert_eq!(Ok(()), receiver.pop_with_header(|header, bytes| assert_eq!((7, &b""[..]), (header, bytes))));
+        sender.push(b"last").unwrap();
+        sender.close().unwrap();
+        assert_eq!(Err(PushError::Closed), sender.push(b""));
+        let mut seen = Vec::new();
+        assert_eq!(1, receiver.recv_many(&mut seen, 10));
+        assert_eq!(vec![b"last".to_vec()], seen);
+        assert_eq!(Err(PopError::Closed), receiver.try_pop(|_| {}));
+        assert_eq!((3, 3), (sender.stats().messages, sender.stats().failed_pushes));
+
+        let (mut sender, receiver) = channel();
+        let other = sender.clone();
+        sender.push(b"orphan").unwrap();
+        drop((sender, other));
+        assert!(!receiver.peer_alive());
+        assert_eq!(1, receiver.pop_batch(10, |bytes| assert_eq!(b"orphan", bytes)));
+        assert_eq!(Err(PopError::Disconnected), receiver.pop(|_| {}));
+    }
+
+    /// Code under test, written once against the traits.
+    fn relay<S: Push, R: Pop>(sender: &mut S, receiver: &mut R) -> Vec<Vec<u8>> {
+        sender.push_all([&b"a"[..], b"bb", b"ccc", b"dddd"].iter().copied());
+        sender.push_with_header(1, b"header").unwrap();
+        let mut seen = vec![receiver.recv_ref().unwrap().to_vec()];
+        assert_eq!(Some(&b"bb"[..]), receiver.peek_ref().as_deref());
+        assert_eq!(2, receiver.pop_while(|bytes| bytes.len() < 4));
+        seen.extend(receiver.try_recv().as_deref().map(|bytes| bytes.to_vec()));
+        assert_eq!(Ok(()), receiver.pop_with_header(|header, bytes| assert_eq!((1, &b"header"[..]), (header, bytes))));
+        sender.push(b"rest").unwrap();
+        sender.close().unwrap();
+        seen.extend(receiver.try_iter());
+        assert_eq!(Err(PopError::Closed), receiver.try_pop(|_| {}));
+        assert_eq!(6, receiver.stats().popped);
+        seen
+    }
+
+    #[test]
+    fn test_same_as_real() {
+        let expected = vec![b"a".to_vec(), b"dddd".to_vec(), b"rest".to_vec()];
+        let (mut sender, mut receiver) = channel();
+        assert_eq!(expected, relay(&mut sender, &mut receiver));
+        let (mut sender, mut receiver) = cbuffer_raw::channel(BufferSize::Custom(4096));
+        assert_eq!(expected, relay(&mut sender, &mut receiver));
+    }
+}
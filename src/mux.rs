@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use crate::cbuffer_raw::{channel, BufferSize, PopError, PushError, Receiver, Sender};
+
+type Handlers = HashMap<u32, Box<dyn FnMut(&[u8]) + Send>>;
+
+/// Hands out the per-stream senders of a multiplexed channel.
+pub struct MuxSender {
+    inner: Sender,
+}
+
+/// Sending half of one logical stream. Each element goes into the shared ring prefixed
+/// with the stream's id.
+pub struct MuxStream {
+    inner: Sender,
+    id: u32,
+    scratch: Vec<u8>,
+}
+
+/// Receiving half of a multiplexed channel, handing each element to the handler of its
+/// stream. Elements of streams without a handler are dropped.
+pub struct MuxReceiver {
+    inner: Receiver,
+    handlers: Handlers,
+}
+
+/// Creates a channel carrying many logical streams over one ring.
+pub fn channel_mux(s: BufferSize) -> (MuxSender, MuxReceiver) {
+    let (sender, receiver) = channel(s);
+    (MuxSender { inner: sender }, MuxReceiver { inner: receiver, handlers: HashMap::new() })
+}
+
+impl MuxSender {
+    /// A sender for stream `id`. Several senders may share an id.
+    pub fn stream(&self, id: u32) -> MuxStream {
+        MuxStream { inner: self.inner.clone(), id, scratch: Vec::new() }
+    }
+}
+
+impl MuxStream {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn try_push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        self.frame(elem);
+        self.inner.try_push(&self.scratch)
+    }
+
+    /// Pushes `elem`, parking the calling thread until the receiver frees enough space.
+    pub fn push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        self.frame(elem);
+        self.inner.push(&self.scratch)
+    }
+
+    fn frame(&mut self, elem: &[u8]) {
+        self.scratch.clear();
+        self.scratch.extend_from_slice(&self.id.to_le_bytes());
+        self.scratch.extend_from_slice(elem);
+    }
+}
+
+impl MuxReceiver {
+    /// Routes the elements of stream `id` to `handler`, replacing any earlier one.
+    pub fn on<F>(&mut self, id: u32, handler: F)
+        where F: FnMut(&[u8]) + Send + 'static
+    {
+        self.handlers.insert(id, Box::new(handler));
+    }
+
+    /// Stops routing stream `id`; its elements are dropped from then on.
+    pub fn off(&mut self, id: u32) {
+        self.handlers.remove(&id);
+    }
+
+    /// Pops one element, if there is one, and hands it to its stream's handler.
+    pub fn try_dispatch(&mut self) -> Result<(), PopError> {
+        let handlers = &mut self.handlers;
+        self.inner.try_pop(|bytes| route(handlers, bytes))
+    }
+
+    /// Like `try_dispatch`, but parks the calling thread until there is an element.
+    pub fn dispatch(&mut self) -> Result<(), PopError> {
+        let handlers = &mut self.handlers;
+        self.inner.pop(|bytes| route(handlers, bytes))
+    }
+}
+
+fn route(handlers: &mut Handlers, bytes: &[u8]) {
+    if bytes.len() < 4 {
+        return;
+    }
+    let id = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    if let Some(handler) = handlers.get_mut(&id) {
+        handler(&bytes[4..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use super::channel_mux;
+    use crate::cbuffer_raw::{BufferSize, PopError};
+
+    #[test]
+    fn test_dispatch() {
+        let (sender, mut receiver) = channel_mux(BufferSize::Custom(4096));
+        let seen = Arc::new(Mutex::new(vec![Vec::new(), Vec::new()]));
+        for id in 0..2 {
+            let seen = seen.clone();
+            receiver.on(id, move |bytes| seen.lock().unwrap()[id as usize].push(bytes.to_vec()));
+        }
+
+        let n = 5_000u32;
+        let producers: Vec<_> = (0..3).map(|id| {
+            let mut stream = sender.stream(id);
+            thread::spawn(move || {
+                for i in 0..n {
+                    stream.push(&i.to_le_bytes()).unwrap();
+                }
+            })
+        }).collect();
+        drop(sender);
+        while receiver.dispatch() != Err(PopError::Disconnected) {}
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        // Stream 2 has no handler, and each stream keeps its own order.
+        let expected: Vec<_> = (0..n).map(|i| i.to_le_bytes().to_vec()).collect();
+        let seen = seen.lock().unwrap();
+        assert_eq!(expected, seen[0]);
+        assert_eq!(expected, seen[1]);
+    }
+}
@@ -0,0 +1,82 @@
+//! NUMA placement on Linux: binding a ring's pages to one node and pinning the threads
+//! that use it to the same node, so pushes and pops do not cross the interconnect.
+
+use std::io;
+
+use libc::{c_long, c_ulong, c_void};
+
+use crate::cbuffer_raw::page_size;
+
+/// From `<linux/mempolicy.h>`, which libc does not carry.
+const MPOL_BIND: c_long = 2;
+const MPOL_MF_MOVE: c_ulong = 1 << 1;
+
+/// Binds the pages behind `len` bytes at `pointer` to `node`, moving those already
+/// faulted in. The range is widened to whole pages.
+pub(crate) fn bind(pointer: *mut u8, len: usize, node: usize) -> io::Result<()> {
+    let bits = 8 * std::mem::size_of::<c_ulong>();
+    let mut mask = vec![0 as c_ulong; node / bits + 1];
+    mask[node / bits] |= 1 << (node % bits);
+    let page = page_size();
+    let start = pointer as usize / page * page;
+    let end = (pointer as usize + len).div_ceil(page) * page;
+    let r = unsafe {
+        libc::syscall(libc::SYS_mbind, start as *mut c_void, end - start, MPOL_BIND,
+                      mask.as_ptr(), (mask.len() * bits) as c_ulong, MPOL_MF_MOVE)
+    };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// CPUs belonging to `node`, as listed in sysfs.
+pub fn node_cpus(node: usize) -> io::Result<Vec<usize>> {
+    let list = std::fs::read_to_string(format!("/sys/devices/system/node/node{}/cpulist", node))?;
+    parse_cpu_list(list.trim())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed cpulist"))
+}
+
+/// Parses a list like `0-3,8,10-11`.
+fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.split(',').filter(|range| !range.is_empty()) {
+        let (first, last): (usize, usize) = match range.split_once('-') {
+            Some((first, last)) => (first.parse().ok()?, last.parse().ok()?),
+            None => {
+                let cpu = range.parse().ok()?;
+                (cpu, cpu)
+            }
+        };
+        cpus.extend(first..=last);
+    }
+    Some(cpus)
+}
+
+/// Restricts the calling thread to the CPUs of `node`, e.g. for the producer or consumer
+/// of a ring bound there with `ChannelBuilder::numa_node`.
+pub fn pin_thread_to_node(node: usize) -> io::Result<()> {
+    let cpus = node_cpus(node)?;
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_cpu_list;
+
+    #[test]
+    fn test_cpu_list() {
+        assert_eq!(Some(vec![0, 1, 2, 3, 8, 10, 11]), parse_cpu_list("0-3,8,10-11"));
+        assert_eq!(Some(vec![]), parse_cpu_list(""));
+        assert_eq!(None, parse_cpu_list("0-x"));
+    }
+}
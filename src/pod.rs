@@ -0,0 +1,134 @@
+use std::marker::PhantomData;
+use std::mem;
+use std::ops;
+
+use bytemuck::Pod;
+
+use crate::cbuffer_raw::{page_size, BufferSize, ChannelBuilder, PopError, PushError, Receiver, RecvGuard, Sender};
+use crate::frame::LengthPrefix;
+
+/// Sending half of a channel of plain-old-data values, copied into the ring as they are
+/// in memory.
+pub struct PodSender<T> {
+    inner: Sender,
+    _marker: PhantomData<fn(T)>,
+}
+
+/// Receiving half of a channel of plain-old-data values. Pops hand out a reference into
+/// the ring itself.
+pub struct PodReceiver<T> {
+    inner: Receiver,
+    _marker: PhantomData<fn() -> T>,
+}
+
+/// Creates a channel with room for at least `capacity` values of `T`. Every value is a
+/// `LengthPrefix::Fixed` record of `size_of::<T>()` bytes, so the values stay aligned all
+/// the way around the ring. Panics for zero-sized `T`.
+pub fn channel_of<T: Pod>(capacity: usize) -> (PodSender<T>, PodReceiver<T>) {
+    let size = mem::size_of::<T>();
+    assert!(page_size().is_multiple_of(mem::align_of::<T>()), "values must not be aligned beyond a page");
+    let (sender, receiver) = ChannelBuilder::new()
+        .capacity(BufferSize::Custom(capacity * size + 1))
+        .prefix(LengthPrefix::Fixed(size as u32))
+        .build()
+        .expect("fail to create cbuffer.");
+    (PodSender { inner: sender, _marker: PhantomData },
+     PodReceiver { inner: receiver, _marker: PhantomData })
+}
+
+impl<T: Pod> PodSender<T> {
+    pub fn try_push(&mut self, value: &T) -> Result<(), PushError> {
+        self.inner.try_push(bytemuck::bytes_of(value))
+    }
+
+    /// Pushes `value`, parking the calling thread until the receiver frees enough space.
+    pub fn push(&mut self, value: &T) -> Result<(), PushError> {
+        self.inner.push(bytemuck::bytes_of(value))
+    }
+
+    pub fn close(&mut self) -> Result<(), PushError> {
+        self.inner.close()
+    }
+}
+
+impl<T: Pod> PodReceiver<T> {
+    pub fn try_pop(&mut self) -> Result<PodGuard<'_, T>, PopError> {
+        self.inner.try_recv().map(PodGuard::new)
+    }
+
+    /// Pops one value, parking the calling thread until the sender pushes one.
+    pub fn pop(&mut self) -> Result<PodGuard<'_, T>, PopError> {
+        self.inner.recv().map(PodGuard::new)
+    }
+
+    /// Number of values waiting to be popped.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+
+/// A value still sitting in the ring; it is consumed when the guard is dropped.
+pub struct PodGuard<'a, T> {
+    value: &'a T,
+    _guard: RecvGuard<'a>,
+}
+
+impl<'a, T: Pod> PodGuard<'a, T> {
+    fn new(guard: RecvGuard<'a>) -> PodGuard<'a, T> {
+        PodGuard { value: bytemuck::from_bytes(guard.bytes()), _guard: guard }
+    }
+}
+
+impl<'a, T> ops::Deref for PodGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::channel_of;
+    use crate::{PopError, PushError};
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Tick {
+        price: f64,
+        size: u32,
+        side: u32,
+        seq: u64,
+    }
+
+    unsafe impl bytemuck::Zeroable for Tick {}
+    unsafe impl bytemuck::Pod for Tick {}
+
+    #[test]
+    fn test_channel_of() {
+        let (mut sender, mut receiver) = channel_of::<Tick>(100);
+        let tick = |seq| Tick { price: seq as f64 / 4.0, size: 10, side: 1, seq };
+        let mut next = 0;
+        // Well past the end of the ring, so values wrap around it.
+        for round in 0..50u64 {
+            for seq in round * 20..(round + 1) * 20 {
+                sender.try_push(&tick(seq)).unwrap();
+            }
+            assert_eq!(20, receiver.len());
+            while let Ok(value) = receiver.try_pop() {
+                assert_eq!(0, &*value as *const Tick as usize % std::mem::align_of::<Tick>());
+                assert_eq!(tick(next), *value);
+                next += 1;
+            }
+        }
+        assert_eq!(1000, next);
+
+        sender.close().unwrap();
+        assert_eq!(Err(PushError::Closed), sender.try_push(&tick(0)));
+        assert_eq!(Err(PopError::Closed), receiver.try_pop().map(|value| *value));
+    }
+}
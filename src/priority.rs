@@ -0,0 +1,144 @@
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::cbuffer_raw::{channel, BufferSize, PopError, PushError, Receiver, Sender};
+
+/// Lets a receiver park on all lanes at once. Pushes only take the lock while someone
+/// is actually waiting.
+struct Doorbell {
+    waiters: AtomicUsize,
+    lock: Mutex<()>,
+    ring: Condvar,
+}
+
+impl Doorbell {
+    fn notify(&self) {
+        // Pairs with the fence in `PriorityReceiver::pop`: either the receiver sees the
+        // element, or we see it waiting.
+        fence(Ordering::SeqCst);
+        if self.waiters.load(Ordering::Relaxed) > 0 {
+            let _guard = self.lock.lock().unwrap();
+            self.ring.notify_all();
+        }
+    }
+}
+
+/// Sending half of a priority channel.
+pub struct PrioritySender {
+    lanes: Vec<Sender>,
+    doorbell: Arc<Doorbell>,
+}
+
+/// Receiving half of a priority channel. Pops drain higher priority lanes first.
+pub struct PriorityReceiver {
+    lanes: Vec<Receiver>,
+    doorbell: Arc<Doorbell>,
+}
+
+/// Creates a channel with `lanes` priority classes, 0 being the most urgent. Each lane
+/// is a ring of its own of size `s`.
+pub fn channel_priority(s: BufferSize, lanes: usize) -> (PrioritySender, PriorityReceiver) {
+    assert!(lanes > 0, "a priority channel needs at least one lane");
+    let doorbell = Arc::new(Doorbell { waiters: AtomicUsize::new(0), lock: Mutex::new(()), ring: Condvar::new() });
+    let (senders, receivers) = (0..lanes).map(|_| channel(s)).unzip();
+    (PrioritySender { lanes: senders, doorbell: doorbell.clone() },
+     PriorityReceiver { lanes: receivers, doorbell })
+}
+
+impl PrioritySender {
+    /// Pushes `elem` into lane `priority`. Panics if there is no such lane.
+    pub fn try_push(&mut self, priority: usize, elem: &[u8]) -> Result<(), PushError> {
+        self.lanes[priority].try_push(elem)?;
+        self.doorbell.notify();
+        Ok(())
+    }
+
+    /// Like `try_push`, parking the calling thread until the lane has room.
+    pub fn push(&mut self, priority: usize, elem: &[u8]) -> Result<(), PushError> {
+        self.lanes[priority].push(elem)?;
+        self.doorbell.notify();
+        Ok(())
+    }
+}
+
+impl Drop for PrioritySender {
+    fn drop(&mut self) {
+        // The lanes have to be disconnected before a parked receiver looks again.
+        self.lanes.clear();
+        self.doorbell.notify();
+    }
+}
+
+impl PriorityReceiver {
+    /// Pops the oldest element of the most urgent lane that has one.
+    pub fn try_pop<F>(&self, mut consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        let mut result = Err(PopError::Disconnected);
+        for lane in &self.lanes {
+            match lane.try_pop(&mut consumer) {
+                Ok(()) => return Ok(()),
+                Err(PopError::Empty) => result = Err(PopError::Empty),
+                Err(_) => {}
+            }
+        }
+        result
+    }
+
+    /// Like `try_pop`, parking the calling thread until any lane has an element.
+    pub fn pop<F>(&self, mut consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        loop {
+            match self.try_pop(&mut consumer) {
+                Err(PopError::Empty) => {}
+                r => return r,
+            }
+            let guard = self.doorbell.lock.lock().unwrap();
+            self.doorbell.waiters.fetch_add(1, Ordering::Relaxed);
+            fence(Ordering::SeqCst);
+            if self.lanes.iter().all(|lane| lane.is_empty() && !lane.inner.is_sender_dropped()) {
+                drop(self.doorbell.ring.wait(guard).unwrap());
+            }
+            self.doorbell.waiters.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::channel_priority;
+    use crate::cbuffer_raw::{BufferSize, PopError};
+
+    #[test]
+    fn test_priority_order() {
+        let (mut sender, receiver) = channel_priority(BufferSize::Custom(4096), 2);
+        for i in 0..3u8 {
+            sender.try_push(1, &[i]).unwrap();
+        }
+        sender.try_push(0, b"urgent").unwrap();
+        assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(b"urgent", bytes)));
+        for i in 0..3u8 {
+            assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(&[i], bytes)));
+        }
+        assert_eq!(Err(PopError::Empty), receiver.try_pop(|_| {}));
+        drop(sender);
+        assert_eq!(Err(PopError::Disconnected), receiver.try_pop(|_| {}));
+    }
+
+    #[test]
+    fn test_blocking_pop() {
+        let (mut sender, receiver) = channel_priority(BufferSize::Custom(4096), 3);
+        let n = 10_000u32;
+        let producer = thread::spawn(move || {
+            for i in 0..n {
+                sender.push(i as usize % 3, &i.to_le_bytes()).unwrap();
+            }
+        });
+        let mut count = 0;
+        while receiver.pop(|_| count += 1).is_ok() {}
+        producer.join().unwrap();
+        assert_eq!(n, count);
+    }
+}
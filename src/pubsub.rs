@@ -0,0 +1,137 @@
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+use crate::cbuffer_raw::{channel, BufferSize, PopError, PushError, Receiver, Sender};
+
+/// Sending half of a pub/sub channel. Every element carries its topic in the header slot
+/// of `Sender::push_with_header`, so several feeds can share one ring, shared ones
+/// included.
+pub struct Publisher {
+    inner: Sender,
+}
+
+/// Receiving half of a pub/sub channel. Pops hand out the elements of the subscribed
+/// topics and consume the others unseen.
+pub struct Subscriber {
+    inner: Receiver,
+    topics: HashSet<u32>,
+    skipped: Cell<u64>,
+}
+
+/// Creates a pub/sub channel whose subscriber starts out subscribed to nothing.
+pub fn channel_pubsub(s: BufferSize) -> (Publisher, Subscriber) {
+    let (sender, receiver) = channel(s);
+    (Publisher::new(sender), Subscriber::new(receiver))
+}
+
+impl Publisher {
+    /// Publishes through `inner`, e.g. a sender attached to a shared ring.
+    pub fn new(inner: Sender) -> Publisher {
+        Publisher { inner }
+    }
+
+    pub fn try_publish(&mut self, topic: u32, elem: &[u8]) -> Result<(), PushError> {
+        self.inner.inner.push_parts(&u64::from(topic).to_le_bytes(), elem)
+    }
+
+    /// Publishes `elem` under `topic`, parking the calling thread until the ring has room.
+    pub fn publish(&mut self, topic: u32, elem: &[u8]) -> Result<(), PushError> {
+        self.inner.push_with_header(u64::from(topic), elem)
+    }
+
+    pub fn close(&mut self) -> Result<(), PushError> {
+        self.inner.close()
+    }
+}
+
+impl Subscriber {
+    /// Filters what `inner` receives, e.g. a receiver attached to a shared ring.
+    pub fn new(inner: Receiver) -> Subscriber {
+        Subscriber { inner, topics: HashSet::new(), skipped: Cell::new(0) }
+    }
+
+    pub fn subscribe(&mut self, topic: u32) {
+        self.topics.insert(topic);
+    }
+
+    pub fn unsubscribe(&mut self, topic: u32) {
+        self.topics.remove(&topic);
+    }
+
+    pub fn is_subscribed(&self, topic: u32) -> bool {
+        self.topics.contains(&topic)
+    }
+
+    /// Number of elements consumed so far because nobody subscribed to their topic.
+    pub fn skipped(&self) -> u64 {
+        self.skipped.get()
+    }
+
+    /// Pops the oldest element of a subscribed topic, handing `consumer` its topic along
+    /// with it. Elements of other topics in front of it are consumed.
+    pub fn try_pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u32, &[u8])
+    {
+        let mut consumer = Some(consumer);
+        while consumer.is_some() {
+            self.inner.try_pop_with_header(|topic, bytes| self.deliver(topic, bytes, &mut consumer))?;
+        }
+        Ok(())
+    }
+
+    /// Like `try_pop`, parking the calling thread until an element of a subscribed topic
+    /// arrives.
+    pub fn pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u32, &[u8])
+    {
+        let mut consumer = Some(consumer);
+        while consumer.is_some() {
+            self.inner.pop_with_header(|topic, bytes| self.deliver(topic, bytes, &mut consumer))?;
+        }
+        Ok(())
+    }
+
+    /// Hands the element to `consumer`, taking it out of the option, if its topic is
+    /// subscribed to.
+    fn deliver<F>(&self, topic: u64, bytes: &[u8], consumer: &mut Option<F>)
+        where F: FnOnce(u32, &[u8])
+    {
+        match u32::try_from(topic) {
+            Ok(topic) if self.topics.contains(&topic) => {
+                (consumer.take().expect("element already delivered"))(topic, bytes)
+            }
+            _ => self.skipped.set(self.skipped.get() + 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::channel_pubsub;
+    use crate::cbuffer_raw::{BufferSize, PopError};
+
+    #[test]
+    fn test_pubsub() {
+        let (mut publisher, mut subscriber) = channel_pubsub(BufferSize::Custom(4096));
+        subscriber.subscribe(1);
+        subscriber.subscribe(3);
+        assert!(subscriber.is_subscribed(3) && !subscriber.is_subscribed(2));
+        for topic in 0..5 {
+            publisher.publish(topic, &[topic as u8; 3]).unwrap();
+        }
+        publisher.try_publish(3, b"last").unwrap();
+
+        let mut seen = Vec::new();
+        while subscriber.try_pop(|topic, bytes| seen.push((topic, bytes.to_vec()))).is_ok() {}
+        assert_eq!(vec![(1, vec![1; 3]), (3, vec![3; 3]), (3, b"last".to_vec())], seen);
+        assert_eq!(3, subscriber.skipped());
+
+        subscriber.unsubscribe(3);
+        publisher.publish(3, b"unseen").unwrap();
+        publisher.publish(1, b"seen").unwrap();
+        assert_eq!(Ok(()), subscriber.pop(|topic, bytes| assert_eq!((1, &b"seen"[..]), (topic, bytes))));
+        publisher.close().unwrap();
+        assert_eq!(Err(PopError::Closed), subscriber.pop(|_, _| panic!("nothing left")));
+    }
+}
@@ -0,0 +1,167 @@
+//! Python bindings, built into an extension module named `cbuffer`, a cdylib, e.g. by
+//! `cargo rustc --release --lib --features python --crate-type cdylib`. Blocking calls let
+//! go of the GIL while they wait.
+
+use std::sync::Mutex;
+
+use pyo3::exceptions::{PyBrokenPipeError, PyEOFError, PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::cbuffer_raw::{channel, BufferSize, PopError, PushError, Receiver, Sender};
+
+#[pyclass(name = "Sender")]
+pub struct PySender {
+    inner: Sender,
+}
+
+/// The receiver is not `Sync`, so Python threads take turns on it.
+#[pyclass(name = "Receiver")]
+pub struct PyReceiver {
+    inner: Mutex<Receiver>,
+}
+
+fn push_error(err: PushError) -> PyErr {
+    match err {
+        PushError::MessageTooLarge => PyValueError::new_err(err.to_string()),
+        _ => PyBrokenPipeError::new_err(err.to_string()),
+    }
+}
+
+fn pop_error(err: PopError) -> PyErr {
+    match err {
+        PopError::Disconnected | PopError::Closed => PyEOFError::new_err(err.to_string()),
+        _ => PyValueError::new_err(err.to_string()),
+    }
+}
+
+#[pymethods]
+impl PySender {
+    /// Joins the shared channel `name` as another sender.
+    #[cfg(unix)]
+    #[staticmethod]
+    fn attach(name: &str) -> PyResult<PySender> {
+        let inner = Sender::attach(name).map_err(PyOSError::new_err)?;
+        Ok(PySender { inner })
+    }
+
+    /// Pushes `data`, waiting for room.
+    fn push(&mut self, py: Python<'_>, data: &[u8]) -> PyResult<()> {
+        let inner = &mut self.inner;
+        py.allow_threads(|| inner.push(data)).map_err(push_error)
+    }
+
+    /// Pushes `data` if it fits right now; returns whether it did.
+    fn try_push(&mut self, data: &[u8]) -> PyResult<bool> {
+        match self.inner.try_push(data) {
+            Ok(()) => Ok(true),
+            Err(PushError::Full) => Ok(false),
+            Err(err) => Err(push_error(err)),
+        }
+    }
+
+    fn close(&mut self, py: Python<'_>) -> PyResult<()> {
+        let inner = &mut self.inner;
+        py.allow_threads(|| inner.close()).map_err(push_error)
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+#[pymethods]
+impl PyReceiver {
+    /// Joins the shared channel `name` as another receiver.
+    #[cfg(unix)]
+    #[staticmethod]
+    fn attach(name: &str) -> PyResult<PyReceiver> {
+        let inner = Receiver::attach(name).map_err(PyOSError::new_err)?;
+        Ok(PyReceiver { inner: Mutex::new(inner) })
+    }
+
+    /// Pops the oldest element, waiting for one. Raises `EOFError` once the channel is
+    /// drained and disconnected or closed.
+    fn pop<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let inner = &self.inner;
+        let elem = py.allow_threads(|| {
+            let mut elem = Vec::new();
+            inner.lock().unwrap().pop(|bytes| elem.extend_from_slice(bytes)).map(|()| elem)
+        });
+        elem.map(|elem| PyBytes::new(py, &elem)).map_err(pop_error)
+    }
+
+    /// Pops the oldest element if there is one, else returns `None`.
+    fn try_pop<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyBytes>>> {
+        let mut elem = None;
+        match self.inner.lock().unwrap().try_pop(|bytes| elem = Some(PyBytes::new(py, bytes))) {
+            Ok(()) | Err(PopError::Empty) => Ok(elem),
+            Err(err) => Err(pop_error(err)),
+        }
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+}
+
+/// Creates a channel of `capacity` bytes, rounded up to a power of two of at least a page.
+#[pyfunction(name = "channel")]
+fn py_channel(capacity: usize) -> PyResult<(PySender, PyReceiver)> {
+    BufferSize::Custom(capacity).bytes().map_err(|err| PyValueError::new_err(err.to_string()))?;
+    let (sender, receiver) = channel(BufferSize::Custom(capacity));
+    Ok((PySender { inner: sender }, PyReceiver { inner: Mutex::new(receiver) }))
+}
+
+/// Creates a channel in the shared memory object `name`, for other processes to attach to.
+#[cfg(unix)]
+#[pyfunction(name = "channel_shared")]
+fn py_channel_shared(name: &str, capacity: usize) -> PyResult<(PySender, PyReceiver)> {
+    let (sender, receiver) = crate::cbuffer_raw::channel_shared(name, BufferSize::Custom(capacity))
+        .map_err(PyOSError::new_err)?;
+    Ok((PySender { inner: sender }, PyReceiver { inner: Mutex::new(receiver) }))
+}
+
+#[pymodule]
+fn cbuffer(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PySender>()?;
+    m.add_class::<PyReceiver>()?;
+    m.add_function(wrap_pyfunction!(py_channel, m)?)?;
+    #[cfg(unix)]
+    m.add_function(wrap_pyfunction!(py_channel_shared, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_python() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "cbuffer").unwrap();
+            super::cbuffer(&module).unwrap();
+            let locals = PyDict::new(py);
+            locals.set_item("cbuffer", module).unwrap();
+            let code = CString::new("
+tx, rx = cbuffer.channel(4096)
+tx.push(b'frame')
+assert tx.try_push(b'x')
+assert len(rx) == 2
+assert rx.pop() == b'frame'
+assert rx.try_pop() == b'x'
+assert rx.try_pop() is None
+del tx
+try:
+    rx.pop()
+    raise AssertionError('pop after disconnect')
+except EOFError:
+    pass
+").unwrap();
+            py.run(&code, None, Some(&locals)).unwrap();
+        });
+    }
+}
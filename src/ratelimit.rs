@@ -0,0 +1,184 @@
+//! Token buckets limiting how fast a `Sender` pushes.
+
+use std::time::{Duration, Instant};
+
+/// Most a sender may push per second, in elements and in payload bytes. Either limit may
+/// be left out. Up to a second's worth can go out in one burst.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RateLimit {
+    pub messages_per_sec: Option<u64>,
+    pub bytes_per_sec: Option<u64>,
+}
+
+/// The tokens left to one sender under its `RateLimit`.
+pub(crate) struct TokenBucket {
+    limit: RateLimit,
+    messages: f64,
+    bytes: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// Starts out full, so the first burst goes through right away.
+    pub(crate) fn new(limit: RateLimit) -> TokenBucket {
+        TokenBucket {
+            limit,
+            messages: limit.messages_per_sec.unwrap_or(0) as f64,
+            bytes: limit.bytes_per_sec.unwrap_or(0) as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// Takes the tokens for `count` elements of `bytes` bytes in total, or says how long
+    /// until there are enough. Asking for more than a whole burst only waits for a full
+    /// bucket, so that no element is held back forever.
+    pub(crate) fn take(&mut self, count: usize, bytes: usize) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        let mut wait = 0f64;
+        if let Some(rate) = self.limit.messages_per_sec {
+            self.messages = (self.messages + elapsed * rate as f64).min(rate as f64);
+            wait = wait.max(shortfall(self.messages, count as f64, rate));
+        }
+        if let Some(rate) = self.limit.bytes_per_sec {
+            self.bytes = (self.bytes + elapsed * rate as f64).min(rate as f64);
+            wait = wait.max(shortfall(self.bytes, bytes as f64, rate));
+        }
+        if wait > 0.0 {
+            return Err(Duration::from_secs_f64(wait));
+        }
+        self.messages -= count as f64;
+        self.bytes -= bytes as f64;
+        Ok(())
+    }
+
+    /// Like `take`, but sleeps until there are enough tokens or `deadline` passes.
+    /// Returns whether it got them.
+    pub(crate) fn wait_for(&mut self, count: usize, bytes: usize, deadline: Option<Instant>) -> bool {
+        loop {
+            let wait = match self.take(count, bytes) {
+                Ok(()) => return true,
+                Err(wait) => wait,
+            };
+            let wait = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(left) if left > Duration::ZERO => wait.min(left),
+                    _ => return false,
+                },
+                None => wait,
+            };
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Gives back what `take` took for a push that failed after all, up to a full bucket.
+    pub(crate) fn refund(&mut self, count: usize, bytes: usize) {
+        self.messages = (self.messages + count as f64).min(self.limit.messages_per_sec.unwrap_or(0) as f64);
+        self.bytes = (self.bytes + bytes as f64).min(self.limit.bytes_per_sec.unwrap_or(0) as f64);
+    }
+}
+
+/// Seconds until `tokens` refilling at `rate` per second cover `cost`, capped at a full
+/// bucket. A rate of zero never refills, so it waits for good.
+fn shortfall(tokens: f64, cost: f64, rate: u64) -> f64 {
+    let needed = cost.min(rate as f64);
+    if tokens >= needed {
+        return 0.0;
+    }
+    if rate == 0 {
+        return f64::from(u32::MAX);
+    }
+    (needed - tokens) / rate as f64
+}
+
+/// Wakes tasks whose pushes were held back by their rate limit once they may go on,
+/// from a thread started on first use, since no particular runtime's timers are at hand.
+#[cfg(feature = "async")]
+pub(crate) fn wake_after(wait: Duration, waker: std::task::Waker) {
+    use std::collections::BinaryHeap;
+    use std::cmp::Reverse;
+    use std::sync::{Condvar, Mutex, OnceLock};
+
+    struct Timer {
+        queue: Mutex<BinaryHeap<Reverse<Entry>>>,
+        changed: Condvar,
+    }
+
+    struct Entry(Instant, std::task::Waker);
+
+    impl PartialEq for Entry {
+        fn eq(&self, other: &Entry) -> bool {
+            self.0 == other.0
+        }
+    }
+
+    impl Eq for Entry {}
+
+    impl PartialOrd for Entry {
+        fn partial_cmp(&self, other: &Entry) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Entry {
+        fn cmp(&self, other: &Entry) -> std::cmp::Ordering {
+            self.0.cmp(&other.0)
+        }
+    }
+
+    static TIMER: OnceLock<&'static Timer> = OnceLock::new();
+    let timer = *TIMER.get_or_init(|| {
+        let timer: &'static Timer = Box::leak(Box::new(Timer { queue: Mutex::new(BinaryHeap::new()), changed: Condvar::new() }));
+        std::thread::Builder::new().name("cbuffer-timer".into()).spawn(move || {
+            let mut queue = timer.queue.lock().unwrap();
+            loop {
+                let now = Instant::now();
+                queue = match queue.peek() {
+                    Some(Reverse(Entry(at, _))) if *at <= now => {
+                        let Reverse(Entry(_, waker)) = queue.pop().unwrap();
+                        waker.wake();
+                        continue;
+                    }
+                    Some(Reverse(Entry(at, _))) => {
+                        let at = *at;
+                        timer.changed.wait_timeout(queue, at - now).unwrap().0
+                    }
+                    None => timer.changed.wait(queue).unwrap(),
+                };
+            }
+        }).expect("fail to start timer thread.");
+        timer
+    });
+    timer.queue.lock().unwrap().push(Reverse(Entry(Instant::now() + wait, waker)));
+    timer.changed.notify_one();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimit, TokenBucket};
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(RateLimit { messages_per_sec: Some(10), bytes_per_sec: Some(1000) });
+        assert_eq!(Ok(()), bucket.take(10, 100));
+        let wait = bucket.take(1, 0).unwrap_err();
+        assert!(wait.as_secs_f64() > 0.09 && wait.as_secs_f64() <= 0.1);
+        assert!(!bucket.wait_for(1, 0, Some(std::time::Instant::now())));
+        assert!(bucket.wait_for(1, 0, None));
+        bucket.refund(1, 0);
+        assert_eq!(Ok(()), bucket.take(1, 0));
+
+        // Refunds never fill the bucket past a burst.
+        let mut bucket = TokenBucket::new(RateLimit { messages_per_sec: Some(10), bytes_per_sec: None });
+        bucket.refund(5, 0);
+        assert_eq!(Ok(()), bucket.take(10, 0));
+        assert!(bucket.take(1, 0).is_err());
+
+        // An element larger than a burst goes through once the bucket is full.
+        let mut bucket = TokenBucket::new(RateLimit { messages_per_sec: None, bytes_per_sec: Some(1000) });
+        assert_eq!(Ok(()), bucket.take(1, 5000));
+        assert!(bucket.take(1, 1).is_err());
+        assert_eq!(Ok(()), TokenBucket::new(RateLimit::default()).take(1_000_000, 1_000_000));
+    }
+}
@@ -0,0 +1,165 @@
+use std::cell::{Cell, RefCell};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cbuffer_raw::{PopError, Receiver};
+
+/// Bytes in front of every element in a recording: its sequence number, the time it was
+/// popped in nanoseconds since the Unix epoch, and its length, all little-endian.
+const RECORD_HEADER: usize = 20;
+
+/// Receiving half of a channel that appends every element it pops to a log file, for
+/// replaying the traffic later with `Recording`. Writes go through a buffer, so the ring
+/// is not held up by the disk.
+pub struct RecordingReceiver {
+    inner: Receiver,
+    log: RefCell<BufWriter<File>>,
+    seq: Cell<u64>,
+    /// The first write that failed; nothing is recorded after it.
+    error: RefCell<Option<io::Error>>,
+}
+
+impl RecordingReceiver {
+    /// Records the elements popped from `inner` to the end of the file at `path`,
+    /// creating it if need be.
+    pub fn new<P: AsRef<Path>>(inner: Receiver, path: P) -> io::Result<RecordingReceiver> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(RecordingReceiver {
+            inner,
+            log: RefCell::new(BufWriter::new(file)),
+            seq: Cell::new(0),
+            error: RefCell::new(None),
+        })
+    }
+
+    pub fn try_pop<F>(&self, mut consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        self.inner.try_pop(|bytes| {
+            self.record(bytes);
+            consumer(bytes);
+        })
+    }
+
+    /// Pops one element, parking the calling thread until the sender pushes one.
+    pub fn pop<F>(&self, mut consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        self.inner.pop(|bytes| {
+            self.record(bytes);
+            consumer(bytes);
+        })
+    }
+
+    /// Writes out whatever is still buffered, or reports why recording stopped.
+    pub fn flush(&self) -> io::Result<()> {
+        if let Some(err) = self.error.borrow_mut().take() {
+            return Err(err);
+        }
+        self.log.borrow_mut().flush()
+    }
+
+    fn record(&self, bytes: &[u8]) {
+        if self.error.borrow().is_some() {
+            return;
+        }
+        let seq = self.seq.get();
+        self.seq.set(seq + 1);
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_nanos() as u64);
+        let mut header = [0u8; RECORD_HEADER];
+        header[..8].copy_from_slice(&seq.to_le_bytes());
+        header[8..16].copy_from_slice(&timestamp.to_le_bytes());
+        header[16..].copy_from_slice(&(bytes.len() as u32).to_le_bytes());
+        let mut log = self.log.borrow_mut();
+        if let Err(err) = log.write_all(&header).and_then(|()| log.write_all(bytes)) {
+            *self.error.borrow_mut() = Some(err);
+        }
+    }
+}
+
+/// One element read back from a recording.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    /// Position among the elements popped by the receiver that recorded it.
+    pub seq: u64,
+    /// When the recording receiver popped it.
+    pub timestamp: SystemTime,
+    pub data: Vec<u8>,
+}
+
+/// Reads back the elements a `RecordingReceiver` logged, oldest first.
+pub struct Recording {
+    reader: BufReader<File>,
+}
+
+impl Recording {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Recording> {
+        Ok(Recording { reader: BufReader::new(File::open(path)?) })
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<Record>> {
+        let mut header = [0u8; RECORD_HEADER];
+        // A clean end of file falls between two records.
+        match self.reader.read(&mut header[..1])? {
+            0 => return Ok(None),
+            _ => self.reader.read_exact(&mut header[1..])?,
+        }
+        let field = |range: std::ops::Range<usize>| {
+            let mut bytes = [0u8; 8];
+            bytes[..range.len()].copy_from_slice(&header[range]);
+            u64::from_le_bytes(bytes)
+        };
+        let mut data = vec![0u8; field(16..20) as usize];
+        self.reader.read_exact(&mut data)?;
+        Ok(Some(Record {
+            seq: field(0..8),
+            timestamp: UNIX_EPOCH + std::time::Duration::from_nanos(field(8..16)),
+            data,
+        }))
+    }
+}
+
+impl Iterator for Recording {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Recording, RecordingReceiver};
+    use crate::cbuffer_raw::{channel, BufferSize};
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_recording() {
+        let path = std::env::temp_dir().join(format!("cbuffer-recording-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let before = SystemTime::now();
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let receiver = RecordingReceiver::new(receiver, &path).unwrap();
+        for elem in &[&b"first"[..], b"", b"third"] {
+            sender.push(elem).unwrap();
+        }
+        for _i in 0..3 {
+            receiver.try_pop(|_| {}).unwrap();
+        }
+        receiver.flush().unwrap();
+
+        let records: Vec<_> = Recording::open(&path).unwrap().map(Result::unwrap).collect();
+        assert_eq!(vec![0, 1, 2], records.iter().map(|r| r.seq).collect::<Vec<_>>());
+        assert_eq!(vec![b"first".to_vec(), Vec::new(), b"third".to_vec()],
+                   records.iter().map(|r| r.data.clone()).collect::<Vec<_>>());
+        assert!(records.iter().all(|r| r.timestamp >= before));
+
+        // A cut-off record shows up as an error rather than being skipped.
+        let len = std::fs::metadata(&path).unwrap().len();
+        std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 2).unwrap();
+        assert!(Recording::open(&path).unwrap().nth(2).unwrap().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,91 @@
+//! Shared channels found by name, so that processes can meet without passing file
+//! descriptors or object names around. A channel called `ticker-feed` lives in the shared
+//! memory object `/cbuffer.ticker-feed`, which Linux shows as `/dev/shm/cbuffer.ticker-feed`.
+
+use std::io;
+
+use crate::cbuffer_raw::{CBuffer, ChannelBuilder, Receiver, Sender};
+
+const PREFIX: &str = "cbuffer.";
+
+/// Creates the channel `name` with `options`, like `ChannelBuilder::build_shared`. A
+/// channel left behind under that name by processes that are all gone is removed first;
+/// one still in use makes this fail with `io::ErrorKind::AlreadyExists`.
+pub fn create(name: &str, options: ChannelBuilder) -> io::Result<(Sender, Receiver)> {
+    let object = object_name(name)?;
+    CBuffer::remove_abandoned(&object)?;
+    options.build_shared(&object)
+}
+
+/// Joins the channel `name` as a receiver, like `Receiver::attach`.
+pub fn connect(name: &str) -> io::Result<Receiver> {
+    Receiver::attach(&object_name(name)?)
+}
+
+/// Joins the channel `name` as a sender, like `Sender::attach`.
+pub fn connect_sender(name: &str) -> io::Result<Sender> {
+    Sender::attach(&object_name(name)?)
+}
+
+/// Takes the channel `name` out of the registry. Handles already attached keep working,
+/// but nothing can connect to it any more.
+pub fn remove(name: &str) -> io::Result<()> {
+    CBuffer::unlink_shared(&object_name(name)?)
+}
+
+/// Names of the channels in the registry, including abandoned ones.
+#[cfg(target_os = "linux")]
+pub fn list() -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir("/dev/shm")? {
+        if let Some(name) = entry?.file_name().to_str().and_then(|name| name.strip_prefix(PREFIX)) {
+            names.push(name.to_owned());
+        }
+    }
+    Ok(names)
+}
+
+/// Removes every channel whose processes are all gone, returning how many there were.
+#[cfg(target_os = "linux")]
+pub fn cleanup() -> io::Result<usize> {
+    let mut removed = 0;
+    for name in list()? {
+        if CBuffer::remove_abandoned(&object_name(&name)?)? {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+fn object_name(name: &str) -> io::Result<String> {
+    if name.is_empty() || name.len() > 200 || name.contains(['/', '\0']) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid channel name"));
+    }
+    Ok(format!("/{}{}", PREFIX, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{connect, connect_sender, create, remove};
+    use crate::cbuffer_raw::ChannelBuilder;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn test_registry() {
+        let name = format!("registry-{}", std::process::id());
+        let (_sender, _receiver) = create(&name, ChannelBuilder::new().capacity_bytes(4096)).unwrap();
+        let busy = create(&name, ChannelBuilder::new().capacity_bytes(4096));
+        assert_eq!(ErrorKind::AlreadyExists, busy.err().unwrap().kind());
+        #[cfg(target_os = "linux")]
+        assert!(super::list().unwrap().contains(&name));
+
+        let mut sender = connect_sender(&name).unwrap();
+        let receiver = connect(&name).unwrap();
+        sender.push(b"tick").unwrap();
+        assert_eq!(Some(b"tick".to_vec()), receiver.pop_owned());
+
+        remove(&name).unwrap();
+        assert_eq!(ErrorKind::NotFound, connect(&name).err().unwrap().kind());
+        assert_eq!(ErrorKind::InvalidInput, connect("a/b").err().unwrap().kind());
+    }
+}
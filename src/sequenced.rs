@@ -0,0 +1,132 @@
+use crate::cbuffer_raw::{channel_with_policy, BufferSize, FullPolicy, PopError, PushError, Receiver, Sender};
+
+/// Sending half of a sequenced channel; every push takes the next sequence number, even
+/// one the policy ends up dropping.
+pub struct SequencedSender {
+    inner: Sender,
+    next: u64,
+}
+
+/// Receiving half of a sequenced channel, which hands out elements together with their
+/// sequence number and counts the numbers it never saw.
+pub struct SequencedReceiver {
+    inner: Receiver,
+    expected: u64,
+    missed: u64,
+}
+
+/// Creates a channel whose elements carry a sequence number in the header slot of
+/// `Sender::push_with_header`, so that a lossy `policy` leaves visible gaps.
+pub fn channel_sequenced(s: BufferSize, policy: FullPolicy) -> (SequencedSender, SequencedReceiver) {
+    let (sender, receiver) = channel_with_policy(s, policy);
+    (SequencedSender { inner: sender, next: 0 }, SequencedReceiver { inner: receiver, expected: 0, missed: 0 })
+}
+
+impl SequencedSender {
+    pub fn try_push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        self.inner.inner.push_parts(&self.next.to_le_bytes(), elem)?;
+        self.next += 1;
+        Ok(())
+    }
+
+    /// Pushes `elem`, parking the calling thread until the receiver frees enough space.
+    pub fn push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        self.inner.push_with_header(self.next, elem)?;
+        self.next += 1;
+        Ok(())
+    }
+
+    /// Number of pushes discarded by `FullPolicy::DropNewest`.
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped()
+    }
+
+    /// Number of elements evicted by `FullPolicy::OverwriteOldest`.
+    pub fn overwritten(&self) -> u64 {
+        self.inner.overwritten()
+    }
+}
+
+impl SequencedReceiver {
+    /// Pops the oldest element, handing `consumer` its sequence number along with it.
+    pub fn try_pop<F>(&mut self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u64, &[u8])
+    {
+        let mut seq = 0;
+        self.inner.try_pop_with_header(|s, bytes| {
+            seq = s;
+            consumer(s, bytes)
+        })?;
+        self.advance(seq);
+        Ok(())
+    }
+
+    /// Like `try_pop`, parking the calling thread until an element arrives.
+    pub fn pop<F>(&mut self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(u64, &[u8])
+    {
+        let mut seq = 0;
+        self.inner.pop_with_header(|s, bytes| {
+            seq = s;
+            consumer(s, bytes)
+        })?;
+        self.advance(seq);
+        Ok(())
+    }
+
+    /// Sequence numbers skipped so far, i.e. elements that were dropped or overwritten
+    /// before this receiver got to them.
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped()
+    }
+
+    pub fn overwritten(&self) -> u64 {
+        self.inner.overwritten()
+    }
+
+    fn advance(&mut self, seq: u64) {
+        self.missed += seq.saturating_sub(self.expected);
+        self.expected = seq + 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::channel_sequenced;
+    use crate::cbuffer_raw::{BufferSize, FullPolicy, PopError};
+
+    #[test]
+    fn test_drop_gaps() {
+        let (mut sender, mut receiver) = channel_sequenced(BufferSize::Custom(4096), FullPolicy::DropNewest);
+        let mut pushed = 0;
+        while sender.dropped() == 0 {
+            sender.push(&[0; 100]).unwrap();
+            pushed += 1;
+        }
+        sender.push(b"last").unwrap();
+        let mut seen = Vec::new();
+        while receiver.try_pop(|seq, _| seen.push(seq)) == Ok(()) {}
+        assert_eq!((0..pushed - 1).chain([pushed]).collect::<Vec<_>>(), seen);
+        assert_eq!(1, receiver.missed());
+        assert_eq!(1, receiver.dropped());
+        assert_eq!(Err(PopError::Empty), receiver.try_pop(|_, _| {}));
+    }
+
+    #[test]
+    fn test_overwrite_gaps() {
+        let (mut sender, mut receiver) = channel_sequenced(BufferSize::Custom(4096), FullPolicy::OverwriteOldest);
+        for _ in 0..100 {
+            sender.try_push(&[0; 100]).unwrap();
+        }
+        drop(sender);
+        let mut seen = Vec::new();
+        while receiver.pop(|seq, _| seen.push(seq)) == Ok(()) {}
+        assert_eq!(Some(&99), seen.last());
+        assert_eq!(seen[0], receiver.missed());
+        assert_eq!(receiver.missed(), receiver.overwritten());
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::cbuffer_raw::{channel, BufferSize, PushError, Receiver, Sender};
+
+/// Sending half of a sharded channel. Every key always goes to the same shard, so the
+/// elements of one key arrive in the order they were pushed.
+pub struct ShardedSender {
+    shards: Vec<Sender>,
+}
+
+/// Creates a channel striped across `shards` rings of size `s`, returning one receiver
+/// per shard so that each can be drained by a consumer thread of its own.
+pub fn channel_sharded(s: BufferSize, shards: usize) -> (ShardedSender, Vec<Receiver>) {
+    assert!(shards > 0, "a sharded channel needs at least one shard");
+    let (senders, receivers) = (0..shards).map(|_| channel(s)).unzip();
+    (ShardedSender { shards: senders }, receivers)
+}
+
+impl ShardedSender {
+    /// Index of the receiver that elements pushed under `key` go to.
+    pub fn shard<K: Hash + ?Sized>(&self, key: &K) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn try_push<K: Hash + ?Sized>(&mut self, key: &K, elem: &[u8]) -> Result<(), PushError> {
+        let shard = self.shard(key);
+        self.shards[shard].try_push(elem)
+    }
+
+    /// Pushes `elem` to the shard of `key`, parking the calling thread until it has room.
+    /// A full shard holds up its own keys only until it drains.
+    pub fn push<K: Hash + ?Sized>(&mut self, key: &K, elem: &[u8]) -> Result<(), PushError> {
+        let shard = self.shard(key);
+        self.shards[shard].push(elem)
+    }
+
+    /// Closes every shard; see `Sender::close`.
+    pub fn close(&mut self) -> Result<(), PushError> {
+        self.shards.iter_mut().try_for_each(|shard| shard.close())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::channel_sharded;
+    use crate::{BufferSize, PopError};
+
+    #[test]
+    fn test_sharded() {
+        let (mut sender, receivers) = channel_sharded(BufferSize::Custom(4096), 4);
+        assert_eq!(4, sender.shards());
+        assert_eq!(sender.shard("key"), sender.shard("key"));
+
+        let consumers: Vec<_> = receivers.into_iter().enumerate().map(|(shard, receiver)| {
+            std::thread::spawn(move || {
+                let mut seen = Vec::new();
+                loop {
+                    match receiver.pop(|bytes| seen.push((bytes[0], u32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]])))) {
+                        Ok(()) => {}
+                        Err(PopError::Closed) => return (shard, seen),
+                        Err(err) => panic!("{}", err),
+                    }
+                }
+            })
+        }).collect();
+
+        let keys = 16u8;
+        for i in 0..10_000u32 {
+            let key = (i % keys as u32) as u8;
+            let mut elem = [key, 0, 0, 0, 0];
+            elem[1..].copy_from_slice(&i.to_le_bytes());
+            sender.push(&key, &elem).unwrap();
+        }
+        sender.close().unwrap();
+
+        let mut total = 0;
+        for consumer in consumers {
+            let (shard, seen) = consumer.join().unwrap();
+            let mut last = vec![None; keys as usize];
+            for (key, i) in seen {
+                assert_eq!(shard, sender.shard(&key));
+                assert!(last[key as usize] < Some(i));
+                last[key as usize] = Some(i);
+                total += 1;
+            }
+        }
+        assert_eq!(10_000, total);
+    }
+}
@@ -0,0 +1,217 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::cbuffer_raw::{channel, BufferSize, PopError, PushError, Receiver, Sender};
+
+/// Elements that did not fit into the ring, queued in a temporary file in the order they
+/// were pushed. Each is stored behind its little-endian `u32` length.
+struct SpillQueue {
+    file: File,
+    path: PathBuf,
+    read_pos: u64,
+    write_pos: u64,
+}
+
+impl SpillQueue {
+    fn create() -> io::Result<SpillQueue> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("cbuffer-spill-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+        let path = std::env::temp_dir().join(name);
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        Ok(SpillQueue { file, path, read_pos: 0, write_pos: 0 })
+    }
+
+    fn push_back(&mut self, elem: &[u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(self.write_pos))?;
+        self.file.write_all(&(elem.len() as u32).to_le_bytes())?;
+        self.file.write_all(elem)?;
+        self.write_pos += 4 + elem.len() as u64;
+        Ok(())
+    }
+
+    /// Reads the oldest element without removing it.
+    fn front(&mut self) -> io::Result<Vec<u8>> {
+        self.file.seek(SeekFrom::Start(self.read_pos))?;
+        let mut len = [0u8; 4];
+        self.file.read_exact(&mut len)?;
+        let mut elem = vec![0u8; u32::from_le_bytes(len) as usize];
+        self.file.read_exact(&mut elem)?;
+        Ok(elem)
+    }
+
+    /// Removes the oldest element, `front` having returned `len` bytes for it.
+    fn pop_front(&mut self, len: usize) {
+        self.read_pos += 4 + len as u64;
+        if self.read_pos == self.write_pos {
+            // Start over at the beginning rather than letting the file grow forever.
+            self.read_pos = 0;
+            self.write_pos = 0;
+            let _ = self.file.set_len(0);
+        }
+    }
+}
+
+impl Drop for SpillQueue {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+struct Spill {
+    queue: Mutex<SpillQueue>,
+    /// Elements in `queue`, so that neither side has to lock it while it is empty.
+    len: AtomicUsize,
+}
+
+/// Sending half of a spilling channel. Pushes that do not fit into the ring go to a file
+/// on disk instead, and move into the ring as it frees up.
+pub struct SpillSender {
+    inner: Sender,
+    spill: Arc<Spill>,
+}
+
+/// Receiving half of a spilling channel. Elements come out in the order they were
+/// pushed, wherever they were kept in between.
+pub struct SpillReceiver {
+    inner: Receiver,
+    spill: Arc<Spill>,
+}
+
+/// Creates a channel whose ring of size `s` overflows into a temporary file rather than
+/// ever reporting it full, trading bounded memory for unbounded disk.
+pub fn channel_spill(s: BufferSize) -> io::Result<(SpillSender, SpillReceiver)> {
+    let (sender, receiver) = channel(s);
+    let spill = Arc::new(Spill { queue: Mutex::new(SpillQueue::create()?), len: AtomicUsize::new(0) });
+    Ok((SpillSender { inner: sender, spill: spill.clone() }, SpillReceiver { inner: receiver, spill }))
+}
+
+impl SpillSender {
+    /// Pushes `elem` into the ring, or into the spill file if the ring is full or
+    /// earlier elements are still waiting there. Only fails with `PushError::Full` if
+    /// the spill file cannot be written.
+    pub fn push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        if self.spill.len.load(Ordering::Acquire) > 0 {
+            let mut queue = self.spill.queue.lock().unwrap();
+            self.refill(&mut queue);
+            if self.spill.len.load(Ordering::Acquire) > 0 {
+                return self.spill_back(&mut queue, elem);
+            }
+        }
+        match self.inner.try_push(elem) {
+            Err(PushError::Full) => self.spill_back(&mut self.spill.queue.lock().unwrap(), elem),
+            r => r,
+        }
+    }
+
+    /// Moves spilled elements into the ring as far as they fit, e.g. while the sender has
+    /// nothing new to push. Returns how many are left on disk.
+    pub fn refill_now(&mut self) -> usize {
+        if self.spill.len.load(Ordering::Acquire) > 0 {
+            self.refill(&mut self.spill.queue.lock().unwrap());
+        }
+        self.spilled()
+    }
+
+    /// Number of elements waiting in the spill file.
+    pub fn spilled(&self) -> usize {
+        self.spill.len.load(Ordering::Acquire)
+    }
+
+    fn spill_back(&self, queue: &mut SpillQueue, elem: &[u8]) -> Result<(), PushError> {
+        queue.push_back(elem).map_err(|_| PushError::Full)?;
+        self.spill.len.fetch_add(1, Ordering::Release);
+        Ok(())
+    }
+
+    fn refill(&self, queue: &mut SpillQueue) {
+        while self.spill.len.load(Ordering::Acquire) > 0 {
+            let elem = match queue.front() {
+                Ok(elem) => elem,
+                Err(_) => return,
+            };
+            // Checked first so that a full ring does not count as a failed push.
+            if !self.inner.inner.fits(elem.len()) || self.inner.inner.push(&elem).is_err() {
+                return;
+            }
+            queue.pop_front(elem.len());
+            self.spill.len.fetch_sub(1, Ordering::Release);
+        }
+    }
+}
+
+impl SpillReceiver {
+    /// Pops the oldest element, from the ring or, once that has run dry, from the spill
+    /// file.
+    pub fn try_pop<F>(&self, mut consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        match self.inner.try_pop(&mut consumer) {
+            Err(err @ PopError::Empty) | Err(err @ PopError::Disconnected) if self.spill.len.load(Ordering::Acquire) > 0 => {
+                let mut queue = self.spill.queue.lock().unwrap();
+                // The sender moves elements into the ring under the lock, so whatever it
+                // moved last is older than the rest of the file.
+                match self.inner.try_pop(&mut consumer) {
+                    Err(PopError::Empty) | Err(PopError::Disconnected) => {}
+                    r => return r,
+                }
+                if self.spill.len.load(Ordering::Acquire) == 0 {
+                    return Err(err);
+                }
+                let elem = queue.front().map_err(|_| err)?;
+                consumer(&elem);
+                queue.pop_front(elem.len());
+                self.spill.len.fetch_sub(1, Ordering::Release);
+                Ok(())
+            }
+            r => r,
+        }
+    }
+
+    /// Like `try_pop`, parking the calling thread until there is an element.
+    pub fn pop<F>(&self, mut consumer: F) -> Result<(), PopError>
+        where F: FnMut(&[u8])
+    {
+        let ring = &self.inner.inner;
+        loop {
+            match self.try_pop(&mut consumer) {
+                // Nothing was on disk either, and elements only spill into a full ring, so
+                // the next one arrives through the ring.
+                Err(PopError::Empty) => ring.wait_readable(|| !ring.is_empty() || ring.is_sender_dropped(), None),
+                r => return r,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::channel_spill;
+    use crate::cbuffer_raw::{BufferSize, PopError};
+
+    #[test]
+    fn test_spill() {
+        let page = crate::cbuffer_raw::page_size();
+        let (mut sender, receiver) = channel_spill(BufferSize::Custom(page)).unwrap();
+        let n = page as u32;
+        for i in 0..n {
+            sender.push(&i.to_le_bytes()).unwrap();
+        }
+        assert!(sender.spilled() > 0);
+
+        for i in 0..n / 2 {
+            assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(&i.to_le_bytes()[..], bytes)));
+        }
+        let left = sender.spilled();
+        assert!(sender.refill_now() < left);
+        sender.push(b"last").unwrap();
+        drop(sender);
+        for i in n / 2..n {
+            assert_eq!(Ok(()), receiver.pop(|bytes| assert_eq!(&i.to_le_bytes()[..], bytes)));
+        }
+        assert_eq!(Ok(()), receiver.try_pop(|bytes| assert_eq!(b"last", bytes)));
+        assert_eq!(Err(PopError::Disconnected), receiver.try_pop(|_| {}));
+    }
+}
@@ -0,0 +1,118 @@
+use std::io;
+use std::sync::Arc;
+
+use crate::cbuffer_raw::{BufferSize, CBuffer, ChannelBuilder, PushError};
+
+/// Writing half of a stream channel; bytes go into the ring without length prefixes.
+/// Separate from `Sender` so that raw bytes never end up in a ring read as frames.
+pub struct StreamSender {
+    inner: Arc<CBuffer>,
+}
+
+/// Reading half of a stream channel. Reads hit end-of-file once the sender is dropped
+/// and everything it wrote has been consumed.
+pub struct StreamReceiver {
+    inner: Arc<CBuffer>,
+}
+
+/// Creates a byte-stream channel, the `std::io` counterpart of `channel`. See
+/// `ChannelBuilder::build_stream` for other backends and wait strategies.
+pub fn stream_channel(s: BufferSize) -> (StreamSender, StreamReceiver) {
+    ChannelBuilder::new().capacity(s).build_stream().expect("fail to create cbuffer.")
+}
+
+impl StreamSender {
+    pub(crate) fn new(inner: Arc<CBuffer>) -> StreamSender {
+        StreamSender { inner }
+    }
+}
+
+impl StreamReceiver {
+    pub(crate) fn new(inner: Arc<CBuffer>) -> StreamReceiver {
+        StreamReceiver { inner }
+    }
+}
+
+impl io::Write for StreamSender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write_stream(buf).map_err(|err| match err {
+            PushError::Disconnected => io::Error::new(io::ErrorKind::BrokenPipe, err),
+            _ => io::Error::other(err),
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for StreamSender {
+    fn drop(&mut self) {
+        self.inner.disconnect_sender();
+    }
+}
+
+impl io::Read for StreamReceiver {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(self.inner.read_stream(buf))
+    }
+}
+
+impl Drop for StreamReceiver {
+    fn drop(&mut self) {
+        self.inner.disconnect_receiver();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::thread;
+    use super::stream_channel;
+    use crate::cbuffer_raw::BufferSize;
+
+    #[test]
+    fn test_copy() {
+        let (mut writer, mut reader) = stream_channel(BufferSize::Custom(4096));
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+
+        let expected = data.clone();
+        let producer = thread::spawn(move || {
+            io::copy(&mut data.as_slice(), &mut writer).unwrap();
+        });
+
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        producer.join().unwrap();
+        assert_eq!(expected, out);
+    }
+
+    #[test]
+    fn test_builder() {
+        use crate::cbuffer_raw::{ChannelBuilder, MemoryBackend, WaitStrategy};
+
+        let (mut writer, mut reader) = ChannelBuilder::new()
+            .capacity(BufferSize::Custom(4096))
+            .backend(MemoryBackend::Heap)
+            .wait_strategy(WaitStrategy::Yield)
+            .build_stream()
+            .unwrap();
+        let data: Vec<u8> = (0..50_000u32).map(|i| (i % 253) as u8).collect();
+
+        let expected = data.clone();
+        let producer = thread::spawn(move || {
+            io::copy(&mut data.as_slice(), &mut writer).unwrap();
+        });
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        producer.join().unwrap();
+        assert_eq!(expected, out);
+    }
+
+    #[test]
+    fn test_broken_pipe() {
+        let (mut writer, reader) = stream_channel(BufferSize::Custom(4096));
+        drop(reader);
+        assert_eq!(io::ErrorKind::BrokenPipe, writer.write(b"abc").unwrap_err().kind());
+    }
+}
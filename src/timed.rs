@@ -0,0 +1,175 @@
+#[cfg(feature = "metrics")]
+use std::cell::{Ref, RefCell};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "metrics")]
+use hdrhistogram::Histogram;
+
+use crate::cbuffer_raw::{channel, BufferSize, PopError, PushError, Receiver, Sender};
+
+/// Longest delay the latency histogram tells apart, in nanoseconds; longer ones count as
+/// this.
+#[cfg(feature = "metrics")]
+const LATENCY_MAX: u64 = 3_600_000_000_000;
+
+/// Sending half of a timed channel; every element is stamped with the time it was pushed.
+pub struct TimedSender {
+    inner: Sender,
+    epoch: Instant,
+}
+
+/// Receiving half of a timed channel, which hands out elements together with their age.
+pub struct TimedReceiver {
+    inner: Receiver,
+    epoch: Instant,
+    ttl: Option<Duration>,
+    /// Ages of everything popped so far, in nanoseconds.
+    #[cfg(feature = "metrics")]
+    latency: RefCell<Histogram<u64>>,
+}
+
+/// Creates a channel whose elements carry the monotonic time they were pushed at, in the
+/// header slot of `Sender::push_with_header`.
+pub fn channel_timed(s: BufferSize) -> (TimedSender, TimedReceiver) {
+    let (sender, receiver) = channel(s);
+    let epoch = Instant::now();
+    let receiver = TimedReceiver {
+        inner: receiver,
+        epoch,
+        ttl: None,
+        #[cfg(feature = "metrics")]
+        latency: RefCell::new(Histogram::new_with_max(LATENCY_MAX, 3).expect("valid histogram bounds")),
+    };
+    (TimedSender { inner: sender, epoch }, receiver)
+}
+
+impl TimedSender {
+    pub fn try_push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        self.inner.inner.push_parts(&self.stamp().to_le_bytes(), elem)
+    }
+
+    /// Pushes `elem`, parking the calling thread until the receiver frees enough space.
+    pub fn push(&mut self, elem: &[u8]) -> Result<(), PushError> {
+        let stamp = self.stamp();
+        self.inner.push_with_header(stamp, elem)
+    }
+
+    /// Nanoseconds since the channel was created.
+    fn stamp(&self) -> u64 {
+        self.epoch.elapsed().as_nanos() as u64
+    }
+}
+
+impl TimedReceiver {
+    /// Drops elements older than `ttl` instead of handing them out, or stops doing so
+    /// for `None`.
+    pub fn set_ttl(&mut self, ttl: Option<Duration>) {
+        self.ttl = ttl;
+    }
+
+    /// Enqueue-to-dequeue delays in nanoseconds of every element popped so far, expired
+    /// ones included.
+    #[cfg(feature = "metrics")]
+    pub fn latency(&self) -> Ref<'_, Histogram<u64>> {
+        self.latency.borrow()
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn reset_latency(&self) {
+        self.latency.borrow_mut().reset();
+    }
+
+    /// Pops the oldest element that has not expired, handing `consumer` its age along
+    /// with it.
+    pub fn try_pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(Duration, &[u8])
+    {
+        let mut consumer = Some(consumer);
+        while consumer.is_some() {
+            self.inner.try_pop_with_header(|stamp, bytes| self.deliver(stamp, bytes, &mut consumer))?;
+        }
+        Ok(())
+    }
+
+    /// Like `try_pop`, parking the calling thread until an element arrives.
+    pub fn pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(Duration, &[u8])
+    {
+        let mut consumer = Some(consumer);
+        while consumer.is_some() {
+            self.inner.pop_with_header(|stamp, bytes| self.deliver(stamp, bytes, &mut consumer))?;
+        }
+        Ok(())
+    }
+
+    /// Hands the element to `consumer`, taking it out of the option, unless it expired.
+    fn deliver<F>(&self, stamp: u64, bytes: &[u8], consumer: &mut Option<F>)
+        where F: FnOnce(Duration, &[u8])
+    {
+        let age = self.epoch.elapsed().saturating_sub(Duration::from_nanos(stamp));
+        #[cfg(feature = "metrics")]
+        self.latency.borrow_mut().saturating_record(age.as_nanos() as u64);
+        if self.ttl.is_none_or(|ttl| age <= ttl) {
+            (consumer.take().expect("element already delivered"))(age, bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+    use super::channel_timed;
+    use crate::cbuffer_raw::{BufferSize, PopError};
+
+    #[test]
+    fn test_age() {
+        let (mut sender, receiver) = channel_timed(BufferSize::Custom(4096));
+        sender.push(b"old").unwrap();
+        thread::sleep(Duration::from_millis(20));
+        sender.try_push(b"new").unwrap();
+        let mut old = Duration::ZERO;
+        assert_eq!(Ok(()), receiver.try_pop(|age, bytes| {
+            assert_eq!(b"old", bytes);
+            assert!(age >= Duration::from_millis(20));
+            old = age;
+        }));
+        assert_eq!(Ok(()), receiver.pop(|age, bytes| {
+            assert_eq!(b"new", bytes);
+            assert!(age < old);
+        }));
+    }
+
+    #[test]
+    fn test_ttl() {
+        let (mut sender, mut receiver) = channel_timed(BufferSize::Custom(4096));
+        receiver.set_ttl(Some(Duration::from_millis(100)));
+        for i in 0..3u8 {
+            sender.push(&[i]).unwrap();
+        }
+        thread::sleep(Duration::from_millis(150));
+        sender.push(b"fresh").unwrap();
+        assert_eq!(Ok(()), receiver.try_pop(|_, bytes| assert_eq!(b"fresh", bytes)));
+        assert!(receiver.inner.is_empty());
+        sender.push(b"stale").unwrap();
+        thread::sleep(Duration::from_millis(150));
+        assert_eq!(Err(PopError::Empty), receiver.try_pop(|_, _| {}));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_latency() {
+        let (mut sender, receiver) = channel_timed(BufferSize::Custom(4096));
+        sender.push(b"slow").unwrap();
+        thread::sleep(Duration::from_millis(20));
+        sender.push(b"fast").unwrap();
+        while receiver.try_pop(|_, _| {}).is_ok() {}
+        let latency = receiver.latency();
+        assert_eq!(2, latency.len());
+        assert!(latency.max() >= Duration::from_millis(20).as_nanos() as u64);
+        assert!(latency.min() < latency.max());
+        drop(latency);
+        receiver.reset_latency();
+        assert!(receiver.latency().is_empty());
+    }
+}
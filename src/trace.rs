@@ -0,0 +1,94 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+
+/// Events a channel keeps in its trace; older ones are dropped.
+const TRACE_LIMIT: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceOp {
+    Push,
+    Pop,
+}
+
+/// One move of a cursor, recorded with the `trace` feature.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEvent {
+    /// Counts up from 0 over the life of the channel, so a trace that has dropped older
+    /// events starts above 0.
+    pub seq: u64,
+    pub thread: ThreadId,
+    pub op: TraceOp,
+    /// The cursors right after the move: the one moved, and the other one as this thread
+    /// saw it.
+    pub head: u64,
+    pub tail: u64,
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let op = match self.op {
+            TraceOp::Push => "push",
+            TraceOp::Pop => "pop",
+        };
+        write!(f, "#{} {:?} {} head={} tail={}", self.seq, self.thread, op, self.head, self.tail)
+    }
+}
+
+/// The last `TRACE_LIMIT` cursor moves made through one mapping of a ring. Moves made by
+/// other processes are not in it.
+pub(crate) struct Trace {
+    events: Mutex<(u64, VecDeque<TraceEvent>)>,
+}
+
+impl Trace {
+    pub(crate) fn new() -> Trace {
+        Trace { events: Mutex::new((0, VecDeque::new())) }
+    }
+
+    pub(crate) fn record(&self, op: TraceOp, head: u64, tail: u64) {
+        let mut events = self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (next, events) = &mut *events;
+        if events.len() == TRACE_LIMIT {
+            events.pop_front();
+        }
+        events.push_back(TraceEvent { seq: *next, thread: thread::current().id(), op, head, tail });
+        *next += 1;
+    }
+
+    pub(crate) fn events(&self) -> Vec<TraceEvent> {
+        let events = self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        events.1.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::TraceOp;
+    use crate::cbuffer_raw::{channel, BufferSize};
+
+    #[test]
+    fn test_trace() {
+        let (mut sender, receiver) = channel(BufferSize::Custom(4096));
+        let producer = thread::spawn(move || {
+            sender.push(b"first").unwrap();
+            sender.push(b"second").unwrap();
+            sender
+        });
+        let sender = producer.join().unwrap();
+        receiver.pop(|_| {}).unwrap();
+
+        let trace = receiver.trace();
+        assert_eq!(trace, sender.trace());
+        let ops: Vec<_> = trace.iter().map(|event| (event.seq, event.op)).collect();
+        assert_eq!(vec![(0, TraceOp::Push), (1, TraceOp::Push), (2, TraceOp::Pop)], ops);
+        assert_ne!(thread::current().id(), trace[0].thread);
+        assert_eq!(thread::current().id(), trace[2].thread);
+        assert_eq!((0, trace[0].tail), (trace[0].head, trace[2].head));
+        assert_eq!(trace[1].tail, trace[2].tail);
+        assert_eq!(format!("#2 {:?} pop head={} tail={}", trace[2].thread, trace[2].head, trace[2].tail), trace[2].to_string());
+    }
+}
@@ -0,0 +1,130 @@
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::cbuffer_raw::{channel, BufferSize, PopError, PushError, Receiver, Sender};
+
+/// Sending half of a typed channel; values are encoded with bincode into the ring.
+pub struct TypedSender<T> {
+    inner: Sender,
+    scratch: Vec<u8>,
+    _marker: PhantomData<fn(T)>,
+}
+
+/// Receiving half of a typed channel.
+pub struct TypedReceiver<T> {
+    inner: Receiver,
+    _marker: PhantomData<fn() -> T>,
+}
+
+/// Creates a channel carrying values of `T` instead of raw byte slices.
+pub fn channel_typed<T>(s: BufferSize) -> (TypedSender<T>, TypedReceiver<T>)
+    where T: Serialize + DeserializeOwned
+{
+    let (sender, receiver) = channel(s);
+    (TypedSender { inner: sender, scratch: Vec::new(), _marker: PhantomData },
+     TypedReceiver { inner: receiver, _marker: PhantomData })
+}
+
+impl<T: Serialize> TypedSender<T> {
+    pub fn try_push(&mut self, value: &T) -> Result<(), TypedError<PushError>> {
+        self.encode(value)?;
+        Ok(self.inner.try_push(&self.scratch)?)
+    }
+
+    /// Pushes `value`, parking the calling thread until the receiver frees enough space.
+    pub fn push(&mut self, value: &T) -> Result<(), TypedError<PushError>> {
+        self.encode(value)?;
+        Ok(self.inner.push(&self.scratch)?)
+    }
+
+    pub fn close(&mut self) -> Result<(), PushError> {
+        self.inner.close()
+    }
+
+    fn encode(&mut self, value: &T) -> Result<(), TypedError<PushError>> {
+        self.scratch.clear();
+        bincode::serialize_into(&mut self.scratch, value).map_err(TypedError::Codec)
+    }
+}
+
+impl<T: DeserializeOwned> TypedReceiver<T> {
+    pub fn try_pop(&self) -> Result<T, TypedError<PopError>> {
+        let mut value = None;
+        self.inner.try_pop(|bytes| value = Some(bincode::deserialize(bytes)))?;
+        decoded(value)
+    }
+
+    /// Pops one value, parking the calling thread until the sender pushes one.
+    pub fn pop(&self) -> Result<T, TypedError<PopError>> {
+        let mut value = None;
+        self.inner.pop(|bytes| value = Some(bincode::deserialize(bytes)))?;
+        decoded(value)
+    }
+}
+
+fn decoded<T>(value: Option<bincode::Result<T>>) -> Result<T, TypedError<PopError>> {
+    value.expect("popped without a frame").map_err(TypedError::Codec)
+}
+
+/// Error of a typed channel: either the underlying channel error `E`, or a value that
+/// failed to encode or decode.
+#[derive(Debug)]
+pub enum TypedError<E> {
+    Channel(E),
+    Codec(bincode::Error),
+}
+
+impl<E: std::error::Error> std::error::Error for TypedError<E> {}
+
+impl<E: std::fmt::Display> std::fmt::Display for TypedError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            TypedError::Channel(ref err) => write!(f, "{}", err),
+            TypedError::Codec(ref err) => write!(f, "codec error: {}", err),
+        }
+    }
+}
+
+impl<E> From<E> for TypedError<E> {
+    fn from(err: E) -> TypedError<E> {
+        TypedError::Channel(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::{channel_typed, TypedError};
+    use crate::cbuffer_raw::{BufferSize, PopError};
+
+    #[test]
+    fn test_round_trip() {
+        let (mut sender, receiver) = channel_typed::<(u32, String, Vec<u64>)>(BufferSize::Custom(4096));
+        let n = 10_000;
+        let producer = thread::spawn(move || {
+            for i in 0..n {
+                sender.push(&(i, i.to_string(), vec![i as u64; (i % 7) as usize])).unwrap();
+            }
+        });
+        for i in 0..n {
+            assert_eq!((i, i.to_string(), vec![i as u64; (i % 7) as usize]), receiver.pop().unwrap());
+        }
+        producer.join().unwrap();
+        match receiver.try_pop() {
+            Err(TypedError::Channel(PopError::Disconnected)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_decode_error() {
+        let (mut sender, receiver) = channel_typed::<String>(BufferSize::Custom(4096));
+        sender.inner.try_push(&[0xff; 12]).unwrap();
+        match receiver.try_pop() {
+            Err(TypedError::Codec(_)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::{AsFd, AsRawFd};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::cbuffer_raw::{join_both, page_size, CBuffer, PopError, PushError, Receiver, Sender};
+
+/// Ends the header in front of every element, after its CRC32: an element only counts
+/// as committed once both are in place.
+const COMMIT: u32 = 0x5741_4c43;
+const HEADER: usize = 8;
+
+/// When a `WalSender` flushes the log file to disk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncPolicy {
+    /// Once this many bytes have been pushed since the last flush.
+    EveryBytes(usize),
+    /// On the first push this long after the last flush.
+    Every(Duration),
+    /// Only in `WalSender::sync` and when the sender is dropped.
+    OnDemand,
+}
+
+/// Why a push into a write-ahead log failed. The element is in the ring after a
+/// `Sync` failure, but may not have made it to disk.
+#[derive(Debug)]
+pub enum WalError {
+    Push(PushError),
+    Sync(io::Error),
+}
+
+impl std::error::Error for WalError {}
+
+impl std::fmt::Display for WalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            WalError::Push(err) => write!(f, "{}", err),
+            WalError::Sync(err) => write!(f, "fail to sync log: {}", err),
+        }
+    }
+}
+
+impl From<PushError> for WalError {
+    fn from(err: PushError) -> WalError {
+        WalError::Push(err)
+    }
+}
+
+/// Sending half of a write-ahead log: a ring kept in a file, which outlives both
+/// processes and crashes of either.
+pub struct WalSender {
+    inner: Sender,
+    file: File,
+    policy: SyncPolicy,
+    unsynced: usize,
+    synced_at: Instant,
+}
+
+/// Receiving half of a write-ahead log. Elements stay in the file until popped.
+pub struct WalReceiver {
+    inner: Receiver,
+    file: File,
+}
+
+/// Creates both halves of the write-ahead log at `path`, holding `capacity` bytes, a
+/// power of two multiple of the page size. See `WalSender::open`.
+pub fn channel_wal<P: AsRef<Path>>(path: P, capacity: usize, policy: SyncPolicy) -> io::Result<(WalSender, WalReceiver)> {
+    let (file, ring) = open_log(path.as_ref(), capacity)?;
+    let receiver_file = file.try_clone()?;
+    let (sender, receiver) = join_both(ring);
+    Ok((WalSender::new(sender, file, policy), WalReceiver { inner: receiver, file: receiver_file }))
+}
+
+/// Opens or creates the log file. Whoever opens it while nobody else has it open
+/// recovers it: a crash may have left elements half written, or written but not
+/// flushed past the ring's own cursors, and those are dropped along with everything
+/// after them.
+fn open_log(path: &Path, capacity: usize) -> io::Result<(File, CBuffer)> {
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+    let alone = flock(&file, libc::LOCK_EX | libc::LOCK_NB).is_ok();
+    if !alone {
+        // Waits for whoever is recovering the file.
+        flock(&file, libc::LOCK_SH)?;
+    }
+    let result = (|| {
+        let size = (page_size() + capacity) as u64;
+        if alone && file.metadata()?.len() < size {
+            file.set_len(size)?;
+        }
+        CBuffer::open_log(file.as_fd(), capacity, if alone { Some(is_committed) } else { None })
+    })();
+    if alone {
+        flock(&file, libc::LOCK_SH)?;
+    }
+    result.map(|ring| (file, ring))
+}
+
+/// Holds the lock of kind `operation` on `file` until it is closed.
+fn flock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn is_committed(bytes: &[u8]) -> bool {
+    unwrap(bytes).is_some()
+}
+
+/// The element inside `bytes` if its commit header checks out.
+fn unwrap(bytes: &[u8]) -> Option<&[u8]> {
+    if bytes.len() < HEADER {
+        return None;
+    }
+    let word = |at: usize| u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+    let elem = &bytes[HEADER..];
+    Some(elem).filter(|elem| word(4) == COMMIT && word(0) == crc32fast::hash(elem))
+}
+
+fn header(elem: &[u8]) -> [u8; HEADER] {
+    let mut header = [0u8; HEADER];
+    header[..4].copy_from_slice(&crc32fast::hash(elem).to_le_bytes());
+    header[4..].copy_from_slice(&COMMIT.to_le_bytes());
+    header
+}
+
+impl WalSender {
+    /// Joins the log at `path` as a sender, creating the file if need be, e.g. in a
+    /// collector process whose indexer opens it with `WalReceiver::open`.
+    pub fn open<P: AsRef<Path>>(path: P, capacity: usize, policy: SyncPolicy) -> io::Result<WalSender> {
+        let (file, ring) = open_log(path.as_ref(), capacity)?;
+        Ok(WalSender::new(Sender::join(ring), file, policy))
+    }
+
+    fn new(inner: Sender, file: File, policy: SyncPolicy) -> WalSender {
+        WalSender { inner, file, policy, unsynced: 0, synced_at: Instant::now() }
+    }
+
+    pub fn try_push(&mut self, elem: &[u8]) -> Result<(), WalError> {
+        self.inner.inner.push_parts(&header(elem), elem)?;
+        self.pushed(elem.len())
+    }
+
+    /// Pushes `elem`, parking the calling thread until the receiver frees enough space,
+    /// then flushes the log if the policy says so.
+    pub fn push(&mut self, elem: &[u8]) -> Result<(), WalError> {
+        self.inner.inner.push_parts_blocking(&header(elem), elem)?;
+        self.pushed(elem.len())
+    }
+
+    fn pushed(&mut self, len: usize) -> Result<(), WalError> {
+        self.unsynced += HEADER + len;
+        let due = match self.policy {
+            SyncPolicy::EveryBytes(bytes) => self.unsynced >= bytes,
+            SyncPolicy::Every(period) => self.synced_at.elapsed() >= period,
+            SyncPolicy::OnDemand => false,
+        };
+        if due {
+            self.sync().map_err(WalError::Sync)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes everything pushed so far to disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        self.synced_at = Instant::now();
+        Ok(())
+    }
+
+    /// Bytes pushed since the last flush.
+    pub fn unsynced(&self) -> usize {
+        self.unsynced
+    }
+
+    pub fn close(&mut self) -> Result<(), PushError> {
+        self.inner.close()
+    }
+}
+
+impl Drop for WalSender {
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
+}
+
+impl WalReceiver {
+    /// Joins the log at `path` as the receiver, like `WalSender::open`.
+    pub fn open<P: AsRef<Path>>(path: P, capacity: usize) -> io::Result<WalReceiver> {
+        let (file, ring) = open_log(path.as_ref(), capacity)?;
+        Ok(WalReceiver { inner: Receiver::join(ring)?, file })
+    }
+
+    /// Pops the oldest element, handing it to `consumer` if its commit header checks out.
+    /// A corrupted element is discarded all the same.
+    pub fn try_pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(&[u8])
+    {
+        let mut result = Ok(());
+        let mut consumer = Some(consumer);
+        self.inner.try_pop(|bytes| result = deliver(bytes, &mut consumer))?;
+        result
+    }
+
+    /// Like `try_pop`, parking the calling thread until an element arrives.
+    pub fn pop<F>(&self, consumer: F) -> Result<(), PopError>
+        where F: FnOnce(&[u8])
+    {
+        let mut result = Ok(());
+        let mut consumer = Some(consumer);
+        self.inner.pop(|bytes| result = deliver(bytes, &mut consumer))?;
+        result
+    }
+
+    /// Flushes which elements have been popped to disk, so that they are not handed out
+    /// again after a crash.
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+
+fn deliver<F>(bytes: &[u8], consumer: &mut Option<F>) -> Result<(), PopError>
+    where F: FnOnce(&[u8])
+{
+    let elem = unwrap(bytes).ok_or(PopError::Corrupted)?;
+    (consumer.take().expect("element already delivered"))(elem);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::FileExt;
+
+    use super::{channel_wal, SyncPolicy, WalReceiver, WalSender};
+    use crate::cbuffer_raw::{page_size, PopError};
+
+    #[test]
+    fn test_wal_recovery() {
+        let path = std::env::temp_dir().join(format!("cbuffer-wal-{}", std::process::id()));
+        let capacity = page_size();
+        {
+            let (mut sender, receiver) = channel_wal(&path, capacity, SyncPolicy::EveryBytes(40)).unwrap();
+            for elem in [&b"popped"[..], b"first", b"second", b"torn"] {
+                sender.push(elem).unwrap();
+            }
+            assert_eq!(8 + 4, sender.unsynced());
+            assert_eq!(Ok(()), receiver.pop(|bytes| assert_eq!(b"popped", bytes)));
+            receiver.sync().unwrap();
+        }
+
+        // Tear the last element as if the machine went down while writing it out: 4
+        // bytes of length prefix, then 8 of commit header for each element.
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        let torn = page_size() + 3 * 12 + 6 + 5 + 6 + 12;
+        file.write_at(b"XX", torn as u64).unwrap();
+        drop(file);
+
+        let mut sender = WalSender::open(&path, capacity, SyncPolicy::OnDemand).unwrap();
+        let receiver = WalReceiver::open(&path, capacity).unwrap();
+        assert_eq!(2, receiver.len());
+        sender.push(b"third").unwrap();
+        assert_eq!(8 + 5, sender.unsynced());
+        sender.close().unwrap();
+        let mut seen = Vec::new();
+        while receiver.pop(|bytes| seen.push(bytes.to_vec())).is_ok() {}
+        assert_eq!(vec![b"first".to_vec(), b"second".to_vec(), b"third".to_vec()], seen);
+        assert_eq!(Err(PopError::Closed), receiver.try_pop(|_| {}));
+        drop((sender, receiver));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
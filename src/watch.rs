@@ -0,0 +1,219 @@
+use std::cell::Cell;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::cbuffer_raw::{channel, BufferSize, CBuffer, PopError, PushError, Receiver, Sender};
+
+/// Bytes in front of the value: the sequence number guarding it, then its length.
+const SLOT_HEADER: usize = 16;
+
+/// Sending half of a watch channel. Instead of queueing elements, the ring's memory holds
+/// a single value that every send replaces.
+pub struct WatchSender {
+    inner: Sender,
+}
+
+/// Receiving half of a watch channel, which always reads the most recent value.
+pub struct WatchReceiver {
+    inner: Receiver,
+    /// Version of the value read last.
+    seen: Cell<u64>,
+}
+
+/// Creates a watch channel holding values of up to `s` bytes less a 16-byte header.
+pub fn channel_watch(s: BufferSize) -> (WatchSender, WatchReceiver) {
+    let (sender, receiver) = channel(s);
+    (WatchSender::new(sender), WatchReceiver::new(receiver))
+}
+
+/// The value slot at the start of `ring`. Its sequence number is odd while a send is
+/// writing the value and counts up by two with every send, so half of it is the version
+/// of the value.
+fn sequence(ring: &CBuffer) -> &AtomicU64 {
+    // The mapping is page aligned.
+    unsafe { &*(ring.data() as *const AtomicU64) }
+}
+
+fn len(ring: &CBuffer) -> &AtomicU64 {
+    unsafe { &*(ring.data().add(8) as *const AtomicU64) }
+}
+
+impl WatchSender {
+    /// Sends through `inner`, which has to be the sender of a fresh channel, e.g. a shared
+    /// one for other processes to watch. Nothing else may push into it.
+    pub fn new(inner: Sender) -> WatchSender {
+        WatchSender { inner }
+    }
+
+    /// Replaces the value with `value`, returning its version. Senders of the same
+    /// channel take turns.
+    pub fn send(&mut self, value: &[u8]) -> Result<u64, PushError> {
+        let ring = &*self.inner.inner;
+        if SLOT_HEADER + value.len() > ring.size() {
+            return Err(PushError::MessageTooLarge);
+        }
+        if ring.is_receiver_dropped() {
+            return Err(PushError::Disconnected);
+        }
+        let sequence = sequence(ring);
+        let mut seq = sequence.load(Ordering::Relaxed);
+        loop {
+            if seq % 2 == 1 {
+                std::hint::spin_loop();
+                seq = sequence.load(Ordering::Relaxed);
+                continue;
+            }
+            match sequence.compare_exchange_weak(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(current) => seq = current,
+            }
+        }
+        // Orders the odd sequence number before the value: a reader that sees any of the
+        // new bytes sees the send in progress when it checks again.
+        fence(Ordering::Release);
+        len(ring).store(value.len() as u64, Ordering::Relaxed);
+        unsafe {
+            ptr::copy_nonoverlapping(value.as_ptr(), ring.data().add(SLOT_HEADER), value.len());
+        }
+        sequence.store(seq + 2, Ordering::Release);
+        ring.notify_readable();
+        Ok(seq / 2 + 1)
+    }
+
+    /// Version of the current value; 0 until the first send.
+    pub fn version(&self) -> u64 {
+        sequence(&self.inner.inner).load(Ordering::Acquire) / 2
+    }
+}
+
+impl WatchReceiver {
+    /// Watches through `inner`, e.g. a receiver attached to the shared ring of a
+    /// `WatchSender`.
+    pub fn new(inner: Receiver) -> WatchReceiver {
+        WatchReceiver { inner, seen: Cell::new(0) }
+    }
+
+    /// Version of the current value; 0 until the first send.
+    pub fn version(&self) -> u64 {
+        sequence(&self.inner.inner).load(Ordering::Acquire) / 2
+    }
+
+    /// Whether a value newer than the one read last has been sent.
+    pub fn has_changed(&self) -> bool {
+        self.version() != self.seen.get()
+    }
+
+    /// Copies out the current value along with its version, or `None` before the first
+    /// send. Retries while a send overwrites it.
+    pub fn latest(&self) -> Option<(u64, Vec<u8>)> {
+        let ring = &*self.inner.inner;
+        let sequence = sequence(ring);
+        let mut value = Vec::new();
+        loop {
+            let seq = sequence.load(Ordering::Acquire);
+            if seq == 0 {
+                return None;
+            }
+            if seq % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            // A torn length only leads to a retry.
+            let n = (len(ring).load(Ordering::Relaxed) as usize).min(ring.size() - SLOT_HEADER);
+            value.clear();
+            value.reserve(n);
+            unsafe {
+                ptr::copy_nonoverlapping(ring.data().add(SLOT_HEADER), value.as_mut_ptr(), n);
+                value.set_len(n);
+            }
+            fence(Ordering::Acquire);
+            if sequence.load(Ordering::Relaxed) == seq {
+                self.seen.set(seq / 2);
+                return Some((seq / 2, value));
+            }
+        }
+    }
+
+    /// Parks until a value newer than the one read last is sent. Fails with
+    /// `PopError::Disconnected` once every sender is gone without one.
+    pub fn changed(&self) -> Result<(), PopError> {
+        self.changed_with(None)
+    }
+
+    /// Like `changed`, giving up after `timeout` with `PopError::Empty`.
+    pub fn changed_timeout(&self, timeout: Duration) -> Result<(), PopError> {
+        self.changed_with(Some(timeout))
+    }
+
+    fn changed_with(&self, timeout: Option<Duration>) -> Result<(), PopError> {
+        let ring = &*self.inner.inner;
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            if self.has_changed() {
+                return Ok(());
+            }
+            if ring.is_sender_dropped() {
+                return Err(PopError::Disconnected);
+            }
+            // Waits can end early, e.g. to check on the peer of a shared ring.
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if remaining > Duration::from_secs(0) => Some(remaining),
+                    _ => return Err(PopError::Empty),
+                },
+                None => None,
+            };
+            ring.wait_readable(|| self.has_changed() || ring.is_sender_dropped(), remaining);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::channel_watch;
+    use crate::cbuffer_raw::{BufferSize, PopError, PushError};
+
+    #[test]
+    fn test_watch() {
+        let (mut sender, receiver) = channel_watch(BufferSize::Custom(4096));
+        assert_eq!(None, receiver.latest());
+        assert_eq!(Err(PopError::Empty), receiver.changed_timeout(Duration::from_millis(1)));
+        assert_eq!(Ok(1), sender.send(b"first"));
+        assert_eq!(Ok(2), sender.send(b"second"));
+        assert!(receiver.has_changed());
+        assert_eq!(Some((2, b"second".to_vec())), receiver.latest());
+        assert_eq!(Some((2, b"second".to_vec())), receiver.latest());
+        assert!(!receiver.has_changed());
+        assert_eq!(Err(PushError::MessageTooLarge), sender.send(&vec![0; 4096 - 15]));
+
+        // Readers never see a value torn by a concurrent send.
+        let writer = thread::spawn(move || {
+            for i in 3..20_000u64 {
+                let len = (i % 500) as usize;
+                sender.send(&vec![i as u8; len]).unwrap();
+            }
+        });
+        let mut last = 2;
+        loop {
+            match receiver.changed() {
+                Ok(()) => {
+                    let (version, value) = receiver.latest().unwrap();
+                    assert!(version > last);
+                    assert_eq!(version as usize % 500, value.len());
+                    assert!(value.iter().all(|&b| b == version as u8));
+                    last = version;
+                }
+                Err(err) => {
+                    assert_eq!(PopError::Disconnected, err);
+                    break;
+                }
+            }
+        }
+        writer.join().unwrap();
+        assert_eq!(Some(19_999), receiver.latest().map(|(version, _)| version));
+    }
+}
@@ -0,0 +1,85 @@
+use std::ptr;
+
+use windows_sys::Win32::Foundation::{CloseHandle, INVALID_HANDLE_VALUE};
+use windows_sys::Win32::System::Memory::{
+    CreateFileMappingW, MapViewOfFile3, UnmapViewOfFile, VirtualAlloc2, VirtualFree,
+    MEMORY_MAPPED_VIEW_ADDRESS, MEM_PRESERVE_PLACEHOLDER, MEM_RELEASE, MEM_REPLACE_PLACEHOLDER,
+    MEM_RESERVE, MEM_RESERVE_PLACEHOLDER, PAGE_NOACCESS, PAGE_READWRITE,
+};
+use windows_sys::Win32::System::SystemInformation::{GetSystemInfo, SYSTEM_INFO};
+
+use crate::cbuffer_raw::Error;
+
+pub fn allocation_granularity() -> usize {
+    unsafe {
+        let mut info: SYSTEM_INFO = std::mem::zeroed();
+        GetSystemInfo(&mut info);
+        info.dwAllocationGranularity as usize
+    }
+}
+
+/// Maps one pagefile-backed section of `capacity` bytes twice back to back. The two views
+/// replace the halves of a split placeholder reservation, so nothing else can be mapped
+/// into the gap between reserving the range and placing the views.
+pub fn map_mirror(capacity: usize) -> Result<ptr::NonNull<u8>, Error> {
+    unsafe {
+        let section = CreateFileMappingW(INVALID_HANDLE_VALUE,
+                                         ptr::null(),
+                                         PAGE_READWRITE,
+                                         (capacity as u64 >> 32) as u32,
+                                         capacity as u32,
+                                         ptr::null());
+        if section.is_null() {
+            return Err(Error::OS);
+        }
+        let base = VirtualAlloc2(ptr::null_mut(),
+                                 ptr::null(),
+                                 2 * capacity,
+                                 MEM_RESERVE | MEM_RESERVE_PLACEHOLDER,
+                                 PAGE_NOACCESS,
+                                 ptr::null_mut(),
+                                 0);
+        if base.is_null() {
+            CloseHandle(section);
+            return Err(Error::OS);
+        }
+        if VirtualFree(base, capacity, MEM_RELEASE | MEM_PRESERVE_PLACEHOLDER) == 0 {
+            VirtualFree(base, 0, MEM_RELEASE);
+            CloseHandle(section);
+            return Err(Error::OS);
+        }
+        let map_view = |at: *mut u8| {
+            MapViewOfFile3(section,
+                           ptr::null_mut(),
+                           at as *const _,
+                           0,
+                           capacity,
+                           MEM_REPLACE_PLACEHOLDER,
+                           PAGE_READWRITE,
+                           ptr::null_mut(),
+                           0).Value
+        };
+        let base = base as *mut u8;
+        let first = map_view(base);
+        let second = if first.is_null() { ptr::null_mut() } else { map_view(base.add(capacity)) };
+        // The views keep the section alive on their own.
+        CloseHandle(section);
+        if second.is_null() {
+            if first.is_null() {
+                VirtualFree(base as *mut _, 0, MEM_RELEASE);
+            } else {
+                UnmapViewOfFile(MEMORY_MAPPED_VIEW_ADDRESS { Value: first });
+            }
+            VirtualFree(base.add(capacity) as *mut _, 0, MEM_RELEASE);
+            return Err(Error::OS);
+        }
+        Ok(ptr::NonNull::new_unchecked(base))
+    }
+}
+
+/// Undoes `map_mirror`.
+pub unsafe fn unmap_mirror(pointer: ptr::NonNull<u8>, capacity: usize) {
+    let base = pointer.as_ptr();
+    UnmapViewOfFile(MEMORY_MAPPED_VIEW_ADDRESS { Value: base as *mut _ });
+    UnmapViewOfFile(MEMORY_MAPPED_VIEW_ADDRESS { Value: base.add(capacity) as *mut _ });
+}